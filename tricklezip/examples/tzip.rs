@@ -0,0 +1,213 @@
+//! Compresses or decompresses a file or stdin with TrickleZip.
+//!
+//! ```text
+//! tzip [-d] [-f raw|zlib|gzip] [-l 0-9] [-p PROFILE] [-v] [-o OUTPUT] [INPUT]
+//! ```
+//!
+//! Reads INPUT (or stdin) and writes OUTPUT (or stdout). The default
+//! container is gzip, so `tzip < log.txt | gzip -d` checks interop with a
+//! stock decoder. `-p` picks a preset instead of a level: `text`,
+//! `telemetry`, `firmware`, `json`, `low-memory` or `tiny-N` for a tiny
+//! window of N bytes. `-v` prints sizes, ratio and throughput to stderr.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::process::ExitCode;
+use std::time::Instant;
+
+use tricklezip::{
+    CompressionConfig, CompressionLevel, ContainerFormat, TrickleCompressor, TrickleDecompressor,
+    TrickleStatus,
+};
+
+const BUF_SIZE: usize = 64 * 1024;
+
+struct Options {
+    decompress: bool,
+    format: ContainerFormat,
+    config: CompressionConfig,
+    verbose: bool,
+    input: Option<String>,
+    output: Option<String>,
+}
+
+fn usage() -> String {
+    "usage: tzip [-d] [-f raw|zlib|gzip] [-l 0-9] [-p PROFILE] [-v] [-o OUTPUT] [INPUT]".into()
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        decompress: false,
+        format: ContainerFormat::Gzip,
+        config: CompressionConfig::default(),
+        verbose: false,
+        input: None,
+        output: None,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{name} needs a value"));
+        match arg.as_str() {
+            "-d" => options.decompress = true,
+            "-v" => options.verbose = true,
+            "-f" => {
+                options.format = match value("-f")?.as_str() {
+                    "raw" => ContainerFormat::Raw,
+                    "zlib" => ContainerFormat::Zlib,
+                    "gzip" => ContainerFormat::Gzip,
+                    other => return Err(format!("unknown format {other}")),
+                }
+            }
+            "-l" => {
+                let level = value("-l")?;
+                let level = level
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|&l| l <= 9)
+                    .ok_or(format!("bad level {level}"))?;
+                options.config = CompressionConfig::new(CompressionLevel::new(level));
+            }
+            "-p" => options.config = profile(&value("-p")?)?,
+            "-o" => options.output = Some(value("-o")?),
+            "-h" | "--help" => return Err(usage()),
+            _ if arg.starts_with('-') && arg != "-" => return Err(usage()),
+            _ => options.input = Some(arg),
+        }
+    }
+    options.config.format = options.format;
+    Ok(options)
+}
+
+fn profile(name: &str) -> Result<CompressionConfig, String> {
+    Ok(match name {
+        "text" => CompressionConfig::text(),
+        "telemetry" => CompressionConfig::telemetry(),
+        "firmware" => CompressionConfig::firmware_image(),
+        "json" => CompressionConfig::json_logs(),
+        "low-memory" => CompressionConfig::low_memory(),
+        _ => match name.strip_prefix("tiny-").and_then(|n| n.parse().ok()) {
+            Some(window) => CompressionConfig::tiny_window(window),
+            None => return Err(format!("unknown profile {name}")),
+        },
+    })
+}
+
+fn compress(options: &Options, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+    let mut compressor = TrickleCompressor::new(options.config.clone());
+    let mut inbuf = vec![0u8; BUF_SIZE];
+    let mut outbuf = vec![0u8; BUF_SIZE];
+    let mut total_in = 0;
+    loop {
+        let len = input.read(&mut inbuf)?;
+        total_in += len as u64;
+        let finish = len == 0;
+        let mut pos = 0;
+        loop {
+            let r = compressor.compress_trickle(&inbuf[pos..len], &mut outbuf, finish.into())?;
+            pos += r.consumed;
+            output.write_all(&outbuf[..r.written])?;
+            match r.status {
+                TrickleStatus::Finished => return Ok(total_in),
+                TrickleStatus::NeedInput if pos == len => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+fn decompress(options: &Options, input: &mut dyn Read, output: &mut dyn Write) -> io::Result<u64> {
+    let mut decompressor = TrickleDecompressor::new(options.format);
+    let mut inbuf = vec![0u8; BUF_SIZE];
+    let mut outbuf = vec![0u8; BUF_SIZE];
+    let mut total_in = 0;
+    loop {
+        let len = input.read(&mut inbuf)?;
+        total_in += len as u64;
+        let mut pos = 0;
+        loop {
+            let r = decompressor.decompress_trickle(&inbuf[pos..len], &mut outbuf)?;
+            pos += r.consumed;
+            output.write_all(&outbuf[..r.written])?;
+            if r.status == TrickleStatus::Finished {
+                return Ok(total_in - (len - pos) as u64);
+            }
+            if r.consumed == 0 && r.written == 0 {
+                break;
+            }
+        }
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+}
+
+/// Counts the bytes written through it.
+struct Counter<W> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn run(options: &Options) -> io::Result<()> {
+    let mut input: Box<dyn Read> = match &options.input {
+        Some(path) if path != "-" => Box::new(File::open(path)?),
+        _ => Box::new(io::stdin().lock()),
+    };
+    let output: Box<dyn Write> = match &options.output {
+        Some(path) if path != "-" => Box::new(File::create(path)?),
+        _ => Box::new(io::stdout().lock()),
+    };
+    let mut output = Counter {
+        inner: BufWriter::new(output),
+        count: 0,
+    };
+    let start = Instant::now();
+    let read = if options.decompress {
+        decompress(options, &mut input, &mut output)?
+    } else {
+        compress(options, &mut input, &mut output)?
+    };
+    output.flush()?;
+    if options.verbose {
+        let elapsed = start.elapsed().as_secs_f64();
+        let (raw, packed) = if options.decompress {
+            (output.count, read)
+        } else {
+            (read, output.count)
+        };
+        eprintln!(
+            "{raw} -> {packed} bytes, ratio {:.3}, {:.1} MB/s",
+            packed as f64 / raw.max(1) as f64,
+            raw as f64 / elapsed.max(1e-9) / 1e6
+        );
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(msg) => {
+            eprintln!("{msg}");
+            return ExitCode::from(2);
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("tzip: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Match effort control against a throughput target.
+
+use std::time::{Duration, Instant};
+
+use crate::compressor::{TrickleCompressor, TrickleResult};
+use crate::config::{CompressionConfig, Strategy};
+use crate::error::TrickleError;
+
+/// Effort rungs as (max chain length, nice length), cheapest first. Rung 0
+/// skips match finding and only Huffman codes literals.
+const RUNGS: [(u16, u16); 7] = [
+    (0, 3),
+    (1, 16),
+    (4, 32),
+    (8, 64),
+    (32, 128),
+    (128, 258),
+    (512, 258),
+];
+/// Throughput may deviate this many percent from the target before the
+/// effort changes, so the controller does not oscillate.
+const HYSTERESIS: u64 = 10;
+
+/// Compressor that keeps an uplink saturated by trading ratio for speed.
+///
+/// Wall-clock time of every call is measured. Each time a block is emitted,
+/// the throughput since the previous block is compared with the target and
+/// the match finder moves one rung towards more or less effort.
+pub struct AdaptiveCompressor {
+    compressor: TrickleCompressor,
+    target_bytes_per_ms: u64,
+    rung: usize,
+    /// Input encoded and time spent since the last block boundary.
+    bytes: u64,
+    elapsed: Duration,
+    blocks: u32,
+}
+
+impl AdaptiveCompressor {
+    /// Wraps a compressor for `config`, starting at the rung closest to its
+    /// `max_chain_length`. Literal-only strategies are replaced by
+    /// [`Strategy::Default`] so there is something to adapt.
+    pub fn new(mut config: CompressionConfig, target_bytes_per_ms: u32) -> Self {
+        if matches!(config.strategy, Strategy::HuffmanOnly | Strategy::Rle) {
+            config.strategy = Strategy::Default;
+        }
+        let rung = RUNGS
+            .iter()
+            .rposition(|&(chain, _)| chain <= config.max_chain_length)
+            .unwrap_or(0);
+        // Allocate chains deep enough for the top rung.
+        config.max_chain_length = RUNGS[RUNGS.len() - 1].0;
+        let mut compressor = TrickleCompressor::new(config);
+        let (chain, nice) = RUNGS[rung];
+        compressor.set_effort(chain as usize, nice as usize);
+        Self {
+            compressor,
+            target_bytes_per_ms: target_bytes_per_ms.max(1) as u64,
+            rung,
+            bytes: 0,
+            elapsed: Duration::ZERO,
+            blocks: 0,
+        }
+    }
+
+    pub fn compressor(&self) -> &TrickleCompressor {
+        &self.compressor
+    }
+
+    /// Current effort rung, from 0 (literals only) to [`max_effort`](Self::max_effort).
+    pub fn effort(&self) -> usize {
+        self.rung
+    }
+
+    pub fn max_effort(&self) -> usize {
+        RUNGS.len() - 1
+    }
+
+    /// Same contract as [`TrickleCompressor::compress_trickle`].
+    pub fn compress_trickle(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        finish: bool,
+    ) -> Result<TrickleResult, TrickleError> {
+        let start = Instant::now();
+        let processed = self.compressor.stats().work_units;
+        let result = self
+            .compressor
+            .compress_trickle(input, output, finish.into())?;
+        self.elapsed += start.elapsed();
+        self.bytes += self.compressor.stats().work_units - processed;
+        let blocks = self.compressor.stats().blocks();
+        if blocks != self.blocks {
+            self.blocks = blocks;
+            self.adjust();
+        }
+        Ok(result)
+    }
+
+    fn adjust(&mut self) {
+        // Compare bytes * 1000 against target * micros to stay in integers.
+        let achieved = self.bytes * 1000 * 100;
+        let micros = self.elapsed.as_micros().max(1) as u64;
+        let wanted = self.target_bytes_per_ms * micros;
+        let rung = if achieved < wanted * (100 - HYSTERESIS) {
+            self.rung.saturating_sub(1)
+        } else if achieved > wanted * (100 + HYSTERESIS) {
+            (self.rung + 1).min(RUNGS.len() - 1)
+        } else {
+            self.rung
+        };
+        if rung != self.rung {
+            self.rung = rung;
+            let (chain, nice) = RUNGS[rung];
+            self.compressor.set_effort(chain as usize, nice as usize);
+        }
+        self.bytes = 0;
+        self.elapsed = Duration::ZERO;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decompress_to_vec, ContainerFormat, TrickleStatus};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn run(target: u32) -> (usize, Vec<u8>, Vec<u8>) {
+        let mut data = Vec::new();
+        for i in 0..3000u32 {
+            let line = alloc::format!("pressure={} humidity={} seq={i}\n", 990 + i % 40, i % 7);
+            data.extend_from_slice(line.as_bytes());
+        }
+        let mut config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        config.block_size = 64;
+        let mut adaptive = AdaptiveCompressor::new(config, target);
+        let mut out = vec![0u8; data.len()];
+        let (mut consumed, mut written) = (0, 0);
+        loop {
+            let r = adaptive
+                .compress_trickle(&data[consumed..], &mut out[written..], true)
+                .unwrap();
+            consumed += r.consumed;
+            written += r.written;
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+        }
+        out.truncate(written);
+        (adaptive.effort(), out, data)
+    }
+
+    #[test]
+    fn unreachable_target_drops_to_literals() {
+        let (effort, packed, data) = run(u32::MAX);
+        assert_eq!(effort, 0);
+        assert_eq!(
+            decompress_to_vec(&packed, ContainerFormat::Zlib).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn slack_target_climbs_to_full_effort() {
+        let (effort, packed, data) = run(1);
+        assert_eq!(effort, RUNGS.len() - 1);
+        assert_eq!(
+            decompress_to_vec(&packed, ContainerFormat::Zlib).unwrap(),
+            data
+        );
+    }
+}
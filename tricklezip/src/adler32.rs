@@ -0,0 +1,71 @@
+//! Adler-32 as used by the zlib container.
+
+const MOD: u32 = 65521;
+/// Largest block for which the sums cannot overflow a u32 before reduction.
+const NMAX: usize = 5552;
+
+/// Continues an Adler-32 over `data`. Start with `adler = 1`.
+pub(crate) fn update(adler: u32, data: &[u8]) -> u32 {
+    let mut a = adler & 0xFFFF;
+    let mut b = adler >> 16;
+    for chunk in data.chunks(NMAX) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Adler-32 of `a` followed by `b`, from the Adler-32 of each and the length
+/// of `b`, without the data.
+pub(crate) fn combine(adler_a: u32, adler_b: u32, len_b: u64) -> u32 {
+    let m = MOD as u64;
+    let rem = len_b % m;
+    let (a1, b1) = ((adler_a & 0xFFFF) as u64, (adler_a >> 16) as u64);
+    let (a2, b2) = ((adler_b & 0xFFFF) as u64, (adler_b >> 16) as u64);
+    // Every byte of `b` adds `a1` once more to the second sum.
+    let a = (a1 + a2 + m - 1) % m;
+    let b = (rem * a1 + b1 + b2 + m - rem) % m;
+    ((b << 16) | a) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(update(1, b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(update(update(1, b"Wiki"), b"pedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn long_runs_of_0xff_do_not_overflow() {
+        // Reducing once per byte is slow but cannot overflow.
+        let naive = |data: &[u8]| {
+            let (mut a, mut b) = (1u32, 0u32);
+            for &byte in data {
+                a = (a + byte as u32) % MOD;
+                b = (b + a) % MOD;
+            }
+            (b << 16) | a
+        };
+        let data = [0xFFu8; 3 * NMAX + 17];
+        assert_eq!(update(1, &data), naive(&data));
+        let pieces = data.chunks(NMAX - 1).fold(1, update);
+        assert_eq!(pieces, naive(&data));
+    }
+
+    #[test]
+    fn combine_matches_contiguous() {
+        let data = [0xFFu8; 20000];
+        for cut in [0, 1, 4, 5552, 12345, data.len()] {
+            let (a, b) = data.split_at(cut);
+            let joined = combine(update(1, a), update(1, b), b.len() as u64);
+            assert_eq!(joined, update(1, &data), "cut at {cut}");
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Allocator parameter for the compressor's buffers.
+//!
+//! With the `allocator_api` feature (nightly only) these are the standard
+//! library's `Allocator`, `Global`, `Vec` and `Box`. Without it, a stand-in
+//! trait with [`Global`] as its only implementation keeps the same generic
+//! signatures compiling on stable, and the buffer types are thin wrappers
+//! around the global-allocator `Vec` and `Box`.
+
+#[cfg(feature = "allocator_api")]
+pub use alloc::alloc::{Allocator, Global};
+
+#[cfg(feature = "allocator_api")]
+pub(crate) type VecIn<T, A> = alloc::vec::Vec<T, A>;
+
+#[cfg(all(feature = "allocator_api", not(feature = "tiny")))]
+pub(crate) type BoxIn<T, A> = alloc::boxed::Box<T, A>;
+
+#[cfg(all(not(feature = "allocator_api"), not(feature = "tiny")))]
+pub(crate) use stand_in::BoxIn;
+#[cfg(not(feature = "allocator_api"))]
+pub use stand_in::{Allocator, Global, VecIn};
+
+/// A vector of `len` copies of `value` in `alloc`.
+pub(crate) fn filled_in<T: Clone, A: Allocator>(value: T, len: usize, alloc: A) -> VecIn<T, A> {
+    let mut v = VecIn::with_capacity_in(len, alloc);
+    v.resize(len, value);
+    v
+}
+
+#[cfg(not(feature = "allocator_api"))]
+mod stand_in {
+    #[cfg(not(feature = "tiny"))]
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+    use core::marker::PhantomData;
+    use core::ops::{Deref, DerefMut};
+
+    /// Stand-in for `core::alloc::Allocator` on stable toolchains.
+    pub trait Allocator {}
+
+    /// The global allocator.
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct Global;
+
+    impl Allocator for Global {}
+
+    pub struct VecIn<T, A>(Vec<T>, PhantomData<A>);
+
+    impl<T, A> VecIn<T, A> {
+        pub(crate) fn new_in(_: A) -> Self {
+            Self(Vec::new(), PhantomData)
+        }
+
+        pub(crate) fn with_capacity_in(capacity: usize, _: A) -> Self {
+            Self(Vec::with_capacity(capacity), PhantomData)
+        }
+    }
+
+    impl<T, A> Deref for VecIn<T, A> {
+        type Target = Vec<T>;
+
+        #[inline]
+        fn deref(&self) -> &Vec<T> {
+            &self.0
+        }
+    }
+
+    impl<T, A> DerefMut for VecIn<T, A> {
+        #[inline]
+        fn deref_mut(&mut self) -> &mut Vec<T> {
+            &mut self.0
+        }
+    }
+
+    #[cfg(not(feature = "tiny"))]
+    pub struct BoxIn<T, A>(Box<T>, PhantomData<A>);
+
+    #[cfg(not(feature = "tiny"))]
+    impl<T, A> BoxIn<T, A> {
+        pub(crate) fn new_in(value: T, _: A) -> Self {
+            Self(Box::new(value), PhantomData)
+        }
+    }
+
+    #[cfg(not(feature = "tiny"))]
+    impl<T, A> Deref for BoxIn<T, A> {
+        type Target = T;
+
+        #[inline]
+        fn deref(&self) -> &T {
+            &self.0
+        }
+    }
+
+    #[cfg(not(feature = "tiny"))]
+    impl<T, A> DerefMut for BoxIn<T, A> {
+        #[inline]
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.0
+        }
+    }
+}
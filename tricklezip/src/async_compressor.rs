@@ -0,0 +1,203 @@
+//! Compression as a future that yields to the executor between steps.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use crate::compressor::{FlushMode, TrickleCompressor, TrickleResult, TrickleStatus};
+use crate::config::CompressionConfig;
+use crate::error::TrickleError;
+
+/// [`TrickleCompressor`] for async code: [`compress`](Self::compress) runs
+/// as many `config.trickle_size` steps as the buffers allow, yielding to
+/// the executor after each, so other tasks run in between. Works with any
+/// executor, including single-threaded ones on microcontrollers.
+///
+/// ```
+/// use core::future::Future;
+/// use core::task::{Context, Poll, Waker};
+/// use tricklezip::{compress_to_vec, AsyncTrickleCompressor, CompressionConfig, FlushMode};
+///
+/// let data = b"door=closed temp=19.5 ".repeat(200);
+/// let config = CompressionConfig::default();
+/// let mut compressor = AsyncTrickleCompressor::new(config.clone());
+/// let mut out = vec![0u8; data.len()];
+/// let r = {
+///     let mut task = core::pin::pin!(compressor.compress(&data, &mut out, FlushMode::Finish));
+///     let mut cx = Context::from_waker(Waker::noop());
+///     loop {
+///         if let Poll::Ready(r) = task.as_mut().poll(&mut cx) {
+///             break r.unwrap();
+///         }
+///     }
+/// };
+/// assert_eq!(out[..r.written], compress_to_vec(&data, &config));
+/// ```
+pub struct AsyncTrickleCompressor {
+    compressor: TrickleCompressor,
+}
+
+impl AsyncTrickleCompressor {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            compressor: TrickleCompressor::new(config),
+        }
+    }
+
+    pub fn compressor(&self) -> &TrickleCompressor {
+        &self.compressor
+    }
+
+    pub fn compressor_mut(&mut self) -> &mut TrickleCompressor {
+        &mut self.compressor
+    }
+
+    pub fn into_inner(self) -> TrickleCompressor {
+        self.compressor
+    }
+
+    /// Compresses `input` into `output` until all input is consumed, the
+    /// output is full or the stream is finished, with the same contract
+    /// for `flush` and the returned status as
+    /// [`TrickleCompressor::compress_trickle`]; `consumed` and `written`
+    /// count over all steps.
+    pub async fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.compress_with(input, output, flush, || YieldNow(false))
+            .await
+    }
+
+    /// [`compress`](Self::compress), yielding by awaiting `yield_now()`
+    /// between steps, e.g. the executor's own `yield_now`, or a timer to
+    /// leave the CPU to lower-priority work too.
+    pub async fn compress_with<F: Future<Output = ()>>(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+        yield_now: impl FnMut() -> F,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.run(input, output, flush, yield_now, || false).await
+    }
+
+    /// The step loop of [`compress_with`](Self::compress_with), also
+    /// returning early after a step once `expired()`.
+    pub(crate) async fn run<F: Future<Output = ()>>(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+        mut yield_now: impl FnMut() -> F,
+        mut expired: impl FnMut() -> bool,
+    ) -> Result<TrickleResult, TrickleError> {
+        let mut total = TrickleResult {
+            consumed: 0,
+            written: 0,
+            status: TrickleStatus::InProgress,
+        };
+        loop {
+            let r = self.compressor.compress_trickle(
+                &input[total.consumed..],
+                &mut output[total.written..],
+                flush,
+            )?;
+            total.consumed += r.consumed;
+            total.written += r.written;
+            total.status = r.status;
+            if r.status != TrickleStatus::InProgress || expired() {
+                return Ok(total);
+            }
+            yield_now().await;
+        }
+    }
+}
+
+impl From<TrickleCompressor> for AsyncTrickleCompressor {
+    fn from(compressor: TrickleCompressor) -> Self {
+        Self { compressor }
+    }
+}
+
+/// Pending once, waking its task right away.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decompress_to_vec, ContainerFormat};
+    use alloc::vec;
+    use core::cell::Cell;
+    use core::task::Waker;
+
+    fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+        let mut future = core::pin::pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut pending = 0;
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return (output, pending),
+                Poll::Pending => pending += 1,
+            }
+        }
+    }
+
+    #[test]
+    fn yields_between_steps() {
+        let data = b"lat=52.37 lon=4.89 sats=9\n".repeat(100);
+        let config = CompressionConfig {
+            trickle_size: 256,
+            ..CompressionConfig::default().with_format(ContainerFormat::Gzip)
+        };
+        let mut compressor = AsyncTrickleCompressor::new(config);
+        let mut out = vec![0u8; data.len()];
+        let (r, pending) = block_on(compressor.compress(&data, &mut out, FlushMode::Finish));
+        let r = r.unwrap();
+        assert_eq!(r.status, TrickleStatus::Finished);
+        assert_eq!(r.consumed, data.len());
+        let steps = compressor.compressor().stats().trickle_calls as usize;
+        assert!(steps >= data.len() / 256);
+        assert_eq!(pending, steps - 1);
+        assert_eq!(
+            decompress_to_vec(&out[..r.written], ContainerFormat::Gzip).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn custom_yield_runs_between_steps_only() {
+        let data = b"lat=52.37 lon=4.89 sats=9\n".repeat(100);
+        let yields = Cell::new(0);
+        let mut compressor = AsyncTrickleCompressor::new(CompressionConfig::default());
+        let mut small = [0u8; 16];
+        // A full output ends the call without a final yield.
+        let (r, pending) =
+            block_on(
+                compressor.compress_with(&data, &mut small, FlushMode::Finish, || {
+                    yields.set(yields.get() + 1);
+                    core::future::ready(())
+                }),
+            );
+        assert_eq!(r.unwrap().status, TrickleStatus::NeedOutput);
+        assert!(!compressor.compressor().is_finished());
+        assert_eq!(pending, 0);
+        let steps = compressor.compressor().stats().trickle_calls as usize;
+        assert_eq!(yields.get(), steps - 1);
+    }
+}
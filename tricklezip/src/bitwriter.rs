@@ -0,0 +1,141 @@
+//! LSB-first bit packing into a pending output buffer.
+
+use crate::allocator::{Allocator, Global, VecIn};
+
+/// Collects encoded bits until the caller has room to take them.
+pub(crate) struct BitWriter<A: Allocator = Global> {
+    pending: VecIn<u8, A>,
+    /// Bytes of `pending` already handed out.
+    read_pos: usize,
+    bit_buf: u64,
+    bit_count: u32,
+}
+
+impl<A: Allocator> BitWriter<A> {
+    pub(crate) fn new_in(alloc: A) -> Self {
+        Self {
+            pending: VecIn::new_in(alloc),
+            read_pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Creates a writer with room for `capacity` pending bytes.
+    pub(crate) fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self {
+            pending: VecIn::with_capacity_in(capacity, alloc),
+            read_pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Appends the low `count` bits of `bits` (at most 32).
+    #[inline]
+    pub(crate) fn write_bits(&mut self, bits: u32, count: u32) {
+        debug_assert!(count <= 32);
+        self.bit_buf |= (bits as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.pending.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Appends a run of codes of at most 16 bits each. Equivalent to calling
+    /// `write_bits` for each, but the bit buffer stays in locals and output
+    /// goes out four bytes at a time.
+    #[inline]
+    pub(crate) fn write_codes(&mut self, codes: impl Iterator<Item = (u16, u32)>) {
+        let mut buf = self.bit_buf;
+        let mut count = self.bit_count;
+        for (code, len) in codes {
+            debug_assert!(len <= 16);
+            buf |= (code as u64) << count;
+            count += len;
+            if count >= 32 {
+                self.pending.extend_from_slice(&(buf as u32).to_le_bytes());
+                buf >>= 32;
+                count -= 32;
+            }
+        }
+        self.bit_buf = buf;
+        self.bit_count = 0;
+        self.write_bits(0, count);
+    }
+
+    /// Discards pending output, keeping the buffer's capacity.
+    pub(crate) fn reset(&mut self) {
+        self.pending.clear();
+        self.read_pos = 0;
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    /// Pads with zero bits up to the next byte boundary.
+    pub(crate) fn align(&mut self) {
+        if self.bit_count > 0 {
+            self.pending.push(self.bit_buf as u8);
+            self.bit_buf = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    /// Appends whole bytes. The writer must be byte aligned.
+    pub(crate) fn write_bytes(&mut self, bytes: &[u8]) {
+        debug_assert_eq!(self.bit_count, 0);
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// Bytes that are complete but not yet drained.
+    pub(crate) fn pending_len(&self) -> usize {
+        self.pending.len() - self.read_pos
+    }
+
+    /// Bytes allocated for the pending buffer.
+    pub(crate) fn capacity(&self) -> usize {
+        self.pending.capacity()
+    }
+
+    /// Copies as many pending bytes as fit into `out`.
+    pub(crate) fn drain(&mut self, out: &mut [u8]) -> usize {
+        let n = self.pending_len().min(out.len());
+        out[..n].copy_from_slice(&self.pending[self.read_pos..self.read_pos + n]);
+        self.read_pos += n;
+        if self.read_pos == self.pending.len() {
+            self.pending.clear();
+            self.read_pos = 0;
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn code_runs_match_single_writes() {
+        let codes: Vec<(u16, u32)> = (0..200u32)
+            .map(|i| ((i * 37 % 512) as u16 & ((1 << (i % 9 + 1)) - 1), i % 9 + 1))
+            .collect();
+        let mut single = BitWriter::new_in(Global);
+        let mut batched = BitWriter::new_in(Global);
+        single.write_bits(5, 3);
+        batched.write_bits(5, 3);
+        for &(code, len) in &codes {
+            single.write_bits(code as u32, len);
+        }
+        batched.write_codes(codes.iter().copied());
+        single.align();
+        batched.align();
+        let mut a = alloc::vec![0; single.pending_len()];
+        let mut b = alloc::vec![0; batched.pending_len()];
+        single.drain(&mut a);
+        batched.drain(&mut b);
+        assert_eq!(a, b);
+    }
+}
@@ -0,0 +1,226 @@
+//! Hand-fed DEFLATE blocks, for formats generating their own tokens.
+
+use alloc::vec::Vec;
+
+use crate::allocator::Global;
+use crate::bitwriter::BitWriter;
+use crate::error::TrickleError;
+use crate::huffman::{BlockType, HuffmanCoder};
+use crate::lz77::{Token, MAX_MATCH, MIN_MATCH};
+
+/// Largest distance DEFLATE can express.
+const MAX_DISTANCE: usize = 32768;
+
+/// Writes raw DEFLATE blocks from literals and matches chosen by the
+/// caller, e.g. a format with its own filters and match model. Each block
+/// can be forced to a type or left to the cheapest one, as
+/// [`TrickleCompressor`](crate::TrickleCompressor) does.
+///
+/// Matches are checked against the data fed so far, so the output always
+/// decodes. The encoder keeps the last 32KB of that data plus the current
+/// block, which stored blocks are written from.
+///
+/// ```
+/// use tricklezip::{decompress_to_vec, BlockEncoder, BlockType, ContainerFormat};
+///
+/// let mut encoder = BlockEncoder::new();
+/// encoder.literals(b"row0");
+/// encoder.copy(4, 4).unwrap();
+/// encoder.emit(Some(BlockType::Fixed), false);
+/// encoder.literals(b"tail");
+/// encoder.emit(None, true);
+///
+/// let mut out = vec![0; encoder.pending_len()];
+/// encoder.drain(&mut out);
+/// let data = decompress_to_vec(&out, ContainerFormat::Raw).unwrap();
+/// assert_eq!(data, b"row0row0tail");
+/// ```
+pub struct BlockEncoder {
+    huffman: HuffmanCoder,
+    writer: BitWriter,
+    tokens: Vec<Token>,
+    /// Up to `MAX_DISTANCE` bytes of earlier blocks, then the current one.
+    data: Vec<u8>,
+    /// Start of the current block in `data`.
+    block_start: usize,
+    finished: bool,
+}
+
+impl Default for BlockEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BlockEncoder {
+    pub fn new() -> Self {
+        Self {
+            huffman: HuffmanCoder::new_in(true, Global),
+            writer: BitWriter::new_in(Global),
+            tokens: Vec::new(),
+            data: Vec::new(),
+            block_start: 0,
+            finished: false,
+        }
+    }
+
+    /// Adds a literal byte to the current block.
+    pub fn literal(&mut self, byte: u8) {
+        self.tokens.push(Token::Literal(byte));
+        self.data.push(byte);
+    }
+
+    /// Adds each byte of `bytes` as a literal.
+    pub fn literals(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.literal(byte);
+        }
+    }
+
+    /// Adds a copy of `length` bytes, 3 to 258, from `distance` bytes back.
+    /// Fails with `InvalidData`, adding nothing, if the length is out of
+    /// range or the distance reaches before the start of the data or more
+    /// than 32KB back.
+    pub fn copy(&mut self, length: u16, distance: u16) -> Result<(), TrickleError> {
+        let (len, dist) = (length as usize, distance as usize);
+        if !(MIN_MATCH..=MAX_MATCH).contains(&len)
+            || dist == 0
+            || dist > self.data.len().min(MAX_DISTANCE)
+        {
+            return Err(TrickleError::InvalidData);
+        }
+        self.tokens.push(Token::Match { length, distance });
+        for _ in 0..len {
+            self.data.push(self.data[self.data.len() - dist]);
+        }
+        Ok(())
+    }
+
+    /// Adds `token`; see [`copy`](Self::copy) for when matches fail.
+    pub fn push(&mut self, token: Token) -> Result<(), TrickleError> {
+        match token {
+            Token::Literal(byte) => {
+                self.literal(byte);
+                Ok(())
+            }
+            Token::Match { length, distance } => self.copy(length, distance),
+        }
+    }
+
+    /// Tokens in the current block.
+    pub fn block_tokens(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Writes the current block as `block`, or as the cheapest type with
+    /// `None`, and returns the type written. Dynamic blocks become fixed
+    /// ones with the `tiny` feature. With `last`, the block ends the stream
+    /// and the output is padded to a whole byte.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a block with `last` has already been written.
+    pub fn emit(&mut self, block: Option<BlockType>, last: bool) -> BlockType {
+        assert!(!self.finished, "stream already ended with a last block");
+        let raw = &self.data[self.block_start..];
+        let written = match block {
+            Some(block) => self
+                .huffman
+                .encode_as(block, &self.tokens, raw, last, &mut self.writer),
+            None => {
+                self.huffman
+                    .encode(&self.tokens, raw, last, &mut self.writer)
+                    .0
+            }
+        };
+        self.tokens.clear();
+        let keep_from = self.data.len().saturating_sub(MAX_DISTANCE);
+        self.data.drain(..keep_from);
+        self.block_start = self.data.len();
+        if last {
+            self.writer.align();
+            self.finished = true;
+        }
+        written
+    }
+
+    /// The last block has been written.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Whole bytes of output waiting to be drained.
+    pub fn pending_len(&self) -> usize {
+        self.writer.pending_len()
+    }
+
+    /// Moves pending output into `out`, returning the byte count.
+    pub fn drain(&mut self, out: &mut [u8]) -> usize {
+        self.writer.drain(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::config::ContainerFormat;
+    use crate::decompressor::decompress_to_vec;
+
+    #[test]
+    fn every_block_type_decodes() {
+        let mut encoder = BlockEncoder::new();
+        let mut expected = Vec::new();
+        let kinds = [
+            Some(BlockType::Stored),
+            Some(BlockType::Fixed),
+            Some(BlockType::Dynamic),
+            None,
+        ];
+        for (i, kind) in kinds.into_iter().enumerate() {
+            let row: Vec<u8> = (0..40u8).map(|x| x.wrapping_mul(i as u8 + 3)).collect();
+            encoder.literals(&row);
+            expected.extend_from_slice(&row);
+            for _ in 0..5 {
+                encoder.copy(40, 40).unwrap();
+                let start = expected.len() - 40;
+                expected.extend_from_within(start..start + 40);
+            }
+            assert_eq!(encoder.block_tokens(), 45);
+            let written = encoder.emit(kind, i == kinds.len() - 1);
+            match kind {
+                Some(BlockType::Dynamic) if cfg!(feature = "tiny") => {
+                    assert_eq!(written, BlockType::Fixed)
+                }
+                Some(kind) => assert_eq!(written, kind),
+                None => assert_ne!(written, BlockType::Stored),
+            }
+        }
+        assert!(encoder.is_finished());
+        let mut out = vec![0; encoder.pending_len()];
+        encoder.drain(&mut out);
+        assert_eq!(
+            decompress_to_vec(&out, ContainerFormat::Raw).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn bad_matches_are_rejected() {
+        let mut encoder = BlockEncoder::new();
+        encoder.literals(b"abc");
+        assert_eq!(encoder.copy(3, 4), Err(TrickleError::InvalidData));
+        assert_eq!(encoder.copy(2, 1), Err(TrickleError::InvalidData));
+        assert_eq!(encoder.copy(259, 1), Err(TrickleError::InvalidData));
+        assert_eq!(encoder.copy(3, 0), Err(TrickleError::InvalidData));
+        assert_eq!(encoder.block_tokens(), 3);
+        assert_eq!(
+            encoder.push(Token::Match {
+                length: 10,
+                distance: 1
+            }),
+            Ok(())
+        );
+    }
+}
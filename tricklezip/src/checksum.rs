@@ -0,0 +1,346 @@
+//! Integrity checks over the uncompressed data.
+
+use crate::adler32;
+use crate::crc32::CrcBackend;
+
+/// A running checksum over the uncompressed data of a stream.
+///
+/// [`TrickleCompressor::with_checksum`] and
+/// [`TrickleDecompressor::with_checksum`] keep one up to date over a raw
+/// DEFLATE stream, for framings of your own. Besides user implementations,
+/// e.g. a CRC-16 matching an existing protocol, there are [`NoChecksum`],
+/// [`Adler32`], [`Xxh32`] and every [`CrcBackend`], such as
+/// [`SoftwareCrc`](crate::SoftwareCrc), for CRC-32.
+///
+/// [`TrickleCompressor::with_checksum`]: crate::TrickleCompressor::with_checksum
+/// [`TrickleDecompressor::with_checksum`]: crate::TrickleDecompressor::with_checksum
+pub trait Checksum {
+    /// Restarts the checksum for a new stream.
+    fn reset(&mut self);
+    /// Feeds `data` into the checksum.
+    fn update(&mut self, data: &[u8]);
+    /// Checksum of all data fed since the last reset.
+    fn value(&self) -> u32;
+}
+
+impl<C: CrcBackend> Checksum for C {
+    fn reset(&mut self) {
+        CrcBackend::reset(self);
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        CrcBackend::update(self, data);
+    }
+
+    fn value(&self) -> u32 {
+        CrcBackend::value(self)
+    }
+}
+
+/// No integrity check; the value is always 0.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoChecksum;
+
+impl Checksum for NoChecksum {
+    fn reset(&mut self) {}
+
+    fn update(&mut self, _data: &[u8]) {}
+
+    fn value(&self) -> u32 {
+        0
+    }
+}
+
+/// Adler-32, as in the zlib trailer: cheaper than CRC-32 in software, but
+/// weak on short inputs. Needs no table and no allocation, so it also
+/// suits checking firmware images as they are received.
+///
+/// ```
+/// use tricklezip::Adler32;
+///
+/// let mut adler = Adler32::new();
+/// for chunk in b"Wikipedia".chunks(4) {
+///     adler.update(chunk);
+/// }
+/// assert_eq!(adler.finalize(), 0x11E6_0398);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Adler32 {
+    adler: u32,
+}
+
+impl Adler32 {
+    pub const fn new() -> Self {
+        Self { adler: 1 }
+    }
+
+    /// Feeds `data` into the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        self.adler = adler32::update(self.adler, data);
+    }
+
+    /// Adler-32 of all data fed so far.
+    pub fn finalize(self) -> u32 {
+        self.adler
+    }
+
+    /// Appends the data hashed by `other`, `other_len` bytes long, as if it
+    /// had been fed to `self`.
+    pub fn combine(&mut self, other: &Self, other_len: u64) {
+        self.adler = adler32::combine(self.adler, other.adler, other_len);
+    }
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Checksum for Adler32 {
+    fn reset(&mut self) {
+        self.adler = 1;
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        Adler32::update(self, data);
+    }
+
+    fn value(&self) -> u32 {
+        self.adler
+    }
+}
+
+const PRIME1: u32 = 0x9E37_79B1;
+const PRIME2: u32 = 0x85EB_CA77;
+const PRIME3: u32 = 0xC2B2_AE3D;
+const PRIME4: u32 = 0x27D4_EB2F;
+const PRIME5: u32 = 0x1656_67B1;
+
+/// XXH32, with a seed: several times faster than CRC-32 on cores without
+/// CRC hardware, such as a Cortex-M0 or M4, as it works on whole words
+/// instead of table lookups per byte. Only detects accidental corruption,
+/// like the others.
+#[derive(Debug, Clone)]
+pub struct Xxh32 {
+    seed: u32,
+    lanes: [u32; 4],
+    total_len: u64,
+    /// Input not yet folded into `lanes`.
+    tail: [u8; 16],
+    tail_len: usize,
+}
+
+impl Xxh32 {
+    pub const fn new(seed: u32) -> Self {
+        Self {
+            seed,
+            lanes: Self::initial_lanes(seed),
+            total_len: 0,
+            tail: [0; 16],
+            tail_len: 0,
+        }
+    }
+
+    const fn initial_lanes(seed: u32) -> [u32; 4] {
+        [
+            seed.wrapping_add(PRIME1).wrapping_add(PRIME2),
+            seed.wrapping_add(PRIME2),
+            seed,
+            seed.wrapping_sub(PRIME1),
+        ]
+    }
+
+    fn stripe(&mut self, stripe: &[u8]) {
+        for (lane, word) in self.lanes.iter_mut().zip(stripe.chunks_exact(4)) {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            *lane = lane
+                .wrapping_add(word.wrapping_mul(PRIME2))
+                .rotate_left(13)
+                .wrapping_mul(PRIME1);
+        }
+    }
+}
+
+impl Default for Xxh32 {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl Checksum for Xxh32 {
+    fn reset(&mut self) {
+        *self = Self::new(self.seed);
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.tail_len > 0 {
+            let n = data.len().min(16 - self.tail_len);
+            self.tail[self.tail_len..self.tail_len + n].copy_from_slice(&data[..n]);
+            self.tail_len += n;
+            data = &data[n..];
+            if self.tail_len < 16 {
+                return;
+            }
+            let tail = self.tail;
+            self.stripe(&tail);
+            self.tail_len = 0;
+        }
+        let mut stripes = data.chunks_exact(16);
+        for stripe in &mut stripes {
+            self.stripe(stripe);
+        }
+        let rest = stripes.remainder();
+        self.tail[..rest.len()].copy_from_slice(rest);
+        self.tail_len = rest.len();
+    }
+
+    fn value(&self) -> u32 {
+        let [v1, v2, v3, v4] = self.lanes;
+        let mut h = if self.total_len >= 16 {
+            v1.rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18))
+        } else {
+            self.seed.wrapping_add(PRIME5)
+        };
+        h = h.wrapping_add(self.total_len as u32);
+        let mut words = self.tail[..self.tail_len].chunks_exact(4);
+        for word in &mut words {
+            let word = u32::from_le_bytes([word[0], word[1], word[2], word[3]]);
+            h = h
+                .wrapping_add(word.wrapping_mul(PRIME3))
+                .rotate_left(17)
+                .wrapping_mul(PRIME4);
+        }
+        for &byte in words.remainder() {
+            h = h
+                .wrapping_add((byte as u32).wrapping_mul(PRIME5))
+                .rotate_left(11)
+                .wrapping_mul(PRIME1);
+        }
+        h ^= h >> 15;
+        h = h.wrapping_mul(PRIME2);
+        h ^= h >> 13;
+        h = h.wrapping_mul(PRIME3);
+        h ^ (h >> 16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::compressor::{compress_to_vec, FlushMode, TrickleCompressor, TrickleStatus};
+    use crate::config::{CompressionConfig, ContainerFormat};
+    use crate::decompressor::TrickleDecompressor;
+    use crate::SoftwareCrc;
+
+    /// CRC-16/CCITT-FALSE, as a protocol of one's own might use.
+    struct Crc16(u16);
+
+    impl Checksum for Crc16 {
+        fn reset(&mut self) {
+            self.0 = 0xFFFF;
+        }
+
+        fn update(&mut self, data: &[u8]) {
+            for &b in data {
+                self.0 ^= (b as u16) << 8;
+                for _ in 0..8 {
+                    self.0 = if self.0 & 0x8000 != 0 {
+                        (self.0 << 1) ^ 0x1021
+                    } else {
+                        self.0 << 1
+                    };
+                }
+            }
+        }
+
+        fn value(&self) -> u32 {
+            self.0 as u32
+        }
+    }
+
+    fn round_trip<C: Checksum>(make: impl Fn() -> C, data: &[u8]) -> (u32, u32) {
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let mut compressor = TrickleCompressor::with_checksum(config.clone(), make());
+        let mut packed = [0u8; 512];
+        let r = compressor
+            .compress_trickle(data, &mut packed, FlushMode::Finish)
+            .unwrap();
+        assert_eq!(r.status, TrickleStatus::Finished);
+        let packed = &packed[..r.written];
+        assert_eq!(
+            packed,
+            compress_to_vec(data, &config.with_format(ContainerFormat::Raw))
+        );
+
+        let mut decompressor = TrickleDecompressor::with_checksum(make());
+        let mut out = [0u8; 512];
+        let r = decompressor.decompress_trickle(packed, &mut out).unwrap();
+        assert_eq!(&out[..r.written], data);
+        (
+            compressor.checksum().unwrap(),
+            decompressor.checksum().unwrap(),
+        )
+    }
+
+    #[test]
+    fn raw_streams_carry_any_checksum() {
+        let data = b"123456789";
+        assert_eq!(round_trip(|| Crc16(0), data), (0x29B1, 0x29B1));
+        assert_eq!(round_trip(Adler32::new, data), (0x091E_01DE, 0x091E_01DE));
+        assert_eq!(
+            round_trip(SoftwareCrc::new, data),
+            (0xCBF4_3926, 0xCBF4_3926)
+        );
+        assert_eq!(round_trip(|| NoChecksum, data), (0, 0));
+        assert_eq!(
+            round_trip(|| Xxh32::new(0), data),
+            (0x937B_AD67, 0x937B_AD67)
+        );
+        assert_eq!(
+            TrickleCompressor::new(CompressionConfig::default()).checksum(),
+            None
+        );
+        assert_eq!(
+            TrickleDecompressor::new(ContainerFormat::Raw).checksum(),
+            None
+        );
+    }
+
+    #[test]
+    fn xxh32_matches_reference_vectors() {
+        let stepped: Vec<u8> = (0..100u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut counting: Vec<u8> = (0..=255u8).cycle().take(1024).collect();
+        counting.extend_from_slice(b"tail");
+        let vectors: [(u32, &[u8], u32); 5] = [
+            (0, b"", 0x02CC_5D05),
+            (0, b"0123456789abcdef", 0xC2C4_5B69),
+            (0, b"Nobody inspects the spammish repetition", 0xE229_3B2F),
+            (0x9E37_79B1, &stepped, 0x83F8_B48B),
+            (1, &counting, 0x869B_E2BE),
+        ];
+        for (seed, data, expected) in vectors {
+            let mut whole = Xxh32::new(seed);
+            whole.update(data);
+            assert_eq!(whole.value(), expected, "{} bytes", data.len());
+            // Odd-sized pieces straddle every stripe boundary.
+            let mut chunked = Xxh32::new(seed);
+            let mut rest = data;
+            for size in [1, 3, 5, 7, 11, 13, 17].into_iter().cycle() {
+                if rest.is_empty() {
+                    break;
+                }
+                let (piece, tail) = rest.split_at(size.min(rest.len()));
+                chunked.update(piece);
+                rest = tail;
+            }
+            assert_eq!(chunked.value(), expected, "{} bytes in pieces", data.len());
+        }
+    }
+}
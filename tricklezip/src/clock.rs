@@ -0,0 +1,120 @@
+//! Time sources for deadline-bounded compression.
+
+use core::time::Duration;
+
+/// A monotonic clock, for
+/// [`TrickleCompressor::compress_timed_with`](crate::TrickleCompressor::compress_timed_with).
+///
+/// On a microcontroller this is typically SysTick, a hardware timer or an
+/// RTC; [`StdClock`] wraps `std::time::Instant`. The tick counter may
+/// wrap: differences are taken with wrapping arithmetic.
+///
+/// ```
+/// use core::sync::atomic::{AtomicU64, Ordering};
+/// use tricklezip::TimeSource;
+///
+/// /// Milliseconds counted by the SysTick interrupt.
+/// static UPTIME_MS: AtomicU64 = AtomicU64::new(0);
+///
+/// struct SysTick;
+///
+/// impl TimeSource for SysTick {
+///     fn now_ticks(&self) -> u64 {
+///         UPTIME_MS.load(Ordering::Relaxed)
+///     }
+///
+///     fn ticks_per_ms(&self) -> u64 {
+///         1
+///     }
+/// }
+/// ```
+pub trait TimeSource {
+    /// Current tick count.
+    fn now_ticks(&self) -> u64;
+    /// Ticks per millisecond; at least 1.
+    fn ticks_per_ms(&self) -> u64;
+
+    /// Ticks spanning `duration`, rounded down, but at least 1 for a
+    /// nonzero duration.
+    fn ticks_in(&self, duration: Duration) -> u64 {
+        let ticks = duration.as_micros() * self.ticks_per_ms().max(1) as u128 / 1000;
+        let ticks = u64::try_from(ticks).unwrap_or(u64::MAX);
+        if ticks == 0 && !duration.is_zero() {
+            1
+        } else {
+            ticks
+        }
+    }
+}
+
+impl<T: TimeSource + ?Sized> TimeSource for &T {
+    fn now_ticks(&self) -> u64 {
+        (**self).now_ticks()
+    }
+
+    fn ticks_per_ms(&self) -> u64 {
+        (**self).ticks_per_ms()
+    }
+}
+
+/// [`TimeSource`] counting microseconds since its creation.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct StdClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl StdClock {
+    pub fn new() -> Self {
+        Self {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl TimeSource for StdClock {
+    fn now_ticks(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn ticks_per_ms(&self) -> u64 {
+        1000
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed(u64);
+
+    impl TimeSource for Fixed {
+        fn now_ticks(&self) -> u64 {
+            0
+        }
+
+        fn ticks_per_ms(&self) -> u64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn durations_convert_to_ticks() {
+        let rtc = Fixed(32);
+        assert_eq!(rtc.ticks_in(Duration::from_millis(10)), 320);
+        assert_eq!(rtc.ticks_in(Duration::from_micros(500)), 16);
+        // Shorter than a tick, but not nothing.
+        assert_eq!(rtc.ticks_in(Duration::from_micros(10)), 1);
+        assert_eq!(rtc.ticks_in(Duration::ZERO), 0);
+        assert_eq!(Fixed(1).ticks_in(Duration::MAX), u64::MAX);
+    }
+}
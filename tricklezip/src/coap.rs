@@ -0,0 +1,232 @@
+//! Compressed output cut into CoAP block-wise transfer blocks (RFC 7959).
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::compressor::{TrickleCompressor, TrickleStatus};
+use crate::config::CompressionConfig;
+use crate::error::TrickleError;
+
+/// One block of a block-wise transfer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoapBlock<'a> {
+    /// Block number, counting from 0.
+    pub num: u32,
+    /// The M flag: further blocks follow.
+    pub more: bool,
+    /// Size exponent; blocks are `16 << szx` bytes.
+    pub szx: u8,
+    /// Exactly `16 << szx` bytes, except in the last block.
+    pub data: &'a [u8],
+}
+
+impl CoapBlock<'_> {
+    /// Value of the Block1/Block2 option describing this block.
+    pub fn option_value(&self) -> u32 {
+        self.num << 4 | (self.more as u32) << 3 | self.szx as u32
+    }
+}
+
+/// Compresses a stream and hands out its output in fixed-size CoAP blocks,
+/// each with the block number and "more" flag a block-wise transfer state
+/// machine needs.
+///
+/// A block is only released once at least one byte of the next block
+/// exists, so the M flag is always right, including for a stream that ends
+/// exactly on a block boundary.
+///
+/// ```
+/// use tricklezip::{CoapBlockwise, CompressionConfig};
+///
+/// let reading = b"temp=21.5;hum=40;temp=21.6;hum=41;".repeat(40);
+/// let mut blockwise = CoapBlockwise::new(CompressionConfig::telemetry(), 64);
+/// let mut consumed = 0;
+/// loop {
+///     let (n, block) = blockwise.poll(&reading[consumed..], true).unwrap();
+///     consumed += n;
+///     if let Some(block) = block {
+///         // Send block.data with Block2 = block.option_value().
+///         if !block.more {
+///             break;
+///         }
+///     }
+/// }
+/// ```
+pub struct CoapBlockwise {
+    compressor: TrickleCompressor,
+    /// One block plus the first byte of the next.
+    buf: Vec<u8>,
+    filled: usize,
+    szx: u8,
+    num: u32,
+    /// The last poll released a full block; its extra byte is still at the
+    /// end of `buf`.
+    released_full: bool,
+    finished: bool,
+}
+
+impl CoapBlockwise {
+    /// Cuts the output of a compressor for `config` into blocks of
+    /// `block_size` bytes.
+    ///
+    /// # Panics
+    ///
+    /// If `block_size` is not a power of two from 16 to 1024.
+    pub fn new(config: CompressionConfig, block_size: usize) -> Self {
+        assert!(
+            block_size.is_power_of_two() && (16..=1024).contains(&block_size),
+            "CoAP block size must be a power of two from 16 to 1024"
+        );
+        Self {
+            compressor: TrickleCompressor::new(config),
+            buf: vec![0; block_size + 1],
+            filled: 0,
+            szx: (block_size.trailing_zeros() - 4) as u8,
+            num: 0,
+            released_full: false,
+            finished: false,
+        }
+    }
+
+    pub fn block_size(&self) -> usize {
+        16 << self.szx
+    }
+
+    /// Counters of the underlying compressor.
+    pub fn compressor(&self) -> &TrickleCompressor {
+        &self.compressor
+    }
+
+    /// Runs one `compress_trickle` step over `input` and returns the bytes
+    /// consumed, plus a block if one is complete. With `finish`, the final
+    /// block (`more == false`) is returned once `input` has been consumed
+    /// and the stream is complete.
+    pub fn poll(
+        &mut self,
+        input: &[u8],
+        finish: bool,
+    ) -> Result<(usize, Option<CoapBlock<'_>>), TrickleError> {
+        if self.finished {
+            return Err(TrickleError::StreamFinished);
+        }
+        let size = self.block_size();
+        if self.released_full {
+            self.buf[0] = self.buf[size];
+            self.filled = 1;
+            self.released_full = false;
+        }
+        let mut consumed = 0;
+        let mut stream_done = self.compressor.is_finished();
+        if !stream_done {
+            let r = self.compressor.compress_trickle(
+                input,
+                &mut self.buf[self.filled..],
+                finish.into(),
+            )?;
+            consumed = r.consumed;
+            self.filled += r.written;
+            stream_done = r.status == TrickleStatus::Finished;
+        }
+        let block = if self.filled > size {
+            self.released_full = true;
+            Some((size, true))
+        } else if stream_done {
+            self.finished = true;
+            Some((self.filled, false))
+        } else {
+            None
+        };
+        Ok((
+            consumed,
+            block.map(|(len, more)| {
+                self.num += 1;
+                CoapBlock {
+                    num: self.num - 1,
+                    more,
+                    szx: self.szx,
+                    data: &self.buf[..len],
+                }
+            }),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+
+    #[test]
+    fn blocks_cover_the_stream_exactly() {
+        let data = b"GET /sensors/7 rssi=-70 snr=7.25\n".repeat(120);
+        let config = CompressionConfig::default();
+        let expected = compress_to_vec(&data, &config);
+        for block_size in [16, 64, 1024] {
+            let mut blockwise = CoapBlockwise::new(config.clone(), block_size);
+            let mut out = Vec::new();
+            let mut consumed = 0;
+            let mut next_num = 0;
+            loop {
+                let end = (consumed + 50).min(data.len());
+                let (n, block) = blockwise
+                    .poll(&data[consumed..end], end == data.len())
+                    .unwrap();
+                consumed += n;
+                let Some(block) = block else {
+                    continue;
+                };
+                assert_eq!(block.num, next_num);
+                assert_eq!(16 << block.szx, block_size);
+                assert_eq!(
+                    block.option_value() & 0xF,
+                    block.szx as u32 | (block.more as u32) << 3
+                );
+                next_num += 1;
+                out.extend_from_slice(block.data);
+                if !block.more {
+                    assert!(!block.data.is_empty() && block.data.len() <= block_size);
+                    break;
+                }
+                assert_eq!(block.data.len(), block_size);
+            }
+            assert_eq!(out, expected);
+            assert_eq!(blockwise.poll(&[], true), Err(TrickleError::StreamFinished));
+        }
+    }
+
+    #[test]
+    fn stream_ending_on_a_block_boundary() {
+        let data = b"0123456789";
+        let config = CompressionConfig::default();
+        let expected = compress_to_vec(data, &config);
+        // Pick a block size dividing the output, padding the input until
+        // one does.
+        let mut input = data.to_vec();
+        let mut packed = expected;
+        while packed.len() != 16 {
+            input.push(b'x' + (input.len() % 3) as u8);
+            packed = compress_to_vec(&input, &config);
+            assert!(packed.len() <= 16, "no input with a 16 byte stream");
+        }
+        let mut blockwise = CoapBlockwise::new(config, 16);
+        let mut blocks = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let (n, block) = blockwise.poll(&input[consumed..], true).unwrap();
+            consumed += n;
+            if let Some(block) = block {
+                blocks.push((block.num, block.more, block.data.to_vec()));
+                if !block.more {
+                    break;
+                }
+            }
+        }
+        assert_eq!(blocks, [(0, false, packed)]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn rejects_odd_block_sizes() {
+        CoapBlockwise::new(CompressionConfig::default(), 100);
+    }
+}
@@ -0,0 +1,4 @@
+//! Drop-in replacements for the APIs of other compression crates, so
+//! existing call sites can switch to TrickleZip by changing an import.
+
+pub mod flate2;
@@ -0,0 +1,370 @@
+//! The low-level streaming API of `flate2` (and `miniz_oxide` through it):
+//! [`Compress`] and [`Decompress`] with flush modes and [`Status`] results.
+//!
+//! ```
+//! use tricklezip::compat::flate2::{Compress, Compression, FlushCompress, Status};
+//!
+//! let mut compress = Compress::new(Compression::default(), true);
+//! let mut out = Vec::with_capacity(256);
+//! let status = compress
+//!     .compress_vec(b"temp=21.5 temp=21.5", &mut out, FlushCompress::Finish)
+//!     .unwrap();
+//! assert_eq!(status, Status::StreamEnd);
+//! assert_eq!(compress.total_out(), out.len() as u64);
+//! ```
+//!
+//! Unlike `flate2`, every call still does a bounded amount of work per
+//! internal step, but keeps stepping until the input is used up or the
+//! output is full, so the observable behaviour matches.
+//!
+//! With `std`, the [`read`] and [`write`](mod@write) modules add gzip `Read` and
+//! `Write` adapters.
+
+use alloc::vec::Vec;
+
+#[cfg(feature = "std")]
+pub mod read;
+#[cfg(feature = "std")]
+pub mod write;
+
+use crate::compressor::{FlushMode, TrickleCompressor, TrickleResult, TrickleStatus};
+use crate::config::{CompressionConfig, CompressionLevel, ContainerFormat};
+use crate::decompressor::TrickleDecompressor;
+use crate::error::TrickleError;
+
+/// Error type of [`Compress::compress`].
+pub type CompressError = TrickleError;
+
+/// Error type of [`Decompress::decompress`].
+pub type DecompressError = TrickleError;
+
+/// Compression level from 0 to 9, as in `flate2::Compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Compression(u32);
+
+impl Compression {
+    /// Levels above 9 are clamped when the compressor is created.
+    pub const fn new(level: u32) -> Self {
+        Self(level)
+    }
+
+    pub const fn none() -> Self {
+        Self(0)
+    }
+
+    pub const fn fast() -> Self {
+        Self(1)
+    }
+
+    pub const fn best() -> Self {
+        Self(9)
+    }
+
+    pub fn level(&self) -> u32 {
+        self.0
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self(6)
+    }
+}
+
+impl From<Compression> for CompressionLevel {
+    fn from(level: Compression) -> Self {
+        CompressionLevel::new(level.0.min(9) as u8)
+    }
+}
+
+/// Outcome of a successful call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Progress was made; call again.
+    Ok,
+    /// No progress was possible: the input is empty without a finishing
+    /// flush, or the output buffer has no room.
+    BufError,
+    /// The stream has ended and all of its output has been written.
+    StreamEnd,
+}
+
+/// Flush modes of [`Compress::compress`]. `Partial` flushes like `Sync`;
+/// see [`FlushMode`] for the others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushCompress {
+    None,
+    Sync,
+    Partial,
+    Full,
+    Finish,
+}
+
+/// Flush modes of [`Decompress::decompress`]. The decompressor always
+/// produces as much output as it can, so they all behave the same.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlushDecompress {
+    None,
+    Sync,
+    Finish,
+}
+
+/// Raw DEFLATE or zlib compressor with `flate2::Compress` semantics.
+pub struct Compress {
+    inner: TrickleCompressor,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl Compress {
+    /// Creates a compressor emitting a zlib stream if `zlib_header` is set,
+    /// raw DEFLATE otherwise.
+    pub fn new(level: Compression, zlib_header: bool) -> Self {
+        let config = CompressionConfig::new(level.into()).with_format(zlib_format(zlib_header));
+        Self {
+            inner: TrickleCompressor::new(config),
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Starts a new stream with the same settings.
+    pub fn reset(&mut self) {
+        self.inner.reset();
+        self.total_in = 0;
+        self.total_out = 0;
+    }
+
+    /// Compresses as much of `input` into `output` as possible. With
+    /// [`FlushCompress::Finish`], returns [`Status::StreamEnd`] once the
+    /// stream is complete; keep calling with more output space until then.
+    pub fn compress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushCompress,
+    ) -> Result<Status, CompressError> {
+        if self.inner.is_finished() {
+            return Ok(Status::StreamEnd);
+        }
+        let flush = match flush {
+            FlushCompress::None => FlushMode::None,
+            FlushCompress::Sync | FlushCompress::Partial => FlushMode::Sync,
+            FlushCompress::Full => FlushMode::Full,
+            FlushCompress::Finish => FlushMode::Finish,
+        };
+        let (mut consumed, mut written) = (0, 0);
+        let status = loop {
+            let TrickleResult {
+                consumed: c,
+                written: w,
+                status,
+            } = self
+                .inner
+                .compress_trickle(&input[consumed..], &mut output[written..], flush)?;
+            consumed += c;
+            written += w;
+            if status != TrickleStatus::InProgress {
+                break status;
+            }
+        };
+        self.total_in += consumed as u64;
+        self.total_out += written as u64;
+        Ok(match status {
+            TrickleStatus::Finished => Status::StreamEnd,
+            _ if consumed == 0 && written == 0 => Status::BufError,
+            _ => Status::Ok,
+        })
+    }
+
+    /// Like [`compress`](Self::compress), writing into the spare capacity
+    /// of `output` without reallocating it.
+    pub fn compress_vec(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        flush: FlushCompress,
+    ) -> Result<Status, CompressError> {
+        let (len, before) = (output.len(), self.total_out);
+        output.resize(output.capacity(), 0);
+        let result = self.compress(input, &mut output[len..], flush);
+        output.truncate(len + (self.total_out - before) as usize);
+        result
+    }
+}
+
+/// Raw DEFLATE or zlib decompressor with `flate2::Decompress` semantics.
+pub struct Decompress {
+    inner: TrickleDecompressor,
+    total_in: u64,
+    total_out: u64,
+}
+
+impl Decompress {
+    /// Creates a decompressor expecting a zlib stream if `zlib_header` is
+    /// set, raw DEFLATE otherwise.
+    pub fn new(zlib_header: bool) -> Self {
+        Self {
+            inner: TrickleDecompressor::new(zlib_format(zlib_header)),
+            total_in: 0,
+            total_out: 0,
+        }
+    }
+
+    pub fn total_in(&self) -> u64 {
+        self.total_in
+    }
+
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// Starts a new stream, possibly switching between zlib and raw.
+    pub fn reset(&mut self, zlib_header: bool) {
+        let format = zlib_format(zlib_header);
+        if self.inner.format() == format {
+            self.inner.reset();
+        } else {
+            self.inner = TrickleDecompressor::new(format);
+        }
+        self.total_in = 0;
+        self.total_out = 0;
+    }
+
+    /// Decompresses as much of `input` into `output` as possible,
+    /// returning [`Status::StreamEnd`] once the end of the stream, trailer
+    /// included, has been reached.
+    pub fn decompress(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        _flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        if self.inner.is_finished() {
+            return Ok(Status::StreamEnd);
+        }
+        let r = self.inner.decompress_trickle(input, output)?;
+        self.total_in += r.consumed as u64;
+        self.total_out += r.written as u64;
+        Ok(match r.status {
+            TrickleStatus::Finished => Status::StreamEnd,
+            _ if r.consumed == 0 && r.written == 0 => Status::BufError,
+            _ => Status::Ok,
+        })
+    }
+
+    /// Like [`decompress`](Self::decompress), writing into the spare
+    /// capacity of `output` without reallocating it.
+    pub fn decompress_vec(
+        &mut self,
+        input: &[u8],
+        output: &mut Vec<u8>,
+        flush: FlushDecompress,
+    ) -> Result<Status, DecompressError> {
+        let (len, before) = (output.len(), self.total_out);
+        output.resize(output.capacity(), 0);
+        let result = self.decompress(input, &mut output[len..], flush);
+        output.truncate(len + (self.total_out - before) as usize);
+        result
+    }
+}
+
+fn zlib_format(zlib_header: bool) -> ContainerFormat {
+    if zlib_header {
+        ContainerFormat::Zlib
+    } else {
+        ContainerFormat::Raw
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+
+    fn sample() -> Vec<u8> {
+        b"GET /api/v1/sensors?id=17 HTTP/1.1\r\nHost: gw.local\r\n\r\n".repeat(300)
+    }
+
+    #[test]
+    fn small_buffers_round_trip() {
+        let data = sample();
+        for zlib in [false, true] {
+            let mut compress = Compress::new(Compression::best(), zlib);
+            let mut packed = Vec::new();
+            let mut buf = [0u8; 61];
+            loop {
+                let before = compress.total_in() as usize;
+                let end = (before + 100).min(data.len());
+                let flush = if end == data.len() {
+                    FlushCompress::Finish
+                } else {
+                    FlushCompress::None
+                };
+                let out_before = compress.total_out();
+                let status = compress
+                    .compress(&data[before..end], &mut buf, flush)
+                    .unwrap();
+                packed.extend_from_slice(&buf[..(compress.total_out() - out_before) as usize]);
+                if status == Status::StreamEnd {
+                    break;
+                }
+            }
+            assert_eq!(compress.total_in(), data.len() as u64);
+            assert_eq!(compress.total_out(), packed.len() as u64);
+            let config =
+                CompressionConfig::new(CompressionLevel::BEST).with_format(zlib_format(zlib));
+            assert_eq!(packed, compress_to_vec(&data, &config));
+
+            let mut decompress = Decompress::new(zlib);
+            let mut unpacked = Vec::with_capacity(97);
+            loop {
+                let status = decompress
+                    .decompress_vec(
+                        &packed[decompress.total_in() as usize..],
+                        &mut unpacked,
+                        FlushDecompress::None,
+                    )
+                    .unwrap();
+                if status == Status::StreamEnd {
+                    break;
+                }
+                unpacked.reserve(97);
+            }
+            assert_eq!(unpacked, data);
+            assert_eq!(decompress.total_out(), data.len() as u64);
+        }
+    }
+
+    #[test]
+    fn no_room_is_a_buf_error() {
+        let mut compress = Compress::new(Compression::fast(), true);
+        assert_eq!(
+            compress.compress(b"abc", &mut [], FlushCompress::Finish),
+            Ok(Status::BufError)
+        );
+        let mut out = Vec::with_capacity(64);
+        assert_eq!(
+            compress.compress_vec(b"abc", &mut out, FlushCompress::Finish),
+            Ok(Status::StreamEnd)
+        );
+        let mut decompress = Decompress::new(true);
+        assert_eq!(
+            decompress.decompress(&out, &mut [], FlushDecompress::Finish),
+            Ok(Status::Ok)
+        );
+        decompress.reset(false);
+        assert_eq!(
+            decompress.decompress(&[], &mut [0; 8], FlushDecompress::Finish),
+            Ok(Status::BufError)
+        );
+    }
+}
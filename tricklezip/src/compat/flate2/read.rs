@@ -0,0 +1,91 @@
+//! Decompressing readers, as in `flate2::read`.
+
+use std::io::{self, Read};
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::compressor::TrickleStatus;
+use crate::config::ContainerFormat;
+use crate::decompressor::TrickleDecompressor;
+
+/// Size of the buffer for compressed data read from `R`.
+const BUF_SIZE: usize = 8 * 1024;
+
+/// Reads a single gzip member from `R` and yields its decompressed bytes.
+///
+/// Bytes following the member may already have been read from `R` into the
+/// decoder's buffer.
+///
+/// ```
+/// use std::io::Read;
+/// use tricklezip::compat::flate2::read::GzDecoder;
+/// use tricklezip::{compress_to_vec, CompressionConfig, ContainerFormat};
+///
+/// let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+/// let gz = compress_to_vec(b"uptime=3600 heap=18432\n", &config);
+/// let mut text = String::new();
+/// GzDecoder::new(&gz[..]).read_to_string(&mut text).unwrap();
+/// assert_eq!(text, "uptime=3600 heap=18432\n");
+/// ```
+pub struct GzDecoder<R: Read> {
+    inner: R,
+    decompressor: TrickleDecompressor,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+}
+
+impl<R: Read> GzDecoder<R> {
+    pub fn new(r: R) -> Self {
+        Self {
+            inner: r,
+            decompressor: TrickleDecompressor::new(ContainerFormat::Gzip),
+            buf: vec![0; BUF_SIZE],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reading from the inner reader directly corrupts the gzip stream.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.decompressor.is_finished() {
+                return Ok(0);
+            }
+            let mut eof = false;
+            if self.pos == self.len {
+                self.len = self.inner.read(&mut self.buf)?;
+                self.pos = 0;
+                eof = self.len == 0;
+            }
+            let r = self
+                .decompressor
+                .decompress_trickle(&self.buf[self.pos..self.len], out)?;
+            self.pos += r.consumed;
+            if r.written > 0 || r.status == TrickleStatus::Finished {
+                return Ok(r.written);
+            }
+            if eof {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Compressing writers, as in `flate2::write`.
+
+use std::io::{self, Write};
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use super::Compression;
+use crate::compressor::{FlushMode, TrickleCompressor, TrickleStatus};
+use crate::config::{CompressionConfig, ContainerFormat};
+
+/// Size of the staging buffer between the compressor and the writer.
+const BUF_SIZE: usize = 8 * 1024;
+
+/// Gzip-compresses everything written to it into `W`.
+///
+/// Call [`finish`](Self::finish) to write the trailer and get the writer
+/// back; dropping the encoder finishes the stream too, but swallows errors.
+///
+/// ```
+/// use std::io::Write;
+/// use tricklezip::compat::flate2::write::GzEncoder;
+/// use tricklezip::compat::flate2::Compression;
+///
+/// let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+/// encoder.write_all(b"uptime=3600 heap=18432\n").unwrap();
+/// let gz = encoder.finish().unwrap();
+/// assert_eq!(&gz[..2], &[0x1F, 0x8B]);
+/// ```
+pub struct GzEncoder<W: Write> {
+    inner: Option<W>,
+    compressor: TrickleCompressor,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> GzEncoder<W> {
+    pub fn new(w: W, level: Compression) -> Self {
+        Self::with_config(w, CompressionConfig::new(level.into()))
+    }
+
+    /// Compresses with a full configuration instead of a level; the
+    /// format is forced to gzip.
+    pub(crate) fn with_config(w: W, config: CompressionConfig) -> Self {
+        Self {
+            inner: Some(w),
+            compressor: TrickleCompressor::new(config.with_format(ContainerFormat::Gzip)),
+            buf: vec![0; BUF_SIZE],
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().expect("present until finished")
+    }
+
+    /// Writing to the inner writer directly corrupts the gzip stream.
+    pub fn get_mut(&mut self) -> &mut W {
+        self.inner.as_mut().expect("present until finished")
+    }
+
+    /// Writes the rest of the stream, trailer included, without consuming
+    /// the encoder. Further writes fail.
+    pub fn try_finish(&mut self) -> io::Result<()> {
+        while !self.compressor.is_finished() {
+            self.step(&[], FlushMode::Finish)?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the stream and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().expect("present until finished"))
+    }
+
+    /// Runs one compressor call and passes its output on.
+    fn step(&mut self, input: &[u8], flush: FlushMode) -> io::Result<(usize, TrickleStatus)> {
+        let r = self
+            .compressor
+            .compress_trickle(input, &mut self.buf, flush)?;
+        let inner = self.inner.as_mut().expect("present until finished");
+        inner.write_all(&self.buf[..r.written])?;
+        Ok((r.consumed, r.status))
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut consumed = 0;
+        while consumed < data.len() {
+            let (n, status) = self.step(&data[consumed..], FlushMode::None)?;
+            consumed += n;
+            if status == TrickleStatus::NeedInput {
+                break;
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Sync-flushes the compressor, so that everything written so far can
+    /// be decoded from the inner writer, then flushes that.
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.compressor.is_finished() {
+            while self.step(&[], FlushMode::Sync)?.1 != TrickleStatus::NeedInput {}
+        }
+        self.get_mut().flush()
+    }
+}
+
+impl<W: Write> Drop for GzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compat::flate2::read::GzDecoder;
+    use crate::decompressor::decompress_to_vec;
+    use std::io::Read;
+
+    #[test]
+    fn encoder_output_reads_back() {
+        let data = b"ts=1700000000 level=warn msg=\"retrying uplink\"\n".repeat(500);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        for chunk in data.chunks(333) {
+            encoder.write_all(chunk).unwrap();
+        }
+        let gz = encoder.finish().unwrap();
+        assert_eq!(decompress_to_vec(&gz, ContainerFormat::Gzip).unwrap(), data);
+
+        let mut decoder = GzDecoder::new(&gz[..]);
+        let mut out = Vec::new();
+        let mut buf = [0u8; 100];
+        loop {
+            let n = decoder.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, data);
+
+        let mut truncated = GzDecoder::new(&gz[..gz.len() - 3]);
+        let err = truncated.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn dropping_the_encoder_finishes_the_stream() {
+        let mut gz = Vec::new();
+        {
+            let mut encoder = GzEncoder::new(&mut gz, Compression::fast());
+            encoder.write_all(b"short").unwrap();
+        }
+        assert_eq!(
+            decompress_to_vec(&gz, ContainerFormat::Gzip).unwrap(),
+            b"short"
+        );
+    }
+}
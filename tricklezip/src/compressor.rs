@@ -0,0 +1,2171 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use crate::adler32;
+use crate::allocator::{Allocator, Global};
+use crate::checksum::Checksum;
+#[cfg(feature = "std")]
+use crate::clock::StdClock;
+use crate::clock::TimeSource;
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::crc32::{CrcBackend, SoftwareCrc};
+use crate::deflate::{DeflateState, Observers};
+use crate::error::TrickleError;
+use crate::huffman;
+use crate::logging::debug;
+use crate::lz77::MAX_MATCH;
+use crate::spsc::QueueConsumer;
+use crate::stats::{BlockInfo, CompressionStats, StatsSink};
+use crate::transform::BlockTransform;
+
+/// Where a trickle call stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrickleStatus {
+    /// The work budget for this call ran out; call again with the remaining input.
+    InProgress,
+    /// All input was consumed; supply more (or finish the stream).
+    NeedInput,
+    /// The output buffer is full; call again with more space.
+    NeedOutput,
+    /// The stream is complete and all output has been delivered.
+    Finished,
+}
+
+/// How far a compression call pushes out the data it was given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum FlushMode {
+    /// Encode input as it suits the compressor: output lags behind by up
+    /// to a block.
+    #[default]
+    None,
+    /// Once all input is consumed, close the current block and write an
+    /// empty stored block, which byte-aligns the output, so the receiver
+    /// can decode everything sent so far. Costs about 5 bytes, plus the
+    /// cost of starting a new block.
+    Sync,
+    /// A `Sync` flush that also drops the history, so decoding can start
+    /// right after it, as at a restart point; see
+    /// [`find_restart_point`](crate::find_restart_point).
+    Full,
+    /// End the stream once all input is consumed.
+    Finish,
+}
+
+impl From<bool> for FlushMode {
+    /// `Finish` for `true` and `None` for `false`, for code still thinking
+    /// in terms of a `finish` flag.
+    fn from(finish: bool) -> Self {
+        if finish {
+            Self::Finish
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Work allowed for one [`compress_step`](TrickleCompressor::compress_step),
+/// in input positions run through the match finder, the unit of
+/// [`CompressionStats::work_units`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WorkBudget(usize);
+
+impl WorkBudget {
+    /// Room for the longest match, so every step can make progress.
+    pub const MIN: Self = Self(MAX_MATCH);
+
+    /// A budget of `positions`, raised to [`MIN`](Self::MIN).
+    pub const fn positions(positions: usize) -> Self {
+        if positions < MAX_MATCH {
+            Self::MIN
+        } else {
+            Self(positions)
+        }
+    }
+
+    pub const fn get(self) -> usize {
+        self.0
+    }
+}
+
+/// Progress made by a single call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrickleResult {
+    /// Input bytes consumed.
+    pub consumed: usize,
+    /// Output bytes written.
+    pub written: usize,
+    pub status: TrickleStatus,
+}
+
+/// Incremental compressor that does a bounded amount of work per call.
+///
+/// ```
+/// use tricklezip::{CompressionConfig, FlushMode, TrickleCompressor, TrickleStatus};
+///
+/// let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+/// let input = b"hello hello hello hello";
+/// let mut out = [0u8; 64];
+/// let (mut consumed, mut written) = (0, 0);
+/// loop {
+///     let r = compressor
+///         .compress_trickle(&input[consumed..], &mut out[written..], FlushMode::Finish)
+///         .unwrap();
+///     consumed += r.consumed;
+///     written += r.written;
+///     if r.status == TrickleStatus::Finished {
+///         break;
+///     }
+/// }
+/// assert!(written < input.len());
+/// ```
+///
+/// The gzip CRC-32 is computed by `C`, which defaults to the software
+/// implementation; see [`CrcBackend`] for plugging in a hardware unit. Raw
+/// streams created with [`with_checksum`](TrickleCompressor::with_checksum)
+/// run any [`Checksum`] instead. With the `allocator_api` feature, `A`
+/// chooses where the buffers are allocated.
+pub struct TrickleCompressor<C: Checksum = SoftwareCrc, A: Allocator = Global> {
+    config: CompressionConfig,
+    state: DeflateState<A>,
+    adler: u32,
+    crc: C,
+    header_written: bool,
+    finished: bool,
+    /// The flush under way, or the last one if no input came since, so
+    /// repeating it is a no-op. Restart points count as `Full`.
+    flushed: FlushMode,
+    /// A preset dictionary was set. Until the header announcing it is
+    /// written, `adler` holds its DICTID, as zlib keeps it in `strm->adler`.
+    preset_dict: bool,
+    /// `crc` runs over a raw stream, see `with_checksum`.
+    raw_checksum: bool,
+    /// Input size announced with `set_expected_input_size`, or 0.
+    expected_input: u64,
+    /// The last `poll_compress` step used up its budget, so the next poll
+    /// yields to the executor first.
+    yield_next: bool,
+}
+
+impl TrickleCompressor {
+    /// Creates a compressor for `config`.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, if `config` fails
+    /// [`validate`](CompressionConfig::validate). Release builds clamp such a
+    /// `window_size` to the range the format allows and round it up to a
+    /// power of two; [`try_new`](Self::try_new) reports it instead.
+    pub fn new(config: CompressionConfig) -> Self {
+        Self::with_crc_backend(config, SoftwareCrc::new())
+    }
+
+    /// What [`memory_usage`](Self::memory_usage) returns right after
+    /// `new(config.clone())`, computed without allocating.
+    pub(crate) fn memory_usage_for(config: &CompressionConfig) -> usize {
+        core::mem::size_of::<Self>() + DeflateState::heap_usage_for(config)
+    }
+
+    /// Like [`new`](Self::new), but rejects a `config` that
+    /// [`validate`](CompressionConfig::validate) does not accept instead of
+    /// adjusting it.
+    ///
+    /// ```
+    /// use tricklezip::{CompressionConfig, TrickleCompressor, TrickleError};
+    ///
+    /// let config = CompressionConfig::default().with_window_size(3000);
+    /// assert_eq!(
+    ///     TrickleCompressor::try_new(config).err(),
+    ///     Some(TrickleError::InvalidConfig { field: "window_size" })
+    /// );
+    /// ```
+    pub fn try_new(config: CompressionConfig) -> Result<Self, TrickleError> {
+        config.validate()?;
+        Ok(Self::new(config))
+    }
+}
+
+impl<C: CrcBackend> TrickleCompressor<C> {
+    /// Creates a compressor that computes the gzip CRC-32 with `crc`.
+    /// Panics like [`new`](TrickleCompressor::new).
+    pub fn with_crc_backend(config: CompressionConfig, crc: C) -> Self {
+        Self::with_crc_backend_in(config, crc, Global)
+    }
+}
+
+impl<C: Checksum> TrickleCompressor<C> {
+    /// Creates a raw DEFLATE compressor, whatever the format in `config`,
+    /// that keeps `checksum` up to date over the input, for framings of
+    /// your own. Read it with [`checksum`](Self::checksum). Panics like
+    /// [`new`](TrickleCompressor::new).
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, Adler32, Checksum, CompressionConfig};
+    /// use tricklezip::{FlushMode, TrickleCompressor, TrickleStatus};
+    ///
+    /// let mut compressor = TrickleCompressor::with_checksum(CompressionConfig::default(), Adler32::new());
+    /// let mut out = [0u8; 64];
+    /// while compressor.compress_trickle(b"reading 42", &mut out, FlushMode::Finish).unwrap().status
+    ///     != TrickleStatus::Finished
+    /// {}
+    /// let mut expected = Adler32::new();
+    /// expected.update(b"reading 42");
+    /// assert_eq!(compressor.checksum(), Some(expected.value()));
+    /// ```
+    pub fn with_checksum(config: CompressionConfig, checksum: C) -> Self {
+        let config = config.with_format(ContainerFormat::Raw);
+        Self {
+            raw_checksum: true,
+            ..Self::with_crc_backend_in(config, checksum, Global)
+        }
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<A: Allocator + Clone> TrickleCompressor<SoftwareCrc, A> {
+    /// Creates a compressor whose window, hash chains, token buffer and
+    /// pending output are all allocated in `alloc`. Panics like
+    /// [`new`](TrickleCompressor::new).
+    pub fn new_in(config: CompressionConfig, alloc: A) -> Self {
+        Self::with_crc_backend_in(config, SoftwareCrc::new(), alloc)
+    }
+}
+
+impl<C: Checksum, A: Allocator + Clone> TrickleCompressor<C, A> {
+    /// Combines [`with_crc_backend`](TrickleCompressor::with_crc_backend)
+    /// and [`new_in`](TrickleCompressor::new_in).
+    pub(crate) fn with_crc_backend_in(config: CompressionConfig, mut crc: C, alloc: A) -> Self {
+        debug_assert!(
+            config.validate().is_ok(),
+            "invalid window_size {}",
+            config.window_size
+        );
+        crc.reset();
+        Self {
+            state: DeflateState::new_in(&config, alloc),
+            config,
+            adler: 1,
+            crc,
+            header_written: false,
+            finished: false,
+            flushed: FlushMode::None,
+            preset_dict: false,
+            raw_checksum: false,
+            expected_input: 0,
+            yield_next: false,
+        }
+    }
+
+    pub fn config(&self) -> &CompressionConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &CompressionStats {
+        &self.state.stats
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Registers `hook` to run whenever a DEFLATE block is completed, for
+    /// example to align transport packets with block boundaries. The hook
+    /// runs inside `compress_trickle`, before the block's bytes are handed
+    /// out, and stays registered across [`reset`](Self::reset).
+    pub fn on_block_complete(&mut self, hook: impl FnMut(&BlockInfo) + Send + 'static) {
+        self.observers().on_block = Some(Box::new(hook));
+    }
+
+    /// Publishes [`stats`](Self::stats) to `sink` after every `every_blocks`
+    /// completed blocks (at most once per call) and when the stream
+    /// finishes. Stays registered across [`reset`](Self::reset).
+    pub fn set_stats_sink(&mut self, sink: impl StatsSink + Send + 'static, every_blocks: u32) {
+        let blocks = self.state.stats.blocks();
+        let observers = self.observers();
+        observers.sink = Some(Box::new(sink));
+        observers.publish_every = every_blocks.max(1);
+        observers.published_at = blocks;
+    }
+
+    /// Runs every byte handed out, framing included, through `transform`
+    /// before `compress_trickle` returns, in the caller's output buffer.
+    /// Stays registered across [`reset`](Self::reset); restarting a
+    /// stateful transform, such as a cipher's counter, is up to the caller.
+    pub fn set_transform(&mut self, transform: impl BlockTransform + Send + 'static) {
+        self.observers().transform = Some(Box::new(transform));
+    }
+
+    fn observers(&mut self) -> &mut Observers {
+        self.state.observers.get_or_insert_with(Default::default)
+    }
+
+    /// Hands the stats to the sink if one is due.
+    fn publish_stats(&mut self) {
+        let Some(observers) = self.state.observers.as_mut() else {
+            return;
+        };
+        let Some(sink) = observers.sink.as_mut() else {
+            return;
+        };
+        let blocks = self.state.stats.blocks();
+        if self.finished || blocks - observers.published_at >= observers.publish_every {
+            observers.published_at = blocks;
+            sink.publish(&self.state.stats);
+        }
+    }
+
+    /// Announces the total input size of the stream, for [`progress`].
+    /// Cleared by [`reset`](Self::reset).
+    ///
+    /// [`progress`]: Self::progress
+    pub fn set_expected_input_size(&mut self, bytes: u64) {
+        self.expected_input = bytes;
+    }
+
+    /// Percentage of the expected input that has been compressed, from 0
+    /// to 100. Input waiting in the window does not count yet, and 100 is
+    /// only reported once the stream has finished, so the value does not
+    /// sit at 100 while the last blocks are still being written. Without an
+    /// expected size it stays 0 until the stream finishes.
+    pub fn progress(&self) -> u8 {
+        if self.finished {
+            return 100;
+        }
+        if self.expected_input == 0 {
+            return 0;
+        }
+        let done = self.state.stats.work_units.min(self.expected_input);
+        (done * 100 / self.expected_input).min(99) as u8
+    }
+
+    /// Checksum of the input consumed so far, as the container trailer will
+    /// carry it: Adler-32 for zlib, CRC-32 for gzip and `None` for raw
+    /// DEFLATE, unless a checksum was passed to
+    /// [`with_checksum`](TrickleCompressor::with_checksum). Once the stream
+    /// has finished this is the final value.
+    pub fn checksum(&self) -> Option<u32> {
+        match self.config.format {
+            ContainerFormat::Raw => self.raw_checksum.then(|| self.crc.value()),
+            ContainerFormat::Zlib | ContainerFormat::Auto if !self.header_written => Some(1),
+            ContainerFormat::Zlib | ContainerFormat::Auto => Some(self.adler),
+            ContainerFormat::Gzip => Some(self.crc.value()),
+        }
+    }
+
+    /// Compression ratio of roughly the last `config.ratio_window` input
+    /// bytes, counting only completed blocks and no container framing.
+    /// Unlike [`CompressionStats::ratio`] it follows changes in the content,
+    /// such as a log switching from text to binary samples.
+    pub fn recent_ratio(&self) -> f32 {
+        self.state.recent.ratio()
+    }
+
+    /// Encoded bytes queued because the last output buffer was too small.
+    /// Calls hand them out before consuming any more input.
+    pub fn pending_output_len(&self) -> usize {
+        self.state.writer.pending_len()
+    }
+
+    /// Whether the next call has to be made with more output space before
+    /// it takes new input.
+    pub fn has_pending_output(&self) -> bool {
+        self.pending_output_len() > 0
+    }
+
+    /// Total RAM held by this compressor in bytes: the struct itself plus
+    /// its heap buffers. The pending output buffer grows to fit the largest
+    /// block seen so far, so measure after compressing representative data.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>() + self.state.heap_usage()
+    }
+
+    /// Adjusts match finder effort mid-stream; see [`crate::AdaptiveCompressor`].
+    #[cfg(feature = "std")]
+    pub(crate) fn set_effort(&mut self, max_chain: usize, nice_length: usize) {
+        self.state.set_effort(max_chain, nice_length);
+    }
+
+    /// Uses `dict` as history preceding the stream, without announcing it.
+    /// Only valid before any input.
+    #[cfg(feature = "log-dict")]
+    pub(crate) fn prime(&mut self, dict: &[u8]) {
+        self.state.prime(dict);
+    }
+
+    /// Starts the stream with `dict` as history, as zlib's
+    /// `deflateSetDictionary` does: matches may refer to its last
+    /// `window_size` bytes, so short messages sharing much of their content
+    /// with it shrink considerably. The decompressor needs the same
+    /// dictionary, see
+    /// [`TrickleDecompressor::set_dictionary`](crate::TrickleDecompressor::set_dictionary).
+    /// A zlib header announces it by its Adler-32, the
+    /// `DICTID`; raw streams carry no trace of it.
+    ///
+    /// Only valid before the first `compress_trickle` call after creation
+    /// or [`reset`](Self::reset), which drops the dictionary. Fails with
+    /// [`InvalidConfig`](TrickleError::InvalidConfig) naming `dictionary`
+    /// when called later, and naming `format` for gzip, which has no way to
+    /// announce a dictionary.
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, CompressionConfig, ContainerFormat, FlushMode, TrickleCompressor};
+    ///
+    /// let dict = br#"{"device":"pump-1","status":"ok","temp_c":21}"#;
+    /// let message = br#"{"device":"pump-7","status":"ok","temp_c":23}"#;
+    /// let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+    /// let mut compressor = TrickleCompressor::new(config.clone());
+    /// compressor.set_dictionary(dict).unwrap();
+    /// let mut packed = [0u8; 64];
+    /// let r = compressor.compress_trickle(message, &mut packed, FlushMode::Finish).unwrap();
+    /// assert!(r.written < compress_to_vec(message, &config).len() / 2);
+    /// ```
+    pub fn set_dictionary(&mut self, dict: &[u8]) -> Result<(), TrickleError> {
+        if self.config.format == ContainerFormat::Gzip {
+            return Err(TrickleError::InvalidConfig { field: "format" });
+        }
+        if self.header_written {
+            return Err(TrickleError::InvalidConfig {
+                field: "dictionary",
+            });
+        }
+        self.state.prime(dict);
+        self.preset_dict = true;
+        self.adler = adler32::update(1, dict);
+        Ok(())
+    }
+
+    /// Starts a new stream with the same configuration, reusing the buffers
+    /// already allocated so per-message compression does not churn the heap.
+    pub fn reset(&mut self) {
+        self.state.reset(&self.config);
+        self.adler = 1;
+        self.crc.reset();
+        self.header_written = false;
+        self.finished = false;
+        self.flushed = FlushMode::None;
+        self.preset_dict = false;
+        self.expected_input = 0;
+        self.yield_next = false;
+    }
+
+    /// Compresses up to `config.trickle_size` bytes of `input` into `output`.
+    ///
+    /// Unconsumed input must be passed again on the next call. Pass
+    /// [`FlushMode::Finish`] once `input` holds the end of the data, and
+    /// keep calling until the status is [`TrickleStatus::Finished`].
+    ///
+    /// [`FlushMode::Sync`] and [`FlushMode::Full`] make the output up to
+    /// the end of `input` decodable right away, e.g. at the end of each
+    /// telemetry message. Keep passing the same mode with the rest of the
+    /// input until the status is [`NeedInput`](TrickleStatus::NeedInput):
+    /// the flush is then complete once the pending output is drained.
+    /// Repeating it with no new input adds nothing.
+    ///
+    /// Encoded bytes that do not fit into `output` stay queued inside the
+    /// compressor and are handed out first on the following calls, which
+    /// consume no input until the queue is empty. Input counted in
+    /// `consumed` is never encoded twice, so a small `output` only costs
+    /// extra calls, not extra compression work.
+    pub fn compress_trickle(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.compress_trickle_vectored(input, &mut [output], flush)
+    }
+
+    /// [`compress_trickle`](Self::compress_trickle) for async code, with
+    /// any executor. A poll does one `config.trickle_size` step and is
+    /// `Ready` with its progress; once a step has used up that budget, the
+    /// next poll wakes the task and returns `Pending` first, so the
+    /// executor gets to run other tasks between steps.
+    ///
+    /// ```
+    /// use core::future::{poll_fn, Future};
+    /// use tricklezip::{CompressionConfig, FlushMode, TrickleCompressor, TrickleStatus};
+    ///
+    /// async fn compress(compressor: &mut TrickleCompressor, data: &[u8], out: &mut [u8]) -> usize {
+    ///     let (mut consumed, mut written) = (0, 0);
+    ///     loop {
+    ///         let (input, output) = (&data[consumed..], &mut out[written..]);
+    ///         let r = poll_fn(|cx| compressor.poll_compress(cx, input, output, FlushMode::Finish))
+    ///             .await
+    ///             .unwrap();
+    ///         consumed += r.consumed;
+    ///         written += r.written;
+    ///         if r.status == TrickleStatus::Finished {
+    ///             return written;
+    ///         }
+    ///     }
+    /// }
+    ///
+    /// let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+    /// let data = b"tick tock ".repeat(500);
+    /// let mut out = vec![0u8; data.len()];
+    /// let mut task = core::pin::pin!(compress(&mut compressor, &data, &mut out));
+    /// let mut cx = core::task::Context::from_waker(core::task::Waker::noop());
+    /// let written = loop {
+    ///     if let core::task::Poll::Ready(written) = task.as_mut().poll(&mut cx) {
+    ///         break written;
+    ///     }
+    /// };
+    /// assert!(written < data.len() / 10);
+    /// ```
+    pub fn poll_compress(
+        &mut self,
+        cx: &mut Context<'_>,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+    ) -> Poll<Result<TrickleResult, TrickleError>> {
+        if self.yield_next {
+            self.yield_next = false;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let r = self.compress_trickle(input, output, flush);
+        self.yield_next = matches!(r, Ok(r) if r.status == TrickleStatus::InProgress);
+        Poll::Ready(r)
+    }
+
+    /// Like [`compress_trickle`](Self::compress_trickle), but takes its
+    /// input straight out of `queue` and releases what it consumed, so an
+    /// interrupt handler can keep filling the queue meanwhile.
+    ///
+    /// With the queue empty the call only hands out pending output and
+    /// reports [`NeedInput`](TrickleStatus::NeedInput); the stream simply
+    /// resumes on a later call. Bytes that wrap around the end of the ring
+    /// are taken by the next call, which this one asks for by reporting
+    /// [`InProgress`](TrickleStatus::InProgress). `flush` applies to what
+    /// the queue holds now, and takes effect once that is drained;
+    /// [`Finish`](FlushMode::Finish) means that the producer is done.
+    pub fn compress_from_queue<const N: usize>(
+        &mut self,
+        queue: &mut QueueConsumer<'_, N>,
+        output: &mut [u8],
+        flush: FlushMode,
+    ) -> Result<TrickleResult, TrickleError> {
+        let input = queue.read_slice();
+        let flush = if input.len() == queue.len() {
+            flush
+        } else {
+            FlushMode::None
+        };
+        let mut r = self.compress_trickle(input, output, flush)?;
+        queue.release(r.consumed);
+        if r.status == TrickleStatus::NeedInput && !queue.is_empty() {
+            r.status = TrickleStatus::InProgress;
+        }
+        Ok(r)
+    }
+
+    /// Like [`compress_trickle`](Self::compress_trickle), but writes into
+    /// a chain of buffers, filling each before moving on to the next, e.g.
+    /// straight into pre-allocated network packet buffers. `written`
+    /// counts bytes over the whole chain, so the first buffers are full and
+    /// the one the count ends in is filled up to there.
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, CompressionConfig, FlushMode, TrickleCompressor};
+    ///
+    /// let reading = b"rssi=-71 snr=9.5 ".repeat(20);
+    /// let config = CompressionConfig::default();
+    /// let mut compressor = TrickleCompressor::new(config.clone());
+    /// let mut packets = [[0u8; 24]; 4];
+    /// let mut chain: Vec<&mut [u8]> = packets.iter_mut().map(|p| &mut p[..]).collect();
+    /// let r = compressor.compress_trickle_vectored(&reading, &mut chain, FlushMode::Finish).unwrap();
+    /// assert!(compressor.is_finished());
+    /// assert_eq!(packets.concat()[..r.written], compress_to_vec(&reading, &config));
+    /// ```
+    pub fn compress_trickle_vectored(
+        &mut self,
+        input: &[u8],
+        outputs: &mut [&mut [u8]],
+        flush: FlushMode,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.step(input, outputs, flush, self.config.trickle_size.max(1))
+    }
+
+    /// Like [`compress_trickle`](Self::compress_trickle), but advances the
+    /// match finder by at most `budget` positions instead of
+    /// `config.trickle_size`, without a clock: call it from a main loop or
+    /// scheduler with whatever work the current time slice allows. The
+    /// step also encodes the blocks filled up meanwhile, each at most
+    /// `config.block_size` tokens.
+    ///
+    /// A step that ends with input left over reports
+    /// [`InProgress`](TrickleStatus::InProgress), and
+    /// [`CompressionStats::work_units`] grows by the work actually done.
+    ///
+    /// ```
+    /// use tricklezip::{decompress_to_vec, CompressionConfig, ContainerFormat, FlushMode};
+    /// use tricklezip::{TrickleCompressor, TrickleStatus, WorkBudget};
+    ///
+    /// let data = b"state=idle battery=87% ".repeat(100);
+    /// let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+    /// let mut out = vec![0u8; data.len()];
+    /// let (mut consumed, mut written) = (0, 0);
+    /// loop {
+    ///     let before = compressor.stats().work_units;
+    ///     let r = compressor
+    ///         .compress_step(&data[consumed..], &mut out[written..], FlushMode::Finish, WorkBudget::positions(400))
+    ///         .unwrap();
+    ///     assert!(compressor.stats().work_units - before <= 400);
+    ///     consumed += r.consumed;
+    ///     written += r.written;
+    ///     if r.status == TrickleStatus::Finished {
+    ///         break;
+    ///     }
+    /// }
+    /// assert_eq!(decompress_to_vec(&out[..written], ContainerFormat::Raw).unwrap(), data);
+    /// ```
+    pub fn compress_step(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+        budget: WorkBudget,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.step(input, &mut [output], flush, budget.get())
+    }
+
+    fn step(
+        &mut self,
+        input: &[u8],
+        outputs: &mut [&mut [u8]],
+        flush: FlushMode,
+        budget: usize,
+    ) -> Result<TrickleResult, TrickleError> {
+        if self.finished {
+            debug!("compress_trickle called after the stream finished");
+            return Err(TrickleError::StreamFinished);
+        }
+        self.state.stats.trickle_calls += 1;
+        if !self.header_written {
+            debug!(
+                "starting {:?} stream: level {:?}, window {}",
+                self.config.format, self.config.level, self.config.window_size
+            );
+            self.write_header();
+            self.header_written = true;
+        }
+
+        let mut written = self.drain_chain(outputs, 0);
+        if self.state.writer.pending_len() > 0 {
+            return Ok(TrickleResult {
+                consumed: 0,
+                written,
+                status: TrickleStatus::NeedOutput,
+            });
+        }
+
+        let was_finished = self.state.is_finished();
+        let (segment, close) = self.segment(input.len(), flush);
+        let chunk = self.state.compress_chunk(&input[..segment], budget, close);
+        if chunk.suspended {
+            self.state.stats.budget_suspensions += 1;
+        }
+        let consumed = chunk.consumed;
+        self.update_checksum(&input[..consumed]);
+        self.state.stats.bytes_in += consumed as u64;
+        let finished = chunk.finished && self.state.ends_stream;
+        if chunk.finished && !finished {
+            if self.flushed == FlushMode::Sync {
+                self.state.resume();
+            } else {
+                self.state.restart();
+            }
+        } else if finished && !was_finished {
+            self.write_trailer();
+        }
+        written += self.drain_chain(outputs, written);
+
+        let status = if self.state.writer.pending_len() > 0 {
+            TrickleStatus::NeedOutput
+        } else if finished {
+            self.finished = true;
+            let stats = &self.state.stats;
+            debug!(
+                "stream finished: {} bytes in, {} bytes out, {} blocks",
+                stats.bytes_in,
+                stats.bytes_out,
+                stats.blocks()
+            );
+            TrickleStatus::Finished
+        } else if consumed == input.len() && flush != FlushMode::Finish && self.state.ends_stream {
+            TrickleStatus::NeedInput
+        } else {
+            TrickleStatus::InProgress
+        };
+        self.publish_stats();
+        Ok(TrickleResult {
+            consumed,
+            written,
+            status,
+        })
+    }
+
+    /// Repeats [`compress_trickle`](Self::compress_trickle) until `budget`
+    /// has elapsed or the call cannot make further progress.
+    #[cfg(feature = "std")]
+    pub fn compress_timed(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+        budget: Duration,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.compress_timed_with(input, output, flush, budget, &StdClock::new())
+    }
+
+    /// [`compress_timed`](Self::compress_timed) against `clock`, e.g. the
+    /// SysTick counter, so it also works without `std`. The deadline is
+    /// checked between `config.trickle_size` steps, so the call may
+    /// overrun `budget` by the time of one step.
+    ///
+    /// ```
+    /// use core::cell::Cell;
+    /// use core::time::Duration;
+    /// use tricklezip::{CompressionConfig, FlushMode, TimeSource, TrickleCompressor, TrickleStatus};
+    ///
+    /// /// A timer that advances by 1ms whenever it is read.
+    /// struct Timer(Cell<u64>);
+    ///
+    /// impl TimeSource for Timer {
+    ///     fn now_ticks(&self) -> u64 {
+    ///         self.0.replace(self.0.get() + 1)
+    ///     }
+    ///
+    ///     fn ticks_per_ms(&self) -> u64 {
+    ///         1
+    ///     }
+    /// }
+    ///
+    /// let data = b"accel x=12 y=-3 z=981\n".repeat(500);
+    /// let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+    /// let mut out = vec![0u8; data.len()];
+    /// let timer = Timer(Cell::new(0));
+    /// let r = compressor
+    ///     .compress_timed_with(&data, &mut out, FlushMode::Finish, Duration::from_millis(3), &timer)
+    ///     .unwrap();
+    /// // Three steps, then the time was up.
+    /// assert_eq!(compressor.stats().trickle_calls, 3);
+    /// assert_eq!(r.status, TrickleStatus::InProgress);
+    /// ```
+    pub fn compress_timed_with<T: TimeSource>(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+        budget: Duration,
+        clock: &T,
+    ) -> Result<TrickleResult, TrickleError> {
+        let start = clock.now_ticks();
+        let ticks = clock.ticks_in(budget);
+        let mut total = TrickleResult {
+            consumed: 0,
+            written: 0,
+            status: TrickleStatus::InProgress,
+        };
+        loop {
+            let r = self.compress_trickle(
+                &input[total.consumed..],
+                &mut output[total.written..],
+                flush,
+            )?;
+            total.consumed += r.consumed;
+            total.written += r.written;
+            total.status = r.status;
+            if r.status != TrickleStatus::InProgress
+                || clock.now_ticks().wrapping_sub(start) >= ticks
+            {
+                return Ok(total);
+            }
+        }
+    }
+
+    /// How much of the `available` input to encode now and whether to
+    /// close the segment at its end, stopping at the next restart point
+    /// once it is in reach.
+    fn segment(&mut self, available: usize, flush: FlushMode) -> (usize, bool) {
+        // A cleared `ends_stream` marks a restart or flush under way.
+        let closing = !self.state.ends_stream;
+        if !closing && available > 0 {
+            self.flushed = FlushMode::None;
+        }
+        let interval = self.config.restart_interval as u64;
+        if interval != 0 {
+            let left = interval - self.state.stats.bytes_in % interval;
+            // The first call of a restart always consumes input, so
+            // reaching a multiple of the interval means the segment is
+            // complete. A flush reaching the next one has to restart there.
+            if closing {
+                let end = (left % interval) as usize;
+                if end > 0 && end <= available {
+                    self.flushed = FlushMode::Full;
+                }
+                return (available.min(end), true);
+            }
+            if left < available as u64 || left == available as u64 && flush != FlushMode::Finish {
+                self.state.ends_stream = false;
+                self.flushed = FlushMode::Full;
+                return (left as usize, true);
+            }
+        } else if closing {
+            return (available, true);
+        }
+        match flush {
+            FlushMode::None => (available, false),
+            FlushMode::Finish => (available, true),
+            _ if available == 0 && flush <= self.flushed => (0, false),
+            _ => {
+                self.state.ends_stream = false;
+                self.flushed = flush;
+                (available, true)
+            }
+        }
+    }
+
+    /// Drains pending output into the chain, from `skip` bytes into it.
+    fn drain_chain(&mut self, outputs: &mut [&mut [u8]], mut skip: usize) -> usize {
+        let mut n = 0;
+        for output in outputs.iter_mut() {
+            if skip >= output.len() {
+                skip -= output.len();
+                continue;
+            }
+            n += self.drain(&mut output[skip..]);
+            skip = 0;
+            if self.state.writer.pending_len() == 0 {
+                break;
+            }
+        }
+        n
+    }
+
+    fn drain(&mut self, output: &mut [u8]) -> usize {
+        let n = self.state.writer.drain(output);
+        if let Some(transform) = self
+            .state
+            .observers
+            .as_mut()
+            .and_then(|o| o.transform.as_mut())
+        {
+            transform.apply(&mut output[..n]);
+        }
+        self.state.stats.bytes_out += n as u64;
+        n
+    }
+
+    fn update_checksum(&mut self, data: &[u8]) {
+        match self.config.format {
+            ContainerFormat::Raw if self.raw_checksum => self.crc.update(data),
+            ContainerFormat::Raw => {}
+            ContainerFormat::Zlib | ContainerFormat::Auto => {
+                self.adler = adler32::update(self.adler, data)
+            }
+            ContainerFormat::Gzip => self.crc.update(data),
+        }
+    }
+
+    fn write_header(&mut self) {
+        let w = &mut self.state.writer;
+        match self.config.format {
+            ContainerFormat::Raw => {}
+            ContainerFormat::Zlib | ContainerFormat::Auto if self.preset_dict => {
+                w.write_bytes(&zlib_header_with_dict(&self.config));
+                w.write_bytes(&self.adler.to_be_bytes());
+                self.adler = 1;
+            }
+            ContainerFormat::Zlib | ContainerFormat::Auto => {
+                w.write_bytes(&zlib_header(&self.config))
+            }
+            ContainerFormat::Gzip => {
+                w.write_bytes(&gzip_header(&self.config));
+            }
+        }
+    }
+
+    fn write_trailer(&mut self) {
+        let bytes_in = self.state.stats.bytes_in;
+        let w = &mut self.state.writer;
+        w.align();
+        match self.config.format {
+            ContainerFormat::Raw => {}
+            ContainerFormat::Zlib | ContainerFormat::Auto => {
+                w.write_bytes(&self.adler.to_be_bytes())
+            }
+            ContainerFormat::Gzip => {
+                w.write_bytes(&self.crc.value().to_le_bytes());
+                w.write_bytes(&(bytes_in as u32).to_le_bytes());
+            }
+        }
+    }
+}
+
+/// Gzip header without mtime or name. XFL follows GNU gzip: 2 at the best
+/// level, 4 at the fastest.
+pub(crate) fn gzip_header(config: &CompressionConfig) -> [u8; 10] {
+    let flags = if config.gzip_text { FTEXT } else { 0 };
+    let xfl = match config.level.get() {
+        9 => 2,
+        1 => 4,
+        _ => 0,
+    };
+    [0x1F, 0x8B, 8, flags, 0, 0, 0, 0, xfl, config.gzip_os]
+}
+
+const FTEXT: u8 = 0x01;
+
+/// CMF and FLG bytes for the configured window and level.
+pub(crate) fn zlib_header(config: &CompressionConfig) -> [u8; 2] {
+    let window = config.window();
+    let cinfo = window.trailing_zeros() as u8 - 8;
+    let cmf = (cinfo << 4) | 8;
+    let flevel = match config.level.get() {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let mut flg = flevel << 6;
+    flg += ((31 - (cmf as u16 * 256 + flg as u16) % 31) % 31) as u8;
+    [cmf, flg]
+}
+
+/// zlib FLG bit announcing a preset dictionary, whose DICTID follows.
+pub(crate) const FDICT: u8 = 0x20;
+
+/// [`zlib_header`] with [`FDICT`] set. Like zlib, FCHECK is 31 rather
+/// than 0 when either would do.
+fn zlib_header_with_dict(config: &CompressionConfig) -> [u8; 2] {
+    let [cmf, flg] = zlib_header(config);
+    let flg = (flg & 0xE0) | FDICT;
+    [cmf, flg + (31 - (cmf as u16 * 256 + flg as u16) % 31) as u8]
+}
+
+/// Compresses `input` in one go, driving the trickle loop internally.
+pub fn compress_to_vec(input: &[u8], config: &CompressionConfig) -> Vec<u8> {
+    let mut compressor = TrickleCompressor::new(config.clone());
+    let mut out = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let mut consumed = 0;
+    loop {
+        let r = compressor
+            .compress_trickle(&input[consumed..], &mut buf, FlushMode::Finish)
+            .expect("stream is not finished");
+        consumed += r.consumed;
+        out.extend_from_slice(&buf[..r.written]);
+        if r.status == TrickleStatus::Finished {
+            return out;
+        }
+    }
+}
+
+/// Like [`compress_to_vec`], but takes the input in pieces, such as
+/// flash pages or the two halves of a wrapped ring buffer, so it never has
+/// to be assembled in one place. The output is the same as for the pieces
+/// concatenated.
+///
+/// ```
+/// use tricklezip::{compress_chunks_to_vec, compress_to_vec, CompressionConfig};
+///
+/// let ring = b"2.3V ok\n1.9V low\n2.1V ok\n";
+/// let (tail, head) = ring.split_at(17);
+/// let config = CompressionConfig::default();
+/// let packed = compress_chunks_to_vec([head, tail], &config);
+/// assert_eq!(packed, compress_to_vec(b"2.1V ok\n2.3V ok\n1.9V low\n", &config));
+/// ```
+pub fn compress_chunks_to_vec<'a>(
+    chunks: impl IntoIterator<Item = &'a [u8]>,
+    config: &CompressionConfig,
+) -> Vec<u8> {
+    let mut compressor = TrickleCompressor::new(config.clone());
+    let mut out = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let mut chunks = chunks.into_iter().peekable();
+    let mut chunk: &[u8] = chunks.next().unwrap_or_default();
+    loop {
+        let last = chunks.peek().is_none();
+        let r = compressor
+            .compress_trickle(chunk, &mut buf, last.into())
+            .expect("stream is not finished");
+        chunk = &chunk[r.consumed..];
+        out.extend_from_slice(&buf[..r.written]);
+        match r.status {
+            TrickleStatus::Finished => return out,
+            TrickleStatus::NeedInput if chunk.is_empty() => {
+                chunk = chunks.next().unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// [`compress_to_fit`] stopped because the output would not fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExceedsLimit {
+    /// Input bytes encoded by the time the output reached the limit; the
+    /// rest was never run through the match finder.
+    pub processed: u64,
+}
+
+/// Compresses `input` if the output, container framing included, fits in
+/// `max_output_bytes`, e.g. a LoRaWAN payload at the current data rate.
+///
+/// Output goes straight into a buffer of `max_output_bytes`, so once it is
+/// full with the stream unfinished the limit is provably exceeded and
+/// compression stops there instead of running to the end.
+///
+/// ```
+/// use tricklezip::{compress_to_fit, CompressionConfig};
+///
+/// let uplink = b"t=21.5 h=40 t=21.5 h=40 t=21.6 h=40 t=21.5 h=41".repeat(4);
+/// let packed = compress_to_fit(&uplink, 51, &CompressionConfig::telemetry()).unwrap();
+/// assert!(packed.len() <= 51);
+/// assert!(compress_to_fit(&uplink, 8, &CompressionConfig::telemetry()).is_err());
+/// ```
+pub fn compress_to_fit(
+    input: &[u8],
+    max_output_bytes: usize,
+    config: &CompressionConfig,
+) -> Result<Vec<u8>, ExceedsLimit> {
+    let mut compressor = TrickleCompressor::new(config.clone());
+    let mut out = vec![0u8; max_output_bytes];
+    let (mut consumed, mut written) = (0, 0);
+    loop {
+        let r = compressor
+            .compress_trickle(&input[consumed..], &mut out[written..], FlushMode::Finish)
+            .expect("stream is not finished");
+        consumed += r.consumed;
+        written += r.written;
+        match r.status {
+            TrickleStatus::Finished => {
+                out.truncate(written);
+                return Ok(out);
+            }
+            TrickleStatus::NeedOutput if written == max_output_bytes => {
+                return Err(ExceedsLimit {
+                    processed: compressor.stats().work_units,
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Estimates the size [`compress_to_vec`] would produce for `input`,
+/// including container framing.
+///
+/// Runs match finding and block selection as usual but never writes any
+/// bits, which makes it noticeably cheaper than compressing. Block sizes are
+/// computed exactly except for byte padding, so the estimate is usually
+/// within a few bytes of the real output.
+pub fn estimate_compressed_size(input: &[u8], config: &CompressionConfig) -> usize {
+    let mut state = DeflateState::new(config);
+    state.dry_run = true;
+    let mut consumed = 0;
+    while !state.is_finished() {
+        consumed += state
+            .compress_chunk(&input[consumed..], usize::MAX, true)
+            .consumed;
+    }
+    let framing = match config.format {
+        ContainerFormat::Raw => 0,
+        ContainerFormat::Zlib | ContainerFormat::Auto => 2 + 4,
+        ContainerFormat::Gzip => 10 + 8,
+    };
+    state.emitted_bits.div_ceil(8) as usize + framing
+}
+
+/// Upper bound on the size [`compress_to_vec`] produces for `input_len`
+/// bytes, including container framing and a preset dictionary ID. Holds for
+/// any stream finished in one go; every flush before that can add up to 11
+/// bytes.
+///
+/// No block is written larger than storing its input would be, 5.25 bytes
+/// over, but blocks can be as short as `config.block_size` bytes or, for
+/// windows under 4KB, a fraction of the window. Incompressible data costs
+/// the least with large windows and blocks.
+pub fn compress_bound(input_len: usize, config: &CompressionConfig) -> usize {
+    let len = input_len as u64;
+    let mut blocks = DeflateState::max_blocks_for(config, len);
+    if config.restart_interval != 0 {
+        // Every segment can end in a short block and an empty stored one.
+        blocks += (len / config.restart_interval as u64 + 1) * 2;
+    }
+    let headers = blocks + len / huffman::MAX_STORED as u64;
+    let framing = match config.format {
+        ContainerFormat::Raw => 0,
+        ContainerFormat::Zlib | ContainerFormat::Auto => 2 + 4 + 4,
+        ContainerFormat::Gzip => 10 + 8,
+    };
+    (len + (headers * huffman::STORED_OVERHEAD_BITS as u64).div_ceil(8) + framing) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BufferSizes, CompressionLevel, EarlyAbort, Strategy};
+
+    /// Runs `compressor` through the end of `input`, offering `out_chunk`
+    /// bytes of output per call, and returns everything it wrote.
+    fn drive<C: Checksum, A: Allocator + Clone>(
+        compressor: &mut TrickleCompressor<C, A>,
+        input: &[u8],
+        out_chunk: usize,
+    ) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut buf = vec![0u8; out_chunk];
+        let mut consumed = 0;
+        while !compressor.is_finished() {
+            let r = compressor
+                .compress_trickle(&input[consumed..], &mut buf, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            out.extend_from_slice(&buf[..r.written]);
+        }
+        out
+    }
+
+    #[test]
+    fn tiny_output_buffer_drains_without_losing_bytes() {
+        let data: Vec<u8> = (0..20_000u32)
+            .map(|i| (i % 251) as u8 ^ (i / 97) as u8)
+            .collect();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let expected = compress_to_vec(&data, &config);
+
+        let mut compressor = TrickleCompressor::new(config);
+        let out = drive(&mut compressor, &data, 3);
+        assert_eq!(out, expected);
+        assert_eq!(compressor.stats().bytes_out, out.len() as u64);
+    }
+
+    #[test]
+    fn small_output_never_re_encodes_input() {
+        let data = b"level=3 pressure=1013 humidity=45\n".repeat(500);
+        let config = CompressionConfig::default();
+        let mut reference = TrickleCompressor::new(config.clone());
+        drive(&mut reference, &data, data.len());
+        let mut compressor = TrickleCompressor::new(config);
+        drive(&mut compressor, &data, 1);
+        let (a, b) = (compressor.stats(), reference.stats());
+        assert_eq!(a.bytes_in, data.len() as u64);
+        assert_eq!((a.literals, a.matches), (b.literals, b.matches));
+        assert_eq!(a.bytes_out, b.bytes_out);
+    }
+
+    #[test]
+    fn tiny_window_presets_round_trip_in_little_ram() {
+        let mut data = Vec::new();
+        for i in 0..300u32 {
+            let line = alloc::format!(
+                "{{\"ts\":{},\"temp\":{},\"status\":\"ok\"}}\n",
+                1000 + i * 30,
+                20 + i % 7
+            );
+            data.extend_from_slice(line.as_bytes());
+        }
+        let noise: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        // Window, the footprint and worst-case growth the preset documents.
+        for (window, memory, growth) in [
+            (256, 1024, 3),
+            (512, 1536, 3),
+            (1024, 2560, 6),
+            (2048, 3840, 6),
+        ] {
+            let config = CompressionConfig::tiny_window(window).with_format(ContainerFormat::Zlib);
+            let packed = compress_to_vec(&noise, &config).len();
+            assert!(
+                packed <= noise.len() + noise.len() / growth + 10,
+                "window {window}"
+            );
+            let mut compressor = TrickleCompressor::new(config);
+            let out = drive(&mut compressor, &data, data.len());
+            assert!(out.len() < data.len() / 2, "window {window}");
+            assert_eq!(compressor.stats().dynamic_blocks, 0);
+            let unpacked = crate::decompress_to_vec(&out, ContainerFormat::Zlib);
+            assert_eq!(unpacked.unwrap(), data);
+            // Profiling counters push the struct just over the budget.
+            if !cfg!(feature = "profiling") {
+                assert!(
+                    compressor.memory_usage() < memory,
+                    "{}",
+                    compressor.memory_usage()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn buffer_size_presets_set_the_footprint() {
+        let data = b"id=7 state=idle load=0.31\n".repeat(400);
+        let base = CompressionConfig::default().with_window_size(4096);
+        let mut usage = Vec::new();
+        for sizes in [
+            BufferSizes::LOW_LATENCY,
+            BufferSizes::BALANCED,
+            BufferSizes::HIGH_RATIO,
+        ] {
+            let config = base.clone().with_buffer_sizes(sizes);
+            assert_eq!(config.buffer_sizes(), sizes);
+            let compressor = TrickleCompressor::new(config.clone());
+            usage.push(compressor.memory_usage());
+            let packed = compress_to_vec(&data, &config);
+            assert_eq!(
+                crate::decompress_to_vec(&packed, ContainerFormat::Raw).unwrap(),
+                data
+            );
+        }
+        assert!(usage[0] < usage[1] && usage[1] < usage[2], "{usage:?}");
+
+        // The output buffer is reserved up front.
+        let reserved = base.clone().with_output_buffer_size(2000);
+        assert_eq!(
+            TrickleCompressor::new(reserved).memory_usage(),
+            TrickleCompressor::new(base).memory_usage() + 2000
+        );
+    }
+
+    #[test]
+    fn polling_yields_between_budgets() {
+        let data = b"state=idle load=0.31\n".repeat(200);
+        let config = CompressionConfig {
+            trickle_size: 256,
+            ..CompressionConfig::default()
+        };
+        let mut compressor = TrickleCompressor::new(config.clone());
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+        let mut out = vec![0u8; data.len()];
+        let (mut consumed, mut written, mut steps, mut yields) = (0, 0, 0, 0);
+        loop {
+            let poll = compressor.poll_compress(
+                &mut cx,
+                &data[consumed..],
+                &mut out[written..],
+                FlushMode::Finish,
+            );
+            let Poll::Ready(r) = poll else {
+                yields += 1;
+                continue;
+            };
+            let r = r.unwrap();
+            steps += 1;
+            consumed += r.consumed;
+            written += r.written;
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+        }
+        assert!(steps > data.len() / 256);
+        // Every step but the last few used up its budget.
+        assert!(yields >= steps - 3, "{yields} of {steps}");
+        assert_eq!(out[..written], compress_to_vec(&data, &config));
+    }
+
+    #[test]
+    fn pending_output_is_drained_before_new_input() {
+        let data = b"0123456789abcdefghijklmnopqrstuvwxyz".repeat(50);
+        let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+        assert!(!compressor.has_pending_output());
+        let mut out = [0u8; 8];
+        let mut consumed = 0;
+        loop {
+            let r = compressor
+                .compress_trickle(&data[consumed..], &mut out, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            if r.status == TrickleStatus::NeedOutput {
+                break;
+            }
+            assert!(!compressor.has_pending_output());
+        }
+        let pending = compressor.pending_output_len();
+        assert!(pending > 0);
+        let mut rest = vec![0u8; pending];
+        let r = compressor
+            .compress_trickle(&data[consumed..], &mut rest, FlushMode::Finish)
+            .unwrap();
+        assert_eq!((r.consumed, r.written), (0, pending));
+        assert!(!compressor.has_pending_output());
+    }
+
+    #[test]
+    fn vectored_output_fills_buffers_in_order() {
+        let data = b"seq=1 temp=20.5 hum=40\n".repeat(500);
+        let config = CompressionConfig::default()
+            .with_format(ContainerFormat::Gzip)
+            .with_buffer_sizes(BufferSizes::LOW_LATENCY);
+        let mut compressor = TrickleCompressor::new(config.clone());
+        let mut sent = Vec::new();
+        let mut consumed = 0;
+        while !compressor.is_finished() {
+            // Packet buffers of uneven sizes, one of them empty.
+            let mut packets = [vec![0u8; 40], vec![], vec![0u8; 7], vec![0u8; 90]];
+            let mut chain: Vec<&mut [u8]> = packets.iter_mut().map(|p| &mut p[..]).collect();
+            let r = compressor
+                .compress_trickle_vectored(&data[consumed..], &mut chain, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            sent.extend_from_slice(&packets.concat()[..r.written]);
+        }
+        assert_eq!(sent, compress_to_vec(&data, &config));
+    }
+
+    #[test]
+    fn ram_budget_picks_the_largest_fitting_window() {
+        let data = b"id=7 state=idle load=0.31\n".repeat(400);
+        assert_eq!(CompressionConfig::for_ram_budget(256), None);
+        let mut last_window = 0;
+        for budget in [2000, 4000, 6000, 20_000, 70_000, 150_000, 400_000] {
+            let (config, footprint) = CompressionConfig::for_ram_budget(budget).unwrap();
+            assert!(footprint <= budget);
+            let mut compressor = TrickleCompressor::new(config.clone());
+            assert_eq!(compressor.memory_usage(), footprint);
+            // Blocks fit the reserved output buffer, even when left queued
+            // by a small output.
+            let out = drive(&mut compressor, &data, 7);
+            assert_eq!(compressor.memory_usage(), footprint);
+            assert_eq!(
+                crate::decompress_to_vec(&out, ContainerFormat::Raw).unwrap(),
+                data
+            );
+            assert!(config.window_size >= last_window);
+            last_window = config.window_size;
+        }
+        assert_eq!(last_window, 32768);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "invalid window_size 3000")]
+    fn new_rejects_nonstandard_windows_in_debug_builds() {
+        TrickleCompressor::new(CompressionConfig::default().with_window_size(3000));
+    }
+
+    #[test]
+    fn try_new_rejects_nonstandard_windows() {
+        for window in [0, 128, 300, 4095, 65536] {
+            let config = CompressionConfig::default()
+                .with_format(ContainerFormat::Zlib)
+                .with_window_size(window);
+            assert_eq!(
+                TrickleCompressor::try_new(config).err(),
+                Some(TrickleError::InvalidConfig {
+                    field: "window_size"
+                }),
+                "{window}"
+            );
+        }
+        let presets = [
+            CompressionConfig::default(),
+            CompressionConfig::tiny_window(300),
+            CompressionConfig::small_packet(),
+            CompressionConfig::low_memory(),
+            CompressionConfig::text(),
+            CompressionConfig::telemetry(),
+            CompressionConfig::firmware_image(),
+            CompressionConfig::png(),
+            CompressionConfig::json_logs(),
+        ];
+        for config in presets {
+            assert!(TrickleCompressor::try_new(config).is_ok());
+        }
+        // Auto compresses as zlib.
+        let auto = CompressionConfig::default().with_format(ContainerFormat::Auto);
+        assert!(TrickleCompressor::try_new(auto.clone()).is_ok());
+        assert_eq!(
+            compress_to_vec(b"hello hello", &auto),
+            compress_to_vec(
+                b"hello hello",
+                &CompressionConfig::default().with_format(ContainerFormat::Zlib)
+            )
+        );
+    }
+
+    #[cfg(feature = "extended-window")]
+    #[test]
+    fn extended_window_reaches_past_32k() {
+        let mut x = 5u32;
+        let mut noise = |n| -> Vec<u8> {
+            (0..n)
+                .map(|_| {
+                    x ^= x << 13;
+                    x ^= x >> 17;
+                    x ^= x << 5;
+                    x as u8
+                })
+                .collect()
+        };
+        // A record repeated after 50KB of noise only matches in a 64KB window.
+        let record = noise(4000);
+        let mut data = record.clone();
+        data.extend(noise(50_000));
+        data.extend_from_slice(&record);
+
+        let standard = CompressionConfig::default();
+        let extended = standard.clone().with_window_size(65536);
+        assert!(TrickleCompressor::try_new(extended.clone()).is_ok());
+        let packed = compress_to_vec(&data, &extended);
+        assert!(packed.len() + 3000 < compress_to_vec(&data, &standard).len());
+
+        let mut d = crate::TrickleDecompressor::with_window_size(ContainerFormat::Raw, 65536);
+        assert_eq!(d.window_size(), 65536);
+        let mut out = vec![0u8; data.len()];
+        let r = d.decompress_trickle(&packed, &mut out).unwrap();
+        assert_eq!(r.status, TrickleStatus::Finished);
+        assert_eq!(out, data);
+        assert_eq!(
+            crate::decompress_to_vec(&packed, ContainerFormat::Raw),
+            Err(TrickleError::WindowTooLarge)
+        );
+
+        // Only raw streams get the extended window.
+        let zlib = extended.with_format(ContainerFormat::Zlib);
+        assert!(TrickleCompressor::try_new(zlib.clone()).is_err());
+        assert_eq!(zlib.window(), 32768);
+    }
+
+    #[test]
+    fn chunked_input_matches_contiguous_input() {
+        let data = b"page=17 crc=ok bytes=256\n".repeat(500);
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let expected = compress_to_vec(&data, &config);
+        let pages = data.chunks(256).flat_map(|page| [&[][..], page]);
+        assert_eq!(compress_chunks_to_vec(pages, &config), expected);
+        let (a, b) = data.split_at(7001);
+        assert_eq!(compress_chunks_to_vec([a, b, &[]], &config), expected);
+        assert_eq!(
+            compress_chunks_to_vec(core::iter::empty(), &config),
+            compress_to_vec(&[], &config)
+        );
+    }
+
+    /// Counts the bytes it checksums so the test can see it was used.
+    struct CountingCrc {
+        inner: SoftwareCrc,
+        fed: usize,
+    }
+
+    impl CrcBackend for CountingCrc {
+        fn reset(&mut self) {
+            CrcBackend::reset(&mut self.inner);
+            self.fed = 0;
+        }
+
+        fn update(&mut self, data: &[u8]) {
+            CrcBackend::update(&mut self.inner, data);
+            self.fed += data.len();
+        }
+
+        fn value(&self) -> u32 {
+            CrcBackend::value(&self.inner)
+        }
+    }
+
+    #[test]
+    fn gzip_header_matches_gnu_gzip() {
+        let data = b"plain ascii text\n".repeat(20);
+        let best = CompressionConfig::new(CompressionLevel::BEST)
+            .with_format(ContainerFormat::Gzip)
+            .with_gzip_os(3)
+            .with_gzip_text(true);
+        let gz = compress_to_vec(&data, &best);
+        assert_eq!(&gz[..10], &[0x1F, 0x8B, 8, FTEXT, 0, 0, 0, 0, 2, 3]);
+        assert_eq!(
+            crate::decompress_to_vec(&gz, ContainerFormat::Gzip).unwrap(),
+            data
+        );
+
+        let fast =
+            CompressionConfig::new(CompressionLevel::FAST).with_format(ContainerFormat::Gzip);
+        assert_eq!(
+            &compress_to_vec(&data, &fast)[..10],
+            &[0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 4, 255]
+        );
+    }
+
+    #[test]
+    fn zlib_framing_matches_zlib() {
+        let data = b"plain ascii text\n".repeat(20);
+        for (level, flg) in [(1, 0x01), (3, 0x5E), (6, 0x9C), (9, 0xDA)] {
+            let config = CompressionConfig::new(CompressionLevel::new(level))
+                .with_format(ContainerFormat::Zlib);
+            let z = compress_to_vec(&data, &config);
+            assert_eq!(&z[..2], &[0x78, flg], "level {level}");
+            assert_eq!(&z[z.len() - 4..], &0x6C0A_7B71u32.to_be_bytes());
+            assert_eq!(
+                crate::decompress_to_vec(&z, ContainerFormat::Zlib).unwrap(),
+                data
+            );
+        }
+
+        let small = CompressionConfig::new(CompressionLevel::BEST)
+            .with_format(ContainerFormat::Zlib)
+            .with_window_size(1024);
+        assert_eq!(&compress_to_vec(&data, &small)[..2], &[0x28, 0xCF]);
+    }
+
+    #[test]
+    fn preset_dictionary_is_announced_like_zlib() {
+        let dict = br#"{"device":"pump-1","status":"ok","temp_c":21}"#;
+        let message = br#"{"device":"pump-7","status":"ok","temp_c":23}"#;
+        for (level, flg) in [(1, 0x3F), (6, 0xBB), (9, 0xF9)] {
+            let config = CompressionConfig::new(CompressionLevel::new(level))
+                .with_format(ContainerFormat::Zlib);
+            let mut compressor = TrickleCompressor::new(config);
+            compressor.set_dictionary(dict).unwrap();
+            let mut out = [0u8; 64];
+            let r = compressor
+                .compress_trickle(message, &mut out, FlushMode::Finish)
+                .unwrap();
+            assert_eq!(r.status, TrickleStatus::Finished);
+            assert_eq!(&out[..6], &[0x78, flg, 0x4F, 0x47, 0x0E, 0x3C]);
+            assert!(r.written <= 24, "level {level}: {} bytes", r.written);
+            assert_eq!(
+                compressor.set_dictionary(dict),
+                Err(TrickleError::InvalidConfig {
+                    field: "dictionary"
+                })
+            );
+            // The dictionary does not outlive the stream.
+            compressor.reset();
+            let r = compressor
+                .compress_trickle(message, &mut out, FlushMode::Finish)
+                .unwrap();
+            assert_eq!(&out[..2], &zlib_header(compressor.config()));
+            assert!(r.written > 24);
+        }
+
+        let mut raw = TrickleCompressor::new(CompressionConfig::default());
+        raw.set_dictionary(dict).unwrap();
+        let mut packed = [0u8; 64];
+        let r = raw
+            .compress_trickle(message, &mut packed, FlushMode::Finish)
+            .unwrap();
+        let mut decompressor = crate::TrickleDecompressor::new(ContainerFormat::Raw);
+        decompressor.set_dictionary(dict).unwrap();
+        let mut out = [0u8; 64];
+        let d = decompressor
+            .decompress_trickle(&packed[..r.written], &mut out)
+            .unwrap();
+        assert_eq!(&out[..d.written], message);
+
+        let gzip = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        assert_eq!(
+            TrickleCompressor::new(gzip).set_dictionary(dict),
+            Err(TrickleError::InvalidConfig { field: "format" })
+        );
+    }
+
+    #[test]
+    fn flushes_make_everything_so_far_decodable() {
+        let line = b"t=1712 temp=21.5 hum=40 status=ok\n";
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let mut compressor = TrickleCompressor::new(config);
+        let mut decompressor = crate::TrickleDecompressor::new(ContainerFormat::Zlib);
+        let mut decoded = Vec::new();
+        let mut flush = |input: &[u8], flush: FlushMode| {
+            let mut out = [0u8; 256];
+            let r = compressor.compress_trickle(input, &mut out, flush).unwrap();
+            assert_eq!(r.consumed, input.len());
+            let mut plain = [0u8; 256];
+            let d = decompressor
+                .decompress_trickle(&out[..r.written], &mut plain)
+                .unwrap();
+            assert_eq!(d.consumed, r.written);
+            decoded.extend_from_slice(&plain[..d.written]);
+            (
+                out[..r.written].to_vec(),
+                r.status,
+                compressor.stats().restarts,
+            )
+        };
+
+        let (mut packed, status, _) = flush(line, FlushMode::Sync);
+        assert_eq!(status, TrickleStatus::NeedInput);
+        assert!(packed.ends_with(&[0x00, 0x00, 0xFF, 0xFF]));
+        // Nothing new to flush.
+        assert!(flush(&[], FlushMode::Sync).0.is_empty());
+
+        // A sync flush keeps the history, a full flush forgets it.
+        let (synced, _, restarts) = flush(line, FlushMode::Sync);
+        assert_eq!(restarts, 0);
+        let (full, _, restarts) = flush(line, FlushMode::Full);
+        assert!(full.ends_with(&[0x00, 0x00, 0xFF, 0xFF]));
+        assert_eq!(restarts, 1);
+        // An empty sync after a full flush has nothing to add either.
+        assert!(flush(&[], FlushMode::Sync).0.is_empty());
+        let (end, status, _) = flush(line, FlushMode::Finish);
+        assert_eq!(status, TrickleStatus::Finished);
+        assert_eq!(decoded, line.repeat(4));
+
+        for part in [synced, full, end] {
+            packed.extend_from_slice(&part);
+        }
+        assert_eq!(
+            crate::decompress_to_vec(&packed, ContainerFormat::Zlib).unwrap(),
+            line.repeat(4)
+        );
+    }
+
+    #[test]
+    fn checksum_matches_trailer() {
+        let data = b"id=7 rssi=-61 snr=9.5\n".repeat(200);
+        for format in [
+            ContainerFormat::Raw,
+            ContainerFormat::Zlib,
+            ContainerFormat::Gzip,
+        ] {
+            let config = CompressionConfig::default().with_format(format);
+            let mut compressor = TrickleCompressor::new(config);
+            let mut out = vec![0u8; data.len()];
+            let half = compressor
+                .compress_trickle(&data[..100], &mut out, FlushMode::None)
+                .unwrap();
+            let partial = match format {
+                ContainerFormat::Raw => None,
+                ContainerFormat::Zlib => Some(adler32::update(1, &data[..half.consumed])),
+                ContainerFormat::Gzip => Some(crate::crc32::update(0, &data[..half.consumed])),
+                ContainerFormat::Auto => unreachable!(),
+            };
+            assert_eq!(compressor.checksum(), partial);
+            out.truncate(half.written);
+            out.extend(drive(&mut compressor, &data[half.consumed..], data.len()));
+            let end = out.len();
+            let trailer = match format {
+                ContainerFormat::Raw => None,
+                ContainerFormat::Zlib => {
+                    Some(u32::from_be_bytes(out[end - 4..end].try_into().unwrap()))
+                }
+                ContainerFormat::Gzip => Some(u32::from_le_bytes(
+                    out[end - 8..end - 4].try_into().unwrap(),
+                )),
+                ContainerFormat::Auto => unreachable!(),
+            };
+            assert_eq!(compressor.checksum(), trailer);
+        }
+    }
+
+    #[test]
+    fn custom_crc_backend_produces_same_gzip() {
+        let data = b"sensor=7 value=1234\n".repeat(100);
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let expected = compress_to_vec(&data, &config);
+        let crc = CountingCrc {
+            inner: SoftwareCrc::new(),
+            fed: 0,
+        };
+        let mut compressor = TrickleCompressor::with_crc_backend(config, crc);
+        assert_eq!(drive(&mut compressor, &data, data.len()), expected);
+        assert_eq!(compressor.crc.fed, data.len());
+
+        let mut decompressor = crate::TrickleDecompressor::with_crc_backend(
+            ContainerFormat::Gzip,
+            CountingCrc {
+                inner: SoftwareCrc::new(),
+                fed: 0,
+            },
+        );
+        let mut unpacked = vec![0u8; data.len() + 1];
+        let r = decompressor
+            .decompress_trickle(&expected, &mut unpacked)
+            .unwrap();
+        assert_eq!(r.status, TrickleStatus::Finished);
+        assert_eq!(&unpacked[..r.written], &data[..]);
+    }
+
+    #[test]
+    fn low_memory_preset_stays_under_8k() {
+        let mut text = Vec::new();
+        for i in 0..400u32 {
+            let line = alloc::format!("{i}: the pump reported pressure {} kPa\n", 90 + i % 20);
+            text.extend_from_slice(line.as_bytes());
+        }
+        let noise: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for data in [text, noise] {
+            let config = CompressionConfig::low_memory().with_format(ContainerFormat::Gzip);
+            let mut compressor = TrickleCompressor::new(config);
+            let out = drive(&mut compressor, &data, data.len());
+            assert!(
+                compressor.memory_usage() < 8192,
+                "{}",
+                compressor.memory_usage()
+            );
+            assert!(out.len() <= data.len() + data.len() / 20 + 18);
+            let unpacked = crate::decompress_to_vec(&out, ContainerFormat::Gzip);
+            assert_eq!(unpacked.unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn content_presets_round_trip() {
+        let mut json = Vec::new();
+        let mut records = Vec::new();
+        for i in 0..500u32 {
+            let line = alloc::format!("{{\"ts\":{},\"level\":\"info\",\"v\":{}}}\n", i * 30, i % 9);
+            json.extend_from_slice(line.as_bytes());
+            records.extend_from_slice(&(i * 10).to_le_bytes());
+            records.extend_from_slice(&(2000 + i % 5).to_le_bytes());
+        }
+        let text = b"It was the best of times, it was the worst of times. ".repeat(100);
+        let cases = [
+            (CompressionConfig::text(), text.clone()),
+            (CompressionConfig::telemetry(), records),
+            (CompressionConfig::firmware_image(), text),
+            (CompressionConfig::json_logs(), json),
+        ];
+        for (config, data) in cases {
+            let packed = compress_to_vec(&data, &config);
+            assert!(packed.len() < data.len() / 2, "{config:?}");
+            assert_eq!(
+                crate::decompress_to_vec(&packed, ContainerFormat::Raw).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn early_abort_switches_to_stored_blocks() {
+        let mut x = 0x1234_5678u32;
+        let mut noise: Vec<u8> = (0..40_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        // A compressible tail arrives too late to matter.
+        noise.extend_from_slice(&[b'a'; 20_000]);
+        let mut config = CompressionConfig::default().with_early_abort(EarlyAbort::default());
+        config.block_size = 1024;
+        let packed = compress_to_vec(&noise, &config);
+        assert!(packed.len() > noise.len());
+        assert_eq!(
+            crate::decompress_to_vec(&packed, ContainerFormat::Raw).unwrap(),
+            noise
+        );
+
+        let text = b"the quick brown fox jumps over the lazy dog ".repeat(500);
+        let packed = compress_to_vec(&text, &config);
+        assert!(packed.len() < text.len() / 10);
+    }
+
+    #[test]
+    fn levels_trade_work_for_ratio() {
+        assert_eq!(
+            CompressionConfig::new(CompressionLevel::DEFAULT),
+            CompressionConfig::default()
+        );
+        let mut data = Vec::new();
+        for i in 0..4000u32 {
+            let line = alloc::format!(
+                "t={} dev=sensor-{} temp={} rssi=-{}\n",
+                i * 5,
+                i % 13,
+                200 + i * 7 % 31,
+                40 + i * 11 % 50
+            );
+            data.extend_from_slice(line.as_bytes());
+        }
+        let run = |level| {
+            let config = CompressionConfig::new(CompressionLevel::new(level));
+            let mut compressor = TrickleCompressor::new(config);
+            let out = drive(&mut compressor, &data, data.len());
+            let unpacked = crate::decompress_to_vec(&out, ContainerFormat::Raw);
+            assert_eq!(unpacked.unwrap(), data, "level {level}");
+            (out.len(), compressor.stats().matches)
+        };
+        let (fast, fast_matches) = run(1);
+        let (default, _) = run(6);
+        let (best, best_matches) = run(9);
+        assert!(best < default && default < fast, "{best} {default} {fast}");
+        // Lazy parsing trades short matches for literals and longer ones.
+        assert!(best_matches < fast_matches);
+    }
+
+    #[test]
+    fn level_zero_writes_stored_blocks_only() {
+        let text = b"the quick brown fox jumps over the lazy dog ".repeat(500);
+        let config =
+            CompressionConfig::new(CompressionLevel::NONE).with_format(ContainerFormat::Zlib);
+        let mut compressor = TrickleCompressor::new(config);
+        let packed = drive(&mut compressor, &text, 4096);
+        let stats = compressor.stats();
+        assert_eq!(stats.matches, 0);
+        assert_eq!(stats.fixed_blocks + stats.dynamic_blocks, 0);
+        // Zlib header and trailer, and a header byte and LEN/NLEN per block.
+        assert_eq!(
+            packed.len(),
+            text.len() + 6 + 5 * stats.stored_blocks as usize
+        );
+        assert_eq!(
+            crate::decompress_to_vec(&packed, ContainerFormat::Zlib).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn reset_reuses_buffers() {
+        let data = b"GET /status 200 12ms\n".repeat(400);
+        let config = CompressionConfig::default()
+            .with_format(ContainerFormat::Zlib)
+            .with_early_abort(EarlyAbort::default());
+        let expected = compress_to_vec(&data, &config);
+        let mut compressor = TrickleCompressor::new(config);
+        let mut out = [0u8; 64];
+        // Abandon a stream halfway through, with output still queued.
+        compressor
+            .compress_trickle(&data[..3000], &mut out, FlushMode::None)
+            .unwrap();
+        for _ in 0..3 {
+            let memory = compressor.memory_usage();
+            compressor.reset();
+            assert_eq!(compressor.memory_usage(), memory);
+            assert_eq!(compressor.stats(), &CompressionStats::default());
+            assert_eq!(drive(&mut compressor, &data, out.len()), expected);
+        }
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn buffers_come_from_the_given_allocator() {
+        use core::alloc::{AllocError, Layout};
+        use core::cell::Cell;
+        use core::ptr::NonNull;
+
+        #[derive(Default)]
+        struct Counting {
+            live: Cell<usize>,
+        }
+
+        unsafe impl Allocator for &Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.live.set(self.live.get() + layout.size());
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.live.set(self.live.get() - layout.size());
+                // SAFETY: `ptr` was allocated by `Global` with `layout`.
+                unsafe { Global.deallocate(ptr, layout) }
+            }
+        }
+
+        let arena = Counting::default();
+        let data = b"pressure=1013 humidity=40\n".repeat(200);
+        let config = CompressionConfig::default();
+        let mut compressor = TrickleCompressor::new_in(config.clone(), &arena);
+        let out = drive(&mut compressor, &data, data.len());
+        assert_eq!(out, compress_to_vec(&data, &config));
+        let heap = compressor.memory_usage() - core::mem::size_of_val(&compressor);
+        assert_eq!(arena.live.get(), heap);
+        drop(compressor);
+        assert_eq!(arena.live.get(), 0);
+    }
+
+    #[test]
+    fn recent_ratio_follows_content_changes() {
+        let mut x = 7u32;
+        let noise: Vec<u8> = (0..32_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        let text = b"INFO link up rssi=-61 retries=0\n".repeat(1000);
+        // A small window keeps little input waiting in the lookahead.
+        let config = CompressionConfig::default()
+            .with_window_size(4096)
+            .with_ratio_window(8192);
+        let mut compressor = TrickleCompressor::new(config);
+        let mut out = vec![0u8; 64 * 1024];
+        let mut feed = |compressor: &mut TrickleCompressor, data: &[u8]| {
+            let mut consumed = 0;
+            while consumed < data.len() {
+                consumed += compressor
+                    .compress_trickle(&data[consumed..], &mut out, FlushMode::None)
+                    .unwrap()
+                    .consumed;
+            }
+        };
+        feed(&mut compressor, &text);
+        assert!(compressor.recent_ratio() < 0.1);
+        feed(&mut compressor, &noise);
+        assert!(compressor.recent_ratio() > 0.95);
+        assert!(compressor.stats().ratio() < 0.6);
+    }
+
+    #[test]
+    fn restart_points_survive_corruption() {
+        let log: Vec<u8> = (0..3000u32)
+            .flat_map(|i| alloc::format!("{i} sensor=ok temp=21.{}\n", i % 7).into_bytes())
+            .collect();
+        let config = CompressionConfig::default()
+            .with_format(ContainerFormat::Zlib)
+            .with_restart_interval(8192);
+        let mut compressor = TrickleCompressor::new(config.clone());
+        let mut out = drive(&mut compressor, &log, log.len());
+        assert_eq!(compressor.stats().restarts as usize, (log.len() - 1) / 8192);
+        assert_eq!(
+            crate::decompress_to_vec(&out, ContainerFormat::Zlib).unwrap(),
+            log
+        );
+        // Same output whether fed at once or in pieces.
+        assert_eq!(compress_to_vec(&log, &config), out);
+
+        out[40] ^= 0x55;
+        assert!(crate::decompress_to_vec(&out, ContainerFormat::Zlib) != Ok(log.clone()));
+        let mut from = 41;
+        let tail = loop {
+            let at = crate::find_restart_point(&out, from).unwrap();
+            match crate::decompress_to_vec(&out[at..], ContainerFormat::Raw) {
+                Ok(tail) if log.ends_with(&tail) => break tail,
+                _ => from = at,
+            }
+        };
+        assert_eq!(log.len() - tail.len(), 8192);
+    }
+
+    #[test]
+    fn stats_account_calls_and_work() {
+        let data = b"t=1 ok\n".repeat(1000);
+        let config = CompressionConfig {
+            trickle_size: 100,
+            ..CompressionConfig::default()
+        };
+        let mut compressor = TrickleCompressor::new(config);
+        let mut out = vec![0u8; data.len()];
+        let (mut consumed, mut calls) = (0, 0);
+        while !compressor.is_finished() {
+            let r = compressor
+                .compress_trickle(&data[consumed..], &mut out, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            calls += 1;
+        }
+        let stats = compressor.stats();
+        assert_eq!(stats.trickle_calls, calls);
+        assert_eq!(stats.work_units, data.len() as u64);
+        // Every call but the last runs out of budget: the whole input fits
+        // in the window on the first one.
+        assert_eq!(stats.budget_suspensions, calls - 1);
+    }
+
+    #[test]
+    fn steps_never_exceed_their_budget() {
+        assert_eq!(WorkBudget::positions(0), WorkBudget::MIN);
+        // Long runs of matches, so most steps end at one that does not fit.
+        let mut data = b"pump-3 ok ".repeat(300);
+        data.extend((0..2000u32).map(|i| (i * 7 % 251) as u8));
+        data.extend(b"pump-3 ok ".repeat(300));
+        for budget in [0, 300, 1000, 5000] {
+            let budget = WorkBudget::positions(budget);
+            let config = CompressionConfig::new(CompressionLevel::BEST);
+            let mut compressor = TrickleCompressor::new(config);
+            let mut out = vec![0u8; data.len()];
+            let (mut consumed, mut written) = (0, 0);
+            while !compressor.is_finished() {
+                let before = compressor.stats().work_units;
+                let r = compressor
+                    .compress_step(
+                        &data[consumed..],
+                        &mut out[written..],
+                        FlushMode::Finish,
+                        budget,
+                    )
+                    .unwrap();
+                let work = compressor.stats().work_units - before;
+                assert!(work <= budget.get() as u64, "{work} > {budget:?}");
+                consumed += r.consumed;
+                written += r.written;
+            }
+            assert_eq!(compressor.stats().work_units, data.len() as u64);
+            assert_eq!(
+                crate::decompress_to_vec(&out[..written], ContainerFormat::Raw).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn block_hook_sees_every_block() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        let data = b"seq=42 lat=51.2 lon=-0.1\n".repeat(2000);
+        let config = CompressionConfig::default().with_window_size(4096);
+        let blocks = Arc::new(AtomicU64::new(0));
+        let input = Arc::new(AtomicU64::new(0));
+        let end = Arc::new(AtomicU64::new(0));
+        let mut compressor = TrickleCompressor::new(config);
+        compressor.on_block_complete({
+            let (blocks, input, end) = (blocks.clone(), input.clone(), end.clone());
+            move |info| {
+                blocks.fetch_add(1, Ordering::Relaxed);
+                input.fetch_add(info.input_bytes as u64, Ordering::Relaxed);
+                assert!(info.stream_bits >= end.swap(info.stream_bits, Ordering::Relaxed));
+            }
+        });
+        let written = drive(&mut compressor, &data, data.len()).len();
+        assert!(compressor.stats().blocks() > 1);
+        assert_eq!(
+            blocks.load(Ordering::Relaxed),
+            compressor.stats().blocks() as u64
+        );
+        assert_eq!(input.load(Ordering::Relaxed), data.len() as u64);
+        let end = end.load(Ordering::Relaxed);
+        assert_eq!(end.div_ceil(8), written as u64);
+    }
+
+    #[test]
+    fn stats_sink_gets_periodic_and_final_stats() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+        let data = b"seq=42 lat=51.2 lon=-0.1\n".repeat(4000);
+        let config = CompressionConfig::default().with_window_size(4096);
+        let publications = Arc::new(AtomicU32::new(0));
+        let last_blocks = Arc::new(AtomicU32::new(0));
+        let last_out = Arc::new(AtomicU64::new(0));
+        let mut compressor = TrickleCompressor::new(config);
+        compressor.set_stats_sink(
+            {
+                let (publications, last_blocks, last_out) =
+                    (publications.clone(), last_blocks.clone(), last_out.clone());
+                move |stats: &CompressionStats| {
+                    publications.fetch_add(1, Ordering::Relaxed);
+                    last_blocks.store(stats.blocks(), Ordering::Relaxed);
+                    last_out.store(stats.bytes_out, Ordering::Relaxed);
+                }
+            },
+            3,
+        );
+        let written = drive(&mut compressor, &data, data.len()).len();
+        let blocks = compressor.stats().blocks();
+        assert!(blocks >= 9, "{blocks}");
+        let publications = publications.load(Ordering::Relaxed);
+        assert!(publications >= blocks / 3 && publications <= blocks / 3 + 1);
+        // The last publication is the final one.
+        assert_eq!(last_blocks.load(Ordering::Relaxed), blocks);
+        assert_eq!(last_out.load(Ordering::Relaxed), written as u64);
+    }
+
+    #[test]
+    fn progress_tracks_expected_size() {
+        let data = b"chunk of firmware image \x00\x01\x02\x03".repeat(1000);
+        let config = CompressionConfig::default().with_window_size(4096);
+        let mut compressor = TrickleCompressor::new(config);
+        assert_eq!(compressor.progress(), 0);
+        compressor.set_expected_input_size(data.len() as u64);
+        let mut out = vec![0u8; data.len()];
+        let (mut consumed, mut last) = (0, 0);
+        while !compressor.is_finished() {
+            let r = compressor
+                .compress_trickle(&data[consumed..], &mut out, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            let progress = compressor.progress();
+            assert!(progress >= last);
+            assert!(progress < 100 || compressor.is_finished());
+            last = progress;
+        }
+        assert_eq!(compressor.progress(), 100);
+        compressor.reset();
+        assert_eq!(compressor.progress(), 0);
+    }
+
+    #[test]
+    fn compress_to_fit_stops_at_the_limit() {
+        let data = b"node=3 v=3.31 i=0.120 t=25.0\n".repeat(100);
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let full = compress_to_vec(&data, &config);
+        assert_eq!(
+            compress_to_fit(&data, full.len(), &config),
+            Ok(full.clone())
+        );
+        let err = compress_to_fit(&data, full.len() - 1, &config).unwrap_err();
+        assert!(err.processed <= data.len() as u64);
+
+        let mut x = 7u32;
+        let noise: Vec<u8> = (0..40_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        // Incompressible input is abandoned long before its end.
+        let err = compress_to_fit(&noise, 200, &config).unwrap_err();
+        assert!(err.processed < noise.len() as u64 / 2, "{}", err.processed);
+    }
+
+    #[test]
+    fn estimate_is_close_to_real_size() {
+        let mut data = Vec::new();
+        for i in 0..2000u32 {
+            let line = alloc::format!("id={} temp={} ok\n", i, 20 + i % 9);
+            data.extend_from_slice(line.as_bytes());
+        }
+        for format in [ContainerFormat::Raw, ContainerFormat::Gzip] {
+            for strategy in [Strategy::Default, Strategy::Fixed, Strategy::HuffmanOnly] {
+                let config = CompressionConfig::default()
+                    .with_format(format)
+                    .with_strategy(strategy);
+                let real = compress_to_vec(&data, &config).len();
+                let estimate = estimate_compressed_size(&data, &config);
+                assert!(real.abs_diff(estimate) <= 8, "{real} vs {estimate}");
+            }
+        }
+        assert_eq!(
+            estimate_compressed_size(&[], &CompressionConfig::default()),
+            2
+        );
+    }
+
+    #[test]
+    fn bound_holds_for_short_blocks_and_empty_input() {
+        let data: Vec<u8> = (0..20_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        for format in [
+            ContainerFormat::Raw,
+            ContainerFormat::Zlib,
+            ContainerFormat::Gzip,
+        ] {
+            for config in [
+                CompressionConfig::tiny_window(256),
+                CompressionConfig::default().with_block_size(1),
+            ] {
+                let config = config.with_format(format);
+                for input in [&data[..], &[]] {
+                    let real = compress_to_vec(input, &config).len();
+                    let bound = compress_bound(input.len(), &config);
+                    assert!(real <= bound, "{real} > {bound}");
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "tiny")]
+    #[test]
+    fn tiny_never_emits_dynamic_blocks() {
+        let data = b"the quick brown fox jumps over the lazy dog. ".repeat(200);
+        let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+        let out = drive(&mut compressor, &data, data.len());
+        assert_eq!(compressor.stats().dynamic_blocks, 0);
+        assert!(out.len() < data.len() / 4);
+    }
+}
@@ -0,0 +1,625 @@
+//! Compression and decompression settings.
+
+use crate::compressor::TrickleCompressor;
+use crate::deflate::LEVELS;
+use crate::error::TrickleError;
+use crate::huffman::MAX_DISTANCE;
+
+/// Compression level from 0 (fastest) to 9 (best ratio). Level 0 writes
+/// stored blocks only, copying the input through with 5 bytes of framing
+/// per block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CompressionLevel(u8);
+
+impl CompressionLevel {
+    pub const NONE: Self = Self(0);
+    pub const FAST: Self = Self(1);
+    pub const DEFAULT: Self = Self(6);
+    pub const BEST: Self = Self(9);
+
+    /// Creates a level, clamping values above 9.
+    pub const fn new(level: u8) -> Self {
+        if level > 9 {
+            Self(9)
+        } else {
+            Self(level)
+        }
+    }
+
+    pub const fn get(self) -> u8 {
+        self.0
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Framing written around the raw DEFLATE stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContainerFormat {
+    /// Bare RFC1951 DEFLATE data.
+    #[default]
+    Raw,
+    /// RFC1950 zlib header and Adler-32 trailer.
+    Zlib,
+    /// RFC1952 gzip header and CRC-32/ISIZE trailer.
+    Gzip,
+    /// For decompression, any of the above, told apart by the first two
+    /// bytes: see [`detect`](Self::detect). Compression writes zlib, the
+    /// format other tools detect most reliably.
+    Auto,
+}
+
+impl ContainerFormat {
+    /// Format of a stream starting with `header`, or `None` with fewer
+    /// than two bytes to go by. The gzip magic 0x1F 0x8B and a zlib CMF/FLG
+    /// pair with a valid check value pick those containers; anything else
+    /// is taken as raw DEFLATE, which can only start like either by using
+    /// a reserved block type or nonzero padding bits.
+    pub fn detect(header: &[u8]) -> Option<Self> {
+        let &[cmf, flg, ..] = header else {
+            return None;
+        };
+        Some(if cmf == 0x1F && flg == 0x8B {
+            Self::Gzip
+        } else if cmf & 0x0F == 8
+            && cmf >> 4 <= 7
+            && (cmf as u16 * 256 + flg as u16).is_multiple_of(31)
+        {
+            Self::Zlib
+        } else {
+            Self::Raw
+        })
+    }
+}
+
+/// Match finding and block selection strategy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strategy {
+    /// Regular LZ77 + Huffman.
+    #[default]
+    Default,
+    /// Prefer literals over short matches, for filtered image data.
+    Filtered,
+    /// No match finding, Huffman coding of literals only.
+    HuffmanOnly,
+    /// Only look for runs (distance 1).
+    Rle,
+    /// Never emit dynamic Huffman blocks.
+    Fixed,
+}
+
+/// Gives up on compressing a stream that turns out to be incompressible.
+///
+/// Once `after_bytes` of input have been encoded and the output is larger
+/// than `max_ratio_percent` of it, the rest of the stream is written as
+/// stored blocks without any match finding or Huffman coding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EarlyAbort {
+    pub after_bytes: u64,
+    pub max_ratio_percent: u8,
+}
+
+impl Default for EarlyAbort {
+    /// Abort when, after 4KB of input, the output is still above 95% of it.
+    fn default() -> Self {
+        Self {
+            after_bytes: 4096,
+            max_ratio_percent: 95,
+        }
+    }
+}
+
+/// Sizes of a compressor's internal buffers, applied with
+/// [`CompressionConfig::with_buffer_sizes`].
+///
+/// Larger token buffers give Huffman codes more to adapt to but hold output
+/// back longer, and a larger hash table finds more matches at a cost of two
+/// bytes per head. The presets fit a [`window_size`] of 4KB to 32KB.
+///
+/// [`window_size`]: CompressionConfig::window_size
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferSizes {
+    /// LZ77 tokens buffered before a block is emitted, see
+    /// [`CompressionConfig::block_size`].
+    pub block_size: usize,
+    /// log2 of the number of hash chain heads, see
+    /// [`CompressionConfig::hash_bits`].
+    pub hash_bits: u8,
+    /// Bytes reserved for output waiting to be drained, see
+    /// [`CompressionConfig::output_buffer_size`].
+    pub output_buffer_size: u32,
+}
+
+impl BufferSizes {
+    /// Small blocks that reach the output soon after their input, and a
+    /// 2KB hash table.
+    pub const LOW_LATENCY: Self = Self {
+        block_size: 256,
+        hash_bits: 10,
+        output_buffer_size: 512,
+    };
+    /// The [`CompressionConfig::default`] sizes.
+    pub const BALANCED: Self = Self {
+        block_size: 4096,
+        hash_bits: 15,
+        output_buffer_size: 0,
+    };
+    /// Long blocks and the largest hash table (128KB), for ratio over RAM
+    /// and latency.
+    pub const HIGH_RATIO: Self = Self {
+        block_size: 16384,
+        hash_bits: 16,
+        output_buffer_size: 16384,
+    };
+}
+
+/// Bounds on what a [`TrickleDecompressor`](crate::TrickleDecompressor)
+/// spends on one stream, applied with
+/// [`TrickleDecompressor::with_limits`](crate::TrickleDecompressor::with_limits)
+/// or as part of a [`DecompressorConfig`], for devices decoding input they
+/// cannot trust.
+///
+/// A stream that would go beyond a limit fails with
+/// [`LimitExceeded`](TrickleError::LimitExceeded), naming the field, except
+/// that matches reaching beyond `max_window` fail with
+/// [`WindowTooLarge`](TrickleError::WindowTooLarge) as they do for any
+/// window. The defaults only impose DEFLATE's own bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Steps parsing the code lengths of one dynamic Huffman block header:
+    /// one per code length code length and one per code length or run of
+    /// them. Valid headers take at most 335.
+    pub max_table_iterations: usize,
+    /// Bytes of the zlib or gzip header, including a gzip extra field,
+    /// file name and comment.
+    pub max_header_size: usize,
+    /// DEFLATE blocks in the stream.
+    pub max_blocks: u32,
+    /// History window in bytes, rounded up to a power of two from 256.
+    pub max_window: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_table_iterations: usize::MAX,
+            max_header_size: usize::MAX,
+            max_blocks: u32::MAX,
+            max_window: 32768,
+        }
+    }
+}
+
+/// Configuration for a [`TrickleDecompressor`](crate::TrickleDecompressor),
+/// applied with
+/// [`TrickleDecompressor::with_config`](crate::TrickleDecompressor::with_config).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecompressorConfig {
+    /// Framing of the input. [`Auto`](ContainerFormat::Auto), the default,
+    /// accepts all three.
+    pub format: ContainerFormat,
+    pub limits: DecodeLimits,
+}
+
+impl Default for DecompressorConfig {
+    fn default() -> Self {
+        Self {
+            format: ContainerFormat::Auto,
+            limits: DecodeLimits::default(),
+        }
+    }
+}
+
+impl DecompressorConfig {
+    pub fn with_format(mut self, format: ContainerFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+/// Configuration for a [`TrickleCompressor`](crate::TrickleCompressor).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// Set through [`new`](Self::new), which tunes the match finder for it.
+    /// On its own it only makes level 0 write stored blocks and is recorded
+    /// in zlib and gzip headers.
+    pub level: CompressionLevel,
+    pub format: ContainerFormat,
+    pub strategy: Strategy,
+    /// LZ77 window in bytes. Should be a power of two up to 32768, or
+    /// 65536 for raw streams with the `extended-window` feature.
+    pub window_size: usize,
+    /// log2 of the number of hash chain heads.
+    pub hash_bits: u8,
+    /// Maximum number of chain entries visited per match search.
+    pub max_chain_length: u16,
+    /// Matches shorter than this are only taken if the next position does
+    /// not start a longer one, as in zlib's lazy evaluation; 0 parses
+    /// greedily. Applies to the `Default` and `Filtered` strategies.
+    pub max_lazy_match: u16,
+    /// Stop searching once a match of this length is found.
+    pub nice_length: u16,
+    /// Number of LZ77 tokens buffered before a block is emitted.
+    pub block_size: usize,
+    /// Bytes reserved up front for encoded output waiting to be drained,
+    /// or 0 to grow the buffer as needed. It still grows when a block
+    /// needs more; about `block_size` bytes cover most blocks.
+    pub output_buffer_size: u32,
+    /// Input bytes processed per `compress_trickle` call.
+    pub trickle_size: usize,
+    /// Switch to stored blocks when compression is not paying off.
+    pub early_abort: Option<EarlyAbort>,
+    /// Input bytes [`recent_ratio`](crate::TrickleCompressor::recent_ratio)
+    /// averages over; older content fades out exponentially.
+    pub ratio_window: u32,
+    /// Operating system byte of the gzip header: 255 (unknown) by default,
+    /// 3 for Unix as written by GNU gzip, 0 for FAT/Windows.
+    pub gzip_os: u8,
+    /// Sets the gzip FTEXT flag, declaring the data probably ASCII text.
+    pub gzip_text: bool,
+    /// Input bytes between restart points, or 0 for none. At each one the
+    /// history is dropped and the output is fully flushed, so decoding can
+    /// start there; see [`find_restart_point`](crate::find_restart_point).
+    /// Costs a few bytes per restart plus the matches lost across it.
+    pub restart_interval: u32,
+}
+
+impl CompressionConfig {
+    /// Default configuration with the match finder tuned for `level`:
+    /// `max_chain_length`, `max_lazy_match` and `nice_length` grow from
+    /// greedy parsing over 4 chain entries at [`CompressionLevel::FAST`] to
+    /// lazy parsing over 4096 at [`CompressionLevel::BEST`].
+    pub fn new(level: CompressionLevel) -> Self {
+        let tuning = LEVELS[level.get() as usize];
+        Self {
+            level,
+            max_chain_length: tuning.max_chain_length,
+            max_lazy_match: tuning.max_lazy_match,
+            nice_length: tuning.nice_length,
+            ..Self::default()
+        }
+    }
+
+    /// Preset for heatshrink-class devices with a 256 to 2048 byte window
+    /// (`window_size` is rounded to a power of two in that range).
+    ///
+    /// Only fixed Huffman blocks are used, the match finder looks at a
+    /// single hash candidate and buffers are kept to a few dozen tokens, so
+    /// output remains standard DEFLATE that any inflater can read. Measured
+    /// [`memory_usage`](crate::TrickleCompressor::memory_usage) on a 64-bit
+    /// host (32-bit targets need less), and the most incompressible input
+    /// can grow, as every block of 16 or 32 tokens may cost 5.25 bytes more
+    /// than storing its bytes (see [`compress_bound`](crate::compress_bound)):
+    ///
+    /// | window | total state | worst-case growth |
+    /// |--------|-------------|-------------------|
+    /// | 256    | < 1KB       | 33%               |
+    /// | 512    | ~1.4KB      | 33%               |
+    /// | 1024   | ~2.4KB      | 17%               |
+    /// | 2048   | ~3.6KB      | 17%               |
+    pub fn tiny_window(window_size: usize) -> Self {
+        let window_size = window_size.clamp(256, 2048).next_power_of_two();
+        let (hash_bits, block_size) = match window_size {
+            256 => (6, 16),
+            512 => (7, 16),
+            _ => (8, 32),
+        };
+        Self {
+            level: CompressionLevel::FAST,
+            strategy: Strategy::Fixed,
+            window_size,
+            hash_bits,
+            max_chain_length: 1,
+            max_lazy_match: 0,
+            nice_length: 258,
+            block_size,
+            trickle_size: 32,
+            ..Self::default()
+        }
+    }
+
+    /// Preset behind [`compress_packet`](crate::compress_packet), for
+    /// payloads under about 100 bytes: raw DEFLATE, a single fixed Huffman
+    /// block, so framing costs 10 bits plus padding, and a 256-byte window,
+    /// which covers the whole packet.
+    pub fn small_packet() -> Self {
+        Self {
+            max_chain_length: 4,
+            block_size: 128,
+            trickle_size: 128,
+            ..Self::tiny_window(256)
+        }
+    }
+
+    /// Known-good starting point for parts with around 8KB of RAM to spare,
+    /// such as Cortex-M0+ devices.
+    ///
+    /// Uses a 1KB window, 512 hash heads probed once each and the
+    /// [`Filtered`](Strategy::Filtered) strategy, which turns short matches
+    /// into literals and lets dynamic blocks code them cheaply. Total
+    /// [`memory_usage`](crate::TrickleCompressor::memory_usage) stays below
+    /// 8KB on a 64-bit host (about 6.5KB measured). Blocks of 128 tokens let
+    /// incompressible input grow by up to 5%.
+    pub fn low_memory() -> Self {
+        Self {
+            level: CompressionLevel::FAST,
+            strategy: Strategy::Filtered,
+            window_size: 1024,
+            hash_bits: 9,
+            max_chain_length: 1,
+            max_lazy_match: 0,
+            nice_length: 128,
+            block_size: 128,
+            trickle_size: 64,
+            ..Self::default()
+        }
+    }
+
+    /// Preset for human-readable text such as logs, configuration files or
+    /// documentation. Uses an 8KB window with moderate hash chains, which
+    /// reach the repeated words and phrases typical of prose.
+    pub fn text() -> Self {
+        Self {
+            level: CompressionLevel::DEFAULT,
+            strategy: Strategy::Default,
+            window_size: 8192,
+            hash_bits: 13,
+            max_chain_length: 32,
+            nice_length: 128,
+            block_size: 4096,
+            ..Self::default()
+        }
+    }
+
+    /// Preset for packed binary sensor records. Repeats in such data are
+    /// short and close together, so a 4KB window and short chains are
+    /// enough to find them.
+    pub fn telemetry() -> Self {
+        Self {
+            level: CompressionLevel::FAST,
+            strategy: Strategy::Default,
+            window_size: 4096,
+            hash_bits: 11,
+            max_chain_length: 16,
+            nice_length: 32,
+            block_size: 1024,
+            ..Self::default()
+        }
+    }
+
+    /// Preset for compiled firmware images, typically packed once on a host
+    /// for OTA delivery. Uses the full 32KB window and the best level, so
+    /// the inflating device needs a 32KB window too.
+    pub fn firmware_image() -> Self {
+        Self {
+            level: CompressionLevel::BEST,
+            strategy: Strategy::Default,
+            window_size: 32768,
+            hash_bits: 15,
+            max_chain_length: 64,
+            nice_length: 258,
+            block_size: 4096,
+            ..Self::default()
+        }
+    }
+
+    /// Preset for PNG image data, for [`PngEncoder`](crate::PngEncoder):
+    /// zlib container with the full 32KB window, as PNG decoders expect,
+    /// and the [`Filtered`](Strategy::Filtered) strategy, since row filters
+    /// leave small residuals that are cheaper as literals than as short
+    /// matches.
+    pub fn png() -> Self {
+        Self {
+            format: ContainerFormat::Zlib,
+            level: CompressionLevel::DEFAULT,
+            strategy: Strategy::Filtered,
+            window_size: 32768,
+            hash_bits: 15,
+            max_chain_length: 32,
+            nice_length: 128,
+            block_size: 4096,
+            ..Self::default()
+        }
+    }
+
+    /// Preset for line-delimited JSON logs. Keys and enum-like values repeat
+    /// constantly, so the [`Filtered`](Strategy::Filtered) strategy spends
+    /// matches on long repeats and leaves digits to the literal code.
+    pub fn json_logs() -> Self {
+        Self {
+            level: CompressionLevel::DEFAULT,
+            strategy: Strategy::Filtered,
+            window_size: 8192,
+            hash_bits: 13,
+            max_chain_length: 32,
+            nice_length: 128,
+            block_size: 4096,
+            ..Self::default()
+        }
+    }
+
+    /// The strongest settings whose compressor fits in `bytes` of RAM,
+    /// with the [`memory_usage`](crate::TrickleCompressor::memory_usage) a
+    /// [`TrickleCompressor`](crate::TrickleCompressor) created from them
+    /// has, or `None` if even a 256-byte window does not fit.
+    ///
+    /// The window matters most for the ratio, so the largest one that fits
+    /// is taken, then the most hash heads for it, then hash chains over
+    /// single-probe matching, then dynamic over fixed Huffman blocks. The
+    /// output buffer is reserved up front for the largest block that
+    /// `block_size` tokens can encode to, so the footprint holds for the
+    /// whole stream.
+    ///
+    /// ```
+    /// use tricklezip::{CompressionConfig, TrickleCompressor};
+    ///
+    /// let (config, footprint) = CompressionConfig::for_ram_budget(16 * 1024).unwrap();
+    /// assert!(footprint <= 16 * 1024);
+    /// assert_eq!(TrickleCompressor::new(config).memory_usage(), footprint);
+    /// ```
+    pub fn for_ram_budget(bytes: usize) -> Option<(Self, usize)> {
+        let mut window = 32768usize;
+        while window >= 256 {
+            let window_bits = window.trailing_zeros() as u8;
+            let block_size = (window / 16).clamp(16, 4096);
+            for hash_bits in (window_bits.saturating_sub(3).max(6)..=window_bits.min(15)).rev() {
+                let chained = Self {
+                    window_size: window,
+                    hash_bits,
+                    block_size,
+                    // A fixed-code match takes at most 31 bits, and the
+                    // dynamic or stored encoding is only used when smaller.
+                    output_buffer_size: (block_size * 4 + 32) as u32,
+                    ..Self::default()
+                };
+                let probing = Self {
+                    level: CompressionLevel::FAST,
+                    max_chain_length: 1,
+                    max_lazy_match: 0,
+                    ..chained.clone()
+                };
+                let fixed = probing.clone().with_strategy(Strategy::Fixed);
+                for config in [chained, probing, fixed] {
+                    let footprint = TrickleCompressor::memory_usage_for(&config);
+                    if footprint <= bytes {
+                        return Some((config, footprint));
+                    }
+                }
+            }
+            window /= 2;
+        }
+        None
+    }
+
+    /// Checks that `window_size` is a power of two from 256 to 32768, the
+    /// sizes a zlib header can declare and any inflater can hold, or 65536
+    /// for a raw stream with the `extended-window` feature.
+    pub fn validate(&self) -> Result<(), TrickleError> {
+        let window = self.window_size;
+        if !(256..=self.max_window()).contains(&window) || !window.is_power_of_two() {
+            return Err(TrickleError::InvalidConfig {
+                field: "window_size",
+            });
+        }
+        Ok(())
+    }
+
+    /// Largest window the format allows.
+    fn max_window(&self) -> usize {
+        match self.format {
+            ContainerFormat::Raw => MAX_DISTANCE,
+            _ => 32768,
+        }
+    }
+
+    /// `window_size` as used: clamped to the range the format allows and
+    /// rounded up to a power of two.
+    pub(crate) fn window(&self) -> usize {
+        self.window_size
+            .clamp(256, self.max_window())
+            .next_power_of_two()
+    }
+
+    pub fn with_format(mut self, format: ContainerFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    pub fn with_window_size(mut self, window_size: usize) -> Self {
+        self.window_size = window_size;
+        self
+    }
+
+    pub fn with_hash_bits(mut self, hash_bits: u8) -> Self {
+        self.hash_bits = hash_bits;
+        self
+    }
+
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn with_output_buffer_size(mut self, bytes: u32) -> Self {
+        self.output_buffer_size = bytes;
+        self
+    }
+
+    /// Sets `block_size`, `hash_bits` and `output_buffer_size` at once.
+    pub fn with_buffer_sizes(self, sizes: BufferSizes) -> Self {
+        self.with_block_size(sizes.block_size)
+            .with_hash_bits(sizes.hash_bits)
+            .with_output_buffer_size(sizes.output_buffer_size)
+    }
+
+    /// The buffer sizes currently configured.
+    pub fn buffer_sizes(&self) -> BufferSizes {
+        BufferSizes {
+            block_size: self.block_size,
+            hash_bits: self.hash_bits,
+            output_buffer_size: self.output_buffer_size,
+        }
+    }
+
+    pub fn with_early_abort(mut self, early_abort: EarlyAbort) -> Self {
+        self.early_abort = Some(early_abort);
+        self
+    }
+
+    pub fn with_ratio_window(mut self, ratio_window: u32) -> Self {
+        self.ratio_window = ratio_window;
+        self
+    }
+
+    pub fn with_gzip_os(mut self, os: u8) -> Self {
+        self.gzip_os = os;
+        self
+    }
+
+    pub fn with_gzip_text(mut self, text: bool) -> Self {
+        self.gzip_text = text;
+        self
+    }
+
+    pub fn with_restart_interval(mut self, bytes: u32) -> Self {
+        self.restart_interval = bytes;
+        self
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            level: CompressionLevel::DEFAULT,
+            format: ContainerFormat::Raw,
+            strategy: Strategy::Default,
+            window_size: 32768,
+            hash_bits: 15,
+            max_chain_length: 128,
+            max_lazy_match: 16,
+            nice_length: 128,
+            block_size: 4096,
+            output_buffer_size: 0,
+            trickle_size: 512,
+            early_abort: None,
+            ratio_window: 16 * 1024,
+            gzip_os: 255,
+            gzip_text: false,
+            restart_interval: 0,
+        }
+    }
+}
@@ -0,0 +1,184 @@
+//! CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320) as used by gzip.
+
+const POLY: u32 = 0xEDB8_8320;
+
+#[cfg(not(feature = "crc-bitwise"))]
+static TABLE: [u32; 256] = make_table();
+
+#[cfg(not(feature = "crc-bitwise"))]
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { POLY ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// Continues a CRC-32 over `data`. Start with `crc = 0`.
+#[cfg(not(feature = "crc-bitwise"))]
+pub(crate) fn update(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in data {
+        c = TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+/// Continues a CRC-32 over `data`. Start with `crc = 0`.
+///
+/// Bitwise variant: roughly 8x slower than the table version but needs no
+/// 1KB lookup table in flash.
+#[cfg(feature = "crc-bitwise")]
+pub(crate) fn update(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in data {
+        c ^= b as u32;
+        for _ in 0..8 {
+            c = (c >> 1) ^ (POLY & (c & 1).wrapping_neg());
+        }
+    }
+    !c
+}
+
+/// CRC-32 of `a` followed by `b`, from the CRC-32 of each and the length of
+/// `b`, without the data.
+pub(crate) fn combine(crc_a: u32, crc_b: u32, len_b: u64) -> u32 {
+    // Appending `len_b` zero bytes to `a` multiplies its (pre-conditioned)
+    // CRC by x^(8 * len_b) modulo the polynomial; `b` then adds in.
+    let mut shift = 1 << 31;
+    let mut square = 1 << (31 - 8);
+    let mut n = len_b;
+    while n != 0 {
+        if n & 1 != 0 {
+            shift = mul_mod_poly(square, shift);
+        }
+        square = mul_mod_poly(square, square);
+        n >>= 1;
+    }
+    mul_mod_poly(shift, crc_a) ^ crc_b
+}
+
+/// `a * b` modulo the polynomial, in reflected bit order. `a` must not be 0.
+fn mul_mod_poly(a: u32, mut b: u32) -> u32 {
+    let mut m = 1u32 << 31;
+    let mut p = 0;
+    loop {
+        if a & m != 0 {
+            p ^= b;
+            if a & (m - 1) == 0 {
+                return p;
+            }
+        }
+        m >>= 1;
+        b = if b & 1 != 0 { (b >> 1) ^ POLY } else { b >> 1 };
+    }
+}
+
+/// Source of the CRC-32 that gzip stores in its trailer.
+///
+/// The default [`SoftwareCrc`] runs on the CPU. On MCUs with a CRC
+/// peripheral, implement this trait over the HAL driver and pass it to
+/// [`TrickleCompressor::with_crc_backend`](crate::TrickleCompressor::with_crc_backend)
+/// or [`TrickleDecompressor::with_crc_backend`](crate::TrickleDecompressor::with_crc_backend).
+/// The unit has to produce the reflected gzip variant: polynomial
+/// 0x04C11DB7, initial value 0xFFFFFFFF, bit-reversed input and output and a
+/// final XOR with 0xFFFFFFFF. On STM32 parts this means byte-wise input
+/// reversal, output reversal and inverting the data register on read.
+pub trait CrcBackend {
+    /// Restarts the checksum for a new stream.
+    fn reset(&mut self);
+    /// Feeds `data` into the checksum.
+    fn update(&mut self, data: &[u8]);
+    /// CRC-32 of all data fed since the last reset.
+    fn value(&self) -> u32;
+}
+
+/// CRC-32 computed in software.
+///
+/// Also usable on its own, as [`Crc32`], for framing of your own with the
+/// same code the gzip container uses.
+///
+/// ```
+/// use tricklezip::Crc32;
+///
+/// let mut crc = Crc32::new();
+/// crc.update(b"12345");
+/// let mut tail = Crc32::new();
+/// tail.update(b"6789");
+/// crc.combine(&tail, 4);
+/// assert_eq!(crc.finalize(), 0xCBF4_3926);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct SoftwareCrc {
+    crc: u32,
+}
+
+/// The software CRC-32, under the name used when hashing on its own.
+pub type Crc32 = SoftwareCrc;
+
+impl SoftwareCrc {
+    pub const fn new() -> Self {
+        Self { crc: 0 }
+    }
+
+    /// Feeds `data` into the checksum.
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc = update(self.crc, data);
+    }
+
+    /// CRC-32 of all data fed so far.
+    pub fn finalize(self) -> u32 {
+        self.crc
+    }
+
+    /// Appends the data hashed by `other`, `other_len` bytes long, as if it
+    /// had been fed to `self`: for checksums computed piecewise, e.g. per
+    /// chunk on separate cores or across a restart.
+    pub fn combine(&mut self, other: &Self, other_len: u64) {
+        self.crc = combine(self.crc, other.crc, other_len);
+    }
+}
+
+impl CrcBackend for SoftwareCrc {
+    fn reset(&mut self) {
+        self.crc = 0;
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        SoftwareCrc::update(self, data);
+    }
+
+    fn value(&self) -> u32 {
+        self.crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_value() {
+        assert_eq!(update(0, b"123456789"), 0xCBF4_3926);
+        let split = update(update(0, b"12345"), b"6789");
+        assert_eq!(split, 0xCBF4_3926);
+    }
+
+    #[test]
+    fn combine_matches_contiguous() {
+        let data = b"The quick brown fox jumps over the lazy dog".repeat(40);
+        for cut in [0, 1, 7, 43, 1000, data.len()] {
+            let (a, b) = data.split_at(cut);
+            let joined = combine(update(0, a), update(0, b), b.len() as u64);
+            assert_eq!(joined, update(0, &data), "cut at {cut}");
+        }
+    }
+}
@@ -0,0 +1,32 @@
+//! Cycle counting for the `profiling` feature.
+//!
+//! On bare-metal ARM the DWT cycle counter (CYCCNT) is read directly. It
+//! exists on ARMv7-M and ARMv8-M parts (Cortex-M3 and up) and must be enabled
+//! by the application before compressing, e.g. with the `cortex-m` crate:
+//! `dcb.enable_trace(); dwt.enable_cycle_counter();`. Elsewhere the counter
+//! reads as zero, so the profiling stats stay zero.
+
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+const DWT_CYCCNT: *const u32 = 0xE000_1004 as *const u32;
+
+/// Current cycle count. Wraps every 2^32 cycles; take differences with
+/// [`since`].
+#[cfg(all(target_arch = "arm", target_os = "none"))]
+#[inline(always)]
+pub(crate) fn now() -> u32 {
+    // SAFETY: CYCCNT is a read-only, always-mapped register of the system
+    // control space on every core that has a DWT unit.
+    unsafe { core::ptr::read_volatile(DWT_CYCCNT) }
+}
+
+#[cfg(not(all(target_arch = "arm", target_os = "none")))]
+#[inline(always)]
+pub(crate) fn now() -> u32 {
+    0
+}
+
+/// Cycles elapsed since `start`, as returned by [`now`].
+#[inline(always)]
+pub(crate) fn since(start: u32) -> u64 {
+    now().wrapping_sub(start) as u64
+}
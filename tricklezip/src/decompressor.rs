@@ -0,0 +1,1673 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::task::{Context, Poll};
+
+use crate::adler32;
+use crate::allocator::{Allocator, Global};
+use crate::checksum::Checksum;
+use crate::compressor::{TrickleResult, TrickleStatus, FDICT};
+use crate::config::{ContainerFormat, DecodeLimits, DecompressorConfig};
+use crate::crc32::{CrcBackend, SoftwareCrc};
+use crate::error::TrickleError;
+use crate::huffman::MAX_DISTANCE;
+use crate::inflate::{InflateState, MAX_WINDOW};
+use crate::logging::debug;
+use crate::spsc::QueueConsumer;
+use crate::stats::DecompressProgress;
+use crate::transform::BlockTransform;
+
+/// Callback run when a decompression call makes progress.
+type ProgressHook = Box<dyn FnMut(&DecompressProgress) + Send>;
+
+/// Callback run at every multiple of a number of output bytes.
+struct Watermark {
+    every: u64,
+    /// Output total at which the hook runs next.
+    next: u64,
+    hook: Box<dyn FnMut(u64) + Send>,
+}
+
+/// Output bytes a `poll_decompress` call decodes before yielding.
+const POLL_BUDGET: usize = 4096;
+
+/// Byte-aligned end of the empty stored block of a sync or full flush.
+const FLUSH_MARKER: [u8; 4] = [0, 0, 0xFF, 0xFF];
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Reading the fixed part of the container header.
+    Header {
+        read: usize,
+    },
+    GzipExtraLen {
+        read: usize,
+    },
+    GzipExtra {
+        remaining: usize,
+    },
+    GzipName,
+    GzipComment,
+    GzipHeaderCrc {
+        read: usize,
+    },
+    /// Reading the DICTID after a zlib header with FDICT set.
+    ZlibDictId {
+        read: usize,
+    },
+    /// Waiting for the preset dictionary with Adler-32 `id`.
+    Dictionary {
+        id: u32,
+    },
+    Body,
+    /// Looking for the next full-flush marker after corrupt data, with
+    /// `matched` bytes of it seen.
+    Resync {
+        matched: usize,
+    },
+    Trailer {
+        read: usize,
+    },
+    Done,
+}
+
+/// Incremental decompressor for raw DEFLATE, zlib and gzip streams.
+///
+/// Gzip CRC-32s, both of the header and of the data, are computed by `C`;
+/// see [`CrcBackend`]. Raw streams created with
+/// [`with_checksum`](TrickleDecompressor::with_checksum) run any
+/// [`Checksum`] instead.
+///
+/// The 32KB window is allocated in `A`; see `new_in`. Callbacks and
+/// transforms are boxed with the global allocator.
+pub struct TrickleDecompressor<C: Checksum = SoftwareCrc, A: Allocator = Global> {
+    format: ContainerFormat,
+    /// Created with `ContainerFormat::Auto`: `format` is detected again
+    /// after a reset.
+    detect: bool,
+    state: InflateState<A>,
+    phase: Phase,
+    /// Fixed header, gzip FEXTRA length or trailer bytes collected so far.
+    buf: [u8; 10],
+    flags: u8,
+    /// Container header bytes read so far, up to `max_header_size`.
+    header_read: usize,
+    max_header_size: usize,
+    /// Adler-32 of the dictionary passed to `set_dictionary`.
+    dict_id: Option<u32>,
+    adler: u32,
+    /// Covers the gzip header until the body starts, then the data.
+    crc: C,
+    /// `crc` runs over a raw stream, see `with_checksum`.
+    raw_checksum: bool,
+    total_out: u64,
+    /// Skip corrupt data instead of failing, see `set_recovery`.
+    recovery: bool,
+    resyncs: u32,
+    on_progress: Option<ProgressHook>,
+    watermark: Option<Watermark>,
+    transform: Option<Box<dyn BlockTransform + Send>>,
+    /// Leading bytes of the next `decompress_trickle_mut` input that went
+    /// through `transform` already: the ones left unconsumed last time.
+    transformed: usize,
+    /// The last `poll_decompress` call used up its budget, so the next one
+    /// yields to the executor first.
+    yield_next: bool,
+}
+
+impl TrickleDecompressor {
+    pub fn new(format: ContainerFormat) -> Self {
+        Self::with_crc_backend(format, SoftwareCrc::new())
+    }
+
+    /// Creates a decompressor with a `window_size` byte window instead of
+    /// the full 32KB, rounded up to a power of two from 256. zlib streams
+    /// declaring a larger window in their header, and streams of any format
+    /// whose matches reach further back, fail with
+    /// [`WindowTooLarge`](TrickleError::WindowTooLarge).
+    ///
+    /// With the `extended-window` feature, raw streams may have a 64KB
+    /// window, to decode the non-standard streams a compressor with a
+    /// `window_size` of 65536 writes.
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, CompressionConfig, ContainerFormat, TrickleDecompressor, TrickleError};
+    ///
+    /// let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+    /// let packed = compress_to_vec(b"status=ok", &config);
+    /// let mut d = TrickleDecompressor::with_window_size(ContainerFormat::Zlib, 4096);
+    /// let err = d.decompress_trickle(&packed, &mut [0u8; 16]).unwrap_err();
+    /// assert_eq!(err, TrickleError::WindowTooLarge);
+    /// ```
+    pub fn with_window_size(format: ContainerFormat, window_size: usize) -> Self {
+        let max = match format {
+            ContainerFormat::Raw => MAX_DISTANCE,
+            _ => MAX_WINDOW,
+        };
+        let window_size = window_size.clamp(256, max).next_power_of_two();
+        Self::with_any_checksum_in(format, SoftwareCrc::new(), window_size, Global)
+    }
+
+    /// Creates a decompressor that holds streams to `limits`, with a window
+    /// of `limits.max_window` bytes sized as by
+    /// [`with_window_size`](Self::with_window_size).
+    ///
+    /// ```
+    /// use tricklezip::{ContainerFormat, DecodeLimits, TrickleDecompressor, TrickleError};
+    ///
+    /// let limits = DecodeLimits {
+    ///     max_header_size: 64,
+    ///     ..DecodeLimits::default()
+    /// };
+    /// let mut d = TrickleDecompressor::with_limits(ContainerFormat::Gzip, limits);
+    /// let mut header = vec![0x1F, 0x8B, 8, 0x08, 0, 0, 0, 0, 0, 255];
+    /// header.extend_from_slice(&[b'a'; 100]); // a file name without end
+    /// let err = d.decompress_trickle(&header, &mut [0u8; 16]).unwrap_err();
+    /// assert_eq!(err, TrickleError::LimitExceeded { limit: "max_header_size" });
+    /// ```
+    pub fn with_limits(format: ContainerFormat, limits: DecodeLimits) -> Self {
+        Self::with_config(DecompressorConfig { format, limits })
+    }
+
+    /// Creates a decompressor for `config.format`, held to `config.limits`
+    /// as by [`with_limits`](Self::with_limits).
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, CompressionConfig, ContainerFormat, DecompressorConfig, TrickleDecompressor};
+    ///
+    /// let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+    /// let packed = compress_to_vec(b"status=ok", &config);
+    /// let mut d = TrickleDecompressor::with_config(DecompressorConfig::default());
+    /// let mut out = [0u8; 16];
+    /// let r = d.decompress_trickle(&packed, &mut out).unwrap();
+    /// assert_eq!(&out[..r.written], b"status=ok");
+    /// assert_eq!(d.format(), ContainerFormat::Gzip);
+    /// ```
+    pub fn with_config(config: DecompressorConfig) -> Self {
+        let limits = config.limits;
+        let mut d = Self::with_window_size(config.format, limits.max_window);
+        d.state.set_limits(&limits);
+        d.max_header_size = limits.max_header_size;
+        d
+    }
+}
+
+impl<C: CrcBackend> TrickleDecompressor<C> {
+    /// Creates a decompressor that computes gzip CRC-32s with `crc`.
+    pub fn with_crc_backend(format: ContainerFormat, crc: C) -> Self {
+        Self::with_any_checksum(format, crc)
+    }
+}
+
+impl<C: Checksum> TrickleDecompressor<C> {
+    /// Creates a raw DEFLATE decompressor that keeps `checksum` up to date
+    /// over the output, to check against a framing of your own; see
+    /// [`TrickleCompressor::with_checksum`](crate::TrickleCompressor::with_checksum).
+    pub fn with_checksum(checksum: C) -> Self {
+        Self {
+            raw_checksum: true,
+            ..Self::with_any_checksum(ContainerFormat::Raw, checksum)
+        }
+    }
+
+    fn with_any_checksum(format: ContainerFormat, crc: C) -> Self {
+        Self::with_any_checksum_in(format, crc, MAX_WINDOW, Global)
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<A: Allocator> TrickleDecompressor<SoftwareCrc, A> {
+    /// Creates a decompressor whose window is allocated in `alloc`.
+    pub fn new_in(format: ContainerFormat, alloc: A) -> Self {
+        Self::with_any_checksum_in(format, SoftwareCrc::new(), MAX_WINDOW, alloc)
+    }
+}
+
+impl<C: Checksum, A: Allocator> TrickleDecompressor<C, A> {
+    fn with_any_checksum_in(
+        format: ContainerFormat,
+        mut crc: C,
+        window_size: usize,
+        alloc: A,
+    ) -> Self {
+        crc.reset();
+        Self {
+            format,
+            detect: format == ContainerFormat::Auto,
+            state: InflateState::new_in(window_size, alloc),
+            phase: Phase::Header { read: 0 },
+            buf: [0; 10],
+            flags: 0,
+            header_read: 0,
+            max_header_size: usize::MAX,
+            dict_id: None,
+            adler: 1,
+            crc,
+            raw_checksum: false,
+            total_out: 0,
+            recovery: false,
+            resyncs: 0,
+            on_progress: None,
+            watermark: None,
+            transform: None,
+            transformed: 0,
+            yield_next: false,
+        }
+    }
+
+    /// Format of the stream. For [`Auto`](ContainerFormat::Auto), the one
+    /// detected once the first two bytes are in, and `Auto` until then.
+    pub fn format(&self) -> ContainerFormat {
+        self.format
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.phase == Phase::Done
+    }
+
+    /// Size of the history window in bytes.
+    pub fn window_size(&self) -> usize {
+        self.state.window_size()
+    }
+
+    /// Checksum of the output so far, as the container trailer carries it:
+    /// Adler-32 for zlib, CRC-32 for gzip and `None` for raw DEFLATE, unless
+    /// created with [`with_checksum`](Self::with_checksum). `None` too while
+    /// [`Auto`](ContainerFormat::Auto) has yet to see the header.
+    pub fn checksum(&self) -> Option<u32> {
+        match self.format {
+            ContainerFormat::Raw => self.raw_checksum.then(|| self.crc.value()),
+            ContainerFormat::Auto => None,
+            ContainerFormat::Zlib => Some(self.adler),
+            // The CRC covers the header until the body starts.
+            ContainerFormat::Gzip if self.in_header() => Some(0),
+            ContainerFormat::Gzip => Some(self.crc.value()),
+        }
+    }
+
+    /// Decompressed bytes produced so far.
+    pub fn total_out(&self) -> u64 {
+        self.total_out
+    }
+
+    /// DEFLATE blocks decoded completely so far.
+    pub fn blocks_completed(&self) -> u32 {
+        self.state.blocks
+    }
+
+    /// Decoded bytes that the next call hands out before it needs any more
+    /// input: the rest of a match the last output buffer filled up in.
+    pub fn pending_output_len(&self) -> usize {
+        self.state.pending_copy()
+    }
+
+    /// Whether the next call has output to give even without new input, so
+    /// it has to be made with more output space before feeding more.
+    pub fn has_pending_output(&self) -> bool {
+        self.pending_output_len() > 0
+    }
+
+    /// Makes corrupt DEFLATE data skip ahead instead of failing with
+    /// [`InvalidData`](TrickleError::InvalidData), so a partially damaged
+    /// log still yields what can be recovered. Decoding resumes after the
+    /// next flush marker, `00 00 FF FF`, that is followed by data needing
+    /// no earlier history: the restart points of a stream compressed with
+    /// [`CompressionConfig::restart_interval`](crate::CompressionConfig::restart_interval).
+    /// The output then lacks everything from the corruption to there, and
+    /// may contain garbage decoded before the corruption became evident.
+    ///
+    /// After a skip the container checksum cannot match, so it is not
+    /// checked; [`resyncs`](Self::resyncs) tells whether data was lost. A
+    /// stream corrupted after its last restart point never finishes. Stays
+    /// set across [`reset`](Self::reset).
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, CompressionConfig, ContainerFormat, TrickleDecompressor};
+    ///
+    /// let log = b"boot ok\n".repeat(2000);
+    /// let config = CompressionConfig::default().with_restart_interval(4096);
+    /// let mut packed = compress_to_vec(&log, &config);
+    /// packed[0] |= 0x06; // an invalid block type
+    ///
+    /// let mut d = TrickleDecompressor::new(ContainerFormat::Raw);
+    /// d.set_recovery(true);
+    /// let mut out = vec![0u8; log.len()];
+    /// let r = d.decompress_trickle(&packed, &mut out).unwrap();
+    /// assert!(d.is_finished());
+    /// assert_eq!(d.resyncs(), 1);
+    /// assert_eq!(&out[..r.written], &log[4096..]);
+    /// ```
+    pub fn set_recovery(&mut self, enabled: bool) {
+        self.recovery = enabled;
+    }
+
+    /// Times decoding skipped ahead over corrupt data since the stream
+    /// started; see [`set_recovery`](Self::set_recovery).
+    pub fn resyncs(&self) -> u32 {
+        self.resyncs
+    }
+
+    /// Input was lost between what was passed so far and what comes next.
+    /// With recovery set, decoding skips to the next restart point as it
+    /// does for corrupt data; otherwise, and outside the DEFLATE data, the
+    /// stream fails with [`InvalidData`](TrickleError::InvalidData).
+    pub(crate) fn skip_lost_input(&mut self) -> Result<(), TrickleError> {
+        if !self.recovery || !matches!(self.phase, Phase::Body | Phase::Resync { .. }) {
+            debug!("input lost after {} bytes out", self.total_out);
+            return Err(TrickleError::InvalidData);
+        }
+        if self.phase == Phase::Body {
+            self.resyncs += 1;
+        }
+        self.state.discard_bits();
+        self.phase = Phase::Resync { matched: 0 };
+        Ok(())
+    }
+
+    /// Registers `hook` to run at the end of every `decompress_trickle` call
+    /// that produced output, completed a block or finished the stream, for
+    /// example to drive an update progress bar. Stays registered across
+    /// [`reset`](Self::reset).
+    pub fn on_progress(&mut self, hook: impl FnMut(&DecompressProgress) + Send + 'static) {
+        self.on_progress = Some(Box::new(hook));
+    }
+
+    /// Registers `hook` to run each time another `every` bytes of output
+    /// have been produced, with the total so far, e.g. to program a flash
+    /// page as soon as it is complete. Calls stop decoding at each multiple
+    /// of `every` to run it, so the bytes of a page are all in the output
+    /// buffers passed so far and none of the next one is. Stays registered
+    /// across [`reset`](Self::reset).
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, CompressionConfig, ContainerFormat, TrickleDecompressor};
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let packed = compress_to_vec(&[0xA5; 10_000], &CompressionConfig::default());
+    /// let pages = Arc::new(Mutex::new(Vec::new()));
+    /// let mut d = TrickleDecompressor::new(ContainerFormat::Raw);
+    /// let seen = pages.clone();
+    /// d.on_watermark(4096, move |total| seen.lock().unwrap().push(total));
+    /// d.decompress_trickle(&packed, &mut vec![0; 10_000]).unwrap();
+    /// assert_eq!(*pages.lock().unwrap(), [4096, 8192]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `every` is 0.
+    pub fn on_watermark(&mut self, every: u64, hook: impl FnMut(u64) + Send + 'static) {
+        assert!(every > 0, "watermark interval must not be 0");
+        self.watermark = Some(Watermark {
+            every,
+            next: (self.total_out / every + 1) * every,
+            hook: Box::new(hook),
+        });
+    }
+
+    pub fn reset(&mut self) {
+        self.state.reset();
+        if let Some(mark) = self.watermark.as_mut() {
+            mark.next = mark.every;
+        }
+        if self.detect {
+            self.format = ContainerFormat::Auto;
+        }
+        self.phase = Phase::Header { read: 0 };
+        self.flags = 0;
+        self.header_read = 0;
+        self.dict_id = None;
+        self.adler = 1;
+        self.crc.reset();
+        self.total_out = 0;
+        self.resyncs = 0;
+        self.transformed = 0;
+        self.yield_next = false;
+    }
+
+    /// Reverses the [`BlockTransform`] the stream was compressed with. Input
+    /// then has to be passed to
+    /// [`decompress_trickle_mut`](Self::decompress_trickle_mut), which
+    /// transforms it in place. Stays registered across
+    /// [`reset`](Self::reset).
+    pub fn set_transform(&mut self, transform: impl BlockTransform + Send + 'static) {
+        self.transform = Some(Box::new(transform));
+    }
+
+    /// Supplies the preset dictionary the stream was compressed with, as
+    /// zlib's `inflateSetDictionary` does. A zlib stream announces it in
+    /// its header; decoding one stops with
+    /// [`NeedDictionary`](TrickleError::NeedDictionary) until the
+    /// dictionary with the Adler-32 given there is set, so one can be
+    /// picked out of several. Raw streams carry no trace of a dictionary:
+    /// set it before the first call.
+    ///
+    /// Valid before the first call after creation or
+    /// [`reset`](Self::reset), which drops the dictionary, and after
+    /// `NeedDictionary`. Fails with
+    /// [`InvalidConfig`](TrickleError::InvalidConfig) naming `dictionary`
+    /// at other times or when `dict` is not the one asked for, and naming
+    /// `format` for gzip, which has no preset dictionaries.
+    ///
+    /// ```
+    /// use tricklezip::{Adler32, CompressionConfig, ContainerFormat, FlushMode, TrickleCompressor, TrickleDecompressor, TrickleError};
+    ///
+    /// let dict = br#"{"device":"pump-1","status":"ok","temp_c":21}"#;
+    /// let message = br#"{"device":"pump-7","status":"ok","temp_c":23}"#;
+    /// let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+    /// let mut compressor = TrickleCompressor::new(config);
+    /// compressor.set_dictionary(dict).unwrap();
+    /// let mut packed = [0u8; 64];
+    /// let r = compressor.compress_trickle(message, &mut packed, FlushMode::Finish).unwrap();
+    ///
+    /// let mut dict_id = Adler32::new();
+    /// dict_id.update(dict);
+    /// let mut d = TrickleDecompressor::new(ContainerFormat::Zlib);
+    /// let mut input = &packed[..r.written];
+    /// let mut out = [0u8; 64];
+    /// let mut written = 0;
+    /// while !d.is_finished() {
+    ///     match d.decompress_trickle(input, &mut out[written..]) {
+    ///         Ok(r) => {
+    ///             input = &input[r.consumed..];
+    ///             written += r.written;
+    ///         }
+    ///         Err(TrickleError::NeedDictionary { id }) => {
+    ///             assert_eq!(id, dict_id.finalize());
+    ///             d.set_dictionary(dict).unwrap();
+    ///         }
+    ///         Err(e) => panic!("{e}"),
+    ///     }
+    /// }
+    /// assert_eq!(&out[..written], message);
+    /// ```
+    pub fn set_dictionary(&mut self, dict: &[u8]) -> Result<(), TrickleError> {
+        if self.format == ContainerFormat::Gzip {
+            return Err(TrickleError::InvalidConfig { field: "format" });
+        }
+        let id = adler32::update(1, dict);
+        match self.phase {
+            Phase::Header { read: 0 } => {}
+            Phase::Dictionary { id: wanted } if wanted == id => {}
+            _ => {
+                return Err(TrickleError::InvalidConfig {
+                    field: "dictionary",
+                })
+            }
+        }
+        self.state.prime(dict);
+        self.dict_id = Some(id);
+        Ok(())
+    }
+
+    /// Uses `dict` as history preceding the stream. Only valid right after
+    /// creation or [`reset`](Self::reset).
+    pub(crate) fn prime(&mut self, dict: &[u8]) {
+        self.state.prime(dict);
+    }
+
+    /// Decompresses as much of `input` as fits into `output`.
+    ///
+    /// Input bytes are consumed even when they only complete part of a
+    /// symbol, so the caller never has to re-supply them.
+    ///
+    /// When `output` fills up, even in the middle of a match, the call
+    /// stops right there with [`NeedOutput`](TrickleStatus::NeedOutput);
+    /// the rest of the match is kept and comes first in the next call's
+    /// output, whatever its size. Concatenating the output of all calls
+    /// always gives the decompressed data exactly, with no byte dropped or
+    /// repeated, and an empty `output` only makes the call consume input.
+    pub fn decompress_trickle(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Result<TrickleResult, TrickleError> {
+        if self.phase == Phase::Done {
+            debug!("decompress_trickle called after the stream finished");
+            return Err(TrickleError::StreamFinished);
+        }
+        let mut consumed = self
+            .read_header(input)
+            .inspect_err(|e| debug!("bad {:?} header: {:?}", self.format, e))?;
+        let mut written = 0;
+        let blocks = self.state.blocks;
+        loop {
+            if let Phase::Resync { .. } = self.phase {
+                consumed += self.resync(&input[consumed..]);
+            }
+            if self.phase != Phase::Body {
+                break;
+            }
+            // Stop at the next watermark, if it comes before the end.
+            let end = match &self.watermark {
+                Some(mark) => {
+                    let room = (output.len() - written) as u64;
+                    written + (mark.next - self.total_out).min(room) as usize
+                }
+                None => output.len(),
+            };
+            let r = self
+                .state
+                .decompress_chunk(&input[consumed..], &mut output[written..end]);
+            consumed += r.consumed;
+            self.update_checksum(&output[written..written + r.written]);
+            written += r.written;
+            self.total_out += r.written as u64;
+            if let Some(e) = r.error {
+                debug!(
+                    "corrupt DEFLATE data after {} bytes: {:?}",
+                    self.total_out, e
+                );
+                if !self.recovery || e != TrickleError::InvalidData {
+                    return Err(e);
+                }
+                self.resyncs += 1;
+                self.state.align_to_byte();
+                self.phase = Phase::Resync { matched: 0 };
+                continue;
+            }
+            if r.done {
+                self.phase = Phase::Trailer { read: 0 };
+            }
+            match self.watermark.as_mut() {
+                Some(mark) if self.total_out == mark.next => {
+                    mark.next += mark.every;
+                    (mark.hook)(self.total_out);
+                }
+                _ => break,
+            }
+            if written == output.len() {
+                break;
+            }
+        }
+        if let Phase::Trailer { .. } = self.phase {
+            consumed += self
+                .read_trailer(&input[consumed..])
+                .inspect_err(|e| debug!("bad {:?} trailer: {:?}", self.format, e))?;
+            if self.phase == Phase::Done {
+                debug!(
+                    "stream finished: {} bytes out, {} blocks",
+                    self.total_out, self.state.blocks
+                );
+            }
+        }
+
+        let status = if self.phase == Phase::Done {
+            TrickleStatus::Finished
+        } else if let Phase::Dictionary { .. } = self.phase {
+            // The next call reports which dictionary is needed.
+            TrickleStatus::InProgress
+        } else if written == output.len() && self.phase == Phase::Body {
+            TrickleStatus::NeedOutput
+        } else {
+            TrickleStatus::NeedInput
+        };
+        if let Some(hook) = self.on_progress.as_mut() {
+            let finished = self.phase == Phase::Done;
+            if written > 0 || self.state.blocks != blocks || finished {
+                hook(&DecompressProgress {
+                    bytes_out: self.total_out,
+                    blocks: self.state.blocks,
+                    finished,
+                });
+            }
+        }
+        Ok(TrickleResult {
+            consumed,
+            written,
+            status,
+        })
+    }
+
+    /// [`decompress_trickle`](Self::decompress_trickle) for async code,
+    /// with any executor. A poll decodes at most 4KB of output and is
+    /// `Ready` with its progress, reporting
+    /// [`InProgress`](TrickleStatus::InProgress) if `output` had room for
+    /// more; the next poll then wakes the task and returns `Pending` first,
+    /// like
+    /// [`TrickleCompressor::poll_compress`](crate::TrickleCompressor::poll_compress),
+    /// so a large `output` does not keep other tasks waiting.
+    pub fn poll_decompress(
+        &mut self,
+        cx: &mut Context<'_>,
+        input: &[u8],
+        output: &mut [u8],
+    ) -> Poll<Result<TrickleResult, TrickleError>> {
+        if self.yield_next {
+            self.yield_next = false;
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        let len = output.len().min(POLL_BUDGET);
+        let capped = len < output.len();
+        let mut r = self.decompress_trickle(input, &mut output[..len]);
+        if let Ok(r) = r.as_mut() {
+            if capped && r.status == TrickleStatus::NeedOutput {
+                r.status = TrickleStatus::InProgress;
+                self.yield_next = true;
+            }
+        }
+        Poll::Ready(r)
+    }
+
+    /// Like [`decompress_trickle`](Self::decompress_trickle), but takes
+    /// its input straight out of `queue`, e.g. filled by a UART receive
+    /// interrupt, and releases what it consumed. With the queue empty it
+    /// reports [`NeedInput`](TrickleStatus::NeedInput) and the stream
+    /// resumes on a later call; bytes that wrap around the end of the ring
+    /// are taken by the next call, which this one asks for by reporting
+    /// [`InProgress`](TrickleStatus::InProgress).
+    pub fn decompress_from_queue<const N: usize>(
+        &mut self,
+        queue: &mut QueueConsumer<'_, N>,
+        output: &mut [u8],
+    ) -> Result<TrickleResult, TrickleError> {
+        let mut r = self.decompress_trickle(queue.read_slice(), output)?;
+        queue.release(r.consumed);
+        if r.status == TrickleStatus::NeedInput && !queue.is_empty() {
+            r.status = TrickleStatus::InProgress;
+        }
+        Ok(r)
+    }
+
+    /// Like [`decompress_trickle`](Self::decompress_trickle), but first
+    /// runs the input through the transform registered with
+    /// [`set_transform`](Self::set_transform), in place. Unconsumed bytes
+    /// are left transformed and are not transformed again when passed in
+    /// on the next call, as they must be.
+    pub fn decompress_trickle_mut(
+        &mut self,
+        input: &mut [u8],
+        output: &mut [u8],
+    ) -> Result<TrickleResult, TrickleError> {
+        if let Some(transform) = self.transform.as_mut() {
+            let done = self.transformed.min(input.len());
+            transform.apply(&mut input[done..]);
+            self.transformed = done.max(input.len());
+        }
+        let r = self.decompress_trickle(input, output)?;
+        self.transformed -= r.consumed.min(self.transformed);
+        Ok(r)
+    }
+
+    fn update_checksum(&mut self, data: &[u8]) {
+        match self.format {
+            ContainerFormat::Raw if self.raw_checksum => self.crc.update(data),
+            ContainerFormat::Raw | ContainerFormat::Auto => {}
+            ContainerFormat::Zlib => self.adler = adler32::update(self.adler, data),
+            ContainerFormat::Gzip => self.crc.update(data),
+        }
+    }
+
+    /// Skips to just past the next flush marker, returning how many input
+    /// bytes were consumed, and resumes decoding there.
+    fn resync(&mut self, input: &[u8]) -> usize {
+        let Phase::Resync { mut matched } = self.phase else {
+            return 0;
+        };
+        let mut pos = 0;
+        while matched < FLUSH_MARKER.len() {
+            // Bytes the decoder had buffered past the corruption come first.
+            let b = match self.state.take_byte() {
+                Some(b) => b,
+                None => match input.get(pos) {
+                    Some(&b) => {
+                        pos += 1;
+                        b
+                    }
+                    None => break,
+                },
+            };
+            matched = match (matched, b) {
+                (2, 0) => 2,
+                (3, 0) => 1,
+                _ if b == FLUSH_MARKER[matched] => matched + 1,
+                (_, 0) => 1,
+                _ => 0,
+            };
+        }
+        if matched == FLUSH_MARKER.len() {
+            debug!("resynchronized after {} bytes out", self.total_out);
+            self.state.restart();
+            self.phase = Phase::Body;
+        } else {
+            self.phase = Phase::Resync { matched };
+        }
+        pos
+    }
+
+    /// Parses container header bytes, returning how many were consumed.
+    fn read_header(&mut self, input: &[u8]) -> Result<usize, TrickleError> {
+        let mut pos = 0;
+        loop {
+            let header_len = match self.format {
+                ContainerFormat::Raw => 0,
+                ContainerFormat::Zlib | ContainerFormat::Auto => 2,
+                ContainerFormat::Gzip => 10,
+            };
+            match self.phase {
+                Phase::Header { read } if read == header_len => {
+                    self.phase = self.check_fixed_header()?;
+                    self.start_body_crc();
+                    continue;
+                }
+                Phase::Dictionary { id } if self.dict_id == Some(id) => {
+                    self.phase = Phase::Body;
+                    self.start_body_crc();
+                    continue;
+                }
+                // Stop for the dictionary in a call that consumes nothing,
+                // so it can be repeated once the dictionary is set.
+                Phase::Dictionary { id } if pos == 0 => {
+                    return Err(TrickleError::NeedDictionary { id })
+                }
+                Phase::Dictionary { .. }
+                | Phase::Body
+                | Phase::Resync { .. }
+                | Phase::Trailer { .. }
+                | Phase::Done => return Ok(pos),
+                _ => {}
+            }
+            let Some(&b) = input.get(pos) else {
+                return Ok(pos);
+            };
+            if self.header_read == self.max_header_size {
+                return Err(TrickleError::LimitExceeded {
+                    limit: "max_header_size",
+                });
+            }
+            self.header_read += 1;
+            pos += 1;
+            if !matches!(self.phase, Phase::GzipHeaderCrc { .. }) {
+                self.crc.update(&[b]);
+            }
+            self.phase = match self.phase {
+                Phase::Header { read } => {
+                    self.buf[read] = b;
+                    Phase::Header { read: read + 1 }
+                }
+                Phase::GzipExtraLen { read: 0 } => {
+                    self.buf[0] = b;
+                    Phase::GzipExtraLen { read: 1 }
+                }
+                Phase::GzipExtraLen { .. } => {
+                    let len = u16::from_le_bytes([self.buf[0], b]) as usize;
+                    if len == 0 {
+                        self.after_extra()
+                    } else {
+                        Phase::GzipExtra { remaining: len }
+                    }
+                }
+                Phase::GzipExtra { remaining: 1 } => self.after_extra(),
+                Phase::GzipExtra { remaining } => Phase::GzipExtra {
+                    remaining: remaining - 1,
+                },
+                Phase::GzipName if b == 0 => self.after_name(),
+                Phase::GzipComment if b == 0 => self.after_comment(),
+                Phase::GzipName | Phase::GzipComment => self.phase,
+                Phase::GzipHeaderCrc { read: 0 } => {
+                    self.buf[0] = b;
+                    Phase::GzipHeaderCrc { read: 1 }
+                }
+                Phase::GzipHeaderCrc { .. } => {
+                    let expected = u16::from_le_bytes([self.buf[0], b]);
+                    if expected != self.crc.value() as u16 {
+                        return Err(TrickleError::InvalidHeader);
+                    }
+                    Phase::Body
+                }
+                Phase::ZlibDictId { read } => {
+                    self.buf[2 + read] = b;
+                    if read < 3 {
+                        Phase::ZlibDictId { read: read + 1 }
+                    } else {
+                        let id = u32::from_be_bytes([self.buf[2], self.buf[3], self.buf[4], b]);
+                        debug!("zlib stream needs dictionary {:08x}", id);
+                        Phase::Dictionary { id }
+                    }
+                }
+                Phase::Body
+                | Phase::Dictionary { .. }
+                | Phase::Resync { .. }
+                | Phase::Trailer { .. }
+                | Phase::Done => unreachable!(),
+            };
+            self.start_body_crc();
+        }
+    }
+
+    fn in_header(&self) -> bool {
+        matches!(
+            self.phase,
+            Phase::Header { .. }
+                | Phase::GzipExtraLen { .. }
+                | Phase::GzipExtra { .. }
+                | Phase::GzipName
+                | Phase::GzipComment
+                | Phase::GzipHeaderCrc { .. }
+                | Phase::ZlibDictId { .. }
+        )
+    }
+
+    /// Restarts the CRC once the header is done so it covers only the data.
+    fn start_body_crc(&mut self) {
+        if self.phase == Phase::Body {
+            self.crc.reset();
+        }
+    }
+
+    fn check_fixed_header(&mut self) -> Result<Phase, TrickleError> {
+        match self.format {
+            ContainerFormat::Raw => Ok(Phase::Body),
+            ContainerFormat::Auto => {
+                self.format = ContainerFormat::detect(&self.buf[..2]).unwrap_or_default();
+                debug!("detected a {:?} stream", self.format);
+                match self.format {
+                    ContainerFormat::Raw => {
+                        self.state.unread(&self.buf[..2]);
+                        Ok(Phase::Body)
+                    }
+                    ContainerFormat::Gzip => Ok(Phase::Header { read: 2 }),
+                    _ => self.check_fixed_header(),
+                }
+            }
+            ContainerFormat::Zlib => {
+                let (cmf, flg) = (self.buf[0], self.buf[1]);
+                if cmf & 0x0F != 8
+                    || cmf >> 4 > 7
+                    || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31)
+                {
+                    return Err(TrickleError::InvalidHeader);
+                }
+                if 256 << (cmf >> 4) > self.state.window_size() {
+                    debug!(
+                        "zlib stream needs a window larger than {}",
+                        self.state.window_size()
+                    );
+                    return Err(TrickleError::WindowTooLarge);
+                }
+                Ok(if flg & FDICT != 0 {
+                    Phase::ZlibDictId { read: 0 }
+                } else {
+                    Phase::Body
+                })
+            }
+            ContainerFormat::Gzip => {
+                if self.buf[0] != 0x1F || self.buf[1] != 0x8B || self.buf[2] != 8 {
+                    return Err(TrickleError::InvalidHeader);
+                }
+                self.flags = self.buf[3];
+                if self.flags & 0xE0 != 0 {
+                    return Err(TrickleError::InvalidHeader);
+                }
+                Ok(if self.flags & FEXTRA != 0 {
+                    Phase::GzipExtraLen { read: 0 }
+                } else {
+                    self.after_extra()
+                })
+            }
+        }
+    }
+
+    fn after_extra(&self) -> Phase {
+        if self.flags & FNAME != 0 {
+            Phase::GzipName
+        } else {
+            self.after_name()
+        }
+    }
+
+    fn after_name(&self) -> Phase {
+        if self.flags & FCOMMENT != 0 {
+            Phase::GzipComment
+        } else {
+            self.after_comment()
+        }
+    }
+
+    fn after_comment(&self) -> Phase {
+        if self.flags & FHCRC != 0 {
+            Phase::GzipHeaderCrc { read: 0 }
+        } else {
+            Phase::Body
+        }
+    }
+
+    /// Collects and verifies the container trailer.
+    fn read_trailer(&mut self, input: &[u8]) -> Result<usize, TrickleError> {
+        let len = match self.format {
+            ContainerFormat::Raw | ContainerFormat::Auto => 0,
+            ContainerFormat::Zlib => 4,
+            ContainerFormat::Gzip => 8,
+        };
+        let Phase::Trailer { mut read } = self.phase else {
+            return Ok(0);
+        };
+        let mut pos = 0;
+        while read < len {
+            let b = match self.state.take_byte() {
+                Some(b) => b,
+                None => match input.get(pos) {
+                    Some(&b) => {
+                        pos += 1;
+                        b
+                    }
+                    None => break,
+                },
+            };
+            self.buf[read] = b;
+            read += 1;
+        }
+        if read < len {
+            self.phase = Phase::Trailer { read };
+            return Ok(pos);
+        }
+        let ok = match self.format {
+            ContainerFormat::Raw | ContainerFormat::Auto => true,
+            _ if self.resyncs > 0 => true,
+            ContainerFormat::Zlib => {
+                u32::from_be_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]])
+                    == self.adler
+            }
+            ContainerFormat::Gzip => {
+                let crc = u32::from_le_bytes([self.buf[0], self.buf[1], self.buf[2], self.buf[3]]);
+                let isize =
+                    u32::from_le_bytes([self.buf[4], self.buf[5], self.buf[6], self.buf[7]]);
+                crc == self.crc.value() && isize == self.total_out as u32
+            }
+        };
+        if !ok {
+            return Err(TrickleError::ChecksumMismatch);
+        }
+        self.phase = Phase::Done;
+        Ok(pos)
+    }
+}
+
+/// Offset of the first restart point at or after `from` in a stream
+/// compressed with
+/// [`CompressionConfig::restart_interval`](crate::CompressionConfig::restart_interval),
+/// e.g. to skip past a corrupted flash sector.
+///
+/// Restart points are found by their full-flush marker, `00 00 FF FF`,
+/// which compressed data can contain by chance, so decoding from a
+/// returned offset may still fail; search again past it in that case.
+/// Decode from a restart point with a [`ContainerFormat::Raw`]
+/// decompressor: data there needs no earlier history, and decoding stops
+/// before the container trailer.
+///
+/// ```
+/// use tricklezip::{compress_to_vec, decompress_to_vec, find_restart_point};
+/// use tricklezip::{CompressionConfig, ContainerFormat};
+///
+/// let log = b"boot ok\n".repeat(2000);
+/// let config = CompressionConfig::default()
+///     .with_format(ContainerFormat::Gzip)
+///     .with_restart_interval(4096);
+/// let gz = compress_to_vec(&log, &config);
+/// let at = find_restart_point(&gz, gz.len() / 2).unwrap();
+/// let tail = decompress_to_vec(&gz[at..], ContainerFormat::Raw).unwrap();
+/// assert_eq!(tail.len() % 4096, log.len() % 4096);
+/// assert!(log.ends_with(&tail));
+/// ```
+pub fn find_restart_point(compressed: &[u8], from: usize) -> Option<usize> {
+    compressed
+        .get(from..)?
+        .windows(4)
+        .position(|w| w == [0, 0, 0xFF, 0xFF])
+        .map(|pos| from + pos + 4)
+}
+
+/// Decompresses a complete stream held in memory.
+pub fn decompress_to_vec(input: &[u8], format: ContainerFormat) -> Result<Vec<u8>, TrickleError> {
+    let mut decompressor = TrickleDecompressor::new(format);
+    let mut out = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let mut consumed = 0;
+    loop {
+        let r = decompressor.decompress_trickle(&input[consumed..], &mut buf)?;
+        consumed += r.consumed;
+        out.extend_from_slice(&buf[..r.written]);
+        match r.status {
+            TrickleStatus::Finished => return Ok(out),
+            TrickleStatus::NeedInput if consumed == input.len() => {
+                return Err(TrickleError::UnexpectedEof)
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_to_vec, CompressionConfig};
+
+    fn sample() -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..2000u32 {
+            data.extend_from_slice(b"temperature=");
+            data.extend_from_slice(&(i % 37).to_le_bytes());
+            data.extend_from_slice(b" status=ok\n");
+        }
+        data
+    }
+
+    #[test]
+    fn round_trip_all_formats() {
+        let data = sample();
+        for format in [
+            ContainerFormat::Raw,
+            ContainerFormat::Zlib,
+            ContainerFormat::Gzip,
+        ] {
+            let config = CompressionConfig::default().with_format(format);
+            let packed = compress_to_vec(&data, &config);
+            assert!(packed.len() < data.len() / 4);
+            assert_eq!(decompress_to_vec(&packed, format).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn bytes_after_the_stream_are_not_consumed() {
+        let data = sample();
+        for format in [
+            ContainerFormat::Raw,
+            ContainerFormat::Zlib,
+            ContainerFormat::Gzip,
+        ] {
+            let mut packed =
+                compress_to_vec(&data, &CompressionConfig::default().with_format(format));
+            let len = packed.len();
+            packed.extend_from_slice(b"next stream");
+            let mut d = TrickleDecompressor::new(format);
+            let mut out = vec![0u8; 2 * data.len()];
+            let r = d.decompress_trickle(&packed, &mut out).unwrap();
+            assert_eq!((r.consumed, r.status), (len, TrickleStatus::Finished));
+        }
+    }
+
+    #[test]
+    fn auto_detects_every_format() {
+        let data = sample();
+        for format in [
+            ContainerFormat::Raw,
+            ContainerFormat::Zlib,
+            ContainerFormat::Gzip,
+        ] {
+            let config = CompressionConfig::default().with_format(format);
+            let packed = compress_to_vec(&data, &config);
+            assert_eq!(ContainerFormat::detect(&packed), Some(format));
+            assert_eq!(
+                decompress_to_vec(&packed, ContainerFormat::Auto).unwrap(),
+                data
+            );
+
+            // Split right through the bytes the format is detected from.
+            let mut d = TrickleDecompressor::with_config(DecompressorConfig::default());
+            let mut out = vec![0u8; data.len()];
+            let r = d.decompress_trickle(&packed[..1], &mut out).unwrap();
+            assert_eq!((r.consumed, d.format()), (1, ContainerFormat::Auto));
+            let r = d.decompress_trickle(&packed[1..], &mut out).unwrap();
+            assert_eq!(r.status, TrickleStatus::Finished);
+            assert_eq!(out, data);
+            assert_eq!(d.format(), format);
+
+            d.reset();
+            assert_eq!(d.format(), ContainerFormat::Auto);
+        }
+        assert_eq!(ContainerFormat::detect(&[0x78]), None);
+    }
+
+    #[test]
+    fn decodes_zlib_output_of_every_block_type() {
+        // Written by zlib 1.2 at levels 0, 9 and 9.
+        const STORED: &[u8] = &[
+            0x78, 0x01, 0x01, 0x0D, 0x00, 0xF2, 0xFF, 0x73, 0x74, 0x6F, 0x72, 0x65, 0x64, 0x20,
+            0x62, 0x6C, 0x6F, 0x63, 0x6B, 0x0A, 0x24, 0x47, 0x04, 0xC7,
+        ];
+        const FIXED: &[u8] = &[
+            0x78, 0xDA, 0x4B, 0xCB, 0xAC, 0x48, 0x4D, 0x51, 0x48, 0x43, 0x27, 0xB9, 0x00, 0x70,
+            0x62, 0x08, 0xAB,
+        ];
+        const DYNAMIC: &[u8] = &[
+            0x78, 0xDA, 0x1D, 0xD0, 0xCB, 0x0D, 0x83, 0x50, 0x10, 0x43, 0xD1, 0x7D, 0xAA, 0x48,
+            0x09, 0x8C, 0xFD, 0x7E, 0x2C, 0xE8, 0x0A, 0xA5, 0xFE, 0xF8, 0xCE, 0x0A, 0x0B, 0x89,
+            0xE3, 0xC1, 0xEF, 0x73, 0x7D, 0x7F, 0xCF, 0xF5, 0x79, 0x9F, 0xCA, 0xB3, 0x9C, 0xA0,
+            0x04, 0xAD, 0x04, 0x27, 0xF8, 0x4E, 0x18, 0x09, 0x53, 0x09, 0x33, 0x61, 0xCD, 0x84,
+            0x95, 0xB0, 0x4F, 0xC2, 0x4E, 0xB8, 0x2B, 0xE1, 0xF0, 0x26, 0xCF, 0x9B, 0xAF, 0xDB,
+            0x03, 0x36, 0x60, 0x41, 0x0F, 0xC4, 0xC2, 0x9E, 0x90, 0x05, 0xBE, 0x31, 0x0B, 0xFD,
+            0x80, 0x16, 0x3C, 0x56, 0xC1, 0xD7, 0x20, 0xE1, 0x0B, 0xB7, 0x28, 0x18, 0x0D, 0x53,
+            0x31, 0xFB, 0x52, 0x2A, 0x16, 0xB0, 0xA8, 0xD8, 0xC0, 0xA2, 0xE2, 0x06, 0x16, 0x15,
+            0xDC, 0x28, 0x1A, 0x04, 0x2C, 0x1A, 0x0C, 0x2C, 0x2A, 0x06, 0xB0, 0xA8, 0x58, 0xC0,
+            0xEA, 0x7F, 0x68, 0x98, 0x8A, 0xD3, 0x1B, 0x50, 0x81, 0xE6, 0xDE, 0x87, 0x33, 0xDD,
+            0x03, 0x01, 0x9B, 0x86, 0x01, 0xEC, 0x9E, 0x08, 0xD8, 0xBD, 0x11, 0xB0, 0xA9, 0x38,
+            0xC0, 0xEE, 0x95, 0x80, 0x4D, 0x05, 0x67, 0xBA, 0x77, 0xD2, 0xE7, 0x0F, 0x2C, 0x19,
+            0x5B, 0x6C,
+        ];
+        let lines: Vec<u8> = (0..40u32)
+            .flat_map(|i| alloc::format!("t={i} v={}\n", i * 13 % 97).into_bytes())
+            .collect();
+        let cases: [(&[u8], &[u8], u8); 3] = [
+            (STORED, b"stored block\n", 0),
+            (FIXED, b"fixed fixed fixed fixed\n", 1),
+            (DYNAMIC, &lines, 2),
+        ];
+        for (packed, data, btype) in cases {
+            assert_eq!(packed[2] >> 1 & 3, btype);
+            assert_eq!(
+                decompress_to_vec(packed, ContainerFormat::Zlib).unwrap(),
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn recovery_resumes_at_the_next_restart_point() {
+        let log: Vec<u8> = (0..3000u32)
+            .flat_map(|i| alloc::format!("{i} sensor=ok temp=21.{}\n", i % 7).into_bytes())
+            .collect();
+        let config = CompressionConfig::default()
+            .with_format(ContainerFormat::Zlib)
+            .with_restart_interval(8192);
+        let mut packed = compress_to_vec(&log, &config);
+        // Give the block after the first restart point an invalid type.
+        let at = find_restart_point(&packed, 0).unwrap();
+        packed[at] |= 0x06;
+        assert_eq!(
+            decompress_to_vec(&packed, ContainerFormat::Zlib),
+            Err(TrickleError::InvalidData)
+        );
+        let expected = [&log[..8192], &log[16384..]].concat();
+
+        for step in [1, 3, packed.len()] {
+            let mut d = TrickleDecompressor::new(ContainerFormat::Zlib);
+            d.set_recovery(true);
+            let mut out = Vec::new();
+            let mut buf = [0u8; 300];
+            let mut pos = 0;
+            while !d.is_finished() {
+                let end = (pos + step).min(packed.len());
+                let r = d.decompress_trickle(&packed[pos..end], &mut buf).unwrap();
+                pos += r.consumed;
+                out.extend_from_slice(&buf[..r.written]);
+            }
+            assert_eq!(pos, packed.len());
+            assert_eq!(d.resyncs(), 1);
+            assert!(out == expected, "step {step}");
+            d.reset();
+            assert_eq!(d.resyncs(), 0);
+        }
+    }
+
+    #[test]
+    fn pending_output_reports_the_rest_of_a_match() {
+        let packed = compress_to_vec(&[7u8; 1000], &CompressionConfig::default());
+        let mut d = TrickleDecompressor::new(ContainerFormat::Raw);
+        let mut out = [0u8; 100];
+        let r = d.decompress_trickle(&packed, &mut out).unwrap();
+        assert_eq!(r.status, TrickleStatus::NeedOutput);
+        assert!(d.has_pending_output());
+        let pending = d.pending_output_len();
+        // The rest of the match comes out without any input.
+        let r = d
+            .decompress_trickle(&[], &mut vec![0u8; pending + 10])
+            .unwrap();
+        assert_eq!(r.written, pending);
+        assert!(!d.has_pending_output());
+    }
+
+    #[test]
+    fn byte_at_a_time() {
+        let data = sample();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let packed = compress_to_vec(&data, &config);
+        let mut d = TrickleDecompressor::new(ContainerFormat::Gzip);
+        let mut out = Vec::new();
+        let mut byte = [0u8; 1];
+        let mut pos = 0;
+        while !d.is_finished() {
+            let end = (pos + 1).min(packed.len());
+            let r = d.decompress_trickle(&packed[pos..end], &mut byte).unwrap();
+            pos += r.consumed;
+            out.extend_from_slice(&byte[..r.written]);
+        }
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn uneven_chunks_mix_fast_and_slow_paths() {
+        let data = sample();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let packed = compress_to_vec(&data, &config);
+        for (in_step, out_len) in [(9, 300), (64, 259), (5, 1000), (4096, 258)] {
+            let mut d = TrickleDecompressor::new(ContainerFormat::Gzip);
+            let mut out = Vec::new();
+            let mut buf = vec![0u8; out_len];
+            let mut pos = 0;
+            while !d.is_finished() {
+                let end = (pos + in_step).min(packed.len());
+                let r = d.decompress_trickle(&packed[pos..end], &mut buf).unwrap();
+                pos += r.consumed;
+                out.extend_from_slice(&buf[..r.written]);
+            }
+            assert_eq!(pos, packed.len());
+            assert_eq!(out, data);
+        }
+    }
+
+    #[test]
+    fn progress_hook_reports_bytes_and_blocks() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+        let data = sample();
+        let mut config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        config.block_size = 16;
+        let packed = compress_to_vec(&data, &config);
+        let blocks = crate::explain(&packed, ContainerFormat::Zlib)
+            .unwrap()
+            .blocks
+            .len() as u32;
+        assert!(blocks > 1);
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let last_bytes = Arc::new(AtomicU64::new(0));
+        let last_blocks = Arc::new(AtomicU32::new(0));
+        let total = data.len() as u64;
+        let mut d = TrickleDecompressor::new(ContainerFormat::Zlib);
+        d.on_progress({
+            let (calls, last_bytes, last_blocks) =
+                (calls.clone(), last_bytes.clone(), last_blocks.clone());
+            move |p| {
+                calls.fetch_add(1, Ordering::Relaxed);
+                assert!(p.bytes_out >= last_bytes.swap(p.bytes_out, Ordering::Relaxed));
+                assert!(p.blocks >= last_blocks.swap(p.blocks, Ordering::Relaxed));
+                assert_eq!(p.finished, p.blocks == blocks && p.bytes_out == total);
+            }
+        });
+        let mut buf = [0u8; 1000];
+        let mut pos = 0;
+        while !d.is_finished() {
+            let r = d.decompress_trickle(&packed[pos..], &mut buf).unwrap();
+            pos += r.consumed;
+        }
+        assert_eq!(d.blocks_completed(), blocks);
+        assert_eq!(last_blocks.load(Ordering::Relaxed), blocks);
+        assert_eq!(last_bytes.load(Ordering::Relaxed), data.len() as u64);
+        assert!(calls.load(Ordering::Relaxed) as usize >= data.len() / buf.len());
+    }
+
+    #[test]
+    fn watermarks_fire_at_exact_multiples() {
+        use alloc::sync::Arc;
+        use core::sync::atomic::{AtomicU64, Ordering};
+
+        let data = sample();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let packed = compress_to_vec(&data, &config);
+        let marks = Arc::new(AtomicU64::new(0));
+        let mut d = TrickleDecompressor::new(ContainerFormat::Gzip);
+        let seen = marks.clone();
+        d.on_watermark(4096, move |total| {
+            let n = seen.fetch_add(1, Ordering::Relaxed) + 1;
+            assert_eq!(total, n * 4096);
+        });
+        for _ in 0..2 {
+            marks.store(0, Ordering::Relaxed);
+            let mut out = Vec::new();
+            let mut buf = [0u8; 333];
+            let mut pos = 0;
+            while !d.is_finished() {
+                let end = packed.len().min(pos + 7);
+                let r = d.decompress_trickle(&packed[pos..end], &mut buf).unwrap();
+                pos += r.consumed;
+                out.extend_from_slice(&buf[..r.written]);
+            }
+            assert_eq!(out, data);
+            assert_eq!(marks.load(Ordering::Relaxed), data.len() as u64 / 4096);
+            d.reset();
+        }
+    }
+
+    #[test]
+    fn full_output_resumes_mid_match() {
+        // Long runs and repeats make 258-byte matches, split at every offset
+        // by the odd buffer sizes below.
+        let mut data = vec![b'-'; 3000];
+        data.extend(b"0123456789abcdef".repeat(200));
+        data.extend(sample());
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let packed = compress_to_vec(&data, &config);
+        let sizes = [1, 0, 257, 2, 258, 3, 259, 0, 17, 1000];
+        let mut d = TrickleDecompressor::new(ContainerFormat::Zlib);
+        let mut out = Vec::new();
+        let mut pos = 0;
+        for &size in sizes.iter().cycle() {
+            let mut buf = vec![0u8; size];
+            let r = d.decompress_trickle(&packed[pos..], &mut buf).unwrap();
+            pos += r.consumed;
+            out.extend_from_slice(&buf[..r.written]);
+            assert_eq!(d.total_out(), out.len() as u64);
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+            assert_eq!(r.written, size);
+            assert_eq!(r.status, TrickleStatus::NeedOutput);
+        }
+        assert_eq!(pos, packed.len());
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn corrupt_trailer_is_detected() {
+        let data = sample();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let mut packed = compress_to_vec(&data, &config);
+        let last = packed.len() - 1;
+        packed[last] ^= 1;
+        assert_eq!(
+            decompress_to_vec(&packed, ContainerFormat::Zlib),
+            Err(TrickleError::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn gzip_optional_fields_are_skipped_and_checked() {
+        let data = sample();
+        let body = compress_to_vec(&data, &CompressionConfig::default());
+        let flags = FEXTRA | FNAME | FCOMMENT | FHCRC;
+        let mut member = vec![0x1F, 0x8B, 8, flags, 0x5E, 0x1B, 0x6A, 0x65, 0, 3];
+        member.extend_from_slice(&[6, 0, b'T', b'Z', 2, 0, 1, 2]);
+        member.extend_from_slice(b"sensor.log\0");
+        member.extend_from_slice(b"uploaded by node 7\0");
+        let hcrc = crate::crc32::update(0, &member) as u16;
+        member.extend_from_slice(&hcrc.to_le_bytes());
+        member.extend_from_slice(&body);
+        member.extend_from_slice(&crate::crc32::update(0, &data).to_le_bytes());
+        member.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        assert_eq!(
+            decompress_to_vec(&member, ContainerFormat::Gzip).unwrap(),
+            data
+        );
+
+        let mut bad_isize = member.clone();
+        let last = bad_isize.len() - 1;
+        bad_isize[last] ^= 1;
+        assert_eq!(
+            decompress_to_vec(&bad_isize, ContainerFormat::Gzip),
+            Err(TrickleError::ChecksumMismatch)
+        );
+
+        let mut bad_name = member;
+        bad_name[18] = b'S';
+        assert_eq!(
+            decompress_to_vec(&bad_name, ContainerFormat::Gzip),
+            Err(TrickleError::InvalidHeader)
+        );
+    }
+
+    #[test]
+    fn preset_dictionary_is_asked_for_like_zlib() {
+        let dict = br#"{"device":"pump-1","status":"ok","temp_c":21}"#;
+        let message = br#"{"device":"pump-7","status":"ok","temp_c":23}"#;
+        // zlib.compressobj(9, zdict=dict)
+        let packed = [
+            0x78, 0xF9, 0x4F, 0x47, 0x0E, 0x3C, 0xAB, 0x46, 0x53, 0x6C, 0x8E, 0x5B, 0xB1, 0x71,
+            0x2D, 0x00, 0x4F, 0xF9, 0x0E, 0x44,
+        ];
+        let id = 0x4F47_0E3C;
+        let mut out = [0u8; 64];
+
+        let mut d = TrickleDecompressor::new(ContainerFormat::Zlib);
+        let r = d.decompress_trickle(&packed, &mut out).unwrap();
+        assert_eq!((r.consumed, r.written), (6, 0));
+        assert_eq!(r.status, TrickleStatus::InProgress);
+        let err = d.decompress_trickle(&packed[6..], &mut out).unwrap_err();
+        assert_eq!(err, TrickleError::NeedDictionary { id });
+        assert!(err.is_recoverable());
+        assert_eq!(
+            d.set_dictionary(b"some other dictionary"),
+            Err(TrickleError::InvalidConfig {
+                field: "dictionary"
+            })
+        );
+        d.set_dictionary(dict).unwrap();
+        let r = d.decompress_trickle(&packed[6..], &mut out).unwrap();
+        assert_eq!(r.status, TrickleStatus::Finished);
+        assert_eq!(&out[..r.written], message);
+
+        // Set up front, the dictionary is taken without stopping.
+        d.reset();
+        d.set_dictionary(dict).unwrap();
+        let r = d.decompress_trickle(&packed, &mut out).unwrap();
+        assert_eq!(r.status, TrickleStatus::Finished);
+        assert_eq!(&out[..r.written], message);
+        assert_eq!(
+            d.set_dictionary(dict),
+            Err(TrickleError::InvalidConfig {
+                field: "dictionary"
+            })
+        );
+        assert_eq!(
+            decompress_to_vec(&packed, ContainerFormat::Auto),
+            Err(TrickleError::NeedDictionary { id })
+        );
+        assert_eq!(
+            TrickleDecompressor::new(ContainerFormat::Gzip).set_dictionary(dict),
+            Err(TrickleError::InvalidConfig { field: "format" })
+        );
+    }
+
+    #[test]
+    fn small_window_rejects_larger_streams() {
+        let mut x = 1u32;
+        let mut noise = || {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x as u8
+        };
+        let record: Vec<u8> = (0..2000).map(|_| noise()).collect();
+        let mut data = record.clone();
+        data.extend((0..8000).map(|_| noise()));
+        data.extend_from_slice(&record);
+
+        let decode = |packed: &[u8], format, window| {
+            let mut d = TrickleDecompressor::with_window_size(format, window);
+            let mut out = vec![0u8; data.len()];
+            d.decompress_trickle(packed, &mut out).map(|r| r.written)
+        };
+        let small = CompressionConfig::default()
+            .with_format(ContainerFormat::Zlib)
+            .with_window_size(4096);
+        let packed = compress_to_vec(&data, &small);
+        assert_eq!(decode(&packed, ContainerFormat::Zlib, 3000), Ok(data.len()));
+        assert_eq!(
+            TrickleDecompressor::with_window_size(ContainerFormat::Zlib, 3000).window_size(),
+            4096
+        );
+
+        let full = small.with_window_size(32768);
+        let packed = compress_to_vec(&data, &full);
+        assert_eq!(
+            decode(&packed, ContainerFormat::Zlib, 16384),
+            Err(TrickleError::WindowTooLarge)
+        );
+        let raw = compress_to_vec(&data, &full.with_format(ContainerFormat::Raw));
+        assert_eq!(
+            decode(&raw, ContainerFormat::Raw, 4096),
+            Err(TrickleError::WindowTooLarge)
+        );
+        assert_eq!(decode(&raw, ContainerFormat::Raw, 32768), Ok(data.len()));
+    }
+
+    #[test]
+    fn limits_stop_streams_going_beyond_them() {
+        let data = sample();
+        let config = CompressionConfig::default()
+            .with_format(ContainerFormat::Gzip)
+            .with_block_size(256);
+        let packed = compress_to_vec(&data, &config);
+        let decode = |limits: DecodeLimits| {
+            let mut d = TrickleDecompressor::with_limits(ContainerFormat::Gzip, limits);
+            let mut out = vec![0u8; data.len()];
+            d.decompress_trickle(&packed, &mut out)
+                .map(|r| (r.written, d.blocks_completed()))
+        };
+        let (written, blocks) = decode(DecodeLimits::default()).unwrap();
+        assert_eq!(written, data.len());
+        assert!(blocks > 1);
+
+        let exact = DecodeLimits {
+            max_table_iterations: 335,
+            max_header_size: 10,
+            max_blocks: blocks,
+            max_window: 32768,
+        };
+        assert_eq!(decode(exact), Ok((written, blocks)));
+        let cases = [
+            (
+                DecodeLimits {
+                    max_blocks: blocks - 1,
+                    ..exact
+                },
+                "max_blocks",
+            ),
+            (
+                DecodeLimits {
+                    max_header_size: 9,
+                    ..exact
+                },
+                "max_header_size",
+            ),
+        ];
+        for (limits, limit) in cases {
+            assert_eq!(decode(limits), Err(TrickleError::LimitExceeded { limit }));
+        }
+        // `tiny` writes no dynamic blocks.
+        #[cfg(not(feature = "tiny"))]
+        {
+            let limits = DecodeLimits {
+                max_table_iterations: 20,
+                ..exact
+            };
+            assert_eq!(
+                decode(limits),
+                Err(TrickleError::LimitExceeded {
+                    limit: "max_table_iterations"
+                })
+            );
+        }
+        // zlib headers declare the window the stream needs.
+        let zlib = compress_to_vec(&data, &config.with_format(ContainerFormat::Zlib));
+        let limits = DecodeLimits {
+            max_window: 4096,
+            ..DecodeLimits::default()
+        };
+        let mut d = TrickleDecompressor::with_limits(ContainerFormat::Zlib, limits);
+        assert_eq!(d.window_size(), 4096);
+        assert_eq!(
+            d.decompress_trickle(&zlib, &mut [0u8; 16]),
+            Err(TrickleError::WindowTooLarge)
+        );
+    }
+
+    #[test]
+    fn corrupt_input_always_terminates() {
+        let data = sample();
+        let packed = compress_to_vec(&data, &CompressionConfig::default());
+        let mut seed = 0x2545_f491u32;
+        for round in 0..200 {
+            let mut input = packed.clone();
+            for _ in 0..1 + round % 8 {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                let at = seed as usize % input.len();
+                input[at] ^= (seed >> 24) as u8 | 1;
+            }
+            if round % 4 == 0 {
+                input.iter_mut().for_each(|b| {
+                    seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    *b = (seed >> 16) as u8;
+                });
+            }
+            // The fuel of every step is checked by a debug assertion; a
+            // loop that stopped progressing would trip it here.
+            let mut d = TrickleDecompressor::new(ContainerFormat::Raw);
+            let mut out = vec![0u8; 97];
+            let mut consumed = 0;
+            while consumed < input.len() {
+                let end = (consumed + 1 + round % 13).min(input.len());
+                match d.decompress_trickle(&input[consumed..end], &mut out) {
+                    Ok(r) if r.status == TrickleStatus::Finished => break,
+                    Ok(r) => consumed += r.consumed,
+                    Err(_) => break,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn queue_input_resumes_across_the_ring_end() {
+        let data = sample();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let packed = compress_to_vec(&data, &config);
+        let mut queue = crate::ByteQueue::<16>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let mut d = TrickleDecompressor::new(ContainerFormat::Zlib);
+        let mut out = vec![0u8; data.len()];
+        let (mut sent, mut written, mut empty) = (0, 0, 0);
+        loop {
+            sent += producer.push(&packed[sent..(sent + 5).min(packed.len())]);
+            let r = d
+                .decompress_from_queue(&mut consumer, &mut out[written..])
+                .unwrap();
+            written += r.written;
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+            if r.status == TrickleStatus::NeedInput {
+                assert!(consumer.is_empty());
+                empty += 1;
+            }
+        }
+        assert!(empty > 0);
+        assert_eq!(out[..written], data[..]);
+    }
+
+    #[test]
+    fn polling_yields_between_budgets() {
+        let data = sample();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let packed = compress_to_vec(&data, &config);
+        let mut d = TrickleDecompressor::new(ContainerFormat::Gzip);
+        let mut cx = Context::from_waker(core::task::Waker::noop());
+        // Input and output for the whole stream are there from the start.
+        let mut out = vec![0u8; data.len() + 1];
+        let (mut consumed, mut written, mut polls, mut yields) = (0, 0, 0, 0);
+        loop {
+            let poll = d.poll_decompress(&mut cx, &packed[consumed..], &mut out[written..]);
+            let Poll::Ready(r) = poll else {
+                yields += 1;
+                continue;
+            };
+            let r = r.unwrap();
+            assert!(r.written <= POLL_BUDGET);
+            polls += 1;
+            consumed += r.consumed;
+            written += r.written;
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+        }
+        assert!(polls > data.len() / POLL_BUDGET, "{polls}");
+        assert_eq!(yields, polls - 1);
+        assert_eq!(out[..written], data[..]);
+    }
+}
@@ -0,0 +1,362 @@
+//! Block-level DEFLATE encoder tying LZ77 and Huffman coding together.
+
+use alloc::boxed::Box;
+
+use crate::allocator::{Allocator, Global, VecIn};
+use crate::bitwriter::BitWriter;
+use crate::config::{CompressionConfig, CompressionLevel, EarlyAbort, Strategy};
+use crate::fuel::{limit, Fuel};
+use crate::huffman::{self, BlockType, HuffmanCoder};
+use crate::logging::{debug, trace};
+use crate::lz77::{Lz77Encoder, Token};
+use crate::stats::{BlockInfo, CompressionStats, RatioWindow, StatsSink};
+use crate::transform::BlockTransform;
+
+/// Match finder settings a compression level stands for.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LevelTuning {
+    pub(crate) max_chain_length: u16,
+    pub(crate) max_lazy_match: u16,
+    pub(crate) nice_length: u16,
+}
+
+const fn tuning(max_chain_length: u16, max_lazy_match: u16, nice_length: u16) -> LevelTuning {
+    LevelTuning {
+        max_chain_length,
+        max_lazy_match,
+        nice_length,
+    }
+}
+
+/// Tuning per level, after zlib's configuration table: levels 1 to 3 parse
+/// greedily with short chains, 4 to 9 lazily with ever longer ones. Level 0
+/// writes stored blocks and never searches. Level 6 is the default
+/// configuration.
+pub(crate) const LEVELS: [LevelTuning; 10] = [
+    tuning(0, 0, 0),
+    tuning(4, 0, 8),
+    tuning(8, 0, 16),
+    tuning(32, 0, 32),
+    tuning(16, 4, 16),
+    tuning(32, 16, 32),
+    tuning(128, 16, 128),
+    tuning(256, 32, 128),
+    tuning(1024, 128, 258),
+    tuning(4096, 258, 258),
+];
+
+/// Callback run for every completed block.
+pub(crate) type BlockHook = Box<dyn FnMut(&BlockInfo) + Send>;
+
+/// Callbacks registered on a compressor. Boxed as a whole, so a stream
+/// without any costs a single pointer.
+#[derive(Default)]
+pub(crate) struct Observers {
+    pub(crate) on_block: Option<BlockHook>,
+    pub(crate) sink: Option<Box<dyn StatsSink + Send>>,
+    /// Blocks between two publications to `sink`.
+    pub(crate) publish_every: u32,
+    /// Block count at the last publication.
+    pub(crate) published_at: u32,
+    /// Applied to output as it is handed out.
+    pub(crate) transform: Option<Box<dyn BlockTransform + Send>>,
+}
+
+/// Outcome of one `compress_chunk` call.
+pub(crate) struct ChunkResult {
+    pub(crate) consumed: usize,
+    /// The final block has been written to the pending output.
+    pub(crate) finished: bool,
+    /// Stopped because `budget` ran out, not for lack of input.
+    pub(crate) suspended: bool,
+}
+
+pub(crate) struct DeflateState<A: Allocator = Global> {
+    lz77: Lz77Encoder<A>,
+    huffman: HuffmanCoder<A>,
+    tokens: VecIn<Token, A>,
+    /// Tokens per block. This and the early abort settings are stored
+    /// narrowly so the smallest presets stay under 1KB of state.
+    block_size: u32,
+    /// Encoded output waiting to be drained by the caller.
+    pub(crate) writer: BitWriter<A>,
+    pub(crate) stats: CompressionStats,
+    pub(crate) recent: RatioWindow,
+    /// Whether finishing sets BFINAL. When cleared, the data ends with an
+    /// empty stored block instead so further DEFLATE data can be appended.
+    pub(crate) ends_stream: bool,
+    /// Size of the blocks emitted so far, in bits.
+    pub(crate) emitted_bits: u64,
+    /// Only size blocks instead of writing them.
+    pub(crate) dry_run: bool,
+    /// `EarlyAbort::after_bytes`, or `u64::MAX` when disabled or triggered.
+    abort_after: u64,
+    /// `EarlyAbort::max_ratio_percent`.
+    abort_percent: u8,
+    finished: bool,
+    pub(crate) observers: Option<Box<Observers>>,
+}
+
+impl DeflateState {
+    pub(crate) fn new(config: &CompressionConfig) -> Self {
+        Self::new_in(config, Global)
+    }
+
+    /// What [`heap_usage`](Self::heap_usage) returns right after
+    /// `new_in(config, ..)`, computed without allocating.
+    pub(crate) fn heap_usage_for(config: &CompressionConfig) -> usize {
+        let block_size = config.block_size.clamp(1, u32::MAX as usize);
+        Lz77Encoder::heap_usage_for(config)
+            + HuffmanCoder::heap_usage_for(config.strategy != Strategy::Fixed)
+            + block_size * core::mem::size_of::<Token>()
+            + config.output_buffer_size as usize
+    }
+
+    /// Most blocks `len` bytes compressed in one go end up in, the final
+    /// one included. A block is closed once it holds `block_size` tokens,
+    /// each covering at least a byte, or fills the window.
+    pub(crate) fn max_blocks_for(config: &CompressionConfig, len: u64) -> u64 {
+        let block_size = config.block_size.clamp(1, u32::MAX as usize);
+        len / block_size.min(Lz77Encoder::min_block_len(config)) as u64 + 1
+    }
+}
+
+impl<A: Allocator + Clone> DeflateState<A> {
+    /// Creates the encoder with every buffer allocated in `alloc`.
+    pub(crate) fn new_in(config: &CompressionConfig, alloc: A) -> Self {
+        let block_size = config.block_size.clamp(1, u32::MAX as usize);
+        let (abort_after, abort_percent) = early_abort_settings(config);
+        let mut state = Self {
+            lz77: Lz77Encoder::new_in(config, alloc.clone()),
+            huffman: HuffmanCoder::new_in(config.strategy != Strategy::Fixed, alloc.clone()),
+            tokens: VecIn::with_capacity_in(block_size, alloc.clone()),
+            block_size: block_size as u32,
+            writer: BitWriter::with_capacity_in(config.output_buffer_size as usize, alloc),
+            stats: CompressionStats::default(),
+            recent: RatioWindow::new(config.ratio_window),
+            ends_stream: true,
+            emitted_bits: 0,
+            dry_run: false,
+            abort_after,
+            abort_percent,
+            finished: false,
+            observers: None,
+        };
+        if config.level == CompressionLevel::NONE {
+            state.store_only();
+        }
+        state
+    }
+
+    /// Returns to the state of `new(config)` without freeing or reallocating
+    /// any buffer. `config` must be the one the state was created with.
+    pub(crate) fn reset(&mut self, config: &CompressionConfig) {
+        self.lz77.reset(config);
+        self.huffman.reset();
+        self.tokens.clear();
+        self.writer.reset();
+        self.stats = CompressionStats::default();
+        self.recent = RatioWindow::new(config.ratio_window);
+        self.ends_stream = true;
+        self.emitted_bits = 0;
+        self.dry_run = false;
+        (self.abort_after, self.abort_percent) = early_abort_settings(config);
+        self.finished = false;
+        if config.level == CompressionLevel::NONE {
+            self.store_only();
+        }
+    }
+
+    /// Bytes allocated on the heap by the encoder.
+    pub(crate) fn heap_usage(&self) -> usize {
+        self.lz77.heap_usage()
+            + self.huffman.heap_usage()
+            + self.tokens.capacity() * core::mem::size_of::<Token>()
+            + self.writer.capacity()
+    }
+
+    /// Uses `dict` as preceding history. Must be called before any input.
+    pub(crate) fn prime(&mut self, dict: &[u8]) {
+        self.lz77.prime(dict);
+    }
+
+    /// Starts a new segment after one finished with `ends_stream` cleared:
+    /// matches no longer reach back before this point.
+    pub(crate) fn restart(&mut self) {
+        self.resume();
+        self.lz77.forget_history();
+        self.stats.restarts += 1;
+    }
+
+    /// Like [`restart`](Self::restart), but keeps the history, after a
+    /// sync flush.
+    pub(crate) fn resume(&mut self) {
+        debug_assert!(self.finished && !self.ends_stream);
+        self.ends_stream = true;
+        self.finished = false;
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn set_effort(&mut self, max_chain: usize, nice_length: usize) {
+        self.lz77.set_effort(max_chain, nice_length);
+    }
+
+    pub(crate) fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Feeds `input` through the match finder, advancing at most `budget`
+    /// positions, or one match if that is longer, and emitting blocks as
+    /// the token buffer fills. With
+    /// `finish`, the final block is written once all of `input` has been
+    /// consumed and tokenized.
+    pub(crate) fn compress_chunk(
+        &mut self,
+        input: &[u8],
+        budget: usize,
+        finish: bool,
+    ) -> ChunkResult {
+        let mut consumed = 0;
+        let mut work = 0;
+        let mut deferred = false;
+        // Every pass advances the match finder or takes input, except one
+        // closing a block to free the window, which the next pass then
+        // fills, and the last pass, which breaks out.
+        let mut fuel = Fuel::new(limit(2, budget.saturating_add(input.len()), 2));
+        while !self.finished && work < budget {
+            let burnt = fuel.burn();
+            debug_assert!(burnt, "compression pass made no progress");
+            if !burnt {
+                break;
+            }
+            #[cfg(feature = "profiling")]
+            let start = crate::cycles::now();
+            let filled = self.lz77.fill(&input[consumed..]);
+            consumed += filled;
+            let flush = finish && consumed == input.len();
+            let advanced;
+            (advanced, deferred) = self.lz77.encode(
+                &mut self.tokens,
+                self.block_size as usize,
+                budget - work,
+                work == 0,
+                flush,
+            );
+            #[cfg(feature = "profiling")]
+            {
+                self.stats.lz77_cycles += crate::cycles::since(start);
+            }
+            work += advanced;
+            self.stats.work_units += advanced as u64;
+            if self.tokens.len() >= self.block_size as usize {
+                self.emit_block(false);
+            } else if deferred {
+                break;
+            } else if advanced == 0 && filled == 0 {
+                if consumed == input.len() {
+                    if flush && !self.lz77.has_lookahead() {
+                        self.emit_block(self.ends_stream);
+                        if !self.ends_stream {
+                            huffman::write_stored(&[], false, &mut self.writer);
+                        }
+                        self.finished = true;
+                    }
+                    break;
+                }
+                // The window is full of bytes the current block may still
+                // need for a stored fallback; close the block to free them.
+                self.emit_block(false);
+            }
+        }
+        ChunkResult {
+            consumed,
+            finished: self.finished,
+            suspended: !self.finished && (deferred || work >= budget),
+        }
+    }
+
+    fn emit_block(&mut self, last: bool) {
+        #[cfg(feature = "profiling")]
+        let start = crate::cycles::now();
+        let raw = self.lz77.block_data();
+        let raw_len = raw.len();
+        let (block, bits) = if self.dry_run {
+            self.huffman.choose(&self.tokens, raw)
+        } else {
+            self.huffman
+                .encode(&self.tokens, raw, last, &mut self.writer)
+        };
+        self.emitted_bits += bits as u64;
+        trace!(
+            "{:?} block: {} bytes in {} bits, {} tokens{}",
+            block,
+            raw_len,
+            bits,
+            self.tokens.len(),
+            if last { ", last" } else { "" }
+        );
+        self.recent.record(raw_len as u64, bits as u64);
+        if let Some(hook) = self.observers.as_mut().and_then(|o| o.on_block.as_mut()) {
+            hook(&BlockInfo {
+                block_type: block,
+                input_bytes: raw_len,
+                output_bits: bits,
+                stream_bits: self.emitted_bits,
+                last,
+            });
+        }
+        #[cfg(feature = "profiling")]
+        {
+            let write = core::mem::take(&mut self.huffman.write_cycles);
+            self.stats.bitwriter_cycles += write;
+            self.stats.huffman_cycles += crate::cycles::since(start).saturating_sub(write);
+        }
+        match block {
+            BlockType::Stored => self.stats.stored_blocks += 1,
+            BlockType::Fixed => self.stats.fixed_blocks += 1,
+            BlockType::Dynamic => self.stats.dynamic_blocks += 1,
+        }
+        for token in self.tokens.iter() {
+            match token {
+                Token::Literal(_) => self.stats.literals += 1,
+                Token::Match { .. } => self.stats.matches += 1,
+            }
+        }
+        self.tokens.clear();
+        self.lz77.start_block();
+        self.check_early_abort();
+    }
+
+    fn check_early_abort(&mut self) {
+        let processed = self.stats.work_units;
+        if processed >= self.abort_after
+            && self.emitted_bits * 100 > processed * 8 * self.abort_percent as u64
+        {
+            debug!(
+                "early abort after {} bytes: {} bits out, switching to stored blocks",
+                processed, self.emitted_bits
+            );
+            self.abort_after = u64::MAX;
+            self.store_only();
+        }
+    }
+
+    /// Writes stored blocks from here on, without any match finding or
+    /// Huffman coding: the level 0 path, and where early abort switches to.
+    fn store_only(&mut self) {
+        self.lz77.set_effort(0, 0);
+        self.huffman.stored_only = true;
+    }
+}
+
+/// `(after_bytes, max_ratio_percent)` of the configured early abort, with
+/// `u64::MAX` bytes meaning none.
+fn early_abort_settings(config: &CompressionConfig) -> (u64, u8) {
+    match config.early_abort {
+        Some(EarlyAbort {
+            after_bytes,
+            max_ratio_percent,
+        }) => (after_bytes, max_ratio_percent),
+        None => (u64::MAX, 0),
+    }
+}
@@ -0,0 +1,382 @@
+//! Delta updates: a new image compressed against the one already in flash.
+//!
+//! The target is cut into segments of [`SEGMENT`] bytes, each coded as an
+//! independent raw DEFLATE stream whose history is primed with a window of
+//! the reference image around the segment's counterpart. Unchanged code
+//! then costs a few bits per match instead of the full literals, while the
+//! device needs only the old image, readable in place, plus the usual 32KB
+//! inflate window.
+//!
+//! A patch is a 24-byte header followed by the segments. All fields are
+//! little-endian:
+//!
+//! | offset | size | field                                |
+//! |-------:|-----:|--------------------------------------|
+//! |      0 |    4 | magic, `TZDL`                        |
+//! |      4 |    1 | format version, currently 1          |
+//! |      5 |    3 | reserved, zero                       |
+//! |      8 |    4 | reference length                     |
+//! |     12 |    4 | CRC-32 of the reference              |
+//! |     16 |    4 | target length                        |
+//! |     20 |    4 | CRC-32 of the target                 |
+//!
+//! Each segment starts with the offset and length of its reference window
+//! and the payload length, 4 bytes each, followed by the payload.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::compressor::TrickleStatus;
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::crc32;
+use crate::decompressor::TrickleDecompressor;
+use crate::deflate::DeflateState;
+use crate::error::TrickleError;
+
+const MAGIC: [u8; 4] = *b"TZDL";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 24;
+const SEGMENT_HEADER_LEN: usize = 12;
+/// Target bytes per segment.
+pub const SEGMENT: usize = 8192;
+/// Reference bytes primed per segment. With the segment itself this fills
+/// the 32KB window, so the whole reference window stays reachable until the
+/// segment ends.
+const REFERENCE_WINDOW: usize = 32768 - SEGMENT;
+/// Bytes per sample when aligning a segment with the reference.
+const PROBE: usize = 16;
+
+/// Compresses `target` as a delta against `reference`, for
+/// [`DeltaDecoder`]. The container format and window size of `config` are
+/// ignored; segments are raw DEFLATE with a 32KB window. Meant for the
+/// build host: it keeps an index of the whole reference in memory.
+///
+/// ```
+/// use tricklezip::{build_delta, CompressionConfig, DeltaDecoder};
+///
+/// let old: Vec<u8> = (0..20_000u32).flat_map(|i| (i * 7).to_le_bytes()).collect();
+/// let mut new = old.clone();
+/// new[1000..1004].copy_from_slice(b"v2.1");
+/// let patch = build_delta(&old, &new, &CompressionConfig::firmware_image());
+/// assert!(patch.len() < new.len() / 50);
+///
+/// let mut decoder = DeltaDecoder::new(&old, &patch).unwrap();
+/// let mut out = vec![0u8; decoder.target_len()];
+/// let mut n = 0;
+/// loop {
+///     let m = decoder.read(&mut out[n..]).unwrap();
+///     if m == 0 {
+///         break;
+///     }
+///     n += m;
+/// }
+/// assert_eq!(out, new);
+/// ```
+pub fn build_delta(reference: &[u8], target: &[u8], config: &CompressionConfig) -> Vec<u8> {
+    let config = CompressionConfig {
+        window_size: 32768,
+        ..config.clone().with_format(ContainerFormat::Raw)
+    };
+    let mut patch = Vec::new();
+    patch.extend_from_slice(&MAGIC);
+    patch.extend_from_slice(&[FORMAT_VERSION, 0, 0, 0]);
+    for field in [
+        len_u32(reference.len()),
+        crc32::update(0, reference),
+        len_u32(target.len()),
+        crc32::update(0, target),
+    ] {
+        patch.extend_from_slice(&field.to_le_bytes());
+    }
+
+    let index = index_reference(reference);
+    let mut shift = 0i64;
+    for (i, segment) in target.chunks(SEGMENT).enumerate() {
+        let start = i * SEGMENT;
+        shift = estimate_shift(&index, reference, segment, start).unwrap_or(shift);
+        // Centre the counterpart in the window, leaving room for shifts in
+        // either direction within the segment.
+        let centre = start as i64 + shift + SEGMENT as i64 / 2;
+        let end = (centre + REFERENCE_WINDOW as i64 / 2).clamp(0, reference.len() as i64) as usize;
+        let window = &reference[end.saturating_sub(REFERENCE_WINDOW)..end];
+
+        let payload = compress_segment(segment, window, &config);
+        for field in [
+            len_u32(end - window.len()),
+            len_u32(window.len()),
+            len_u32(payload.len()),
+        ] {
+            patch.extend_from_slice(&field.to_le_bytes());
+        }
+        patch.extend_from_slice(&payload);
+    }
+    patch
+}
+
+fn len_u32(len: usize) -> u32 {
+    u32::try_from(len).expect("image larger than 4GB")
+}
+
+fn probe_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xCBF2_9CE4_8422_2325u64, |h, &b| {
+        (h ^ b as u64).wrapping_mul(0x100_0000_01B3)
+    })
+}
+
+/// Positions of reference samples, one every `PROBE` bytes.
+fn index_reference(reference: &[u8]) -> BTreeMap<u64, usize> {
+    let mut index = BTreeMap::new();
+    for pos in (0..reference.len().saturating_sub(PROBE - 1)).step_by(PROBE) {
+        index
+            .entry(probe_hash(&reference[pos..pos + PROBE]))
+            .or_insert(pos);
+    }
+    index
+}
+
+/// The most common offset between bytes of `segment` and their matches in
+/// the reference, if any match.
+fn estimate_shift(
+    index: &BTreeMap<u64, usize>,
+    reference: &[u8],
+    segment: &[u8],
+    start: usize,
+) -> Option<i64> {
+    let mut votes = BTreeMap::new();
+    for pos in 0..segment.len().saturating_sub(PROBE - 1) {
+        let probe = &segment[pos..pos + PROBE];
+        if let Some(&found) = index.get(&probe_hash(probe)) {
+            if &reference[found..found + PROBE] == probe {
+                *votes
+                    .entry(found as i64 - (start + pos) as i64)
+                    .or_insert(0u32) += 1;
+            }
+        }
+    }
+    votes
+        .into_iter()
+        .max_by_key(|&(_, count)| count)
+        .map(|(shift, _)| shift)
+}
+
+fn compress_segment(segment: &[u8], window: &[u8], config: &CompressionConfig) -> Vec<u8> {
+    let mut state = DeflateState::new(config);
+    state.prime(window);
+    let mut consumed = 0;
+    while !state.is_finished() {
+        consumed += state
+            .compress_chunk(&segment[consumed..], usize::MAX, true)
+            .consumed;
+    }
+    state.writer.align();
+    let mut out = vec![0; state.writer.pending_len()];
+    state.writer.drain(&mut out);
+    out
+}
+
+/// Applies a patch from [`build_delta`] to the reference image, producing
+/// the target a piece at a time, e.g. to program it into the inactive flash
+/// bank.
+///
+/// Besides the reference, which is only read, this needs the memory of one
+/// [`TrickleDecompressor`].
+pub struct DeltaDecoder<'a> {
+    reference: &'a [u8],
+    patch: &'a [u8],
+    /// Offset of the next unread patch byte.
+    pos: usize,
+    /// Unread payload of the current segment.
+    payload: &'a [u8],
+    in_segment: bool,
+    inflater: TrickleDecompressor,
+    target_len: u32,
+    target_crc: u32,
+    written: u64,
+    crc: u32,
+}
+
+impl<'a> DeltaDecoder<'a> {
+    /// Checks the patch header. Fails with `InvalidHeader` if `patch` is
+    /// not a delta patch or was built against a different reference.
+    pub fn new(reference: &'a [u8], patch: &'a [u8]) -> Result<Self, TrickleError> {
+        if patch.len() < HEADER_LEN {
+            return Err(TrickleError::UnexpectedEof);
+        }
+        if patch[..4] != MAGIC || patch[4] != FORMAT_VERSION {
+            return Err(TrickleError::InvalidHeader);
+        }
+        let field = |at: usize| read_u32(patch, at);
+        if field(8) as usize != reference.len() || field(12) != crc32::update(0, reference) {
+            return Err(TrickleError::InvalidHeader);
+        }
+        Ok(Self {
+            reference,
+            patch,
+            pos: HEADER_LEN,
+            payload: &[],
+            in_segment: false,
+            inflater: TrickleDecompressor::new(ContainerFormat::Raw),
+            target_len: field(16),
+            target_crc: field(20),
+            written: 0,
+            crc: 0,
+        })
+    }
+
+    /// Length of the image the patch produces.
+    pub fn target_len(&self) -> usize {
+        self.target_len as usize
+    }
+
+    /// Writes the next part of the target into `out`, returning its length.
+    /// Returns 0 once the whole target has been produced and its length
+    /// and CRC checked.
+    pub fn read(&mut self, out: &mut [u8]) -> Result<usize, TrickleError> {
+        if out.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if !self.in_segment {
+                if self.pos == self.patch.len() {
+                    if self.written != self.target_len as u64 || self.crc != self.target_crc {
+                        return Err(TrickleError::ChecksumMismatch);
+                    }
+                    return Ok(0);
+                }
+                self.start_segment()?;
+            }
+            let r = self.inflater.decompress_trickle(self.payload, out)?;
+            self.payload = &self.payload[r.consumed..];
+            self.crc = crc32::update(self.crc, &out[..r.written]);
+            self.written += r.written as u64;
+            if r.status == TrickleStatus::Finished {
+                self.in_segment = false;
+            } else if r.written == 0 && self.payload.is_empty() {
+                return Err(TrickleError::UnexpectedEof);
+            }
+            if r.written > 0 {
+                return Ok(r.written);
+            }
+        }
+    }
+
+    fn start_segment(&mut self) -> Result<(), TrickleError> {
+        let header = self
+            .patch
+            .get(self.pos..self.pos + SEGMENT_HEADER_LEN)
+            .ok_or(TrickleError::UnexpectedEof)?;
+        let (start, len, payload_len) = (
+            read_u32(header, 0) as usize,
+            read_u32(header, 4) as usize,
+            read_u32(header, 8) as usize,
+        );
+        // Ranges that overflow `usize` on 32-bit targets are as out of
+        // bounds as those that merely pass the end.
+        let window = start
+            .checked_add(len)
+            .and_then(|end| self.reference.get(start..end))
+            .ok_or(TrickleError::InvalidData)?;
+        self.pos += SEGMENT_HEADER_LEN;
+        self.payload = self
+            .pos
+            .checked_add(payload_len)
+            .and_then(|end| self.patch.get(self.pos..end))
+            .ok_or(TrickleError::UnexpectedEof)?;
+        self.pos += payload_len;
+        self.inflater.reset();
+        self.inflater.prime(window);
+        self.in_segment = true;
+        Ok(())
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+
+    fn firmware(seed: u32, len: usize) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|i| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                // Mostly structured, like machine code.
+                if i % 4 == 0 {
+                    (x >> 24) as u8
+                } else {
+                    (i / 64) as u8
+                }
+            })
+            .collect()
+    }
+
+    fn apply(reference: &[u8], patch: &[u8], piece: usize) -> Result<Vec<u8>, TrickleError> {
+        let mut decoder = DeltaDecoder::new(reference, patch)?;
+        let mut target = Vec::new();
+        let mut buf = vec![0u8; piece];
+        loop {
+            let n = decoder.read(&mut buf)?;
+            if n == 0 {
+                return Ok(target);
+            }
+            target.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn shifted_build_patches_small() {
+        let old = firmware(1, 100_000);
+        // A function inserted early shifts everything after it.
+        let mut new = old[..30_000].to_vec();
+        new.extend_from_slice(&firmware(2, 700));
+        new.extend_from_slice(&old[30_000..]);
+        new[90_000..90_010].copy_from_slice(b"2026-10-15");
+
+        let config = CompressionConfig::firmware_image();
+        let patch = build_delta(&old, &new, &config);
+        let full = compress_to_vec(&new, &config);
+        assert!(
+            patch.len() * 10 < full.len(),
+            "{} vs {}",
+            patch.len(),
+            full.len()
+        );
+        for piece in [1, 777, 65536] {
+            assert_eq!(apply(&old, &patch, piece).unwrap(), new);
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_reference_and_corruption() {
+        let old = firmware(1, 20_000);
+        let mut new = old.clone();
+        new[5] ^= 0xFF;
+        let patch = build_delta(&old, &new, &CompressionConfig::default());
+
+        let mut other = old.clone();
+        other[0] ^= 1;
+        assert!(matches!(
+            DeltaDecoder::new(&other, &patch),
+            Err(TrickleError::InvalidHeader)
+        ));
+        assert_eq!(
+            apply(&old, &patch[..patch.len() - 3], 4096),
+            Err(TrickleError::UnexpectedEof)
+        );
+        let mut bad = patch.clone();
+        bad[20] ^= 1;
+        assert_eq!(apply(&old, &bad, 4096), Err(TrickleError::ChecksumMismatch));
+        // Segment ranges reaching the end of the address space.
+        let mut bad = patch.clone();
+        bad[HEADER_LEN..HEADER_LEN + 8].fill(0xFF);
+        assert_eq!(apply(&old, &bad, 4096), Err(TrickleError::InvalidData));
+        let mut bad = patch.clone();
+        bad[HEADER_LEN + 8..HEADER_LEN + 12].fill(0xFF);
+        assert_eq!(apply(&old, &bad, 4096), Err(TrickleError::UnexpectedEof));
+    }
+}
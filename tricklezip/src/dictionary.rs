@@ -0,0 +1,199 @@
+//! Preset dictionaries trained on sample payloads.
+
+use std::collections::HashMap;
+
+use alloc::vec::Vec;
+
+use crate::adler32;
+
+/// Largest useful dictionary: DEFLATE cannot refer further back.
+pub const MAX_DICTIONARY_SIZE: usize = 32768;
+/// Length of the substrings counted across samples.
+const GRAM: usize = 8;
+
+/// A trained preset dictionary and its zlib `DICTID`, the Adler-32 of its
+/// bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dictionary {
+    bytes: Vec<u8>,
+    id: u32,
+}
+
+impl Dictionary {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// The `DICTID` a zlib header announces when this dictionary is used.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Builds a preset dictionary from sample payloads on the host.
+///
+/// Short messages such as telemetry JSON or log lines compress poorly on
+/// their own because each stream starts with an empty window. A dictionary
+/// of fragments common to many samples fills that window up front. The
+/// builder keeps maximal runs of substrings that occur in at least two
+/// samples, scores each by how many samples contain its substrings and
+/// packs the best into the size limit, most valuable last so the closest
+/// matches get the shortest distances.
+///
+/// ```
+/// use tricklezip::DictionaryBuilder;
+///
+/// let mut builder = DictionaryBuilder::new();
+/// for i in 0..100 {
+///     let sample = format!(r#"{{"device":"pump-{i}","status":"ok","temp_c":{}}}"#, 20 + i % 7);
+///     builder.add_sample(sample.as_bytes());
+/// }
+/// let dict = builder.build();
+/// assert!(dict.as_bytes().windows(9).any(|w| w == b"\"status\":"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct DictionaryBuilder {
+    samples: Vec<Vec<u8>>,
+    max_size: usize,
+}
+
+impl Default for DictionaryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DictionaryBuilder {
+    pub fn new() -> Self {
+        Self {
+            samples: Vec::new(),
+            max_size: MAX_DICTIONARY_SIZE,
+        }
+    }
+
+    /// Caps the dictionary at `max_size` bytes, at most
+    /// [`MAX_DICTIONARY_SIZE`]. Smaller dictionaries suit small windows:
+    /// only the last `window_size` bytes are reachable.
+    pub fn max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size.min(MAX_DICTIONARY_SIZE);
+        self
+    }
+
+    pub fn add_sample(&mut self, sample: &[u8]) {
+        self.samples.push(sample.to_vec());
+    }
+
+    pub fn build(&self) -> Dictionary {
+        // Number of samples containing each substring.
+        let mut counts: HashMap<&[u8], (u32, usize)> = HashMap::new();
+        for (i, sample) in self.samples.iter().enumerate() {
+            for gram in sample.windows(GRAM) {
+                let entry = counts.entry(gram).or_insert((0, usize::MAX));
+                if entry.1 != i {
+                    *entry = (entry.0 + 1, i);
+                }
+            }
+        }
+        let count = |gram: &[u8]| counts.get(gram).map_or(0, |&(n, _)| n);
+
+        // Maximal runs of shared substrings, scored by the sample count of
+        // each substring they cover.
+        let mut fragments: HashMap<&[u8], u64> = HashMap::new();
+        for sample in &self.samples {
+            let mut pos = 0;
+            while pos + GRAM <= sample.len() {
+                if count(&sample[pos..pos + GRAM]) < 2 {
+                    pos += 1;
+                    continue;
+                }
+                let start = pos;
+                let mut score = 0u64;
+                while pos + GRAM <= sample.len() && count(&sample[pos..pos + GRAM]) >= 2 {
+                    score += count(&sample[pos..pos + GRAM]) as u64;
+                    pos += 1;
+                }
+                fragments
+                    .entry(&sample[start..pos - 1 + GRAM])
+                    .or_insert(score);
+            }
+        }
+
+        let mut ranked: Vec<(&[u8], u64)> = fragments.into_iter().collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let mut chosen: Vec<&[u8]> = Vec::new();
+        let mut size = 0;
+        for (fragment, _) in ranked {
+            if size + fragment.len() > self.max_size {
+                continue;
+            }
+            if chosen
+                .iter()
+                .any(|c| c.windows(fragment.len()).any(|w| w == fragment))
+            {
+                continue;
+            }
+            size += fragment.len();
+            chosen.push(fragment);
+        }
+        let bytes: Vec<u8> = chosen
+            .iter()
+            .rev()
+            .flat_map(|f| f.iter().copied())
+            .collect();
+        Dictionary {
+            id: adler32::update(1, &bytes),
+            bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CompressionConfig, ContainerFormat};
+    use crate::deflate::DeflateState;
+
+    fn sample(i: u32) -> Vec<u8> {
+        alloc::format!(
+            r#"{{"ts":{},"device":"valve-{}","pressure_kpa":{},"state":"open","fw":"1.4.2"}}"#,
+            1_700_000_000 + i * 60,
+            i % 13,
+            100 + i % 37
+        )
+        .into_bytes()
+    }
+
+    fn compressed_len(data: &[u8], dict: &[u8]) -> usize {
+        let config = CompressionConfig::default().with_format(ContainerFormat::Raw);
+        let mut state = DeflateState::new(&config);
+        state.prime(dict);
+        let mut consumed = 0;
+        while !state.is_finished() {
+            consumed += state
+                .compress_chunk(&data[consumed..], usize::MAX, true)
+                .consumed;
+        }
+        state.writer.align();
+        state.writer.pending_len()
+    }
+
+    #[test]
+    fn dictionary_shrinks_unseen_messages() {
+        let mut builder = DictionaryBuilder::new().max_size(1024);
+        for i in 0..200 {
+            builder.add_sample(&sample(i));
+        }
+        let dict = builder.build();
+        assert!(!dict.as_bytes().is_empty() && dict.as_bytes().len() <= 1024);
+        assert_eq!(dict.id(), adler32::update(1, dict.as_bytes()));
+
+        let message = sample(1000);
+        let plain = compressed_len(&message, &[]);
+        let primed = compressed_len(&message, dict.as_bytes());
+        assert!(primed * 2 < plain, "{primed} vs {plain}");
+    }
+}
@@ -0,0 +1,125 @@
+//! Running compression inside Embassy tasks.
+
+use embassy_futures::yield_now;
+use embassy_time::Instant;
+
+use crate::async_compressor::AsyncTrickleCompressor;
+use crate::clock::TimeSource;
+use crate::compressor::{FlushMode, TrickleResult};
+use crate::error::TrickleError;
+
+/// [`TimeSource`] reading the Embassy time driver, in microseconds
+/// whatever `embassy_time::TICK_HZ` is, for
+/// [`TrickleCompressor::compress_timed_with`](crate::TrickleCompressor::compress_timed_with)
+/// in blocking code on an Embassy target.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmbassyClock;
+
+impl TimeSource for EmbassyClock {
+    fn now_ticks(&self) -> u64 {
+        Instant::now().as_micros()
+    }
+
+    fn ticks_per_ms(&self) -> u64 {
+        1000
+    }
+}
+
+impl AsyncTrickleCompressor {
+    /// [`compress`](Self::compress), yielding through
+    /// `embassy_futures::yield_now` between steps, so the executor polls
+    /// the other ready tasks before the next one.
+    pub async fn compress_embassy(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.compress_with(input, output, flush, yield_now).await
+    }
+
+    /// [`compress_embassy`](Self::compress_embassy), but also returns with
+    /// [`InProgress`](crate::TrickleStatus::InProgress) after the first
+    /// step that ends past `deadline`, so a task can bound the time spent
+    /// per message or per tick of its loop.
+    ///
+    /// ```no_run
+    /// use embassy_time::{Duration, Instant, Timer};
+    /// use tricklezip::{AsyncTrickleCompressor, FlushMode, TrickleStatus};
+    ///
+    /// async fn uplink(compressor: &mut AsyncTrickleCompressor, data: &[u8], packet: &mut [u8]) {
+    ///     let mut consumed = 0;
+    ///     loop {
+    ///         let deadline = Instant::now() + Duration::from_millis(2);
+    ///         let r = compressor
+    ///             .compress_until(&data[consumed..], packet, FlushMode::Finish, deadline)
+    ///             .await
+    ///             .unwrap();
+    ///         consumed += r.consumed;
+    ///         // Send packet[..r.written] here.
+    ///         if r.status == TrickleStatus::Finished {
+    ///             break;
+    ///         }
+    ///         Timer::after_millis(8).await;
+    ///     }
+    /// }
+    /// ```
+    pub async fn compress_until(
+        &mut self,
+        input: &[u8],
+        output: &mut [u8],
+        flush: FlushMode,
+        deadline: Instant,
+    ) -> Result<TrickleResult, TrickleError> {
+        self.run(input, output, flush, yield_now, || {
+            Instant::now() >= deadline
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decompress_to_vec, CompressionConfig, ContainerFormat, TrickleStatus};
+    use alloc::vec;
+    use embassy_futures::block_on;
+    use embassy_time::{Duration, MockDriver};
+
+    #[test]
+    fn clock_follows_the_time_driver() {
+        let before = EmbassyClock.now_ticks();
+        MockDriver::get().advance(Duration::from_millis(3));
+        assert!(EmbassyClock.now_ticks() - before >= 3000);
+    }
+
+    #[test]
+    fn passed_deadline_stops_after_one_step() {
+        let data = b"valve=open psi=41\n".repeat(200);
+        let config = CompressionConfig {
+            trickle_size: 256,
+            ..CompressionConfig::default()
+        };
+        let mut compressor = AsyncTrickleCompressor::new(config);
+        let mut out = vec![0u8; data.len()];
+        // The mock driver only moves when advanced, so this stays past due.
+        let deadline = Instant::now();
+        let r = block_on(compressor.compress_until(&data, &mut out, FlushMode::Finish, deadline))
+            .unwrap();
+        assert_eq!(r.status, TrickleStatus::InProgress);
+        assert_eq!(compressor.compressor().stats().trickle_calls, 1);
+
+        let rest = block_on(compressor.compress_embassy(
+            &data[r.consumed..],
+            &mut out[r.written..],
+            FlushMode::Finish,
+        ))
+        .unwrap();
+        assert_eq!(rest.status, TrickleStatus::Finished);
+        let written = r.written + rest.written;
+        assert_eq!(
+            decompress_to_vec(&out[..written], ContainerFormat::Raw).unwrap(),
+            data
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Cheap byte-level statistics for deciding whether data is worth
+//! compressing at all.
+
+/// Byte histogram of a chunk of data.
+///
+/// Order-0 entropy ignores repeats, so it overestimates what LZ77 can do on
+/// text and logs, but it reliably flags data that is already compressed or
+/// encrypted (close to 8 bits per byte). Feeding a few hundred bytes is
+/// usually enough to decide between compressing and storing a chunk.
+#[derive(Debug, Clone)]
+pub struct ByteHistogram {
+    counts: [u32; 256],
+    total: u32,
+}
+
+impl Default for ByteHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteHistogram {
+    pub const fn new() -> Self {
+        Self {
+            counts: [0; 256],
+            total: 0,
+        }
+    }
+
+    /// Counts the bytes of `data`. Meant for chunks, not whole streams:
+    /// counts saturate after 4GB.
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            self.counts[b as usize] = self.counts[b as usize].saturating_add(1);
+        }
+        self.total = self.total.saturating_add(data.len() as u32);
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Occurrences of each byte value.
+    pub fn counts(&self) -> &[u32; 256] {
+        &self.counts
+    }
+
+    /// Bytes counted so far.
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// Number of distinct byte values seen.
+    pub fn distinct(&self) -> usize {
+        self.counts.iter().filter(|&&c| c != 0).count()
+    }
+
+    /// Shannon entropy in bits per byte, from 0.0 (a single repeated value
+    /// or no data) to 8.0 (uniformly random). Accurate to about 0.01 bits.
+    pub fn entropy(&self) -> f32 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        let total = self.total as f32;
+        let sum: f32 = self
+            .counts
+            .iter()
+            .filter(|&&c| c != 0)
+            .map(|&c| c as f32 * log2(c as f32))
+            .sum();
+        (log2(total) - sum / total).clamp(0.0, 8.0)
+    }
+}
+
+/// Shannon entropy of `data` in bits per byte; see [`ByteHistogram`].
+pub fn byte_entropy(data: &[u8]) -> f32 {
+    let mut histogram = ByteHistogram::new();
+    histogram.update(data);
+    histogram.entropy()
+}
+
+/// Base-2 logarithm of a positive finite `x`. `f32::log2` needs `std`, so
+/// this splits off the exponent and fits the mantissa with a quadratic
+/// (error below 0.005).
+fn log2(x: f32) -> f32 {
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    let m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+    exponent as f32 + (-0.344_848_43 * m + 2.024_665_8) * m - 0.674_877_6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn entropy_bounds() {
+        assert_eq!(byte_entropy(&[]), 0.0);
+        assert!(byte_entropy(&[7; 1000]) < 0.01);
+        let two: Vec<u8> = (0..1000).map(|i| (i % 2) as u8).collect();
+        assert!((byte_entropy(&two) - 1.0).abs() < 0.01);
+        let all: Vec<u8> = (0..4096).map(|i| i as u8).collect();
+        assert!((byte_entropy(&all) - 8.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn text_scores_below_random() {
+        let text = b"The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let mut x = 1u32;
+        let noise: Vec<u8> = (0..1024)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        let mut histogram = ByteHistogram::new();
+        histogram.update(&text);
+        assert_eq!(histogram.total(), text.len() as u32);
+        assert_eq!(histogram.distinct(), 29);
+        assert!(histogram.entropy() < 5.0);
+        assert!(byte_entropy(&noise) > 7.5);
+    }
+}
@@ -0,0 +1,124 @@
+use core::fmt;
+
+/// Errors reported by the compressor and decompressor.
+///
+/// Streaming calls never fail for lack of input or output space; they
+/// report that through [`TrickleStatus`](crate::TrickleStatus) instead.
+/// Every error they return leaves the stream unusable until `reset()`,
+/// except [`NeedDictionary`](Self::NeedDictionary).
+/// Only calls working on complete buffers, such as
+/// [`decompress_into`](crate::decompress_into) and
+/// [`FrameDecoder::decode`](crate::FrameDecoder::decode), fail with the two
+/// errors that [`is_recoverable`](Self::is_recoverable) accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrickleError {
+    /// The stream has already been finished; call `reset()` to start a new one.
+    StreamFinished,
+    /// The compressed data is not valid DEFLATE.
+    InvalidData,
+    /// The zlib or gzip header is malformed or uses an unsupported feature.
+    InvalidHeader,
+    /// The checksum or length in the container trailer does not match the data.
+    ChecksumMismatch,
+    /// The input ended before the end of the compressed stream.
+    UnexpectedEof,
+    /// The output buffer cannot hold the decompressed data.
+    OutputTooSmall,
+    /// The stream needs a larger window than the decompressor has: its zlib
+    /// header declares one, or a match reaches further back.
+    WindowTooLarge,
+    /// A configuration setting is out of range; `field` names it.
+    InvalidConfig { field: &'static str },
+    /// The stream goes beyond one of the decompressor's
+    /// [`DecodeLimits`](crate::DecodeLimits); `limit` names the field.
+    LimitExceeded { limit: &'static str },
+    /// The zlib header asks for the preset dictionary whose Adler-32 is
+    /// `id`. Pass it to
+    /// [`TrickleDecompressor::set_dictionary`](crate::TrickleDecompressor::set_dictionary)
+    /// and repeat the call, which consumed no input.
+    NeedDictionary { id: u32 },
+}
+
+impl TrickleError {
+    /// Whether retrying the same call can succeed: with more input after
+    /// `UnexpectedEof`, a larger output buffer after `OutputTooSmall`, or
+    /// the dictionary set after `NeedDictionary`.
+    /// The other errors mean the data is corrupt or the stream is over, so
+    /// the stream has to be `reset()` and the data obtained again.
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            TrickleError::UnexpectedEof
+            | TrickleError::OutputTooSmall
+            | TrickleError::NeedDictionary { .. } => true,
+            TrickleError::StreamFinished
+            | TrickleError::InvalidData
+            | TrickleError::InvalidHeader
+            | TrickleError::ChecksumMismatch
+            | TrickleError::WindowTooLarge
+            | TrickleError::InvalidConfig { .. }
+            | TrickleError::LimitExceeded { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for TrickleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            TrickleError::StreamFinished => "stream already finished",
+            TrickleError::InvalidData => "invalid compressed data",
+            TrickleError::InvalidHeader => "invalid container header",
+            TrickleError::ChecksumMismatch => "checksum mismatch",
+            TrickleError::UnexpectedEof => "unexpected end of input",
+            TrickleError::OutputTooSmall => "output buffer too small",
+            TrickleError::WindowTooLarge => "stream window larger than decoder window",
+            TrickleError::InvalidConfig { field } => {
+                return write!(f, "invalid configuration: {field}");
+            }
+            TrickleError::LimitExceeded { limit } => {
+                return write!(f, "decode limit exceeded: {limit}");
+            }
+            TrickleError::NeedDictionary { id } => {
+                return write!(f, "preset dictionary {id:08x} needed");
+            }
+        };
+        f.write_str(msg)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for TrickleError {}
+
+#[cfg(feature = "std")]
+impl From<TrickleError> for std::io::Error {
+    fn from(err: TrickleError) -> Self {
+        let kind = match err {
+            TrickleError::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            TrickleError::InvalidConfig { .. } => std::io::ErrorKind::InvalidInput,
+            _ => std::io::ErrorKind::InvalidData,
+        };
+        std::io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compressor::compress_to_vec;
+    use crate::config::CompressionConfig;
+    use crate::oneshot::decompress_into;
+
+    #[test]
+    fn recoverable_errors_succeed_on_retry() {
+        let packed = compress_to_vec(&[b'x'; 300], &CompressionConfig::default());
+        let mut out = [0u8; 300];
+
+        let err = decompress_into(&packed, &mut out[..100]).unwrap_err();
+        assert!(err.is_recoverable());
+        assert_eq!(decompress_into(&packed, &mut out), Ok(300));
+
+        let err = decompress_into(&packed[..packed.len() - 1], &mut out).unwrap_err();
+        assert!(err.is_recoverable());
+
+        let err = decompress_into(&[0xFF; 8], &mut out).unwrap_err();
+        assert!(!err.is_recoverable());
+    }
+}
@@ -0,0 +1,496 @@
+//! Structured description of a DEFLATE stream, in the spirit of Mark
+//! Adler's `infgen`, for debugging interoperability problems.
+//!
+//! Unlike the streaming decoder this works on a complete buffer and keeps
+//! everything it sees: block boundaries as bit offsets, code lengths and
+//! every symbol. Expect the description to take several times the memory of
+//! the decompressed data.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::compressor::FDICT;
+use crate::config::ContainerFormat;
+use crate::error::TrickleError;
+use crate::huffman::{
+    BlockType, CLEN_ORDER, DIST_BASE, DIST_EXTRA, END_OF_BLOCK, LENGTH_BASE, LENGTH_EXTRA,
+};
+use crate::inflate::Huffman;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// One decoded symbol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symbol {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+/// Code lengths transmitted in a dynamic block header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeLengths {
+    /// Lengths of the code length code, in symbol order (not transmission
+    /// order).
+    pub code_length: [u8; 19],
+    /// HLIT + 257 literal/length code lengths.
+    pub literal_length: Vec<u8>,
+    /// HDIST + 1 distance code lengths.
+    pub distance: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockDescription {
+    pub block_type: BlockType,
+    /// BFINAL was set.
+    pub last: bool,
+    /// Bit offset of the block header from the start of the DEFLATE data.
+    pub start_bit: u64,
+    /// Encoded size including the header and end-of-block code.
+    pub bits: u64,
+    /// Decompressed bytes the block produces.
+    pub output_len: usize,
+    /// Present for dynamic blocks only.
+    pub code_lengths: Option<CodeLengths>,
+    /// Decoded symbols, excluding end-of-block. Stored blocks list their
+    /// bytes as literals.
+    pub symbols: Vec<Symbol>,
+}
+
+/// Everything [`explain`] found in a stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamDescription {
+    pub format: ContainerFormat,
+    /// Bytes of container header before the DEFLATE data.
+    pub header_len: usize,
+    pub blocks: Vec<BlockDescription>,
+    /// Bytes after the DEFLATE data, including the container trailer.
+    pub trailing: Vec<u8>,
+}
+
+/// Walks the stream in `data` and describes every block without checking
+/// the container checksum. Fails at the first invalid code, like the
+/// decoder, so a partial description of a corrupt stream is not available.
+///
+/// The [`Display`](fmt::Display) output is infgen-like text, one directive
+/// per line, which diffs well against the output of infgen itself. With
+/// [`ContainerFormat::Auto`] the description carries the detected format.
+pub fn explain(data: &[u8], format: ContainerFormat) -> Result<StreamDescription, TrickleError> {
+    let format = match format {
+        ContainerFormat::Auto => {
+            ContainerFormat::detect(data).ok_or(TrickleError::UnexpectedEof)?
+        }
+        format => format,
+    };
+    let header_len = header_len(data, format)?;
+    let mut bits = Bits {
+        data: &data[header_len..],
+        pos: 0,
+    };
+    let mut blocks = Vec::new();
+    loop {
+        let block = read_block(&mut bits)?;
+        let last = block.last;
+        blocks.push(block);
+        if last {
+            break;
+        }
+    }
+    let end = header_len + bits.pos.div_ceil(8) as usize;
+    Ok(StreamDescription {
+        format,
+        header_len,
+        blocks,
+        trailing: data[end..].to_vec(),
+    })
+}
+
+fn header_len(data: &[u8], format: ContainerFormat) -> Result<usize, TrickleError> {
+    let byte = |i: usize| data.get(i).copied().ok_or(TrickleError::UnexpectedEof);
+    match format {
+        ContainerFormat::Raw | ContainerFormat::Auto => Ok(0),
+        ContainerFormat::Zlib => {
+            let (cmf, flg) = (byte(0)?, byte(1)?);
+            if cmf & 0x0F != 8 || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+                return Err(TrickleError::InvalidHeader);
+            }
+            // A DICTID follows the FLG byte if FDICT is set.
+            Ok(if flg & FDICT != 0 { 6 } else { 2 })
+        }
+        ContainerFormat::Gzip => {
+            if byte(0)? != 0x1F || byte(1)? != 0x8B || byte(2)? != 8 {
+                return Err(TrickleError::InvalidHeader);
+            }
+            let flags = byte(3)?;
+            let mut pos = 10;
+            if flags & FEXTRA != 0 {
+                pos += 2 + u16::from_le_bytes([byte(pos)?, byte(pos + 1)?]) as usize;
+            }
+            for flag in [FNAME, FCOMMENT] {
+                if flags & flag != 0 {
+                    while byte(pos)? != 0 {
+                        pos += 1;
+                    }
+                    pos += 1;
+                }
+            }
+            if flags & FHCRC != 0 {
+                pos += 2;
+            }
+            if pos > data.len() {
+                return Err(TrickleError::UnexpectedEof);
+            }
+            Ok(pos)
+        }
+    }
+}
+
+/// LSB-first reader over a complete buffer.
+struct Bits<'a> {
+    data: &'a [u8],
+    pos: u64,
+}
+
+impl Bits<'_> {
+    /// At least 56 upcoming bits, zero padded past the end.
+    fn peek(&self) -> u64 {
+        let start = (self.pos / 8) as usize;
+        let mut word = [0u8; 8];
+        if start < self.data.len() {
+            let n = (self.data.len() - start).min(8);
+            word[..n].copy_from_slice(&self.data[start..start + n]);
+        }
+        u64::from_le_bytes(word) >> (self.pos % 8)
+    }
+
+    fn skip(&mut self, n: u32) -> Result<(), TrickleError> {
+        if self.pos + n as u64 > self.data.len() as u64 * 8 {
+            return Err(TrickleError::UnexpectedEof);
+        }
+        self.pos += n as u64;
+        Ok(())
+    }
+
+    fn take(&mut self, n: u32) -> Result<u32, TrickleError> {
+        let value = (self.peek() & ((1u64 << n) - 1)) as u32;
+        self.skip(n)?;
+        Ok(value)
+    }
+
+    fn decode<const N: usize>(&mut self, code: &Huffman<N>) -> Result<usize, TrickleError> {
+        let (sym, len) = code
+            .decode_bits(self.peek())
+            .ok_or(TrickleError::InvalidData)?;
+        self.skip(len)?;
+        Ok(sym)
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+fn read_block(bits: &mut Bits) -> Result<BlockDescription, TrickleError> {
+    let start_bit = bits.pos;
+    let last = bits.take(1)? == 1;
+    let mut block = BlockDescription {
+        block_type: BlockType::Stored,
+        last,
+        start_bit,
+        bits: 0,
+        output_len: 0,
+        code_lengths: None,
+        symbols: Vec::new(),
+    };
+    match bits.take(2)? {
+        0 => {
+            bits.align();
+            let len = bits.take(16)?;
+            if bits.take(16)? != !len & 0xFFFF {
+                return Err(TrickleError::InvalidData);
+            }
+            for _ in 0..len {
+                block.symbols.push(Symbol::Literal(bits.take(8)? as u8));
+            }
+        }
+        1 => {
+            block.block_type = BlockType::Fixed;
+            let mut lengths = [0u8; 288 + 30];
+            lengths[..144].fill(8);
+            lengths[144..256].fill(9);
+            lengths[256..280].fill(7);
+            lengths[280..288].fill(8);
+            lengths[288..].fill(5);
+            // Distance codes 30 and 31 are never used, leaving the fixed
+            // distance code incomplete.
+            read_codes(
+                bits,
+                &lengths[..288],
+                &lengths[288..],
+                false,
+                &mut block.symbols,
+            )?;
+        }
+        2 => {
+            block.block_type = BlockType::Dynamic;
+            let lengths = read_code_lengths(bits)?;
+            read_codes(
+                bits,
+                &lengths.literal_length,
+                &lengths.distance,
+                true,
+                &mut block.symbols,
+            )?;
+            block.code_lengths = Some(lengths);
+        }
+        _ => return Err(TrickleError::InvalidData),
+    }
+    block.bits = bits.pos - start_bit;
+    block.output_len = block
+        .symbols
+        .iter()
+        .map(|s| match *s {
+            Symbol::Literal(_) => 1,
+            Symbol::Match { length, .. } => length as usize,
+        })
+        .sum();
+    Ok(block)
+}
+
+fn read_code_lengths(bits: &mut Bits) -> Result<CodeLengths, TrickleError> {
+    let hlit = bits.take(5)? as usize + 257;
+    let hdist = bits.take(5)? as usize + 1;
+    let hclen = bits.take(4)? as usize + 4;
+    if hlit > 286 || hdist > 30 {
+        return Err(TrickleError::InvalidData);
+    }
+    let mut code_length = [0u8; 19];
+    for &sym in &CLEN_ORDER[..hclen] {
+        code_length[sym] = bits.take(3)? as u8;
+    }
+    let mut clen = Huffman::<19>::new();
+    if clen.build(&code_length)? != 0 {
+        return Err(TrickleError::InvalidData);
+    }
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (value, repeat) = match bits.decode(&clen)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => {
+                let &previous = lengths.last().ok_or(TrickleError::InvalidData)?;
+                (previous, 3 + bits.take(2)? as usize)
+            }
+            17 => (0, 3 + bits.take(3)? as usize),
+            _ => (0, 11 + bits.take(7)? as usize),
+        };
+        if lengths.len() + repeat > hlit + hdist {
+            return Err(TrickleError::InvalidData);
+        }
+        lengths.resize(lengths.len() + repeat, value);
+    }
+    let distance = lengths.split_off(hlit);
+    Ok(CodeLengths {
+        code_length,
+        literal_length: lengths,
+        distance,
+    })
+}
+
+fn read_codes(
+    bits: &mut Bits,
+    lit_lengths: &[u8],
+    dist_lengths: &[u8],
+    complete: bool,
+    symbols: &mut Vec<Symbol>,
+) -> Result<(), TrickleError> {
+    let mut lit = Huffman::<288>::new();
+    let mut dist = Huffman::<30>::new();
+    if lit_lengths[END_OF_BLOCK] == 0 {
+        return Err(TrickleError::InvalidData);
+    }
+    let lit_left = lit.build(lit_lengths)?;
+    let dist_left = dist.build(dist_lengths)?;
+    if complete
+        && (lit_left != 0 && !lit.incomplete_ok() || dist_left != 0 && !dist.incomplete_ok())
+    {
+        return Err(TrickleError::InvalidData);
+    }
+    // Distances reaching before the start of the stream are reported as
+    // they are; the description does not track output.
+    loop {
+        let sym = bits.decode(&lit)?;
+        if sym < END_OF_BLOCK {
+            symbols.push(Symbol::Literal(sym as u8));
+            continue;
+        }
+        if sym == END_OF_BLOCK {
+            return Ok(());
+        }
+        let li = sym - 257;
+        if li >= LENGTH_BASE.len() {
+            return Err(TrickleError::InvalidData);
+        }
+        let length = LENGTH_BASE[li] + bits.take(LENGTH_EXTRA[li] as u32)? as u16;
+        let di = bits.decode(&dist)?;
+        if di >= DIST_BASE.len() {
+            return Err(TrickleError::InvalidData);
+        }
+        let distance = DIST_BASE[di] + bits.take(DIST_EXTRA[di] as u32)? as u16;
+        symbols.push(Symbol::Match { length, distance });
+    }
+}
+
+impl fmt::Display for StreamDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            ContainerFormat::Raw | ContainerFormat::Auto => writeln!(f, "! raw deflate")?,
+            ContainerFormat::Zlib => writeln!(f, "zlib")?,
+            ContainerFormat::Gzip => writeln!(f, "gzip")?,
+        }
+        for block in &self.blocks {
+            write!(f, "{block}")?;
+        }
+        writeln!(f, "! {} trailing bytes", self.trailing.len())
+    }
+}
+
+impl fmt::Display for BlockDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "!")?;
+        writeln!(
+            f,
+            "! block at bit {}, {} bits, {} bytes out",
+            self.start_bit, self.bits, self.output_len
+        )?;
+        if self.last {
+            writeln!(f, "last")?;
+        }
+        match self.block_type {
+            BlockType::Stored => writeln!(f, "stored")?,
+            BlockType::Fixed => writeln!(f, "fixed")?,
+            BlockType::Dynamic => writeln!(f, "dynamic")?,
+        }
+        if let Some(lengths) = &self.code_lengths {
+            for (sym, &len) in lengths.code_length.iter().enumerate() {
+                if len != 0 {
+                    writeln!(f, "code {sym} {len}")?;
+                }
+            }
+            for (sym, &len) in lengths.literal_length.iter().enumerate() {
+                if len != 0 {
+                    writeln!(f, "litlen {sym} {len}")?;
+                }
+            }
+            for (sym, &len) in lengths.distance.iter().enumerate() {
+                if len != 0 {
+                    writeln!(f, "dist {sym} {len}")?;
+                }
+            }
+        }
+        let mut text = false;
+        for symbol in &self.symbols {
+            match *symbol {
+                Symbol::Literal(b) if (0x20..0x7F).contains(&b) && b != b'\'' => {
+                    if !text {
+                        write!(f, "literal '")?;
+                        text = true;
+                    }
+                    write!(f, "{}", b as char)?;
+                }
+                Symbol::Literal(b) => {
+                    if text {
+                        writeln!(f)?;
+                        text = false;
+                    }
+                    writeln!(f, "literal {b}")?;
+                }
+                Symbol::Match { length, distance } => {
+                    if text {
+                        writeln!(f)?;
+                        text = false;
+                    }
+                    writeln!(f, "match {length} {distance}")?;
+                }
+            }
+        }
+        if text {
+            writeln!(f)?;
+        }
+        if self.block_type != BlockType::Stored {
+            writeln!(f, "end")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+    use crate::config::{CompressionConfig, Strategy};
+    use alloc::string::ToString;
+
+    #[test]
+    fn describes_every_block_type() {
+        let data = b"status=ok temp=21 status=ok temp=22\n".repeat(300);
+        let dynamic = if cfg!(feature = "tiny") {
+            BlockType::Fixed
+        } else {
+            BlockType::Dynamic
+        };
+        for (strategy, expected) in [
+            (Strategy::Default, dynamic),
+            (Strategy::Fixed, BlockType::Fixed),
+        ] {
+            let config = CompressionConfig::default()
+                .with_format(ContainerFormat::Gzip)
+                .with_strategy(strategy);
+            let packed = compress_to_vec(&data, &config);
+            let description = explain(&packed, ContainerFormat::Gzip).unwrap();
+            assert_eq!(description.header_len, 10);
+            assert_eq!(description.trailing.len(), 8);
+            assert!(description.blocks.iter().all(|b| b.block_type == expected));
+            assert!(description.blocks.last().unwrap().last);
+            let out: usize = description.blocks.iter().map(|b| b.output_len).sum();
+            assert_eq!(out, data.len());
+            let text = description.to_string();
+            assert!(text.starts_with("gzip\n"));
+            assert!(text.contains("literal 'status=ok temp=21"));
+        }
+
+        // Incompressible input falls back to stored blocks.
+        let mut x = 5u32;
+        let noise: Vec<u8> = (0..3000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        let packed = compress_to_vec(&noise, &CompressionConfig::default());
+        let description = explain(&packed, ContainerFormat::Raw).unwrap();
+        assert_eq!(description.blocks[0].block_type, BlockType::Stored);
+        let symbols: Vec<u8> = description
+            .blocks
+            .iter()
+            .flat_map(|b| &b.symbols)
+            .map(|s| match *s {
+                Symbol::Literal(b) => b,
+                Symbol::Match { .. } => panic!("match in stored block"),
+            })
+            .collect();
+        assert_eq!(symbols, noise);
+    }
+
+    #[test]
+    fn truncated_stream_is_reported() {
+        let packed = compress_to_vec(b"hello hello hello", &CompressionConfig::default());
+        assert_eq!(
+            explain(&packed[..packed.len() - 1], ContainerFormat::Raw),
+            Err(TrickleError::UnexpectedEof)
+        );
+    }
+}
@@ -0,0 +1,655 @@
+//! zlib-compatible C ABI, so C code written against `zlib.h` can link
+//! against TrickleZip instead.
+//!
+//! [`z_stream`] has zlib's layout and the exported functions follow zlib's
+//! contracts for the subset implemented here: `deflateInit_`,
+//! `deflateInit2_`, `deflate`, `deflateReset`, `deflateEnd`, `deflateBound`,
+//! `inflateInit_`, `inflateInit2_`, `inflate`, `inflateReset`, `inflateEnd`
+//! and `zlibVersion`. Link it by building the crate as a `staticlib` with
+//! the `ffi-zlib` feature, e.g.
+//! `cargo rustc --release --features ffi-zlib --crate-type staticlib`.
+//!
+//! Differences from zlib:
+//!
+//! - State is allocated with the Rust global allocator; `zalloc`, `zfree`
+//!   and `opaque` are ignored.
+//! - `Z_PARTIAL_FLUSH` and `Z_BLOCK` flush like `Z_SYNC_FLUSH`.
+//! - `memLevel` is ignored, and preset dictionaries are not supported:
+//!   `inflate` returns `Z_NEED_DICT` with the dictionary's Adler-32 in
+//!   `adler` for a zlib stream that needs one, but there is no
+//!   `inflateSetDictionary` to supply it.
+//! - `inflate` with `windowBits` + 32 detects gzip or zlib from the first
+//!   byte of input.
+
+#![allow(non_camel_case_types, non_snake_case)]
+
+use alloc::boxed::Box;
+use core::ffi::{c_char, c_int, c_uint, c_ulong, c_void};
+use core::ptr;
+
+use crate::compressor::{compress_bound, FlushMode, TrickleCompressor, TrickleStatus};
+use crate::config::{CompressionConfig, CompressionLevel, ContainerFormat, Strategy};
+use crate::decompressor::TrickleDecompressor;
+use crate::error::TrickleError;
+
+pub const Z_NO_FLUSH: c_int = 0;
+pub const Z_PARTIAL_FLUSH: c_int = 1;
+pub const Z_SYNC_FLUSH: c_int = 2;
+pub const Z_FULL_FLUSH: c_int = 3;
+pub const Z_FINISH: c_int = 4;
+pub const Z_BLOCK: c_int = 5;
+
+pub const Z_OK: c_int = 0;
+pub const Z_STREAM_END: c_int = 1;
+pub const Z_NEED_DICT: c_int = 2;
+pub const Z_STREAM_ERROR: c_int = -2;
+pub const Z_DATA_ERROR: c_int = -3;
+pub const Z_MEM_ERROR: c_int = -4;
+pub const Z_BUF_ERROR: c_int = -5;
+pub const Z_VERSION_ERROR: c_int = -6;
+
+pub const Z_DEFAULT_COMPRESSION: c_int = -1;
+pub const Z_FILTERED: c_int = 1;
+pub const Z_HUFFMAN_ONLY: c_int = 2;
+pub const Z_RLE: c_int = 3;
+pub const Z_FIXED: c_int = 4;
+pub const Z_DEFLATED: c_int = 8;
+
+/// zlib version this shim claims to be compatible with.
+const VERSION: &core::ffi::CStr = c"1.2.13";
+
+pub type alloc_func = Option<unsafe extern "C" fn(*mut c_void, c_uint, c_uint) -> *mut c_void>;
+pub type free_func = Option<unsafe extern "C" fn(*mut c_void, *mut c_void)>;
+
+/// zlib's `z_stream`, field for field.
+#[repr(C)]
+pub struct z_stream {
+    pub next_in: *const u8,
+    pub avail_in: c_uint,
+    pub total_in: c_ulong,
+    pub next_out: *mut u8,
+    pub avail_out: c_uint,
+    pub total_out: c_ulong,
+    pub msg: *const c_char,
+    pub state: *mut c_void,
+    pub zalloc: alloc_func,
+    pub zfree: free_func,
+    pub opaque: *mut c_void,
+    pub data_type: c_int,
+    pub adler: c_ulong,
+    pub reserved: c_ulong,
+}
+
+/// `None` while waiting for the first byte to tell gzip from zlib.
+type Inflater = Option<TrickleDecompressor>;
+
+/// Checks the header version and struct size the way zlib does, so a
+/// mismatched `zlib.h` is caught instead of corrupting memory.
+unsafe fn version_ok(version: *const c_char, stream_size: c_int) -> bool {
+    !version.is_null()
+        && *version == b'1' as c_char
+        && stream_size as usize == core::mem::size_of::<z_stream>()
+}
+
+/// Format and window size from zlib's `windowBits` convention: 8..15 for
+/// zlib, negative for raw DEFLATE and +16 for gzip. `None` if out of range.
+fn window_format(window_bits: c_int) -> Option<(ContainerFormat, usize)> {
+    let (format, bits) = match window_bits {
+        -15..=-8 => (ContainerFormat::Raw, -window_bits),
+        8..=15 => (ContainerFormat::Zlib, window_bits),
+        24..=31 => (ContainerFormat::Gzip, window_bits - 16),
+        _ => return None,
+    };
+    Some((format, 1 << bits))
+}
+
+fn error_message(err: TrickleError) -> &'static core::ffi::CStr {
+    match err {
+        TrickleError::StreamFinished => c"stream already finished",
+        TrickleError::InvalidData => c"invalid compressed data",
+        TrickleError::InvalidHeader => c"incorrect header check",
+        TrickleError::ChecksumMismatch => c"incorrect data check",
+        TrickleError::UnexpectedEof => c"unexpected end of input",
+        TrickleError::OutputTooSmall => c"output buffer too small",
+        TrickleError::WindowTooLarge => c"invalid window size",
+        TrickleError::InvalidConfig { .. } => c"invalid parameter",
+        TrickleError::LimitExceeded { .. } => c"decode limit exceeded",
+        TrickleError::NeedDictionary { .. } => c"need dictionary",
+    }
+}
+
+/// # Safety
+///
+/// `strm` must point to a `z_stream` and `version` to a C string, as for
+/// zlib's `deflateInit_`.
+#[no_mangle]
+pub unsafe extern "C" fn deflateInit_(
+    strm: *mut z_stream,
+    level: c_int,
+    version: *const c_char,
+    stream_size: c_int,
+) -> c_int {
+    deflateInit2_(strm, level, Z_DEFLATED, 15, 8, 0, version, stream_size)
+}
+
+/// # Safety
+///
+/// As for [`deflateInit_`].
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn deflateInit2_(
+    strm: *mut z_stream,
+    level: c_int,
+    method: c_int,
+    window_bits: c_int,
+    _mem_level: c_int,
+    strategy: c_int,
+    version: *const c_char,
+    stream_size: c_int,
+) -> c_int {
+    if !version_ok(version, stream_size) {
+        return Z_VERSION_ERROR;
+    }
+    let Some(strm) = strm.as_mut() else {
+        return Z_STREAM_ERROR;
+    };
+    let Some((format, window_size)) = window_format(window_bits) else {
+        return Z_STREAM_ERROR;
+    };
+    let level = match level {
+        Z_DEFAULT_COMPRESSION => CompressionLevel::DEFAULT,
+        0..=9 => CompressionLevel::new(level as u8),
+        _ => return Z_STREAM_ERROR,
+    };
+    let strategy = match strategy {
+        0 => Strategy::Default,
+        Z_FILTERED => Strategy::Filtered,
+        Z_HUFFMAN_ONLY => Strategy::HuffmanOnly,
+        Z_RLE => Strategy::Rle,
+        Z_FIXED => Strategy::Fixed,
+        _ => return Z_STREAM_ERROR,
+    };
+    if method != Z_DEFLATED {
+        return Z_STREAM_ERROR;
+    }
+    let config = CompressionConfig::new(level)
+        .with_format(format)
+        .with_strategy(strategy)
+        .with_window_size(window_size);
+    let compressor = Box::new(TrickleCompressor::new(config));
+    strm.state = Box::into_raw(compressor).cast();
+    strm.msg = ptr::null();
+    strm.total_in = 0;
+    strm.total_out = 0;
+    strm.adler = if format == ContainerFormat::Gzip {
+        0
+    } else {
+        1
+    };
+    Z_OK
+}
+
+unsafe fn deflate_state<'a>(
+    strm: *mut z_stream,
+) -> Option<(&'a mut z_stream, &'a mut TrickleCompressor)> {
+    let strm = strm.as_mut()?;
+    let state = strm.state.cast::<TrickleCompressor>().as_mut()?;
+    Some((strm, state))
+}
+
+/// # Safety
+///
+/// `strm` must have been initialized with `deflateInit_` or
+/// `deflateInit2_`, and `next_in`/`next_out` must be valid for
+/// `avail_in`/`avail_out` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn deflate(strm: *mut z_stream, flush: c_int) -> c_int {
+    let Some((strm, compressor)) = deflate_state(strm) else {
+        return Z_STREAM_ERROR;
+    };
+    if !(Z_NO_FLUSH..=Z_BLOCK).contains(&flush) {
+        return Z_STREAM_ERROR;
+    }
+    if compressor.is_finished() {
+        return if flush == Z_FINISH {
+            Z_STREAM_END
+        } else {
+            Z_STREAM_ERROR
+        };
+    }
+    let input = slice_or_empty(strm.next_in, strm.avail_in);
+    let output = slice_mut_or_empty(strm.next_out, strm.avail_out);
+    let flush = match flush {
+        Z_NO_FLUSH => FlushMode::None,
+        Z_FULL_FLUSH => FlushMode::Full,
+        Z_FINISH => FlushMode::Finish,
+        _ => FlushMode::Sync,
+    };
+    let (mut consumed, mut written) = (0, 0);
+    let status = loop {
+        let r = match compressor.compress_trickle(&input[consumed..], &mut output[written..], flush)
+        {
+            Ok(r) => r,
+            Err(err) => {
+                strm.msg = error_message(err).as_ptr();
+                return Z_STREAM_ERROR;
+            }
+        };
+        consumed += r.consumed;
+        written += r.written;
+        if r.status != TrickleStatus::InProgress {
+            break r.status;
+        }
+    };
+    advance(strm, consumed, written);
+    strm.adler = compressor.checksum().unwrap_or(0) as c_ulong;
+    match status {
+        TrickleStatus::Finished => Z_STREAM_END,
+        _ if consumed == 0 && written == 0 => Z_BUF_ERROR,
+        _ => Z_OK,
+    }
+}
+
+/// # Safety
+///
+/// As for [`deflate`].
+#[no_mangle]
+pub unsafe extern "C" fn deflateReset(strm: *mut z_stream) -> c_int {
+    let Some((strm, compressor)) = deflate_state(strm) else {
+        return Z_STREAM_ERROR;
+    };
+    compressor.reset();
+    strm.total_in = 0;
+    strm.total_out = 0;
+    strm.msg = ptr::null();
+    strm.adler = compressor.checksum().unwrap_or(0) as c_ulong;
+    Z_OK
+}
+
+/// # Safety
+///
+/// As for [`deflate`]. The state is freed and `strm.state` cleared.
+#[no_mangle]
+pub unsafe extern "C" fn deflateEnd(strm: *mut z_stream) -> c_int {
+    let Some((strm, _)) = deflate_state(strm) else {
+        return Z_STREAM_ERROR;
+    };
+    drop(Box::from_raw(strm.state.cast::<TrickleCompressor>()));
+    strm.state = ptr::null_mut();
+    Z_OK
+}
+
+/// Upper bound on the compressed size of `source_len` bytes deflated with
+/// `Z_FINISH` in one go, from [`compress_bound`] for the stream's settings.
+/// Without an initialized stream the bound covers every setting
+/// [`deflateInit2_`] accepts, which the 256-byte window limits the most.
+///
+/// # Safety
+///
+/// `strm` is null or points to a `z_stream`, as for zlib's `deflateBound`.
+#[no_mangle]
+pub unsafe extern "C" fn deflateBound(strm: *mut z_stream, source_len: c_ulong) -> c_ulong {
+    let bound = match deflate_state(strm) {
+        Some((_, compressor)) => compress_bound(source_len as usize, compressor.config()),
+        None => compress_bound(
+            source_len as usize,
+            &CompressionConfig::default()
+                .with_format(ContainerFormat::Gzip)
+                .with_window_size(256),
+        ),
+    };
+    bound as c_ulong
+}
+
+/// # Safety
+///
+/// As for [`deflateInit_`].
+#[no_mangle]
+pub unsafe extern "C" fn inflateInit_(
+    strm: *mut z_stream,
+    version: *const c_char,
+    stream_size: c_int,
+) -> c_int {
+    inflateInit2_(strm, 15, version, stream_size)
+}
+
+/// # Safety
+///
+/// As for [`deflateInit_`].
+#[no_mangle]
+pub unsafe extern "C" fn inflateInit2_(
+    strm: *mut z_stream,
+    window_bits: c_int,
+    version: *const c_char,
+    stream_size: c_int,
+) -> c_int {
+    if !version_ok(version, stream_size) {
+        return Z_VERSION_ERROR;
+    }
+    let Some(strm) = strm.as_mut() else {
+        return Z_STREAM_ERROR;
+    };
+    // 0 means "as in the header", so the largest window.
+    let inflater = match window_bits {
+        0 => Some(TrickleDecompressor::new(ContainerFormat::Zlib)),
+        40..=47 => None,
+        _ => match window_format(window_bits) {
+            Some((format, window_size)) => {
+                Some(TrickleDecompressor::with_window_size(format, window_size))
+            }
+            None => return Z_STREAM_ERROR,
+        },
+    };
+    strm.state = Box::into_raw(Box::new(inflater)).cast();
+    strm.msg = ptr::null();
+    strm.total_in = 0;
+    strm.total_out = 0;
+    strm.adler = 1;
+    Z_OK
+}
+
+unsafe fn inflate_state<'a>(strm: *mut z_stream) -> Option<(&'a mut z_stream, &'a mut Inflater)> {
+    let strm = strm.as_mut()?;
+    let state = strm.state.cast::<Inflater>().as_mut()?;
+    Some((strm, state))
+}
+
+/// # Safety
+///
+/// `strm` must have been initialized with `inflateInit_` or
+/// `inflateInit2_`, and `next_in`/`next_out` must be valid for
+/// `avail_in`/`avail_out` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn inflate(strm: *mut z_stream, flush: c_int) -> c_int {
+    let Some((strm, inflater)) = inflate_state(strm) else {
+        return Z_STREAM_ERROR;
+    };
+    if !(Z_NO_FLUSH..=Z_BLOCK).contains(&flush) {
+        return Z_STREAM_ERROR;
+    }
+    let input = slice_or_empty(strm.next_in, strm.avail_in);
+    let output = slice_mut_or_empty(strm.next_out, strm.avail_out);
+    let decompressor = match inflater {
+        Some(decompressor) => decompressor,
+        None => {
+            let Some(&first) = input.first() else {
+                return Z_BUF_ERROR;
+            };
+            let format = if first == 0x1F {
+                ContainerFormat::Gzip
+            } else {
+                ContainerFormat::Zlib
+            };
+            inflater.insert(TrickleDecompressor::new(format))
+        }
+    };
+    if decompressor.is_finished() {
+        return Z_STREAM_END;
+    }
+    // Keep going while there is progress: with exactly enough output space
+    // the first call stops short of the end-of-block code and trailer.
+    let (mut consumed, mut written) = (0, 0);
+    let status = loop {
+        let r = match decompressor.decompress_trickle(&input[consumed..], &mut output[written..]) {
+            Ok(r) => r,
+            Err(err) => {
+                advance(strm, consumed, written);
+                if let TrickleError::NeedDictionary { id } = err {
+                    strm.adler = id as c_ulong;
+                    return Z_NEED_DICT;
+                }
+                strm.adler = decompressor.checksum().unwrap_or(0) as c_ulong;
+                strm.msg = error_message(err).as_ptr();
+                return Z_DATA_ERROR;
+            }
+        };
+        consumed += r.consumed;
+        written += r.written;
+        if r.status == TrickleStatus::Finished || r.consumed == 0 && r.written == 0 {
+            break r.status;
+        }
+    };
+    advance(strm, consumed, written);
+    strm.adler = decompressor.checksum().unwrap_or(0) as c_ulong;
+    match status {
+        TrickleStatus::Finished => Z_STREAM_END,
+        _ if consumed == 0 && written == 0 => Z_BUF_ERROR,
+        _ => Z_OK,
+    }
+}
+
+/// # Safety
+///
+/// As for [`inflate`].
+#[no_mangle]
+pub unsafe extern "C" fn inflateReset(strm: *mut z_stream) -> c_int {
+    let Some((strm, inflater)) = inflate_state(strm) else {
+        return Z_STREAM_ERROR;
+    };
+    if let Some(decompressor) = inflater {
+        decompressor.reset();
+    }
+    strm.total_in = 0;
+    strm.total_out = 0;
+    strm.msg = ptr::null();
+    strm.adler = 1;
+    Z_OK
+}
+
+/// # Safety
+///
+/// As for [`inflate`]. The state is freed and `strm.state` cleared.
+#[no_mangle]
+pub unsafe extern "C" fn inflateEnd(strm: *mut z_stream) -> c_int {
+    let Some((strm, _)) = inflate_state(strm) else {
+        return Z_STREAM_ERROR;
+    };
+    drop(Box::from_raw(strm.state.cast::<Inflater>()));
+    strm.state = ptr::null_mut();
+    Z_OK
+}
+
+#[no_mangle]
+pub extern "C" fn zlibVersion() -> *const c_char {
+    VERSION.as_ptr()
+}
+
+unsafe fn slice_or_empty<'a>(data: *const u8, len: c_uint) -> &'a [u8] {
+    if data.is_null() {
+        &[]
+    } else {
+        core::slice::from_raw_parts(data, len as usize)
+    }
+}
+
+unsafe fn slice_mut_or_empty<'a>(data: *mut u8, len: c_uint) -> &'a mut [u8] {
+    if data.is_null() {
+        &mut []
+    } else {
+        core::slice::from_raw_parts_mut(data, len as usize)
+    }
+}
+
+/// Moves the stream's buffer pointers past what a call used.
+unsafe fn advance(strm: &mut z_stream, consumed: usize, written: usize) {
+    if consumed > 0 {
+        strm.next_in = strm.next_in.add(consumed);
+        strm.avail_in -= consumed as c_uint;
+        strm.total_in += consumed as c_ulong;
+    }
+    if written > 0 {
+        strm.next_out = strm.next_out.add(written);
+        strm.avail_out -= written as c_uint;
+        strm.total_out += written as c_ulong;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn new_stream() -> z_stream {
+        z_stream {
+            next_in: ptr::null(),
+            avail_in: 0,
+            total_in: 0,
+            next_out: ptr::null_mut(),
+            avail_out: 0,
+            total_out: 0,
+            msg: ptr::null(),
+            state: ptr::null_mut(),
+            zalloc: None,
+            zfree: None,
+            opaque: ptr::null_mut(),
+            data_type: 0,
+            adler: 0,
+            reserved: 0,
+        }
+    }
+
+    const SIZE: c_int = core::mem::size_of::<z_stream>() as c_int;
+
+    #[test]
+    fn deflate_then_inflate_like_a_c_caller() {
+        let data = b"POST /telemetry HTTP/1.1\r\nContent-Length: 42\r\n\r\n".repeat(200);
+        unsafe {
+            let mut strm = new_stream();
+            assert_eq!(
+                deflateInit2_(&mut strm, 6, Z_DEFLATED, 31, 8, 0, zlibVersion(), SIZE),
+                Z_OK
+            );
+            let mut packed = vec![0u8; deflateBound(&mut strm, data.len() as c_ulong) as usize];
+            strm.next_in = data.as_ptr();
+            strm.avail_in = data.len() as c_uint;
+            strm.next_out = packed.as_mut_ptr();
+            // Small output windows, as a C loop with a fixed buffer would.
+            let mut status = Z_OK;
+            while status == Z_OK {
+                strm.avail_out = (packed.len() - strm.total_out as usize).min(100) as c_uint;
+                status = deflate(&mut strm, Z_FINISH);
+            }
+            assert_eq!(status, Z_STREAM_END);
+            packed.truncate(strm.total_out as usize);
+            assert_eq!(strm.total_in, data.len() as c_ulong);
+            let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+            assert_eq!(packed, compress_to_vec(&data, &config));
+            let crc = strm.adler;
+            assert_eq!(crc, crate::crc32::update(0, &data) as c_ulong);
+            assert_eq!(deflateEnd(&mut strm), Z_OK);
+            assert!(strm.state.is_null());
+
+            let mut strm = new_stream();
+            assert_eq!(inflateInit2_(&mut strm, 47, zlibVersion(), SIZE), Z_OK);
+            let mut unpacked: Vec<u8> = vec![0; data.len()];
+            strm.next_in = packed.as_ptr();
+            strm.avail_in = packed.len() as c_uint;
+            strm.next_out = unpacked.as_mut_ptr();
+            strm.avail_out = unpacked.len() as c_uint;
+            assert_eq!(inflate(&mut strm, Z_NO_FLUSH), Z_STREAM_END);
+            assert_eq!(unpacked, data);
+            assert_eq!(strm.adler, crc);
+            assert_eq!(inflateEnd(&mut strm), Z_OK);
+        }
+    }
+
+    #[test]
+    fn preset_dictionary_is_reported() {
+        let dict = b"status=ok temp=";
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let mut compressor = TrickleCompressor::new(config);
+        compressor.set_dictionary(dict).unwrap();
+        let mut packed = [0u8; 64];
+        let r = compressor
+            .compress_trickle(b"status=ok temp=21", &mut packed, FlushMode::Finish)
+            .unwrap();
+        unsafe {
+            let mut strm = new_stream();
+            assert_eq!(inflateInit_(&mut strm, zlibVersion(), SIZE), Z_OK);
+            let mut out = [0u8; 64];
+            strm.next_in = packed.as_ptr();
+            strm.avail_in = r.written as c_uint;
+            strm.next_out = out.as_mut_ptr();
+            strm.avail_out = out.len() as c_uint;
+            assert_eq!(inflate(&mut strm, Z_NO_FLUSH), Z_NEED_DICT);
+            // Header and DICTID read, the dictionary's Adler-32 reported.
+            assert_eq!(strm.total_in, 6);
+            assert_eq!(strm.adler, crate::adler32::update(1, dict) as c_ulong);
+            assert!(strm.msg.is_null());
+            assert_eq!(inflateEnd(&mut strm), Z_OK);
+        }
+    }
+
+    #[test]
+    fn incompressible_input_stays_within_the_bound() {
+        let mut x = 0x9E37_79B9u32;
+        let data: Vec<u8> = (0..100_000)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        let presets = [
+            CompressionConfig::default(),
+            CompressionConfig::new(CompressionLevel::NONE),
+            CompressionConfig::tiny_window(256),
+            CompressionConfig::tiny_window(2048),
+            CompressionConfig::small_packet(),
+            CompressionConfig::low_memory(),
+            CompressionConfig::text(),
+            CompressionConfig::telemetry(),
+            CompressionConfig::firmware_image(),
+            CompressionConfig::png(),
+            CompressionConfig::json_logs(),
+            CompressionConfig::for_ram_budget(4096).unwrap().0,
+            CompressionConfig::default().with_restart_interval(1000),
+        ];
+        for config in presets {
+            let len = compress_to_vec(&data, &config).len();
+            unsafe {
+                let mut strm = new_stream();
+                strm.state = Box::into_raw(Box::new(TrickleCompressor::new(config))).cast();
+                let bound = deflateBound(&mut strm, data.len() as c_ulong) as usize;
+                assert!(len <= bound, "{len} > {bound}");
+                assert_eq!(deflateEnd(&mut strm), Z_OK);
+            }
+        }
+        // Without a stream, the bound covers every window deflateInit2_ takes.
+        for window_bits in 8..=15 {
+            let config = CompressionConfig::default().with_window_size(1 << window_bits);
+            let len = compress_to_vec(&data, &config).len();
+            let bound = unsafe { deflateBound(ptr::null_mut(), data.len() as c_ulong) };
+            assert!(len <= bound as usize);
+        }
+    }
+
+    #[test]
+    fn bad_input_is_reported() {
+        unsafe {
+            let mut strm = new_stream();
+            assert_eq!(
+                inflateInit_(&mut strm, c"2.0".as_ptr(), SIZE),
+                Z_VERSION_ERROR
+            );
+            assert_eq!(
+                inflateInit_(&mut strm, zlibVersion(), SIZE - 1),
+                Z_VERSION_ERROR
+            );
+            assert_eq!(inflateInit_(&mut strm, zlibVersion(), SIZE), Z_OK);
+            let garbage = [0x78, 0x9C, 0xFF, 0xFF, 0xFF];
+            let mut out = [0u8; 16];
+            strm.next_in = garbage.as_ptr();
+            strm.avail_in = garbage.len() as c_uint;
+            strm.next_out = out.as_mut_ptr();
+            strm.avail_out = out.len() as c_uint;
+            assert_eq!(inflate(&mut strm, Z_NO_FLUSH), Z_DATA_ERROR);
+            assert!(!strm.msg.is_null());
+            assert_eq!(inflateEnd(&mut strm), Z_OK);
+            assert_eq!(inflate(&mut strm, Z_NO_FLUSH), Z_STREAM_ERROR);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Whole-file gzip helpers for host tools.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use alloc::vec;
+
+use crate::compressor::{gzip_header, TrickleCompressor, TrickleStatus};
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::crc32;
+use crate::decompressor::TrickleDecompressor;
+
+const BUF_SIZE: usize = 64 * 1024;
+const FNAME: u8 = 0x08;
+
+/// Compresses the file at `src` into a gzip file at `dst`, like
+/// `gzip -c src > dst`. The header records the base name of `src`, unless
+/// it is not valid UTF-8, and its modification time; the OS byte and
+/// FTEXT flag come from `config`, whose format is ignored. Returns the size of `src`.
+///
+/// ```no_run
+/// use std::path::Path;
+/// use tricklezip::{gzip_file, gunzip_file, CompressionConfig};
+///
+/// gzip_file(Path::new("trace.bin"), Path::new("trace.bin.gz"), &CompressionConfig::default())?;
+/// gunzip_file(Path::new("trace.bin.gz"), Path::new("trace.copy.bin"))?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn gzip_file(src: &Path, dst: &Path, config: &CompressionConfig) -> io::Result<u64> {
+    let mut input = File::open(src)?;
+    let mtime = input
+        .metadata()?
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .and_then(|d| u32::try_from(d.as_secs()).ok())
+        .unwrap_or(0);
+    let name = src
+        .file_name()
+        .and_then(|n| n.to_str())
+        .filter(|n| !n.contains('\0'));
+
+    let mut output = BufWriter::new(File::create(dst)?);
+    let mut header = gzip_header(config);
+    if name.is_some() {
+        header[3] |= FNAME;
+    }
+    header[4..8].copy_from_slice(&mtime.to_le_bytes());
+    output.write_all(&header)?;
+    if let Some(name) = name {
+        output.write_all(name.as_bytes())?;
+        output.write_all(&[0])?;
+    }
+
+    // The body is raw DEFLATE; the trailer is written here to go with the
+    // header above.
+    let mut compressor = TrickleCompressor::new(config.clone().with_format(ContainerFormat::Raw));
+    let mut inbuf = vec![0u8; BUF_SIZE];
+    let mut outbuf = vec![0u8; BUF_SIZE];
+    let (mut crc, mut total_in) = (0, 0u64);
+    'read: loop {
+        let len = input.read(&mut inbuf)?;
+        crc = crc32::update(crc, &inbuf[..len]);
+        total_in += len as u64;
+        let finish = len == 0;
+        let mut pos = 0;
+        loop {
+            let r = compressor.compress_trickle(&inbuf[pos..len], &mut outbuf, finish.into())?;
+            pos += r.consumed;
+            output.write_all(&outbuf[..r.written])?;
+            match r.status {
+                TrickleStatus::Finished => break 'read,
+                TrickleStatus::NeedInput if pos == len => break,
+                _ => {}
+            }
+        }
+    }
+    output.write_all(&crc.to_le_bytes())?;
+    output.write_all(&(total_in as u32).to_le_bytes())?;
+    output.flush()?;
+    Ok(total_in)
+}
+
+/// Decompresses the gzip file at `src` into `dst`, like
+/// `gunzip -c src > dst`, and gives `dst` the modification time recorded
+/// in the header, if any. Corrupt data fails with `InvalidData` and a
+/// truncated file with `UnexpectedEof`; `dst` is left partly written then.
+/// Returns the size of `dst`.
+pub fn gunzip_file(src: &Path, dst: &Path) -> io::Result<u64> {
+    let mut input = File::open(src)?;
+    let mut output = BufWriter::new(File::create(dst)?);
+    let mut decompressor = TrickleDecompressor::new(ContainerFormat::Gzip);
+    let mut inbuf = vec![0u8; BUF_SIZE];
+    let mut outbuf = vec![0u8; BUF_SIZE];
+    let mut mtime = None;
+    'read: loop {
+        let len = input.read(&mut inbuf)?;
+        if mtime.is_none() && len >= 8 {
+            mtime = Some(u32::from_le_bytes([inbuf[4], inbuf[5], inbuf[6], inbuf[7]]));
+        }
+        let mut pos = 0;
+        loop {
+            let r = decompressor.decompress_trickle(&inbuf[pos..len], &mut outbuf)?;
+            pos += r.consumed;
+            output.write_all(&outbuf[..r.written])?;
+            if r.status == TrickleStatus::Finished {
+                break 'read;
+            }
+            if r.consumed == 0 && r.written == 0 {
+                break;
+            }
+        }
+        if len == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    let file = output.into_inner().map_err(|e| e.into_error())?;
+    if let Some(secs) = mtime.filter(|&t| t != 0) {
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs as u64))?;
+    }
+    Ok(decompressor.total_out())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor::decompress_to_vec;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(std::format!("tricklezip-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn files_round_trip_with_metadata() {
+        let dir = temp_dir("file");
+        let src = dir.join("boot.log");
+        let data = b"[    0.000000] Booting Linux on physical CPU 0x0\n".repeat(3000);
+        std::fs::write(&src, &data).unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_760_000_000);
+        File::options()
+            .write(true)
+            .open(&src)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+
+        let gz = dir.join("boot.log.gz");
+        assert_eq!(
+            gzip_file(&src, &gz, &CompressionConfig::text()).unwrap(),
+            data.len() as u64
+        );
+        let packed = std::fs::read(&gz).unwrap();
+        assert_eq!(packed[3], FNAME);
+        assert_eq!(&packed[4..8], &1_760_000_000u32.to_le_bytes());
+        assert_eq!(&packed[10..19], b"boot.log\0");
+        assert_eq!(
+            decompress_to_vec(&packed, ContainerFormat::Gzip).unwrap(),
+            data
+        );
+
+        let out = dir.join("copy.log");
+        assert_eq!(gunzip_file(&gz, &out).unwrap(), data.len() as u64);
+        assert_eq!(std::fs::read(&out).unwrap(), data);
+        assert_eq!(std::fs::metadata(&out).unwrap().modified().unwrap(), mtime);
+
+        std::fs::write(&gz, &packed[..packed.len() / 2]).unwrap();
+        let err = gunzip_file(&gz, &out).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
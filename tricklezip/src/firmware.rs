@@ -0,0 +1,192 @@
+//! Compressed firmware images for over-the-air updates.
+//!
+//! An image is a fixed 24-byte header followed by a raw DEFLATE payload.
+//! All fields are little-endian:
+//!
+//! | offset | size | field                                   |
+//! |-------:|-----:|-----------------------------------------|
+//! |      0 |    4 | magic, `TZFW`                           |
+//! |      4 |    1 | format version, currently 1             |
+//! |      5 |    3 | reserved, zero                          |
+//! |      8 |    4 | application version, free for the user  |
+//! |     12 |    4 | uncompressed length                     |
+//! |     16 |    4 | CRC-32 of the uncompressed firmware     |
+//! |     20 |    4 | payload length                          |
+//!
+//! Bytes after the payload, e.g. a signature, are ignored by the reader.
+
+use alloc::vec::Vec;
+
+use crate::compressor::compress_to_vec;
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::crc32;
+use crate::error::TrickleError;
+use crate::oneshot::{decompress_in_place, decompress_into, in_place_offset};
+
+/// A parsed firmware image, borrowing its payload.
+///
+/// Built on the host with [`build`](Self::build) and unpacked on the device
+/// with [`parse`](Self::parse) and [`unpack`](Self::unpack), which need no
+/// heap.
+///
+/// ```
+/// use tricklezip::{CompressionConfig, FirmwareImage};
+///
+/// let firmware = b"\x00\x20\x00\x20\x09\x01\x00\x08".repeat(100);
+/// let image = FirmwareImage::build(&firmware, 0x0102_0003, &CompressionConfig::firmware_image());
+///
+/// let parsed = FirmwareImage::parse(&image).unwrap();
+/// assert_eq!(parsed.version, 0x0102_0003);
+/// let mut flash = [0u8; 1024];
+/// let n = parsed.unpack(&mut flash).unwrap();
+/// assert_eq!(&flash[..n], &firmware[..]);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareImage<'a> {
+    /// Application version, stored as given to [`build`](Self::build).
+    pub version: u32,
+    pub uncompressed_len: u32,
+    /// CRC-32 of the uncompressed firmware.
+    pub crc32: u32,
+    /// The raw DEFLATE payload.
+    pub payload: &'a [u8],
+}
+
+impl<'a> FirmwareImage<'a> {
+    pub const MAGIC: [u8; 4] = *b"TZFW";
+    pub const FORMAT_VERSION: u8 = 1;
+    pub const HEADER_LEN: usize = 24;
+
+    /// Compresses `firmware` into an image. The container format of
+    /// `config` is ignored; the payload is always raw DEFLATE.
+    pub fn build(firmware: &[u8], version: u32, config: &CompressionConfig) -> Vec<u8> {
+        let uncompressed_len = u32::try_from(firmware.len()).expect("firmware larger than 4GB");
+        let payload = compress_to_vec(firmware, &config.clone().with_format(ContainerFormat::Raw));
+        let mut image = Vec::with_capacity(Self::HEADER_LEN + payload.len());
+        image.extend_from_slice(&Self::MAGIC);
+        image.extend_from_slice(&[Self::FORMAT_VERSION, 0, 0, 0]);
+        image.extend_from_slice(&version.to_le_bytes());
+        image.extend_from_slice(&uncompressed_len.to_le_bytes());
+        image.extend_from_slice(&crc32::update(0, firmware).to_le_bytes());
+        image.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        image.extend_from_slice(&payload);
+        image
+    }
+
+    /// Reads the header. Fails with `InvalidHeader` on a wrong magic or
+    /// unknown format version and `UnexpectedEof` if the image is cut short.
+    pub fn parse(image: &'a [u8]) -> Result<Self, TrickleError> {
+        if image.len() < Self::HEADER_LEN {
+            return Err(TrickleError::UnexpectedEof);
+        }
+        if image[..4] != Self::MAGIC || image[4] != Self::FORMAT_VERSION {
+            return Err(TrickleError::InvalidHeader);
+        }
+        let field = |at: usize| {
+            u32::from_le_bytes([image[at], image[at + 1], image[at + 2], image[at + 3]])
+        };
+        let payload_len = field(20) as usize;
+        let payload = image[Self::HEADER_LEN..]
+            .get(..payload_len)
+            .ok_or(TrickleError::UnexpectedEof)?;
+        Ok(Self {
+            version: field(8),
+            uncompressed_len: field(12),
+            crc32: field(16),
+            payload,
+        })
+    }
+
+    /// Decompresses the firmware into the start of `out` and checks its
+    /// length and CRC, returning the length. `out` must hold at least
+    /// `uncompressed_len` bytes, or this fails with `OutputTooSmall`
+    /// before decoding anything.
+    pub fn unpack(&self, out: &mut [u8]) -> Result<usize, TrickleError> {
+        let len = self.uncompressed_len as usize;
+        let out = out.get_mut(..len).ok_or(TrickleError::OutputTooSmall)?;
+        if decompress_into(self.payload, out)? != len {
+            return Err(TrickleError::InvalidData);
+        }
+        if crc32::update(0, out) != self.crc32 {
+            return Err(TrickleError::ChecksumMismatch);
+        }
+        Ok(len)
+    }
+
+    /// The smallest offset at which to load this image for
+    /// [`unpack_in_place`](Self::unpack_in_place). Computed on the host,
+    /// e.g. to place the image at the end of the update partition.
+    pub fn in_place_offset(&self) -> Result<usize, TrickleError> {
+        Ok(in_place_offset(self.payload)?.saturating_sub(Self::HEADER_LEN))
+    }
+
+    /// Unpacks the image loaded at `buf[at..]` into the start of `buf`,
+    /// without a second buffer; see
+    /// [`decompress_in_place`](crate::decompress_in_place). `at` must be at
+    /// least [`in_place_offset`](Self::in_place_offset). Checks length and
+    /// CRC like [`unpack`](Self::unpack) and returns the length.
+    pub fn unpack_in_place(buf: &mut [u8], at: usize) -> Result<usize, TrickleError> {
+        let image = FirmwareImage::parse(buf.get(at..).ok_or(TrickleError::UnexpectedEof)?)?;
+        let (len, crc) = (image.uncompressed_len as usize, image.crc32);
+        let start = at + Self::HEADER_LEN;
+        let end = start + image.payload.len();
+        if decompress_in_place(buf, start, end)? != len {
+            return Err(TrickleError::InvalidData);
+        }
+        if crc32::update(0, &buf[..len]) != crc {
+            return Err(TrickleError::ChecksumMismatch);
+        }
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn firmware() -> Vec<u8> {
+        (0..6000u32).flat_map(|i| (i / 7).to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn round_trip_and_corruption() {
+        let firmware = firmware();
+        let mut image = FirmwareImage::build(&firmware, 7, &CompressionConfig::firmware_image());
+        assert!(image.len() < firmware.len() / 2);
+        image.extend_from_slice(b"signature");
+
+        let parsed = FirmwareImage::parse(&image).unwrap();
+        assert_eq!(parsed.uncompressed_len as usize, firmware.len());
+        let mut out = vec![0u8; firmware.len()];
+        assert_eq!(parsed.unpack(&mut out), Ok(firmware.len()));
+        assert_eq!(out, firmware);
+        assert_eq!(
+            parsed.unpack(&mut out[..100]),
+            Err(TrickleError::OutputTooSmall)
+        );
+
+        let at = parsed.in_place_offset().unwrap();
+        let mut ram = vec![0u8; at + image.len()];
+        ram[at..].copy_from_slice(&image);
+        assert_eq!(
+            FirmwareImage::unpack_in_place(&mut ram, at),
+            Ok(firmware.len())
+        );
+        assert_eq!(&ram[..firmware.len()], &firmware[..]);
+        assert!(ram.ends_with(b"signature"));
+
+        let mut bad = image.clone();
+        bad[16] ^= 1;
+        let parsed = FirmwareImage::parse(&bad).unwrap();
+        assert_eq!(parsed.unpack(&mut out), Err(TrickleError::ChecksumMismatch));
+
+        let mut bad = image.clone();
+        bad[0] = b'X';
+        assert_eq!(FirmwareImage::parse(&bad), Err(TrickleError::InvalidHeader));
+        assert_eq!(
+            FirmwareImage::parse(&image[..FirmwareImage::HEADER_LEN + 10]),
+            Err(TrickleError::UnexpectedEof)
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! TrickleFrame: chunked DEFLATE for lossy links, resumable per chunk.
+//!
+//! The input is cut into chunks of a fixed size, each compressed as an
+//! independent raw DEFLATE stream and framed with its sequence number and a
+//! CRC. A receiver that loses or corrupts a chunk keeps everything before
+//! it and asks the sender to resume at [`FrameDecoder::next_sequence`],
+//! instead of restarting the whole transfer. Since no history crosses chunk
+//! boundaries, neither side has to keep any state but the sequence number.
+//!
+//! Each chunk is an 18-byte header followed by the payload. All fields are
+//! little-endian:
+//!
+//! | offset | size | field                                         |
+//! |-------:|-----:|-----------------------------------------------|
+//! |      0 |    4 | resume marker, `TZFR`                         |
+//! |      4 |    4 | sequence number, counting from 0              |
+//! |      8 |    1 | flags: bit 0 marks the last chunk             |
+//! |      9 |    1 | reserved, zero                                |
+//! |     10 |    2 | uncompressed length                           |
+//! |     12 |    2 | payload length                                |
+//! |     14 |    4 | CRC-32 of bytes 4 to 13 and the payload       |
+//!
+//! The decoder scans for the marker, so it resynchronizes on its own after
+//! garbage or a truncated chunk.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::crc32;
+use crate::deflate::DeflateState;
+use crate::error::TrickleError;
+use crate::huffman;
+use crate::oneshot::decompress_into;
+
+const MARKER: [u8; 4] = *b"TZFR";
+const HEADER_LEN: usize = 18;
+const LAST: u8 = 0x01;
+/// Largest uncompressed chunk. A chunk that does not compress is sent as a
+/// single stored block, 5 bytes over its length, so every payload fits the
+/// 16-bit field.
+pub const MAX_FRAME_CHUNK: usize = 32768;
+
+/// Splits a stream into TrickleFrame chunks.
+///
+/// ```
+/// use tricklezip::{CompressionConfig, FrameDecoder, FrameEncoder, FrameStatus};
+///
+/// let log = b"boot ok\nlink up\n".repeat(200);
+/// let mut encoder = FrameEncoder::new(CompressionConfig::default(), 1024);
+/// let mut link = Vec::new();
+/// let mut chunks = log.chunks(encoder.chunk_size()).peekable();
+/// while let Some(chunk) = chunks.next() {
+///     link.extend(encoder.encode_chunk(chunk, chunks.peek().is_none()));
+/// }
+///
+/// let mut decoder = FrameDecoder::new();
+/// let mut out = [0u8; 1024];
+/// let mut received = Vec::new();
+/// let mut input = &link[..];
+/// while !decoder.is_finished() {
+///     let p = decoder.decode(input, &mut out).unwrap();
+///     input = &input[p.consumed..];
+///     received.extend_from_slice(&out[..p.written]);
+///     assert_ne!(p.status, FrameStatus::NeedInput);
+/// }
+/// assert_eq!(received, log);
+/// ```
+pub struct FrameEncoder {
+    config: CompressionConfig,
+    state: DeflateState,
+    chunk_size: usize,
+    sequence: u32,
+}
+
+impl FrameEncoder {
+    /// Compresses chunks of up to `chunk_size` bytes, clamped to
+    /// 1..=[`MAX_FRAME_CHUNK`], with `config`, whose format is forced to raw.
+    pub fn new(config: CompressionConfig, chunk_size: usize) -> Self {
+        let config = config.with_format(ContainerFormat::Raw);
+        Self {
+            state: DeflateState::new(&config),
+            config,
+            chunk_size: chunk_size.clamp(1, MAX_FRAME_CHUNK),
+            sequence: 0,
+        }
+    }
+
+    /// Uncompressed bytes per chunk; every chunk but the last must be this
+    /// long, so that a resumed chunk starts at `sequence * chunk_size`.
+    pub fn chunk_size(&self) -> usize {
+        self.chunk_size
+    }
+
+    /// Sequence number of the next chunk.
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// Continues at chunk `sequence`, as requested by the receiver. The
+    /// next chunk passed in must be the input from
+    /// `sequence * chunk_size()` on.
+    pub fn resume_from(&mut self, sequence: u32) {
+        self.sequence = sequence;
+    }
+
+    /// Returns `chunk` framed as the next chunk of the stream, marked as
+    /// the last one with `last`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk` is longer than [`chunk_size`](Self::chunk_size).
+    pub fn encode_chunk(&mut self, chunk: &[u8], last: bool) -> Vec<u8> {
+        assert!(
+            chunk.len() <= self.chunk_size,
+            "chunk longer than chunk_size"
+        );
+        self.state.reset(&self.config);
+        let mut consumed = 0;
+        while !self.state.is_finished() {
+            consumed += self
+                .state
+                .compress_chunk(&chunk[consumed..], usize::MAX, true)
+                .consumed;
+        }
+        self.state.writer.align();
+        let mut payload_len = self.state.writer.pending_len();
+        if payload_len > chunk.len() + 5 {
+            // Short blocks with a header each, as small windows and block
+            // sizes make; one stored block is smaller.
+            self.state.reset(&self.config);
+            huffman::write_stored(chunk, true, &mut self.state.writer);
+            payload_len = self.state.writer.pending_len();
+        }
+
+        let mut frame = vec![0; HEADER_LEN + payload_len];
+        frame[..4].copy_from_slice(&MARKER);
+        frame[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        frame[8] = if last { LAST } else { 0 };
+        frame[10..12].copy_from_slice(&(chunk.len() as u16).to_le_bytes());
+        frame[12..14].copy_from_slice(&(payload_len as u16).to_le_bytes());
+        self.state.writer.drain(&mut frame[HEADER_LEN..]);
+        let crc = crc32::update(crc32::update(0, &frame[4..14]), &frame[HEADER_LEN..]);
+        frame[14..18].copy_from_slice(&crc.to_le_bytes());
+        self.sequence = self.sequence.wrapping_add(1);
+        frame
+    }
+}
+
+/// What a [`FrameDecoder::decode`] call did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameStatus {
+    /// The input holds no complete chunk yet. Bytes before the next marker
+    /// were skipped.
+    NeedInput,
+    /// A chunk was decoded into the output.
+    Chunk,
+    /// The last chunk was decoded into the output.
+    Finished,
+    /// A damaged chunk, or one out of sequence, was skipped. Unless it was
+    /// a duplicate of one already received, the sender has to resume at
+    /// [`FrameDecoder::next_sequence`].
+    Dropped,
+}
+
+/// Result of a [`FrameDecoder::decode`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameProgress {
+    /// Input bytes to discard before the next call.
+    pub consumed: usize,
+    /// Bytes written to the start of the output.
+    pub written: usize,
+    pub status: FrameStatus,
+}
+
+/// Reassembles a stream from [`FrameEncoder`] chunks, in order, without
+/// allocating.
+///
+/// Chunks are decoded whole, so the output passed to
+/// [`decode`](Self::decode) must hold the sender's chunk size.
+#[derive(Debug, Clone, Default)]
+pub struct FrameDecoder {
+    sequence: u32,
+    finished: bool,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sequence number of the next chunk to decode: the resume point to
+    /// report to the sender after a drop or a reconnect.
+    pub fn next_sequence(&self) -> u32 {
+        self.sequence
+    }
+
+    /// The last chunk has been decoded.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Decodes the first chunk in `input` into `out`, skipping anything
+    /// before its marker. Chunks failing their CRC, and chunks other than
+    /// the next one expected, are dropped. Fails with `OutputTooSmall`,
+    /// consuming nothing, if the chunk does not fit into `out`.
+    pub fn decode(&mut self, input: &[u8], out: &mut [u8]) -> Result<FrameProgress, TrickleError> {
+        let start = find_marker(input);
+        let progress = |consumed, written, status| FrameProgress {
+            consumed,
+            written,
+            status,
+        };
+        if self.finished {
+            return Ok(progress(start, 0, FrameStatus::NeedInput));
+        }
+        let frame = &input[start..];
+        if frame.len() < HEADER_LEN {
+            return Ok(progress(start, 0, FrameStatus::NeedInput));
+        }
+        let field = |at: usize| u16::from_le_bytes([frame[at], frame[at + 1]]) as usize;
+        let (raw_len, payload_len) = (field(10), field(12));
+        let plausible = frame[8] & !LAST == 0
+            && frame[9] == 0
+            && raw_len <= MAX_FRAME_CHUNK
+            && payload_len <= MAX_FRAME_CHUNK + 5;
+        if !plausible {
+            return Ok(progress(start + 1, 0, FrameStatus::Dropped));
+        }
+        let Some(payload) = frame.get(HEADER_LEN..HEADER_LEN + payload_len) else {
+            return Ok(progress(start, 0, FrameStatus::NeedInput));
+        };
+        let crc = u32::from_le_bytes([frame[14], frame[15], frame[16], frame[17]]);
+        if crc32::update(crc32::update(0, &frame[4..14]), payload) != crc {
+            // Possibly a truncated chunk running into the next one; look for
+            // a marker right after this one.
+            return Ok(progress(start + 1, 0, FrameStatus::Dropped));
+        }
+        let end = start + HEADER_LEN + payload_len;
+        let sequence = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+        if sequence != self.sequence {
+            return Ok(progress(end, 0, FrameStatus::Dropped));
+        }
+        if raw_len > out.len() {
+            return Err(TrickleError::OutputTooSmall);
+        }
+        let written = decompress_into(payload, &mut out[..raw_len])?;
+        if written != raw_len {
+            return Err(TrickleError::ChecksumMismatch);
+        }
+        self.sequence = self.sequence.wrapping_add(1);
+        self.finished = frame[8] & LAST != 0;
+        let status = if self.finished {
+            FrameStatus::Finished
+        } else {
+            FrameStatus::Chunk
+        };
+        Ok(progress(end, written, status))
+    }
+}
+
+/// Offset of the first marker in `input`, or of the tail that could still
+/// be the start of one.
+fn find_marker(input: &[u8]) -> usize {
+    (0..input.len())
+        .find(|&i| {
+            let n = (input.len() - i).min(MARKER.len());
+            input[i..i + n] == MARKER[..n]
+        })
+        .unwrap_or(input.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log() -> Vec<u8> {
+        (0..600u32)
+            .flat_map(|i| alloc::format!("t={i} temp=21.{} hum=40\n", i % 10).into_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn damaged_chunks_resume_where_they_left_off() {
+        let log = log();
+        let mut encoder = FrameEncoder::new(CompressionConfig::low_memory(), 2048);
+        let chunks: Vec<&[u8]> = log.chunks(2048).collect();
+        assert!(chunks.len() > 4);
+        let mut decoder = FrameDecoder::new();
+        let mut out = [0u8; 2048];
+        let mut received = Vec::new();
+        let mut transfers = 0;
+        while !decoder.is_finished() {
+            transfers += 1;
+            let first = decoder.next_sequence() as usize;
+            encoder.resume_from(first as u32);
+            let mut link = b"\x00noise TZF".to_vec();
+            for (i, chunk) in chunks.iter().enumerate().skip(first) {
+                let mut frame = encoder.encode_chunk(chunk, i == chunks.len() - 1);
+                if transfers == 1 && i == 2 {
+                    let n = frame.len();
+                    frame[n / 2] ^= 0x10;
+                }
+                if transfers == 2 && i == 3 {
+                    frame.truncate(30);
+                }
+                link.extend(frame);
+            }
+            let mut input = &link[..];
+            loop {
+                let p = decoder.decode(input, &mut out).unwrap();
+                input = &input[p.consumed..];
+                received.extend_from_slice(&out[..p.written]);
+                if p.status == FrameStatus::NeedInput || p.status == FrameStatus::Finished {
+                    break;
+                }
+            }
+        }
+        assert_eq!(transfers, 3);
+        assert_eq!(received, log);
+    }
+
+    #[test]
+    fn chunks_wait_for_input_and_output() {
+        let mut encoder = FrameEncoder::new(CompressionConfig::default(), 100);
+        let frame = encoder.encode_chunk(&[7; 100], true);
+        let mut decoder = FrameDecoder::new();
+        let mut out = [0u8; 100];
+        for cut in [0, 3, HEADER_LEN, frame.len() - 1] {
+            let p = decoder.decode(&frame[..cut], &mut out).unwrap();
+            assert_eq!((p.consumed, p.status), (0, FrameStatus::NeedInput));
+        }
+        assert_eq!(
+            decoder.decode(&frame, &mut out[..99]),
+            Err(TrickleError::OutputTooSmall)
+        );
+        let p = decoder.decode(&frame, &mut out).unwrap();
+        assert_eq!(
+            p,
+            FrameProgress {
+                consumed: frame.len(),
+                written: 100,
+                status: FrameStatus::Finished
+            }
+        );
+        assert!(decoder.is_finished());
+        assert_eq!(out, [7; 100]);
+    }
+
+    #[test]
+    fn incompressible_chunks_fit_the_frame() {
+        let mut x = 0x2545_F491u32;
+        let noise: Vec<u8> = (0..MAX_FRAME_CHUNK)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect();
+        let presets = [
+            CompressionConfig::default(),
+            CompressionConfig::tiny_window(256),
+            CompressionConfig::tiny_window(2048),
+            CompressionConfig::small_packet(),
+            CompressionConfig::low_memory(),
+            CompressionConfig::text(),
+            CompressionConfig::telemetry(),
+            CompressionConfig::firmware_image(),
+            CompressionConfig::png(),
+            CompressionConfig::json_logs(),
+            CompressionConfig::default().with_block_size(1),
+        ];
+        let mut out = vec![0; MAX_FRAME_CHUNK];
+        for config in presets {
+            let mut encoder = FrameEncoder::new(config, MAX_FRAME_CHUNK);
+            let frame = encoder.encode_chunk(&noise, true);
+            assert!(frame.len() <= HEADER_LEN + MAX_FRAME_CHUNK + 5);
+            let p = FrameDecoder::new().decode(&frame, &mut out).unwrap();
+            assert_eq!(
+                (p.written, p.status),
+                (MAX_FRAME_CHUNK, FrameStatus::Finished)
+            );
+            assert_eq!(out, noise);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Iteration caps for the codec's core loops.
+//!
+//! Every loop that is not bounded by the length of a slice it walks takes
+//! one unit of [`Fuel`] per iteration, from an allowance computed up front
+//! out of the call's input and output sizes and the configuration. Each
+//! allowance is documented next to its loop together with the argument
+//! that the loop needs no more; running dry therefore means that argument
+//! is broken, and the loop stops instead of spinning.
+
+/// Iteration allowance of one loop run.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fuel(usize);
+
+impl Fuel {
+    /// Allows `limit` iterations.
+    pub(crate) fn new(limit: usize) -> Self {
+        Self(limit)
+    }
+
+    /// Takes one unit, returning `false` once the allowance is spent.
+    #[inline]
+    pub(crate) fn burn(&mut self) -> bool {
+        match self.0.checked_sub(1) {
+            Some(left) => {
+                self.0 = left;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Units left.
+    pub(crate) fn left(&self) -> usize {
+        self.0
+    }
+}
+
+/// `a * b + c`, saturating instead of overflowing, for computing limits.
+pub(crate) fn limit(a: usize, b: usize, c: usize) -> usize {
+    a.saturating_mul(b).saturating_add(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burns_exactly_the_allowance() {
+        let mut fuel = Fuel::new(3);
+        assert!(fuel.burn() && fuel.burn() && fuel.burn());
+        assert!(!fuel.burn());
+        assert_eq!(fuel.left(), 0);
+        assert_eq!(limit(usize::MAX, 2, 1), usize::MAX);
+    }
+}
@@ -0,0 +1,272 @@
+//! Gzip response bodies for embedded HTTP servers.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::compressor::{TrickleCompressor, TrickleStatus};
+use crate::config::{CompressionConfig, ContainerFormat};
+
+/// Uncompressed response body, pulled as the encoder needs it.
+///
+/// Implemented for byte slices, e.g. a dashboard page in flash, and for
+/// closures filling a buffer.
+pub trait BodySource {
+    /// Fills the start of `buf` and returns the byte count; 0 ends the body.
+    fn read(&mut self, buf: &mut [u8]) -> usize;
+}
+
+impl BodySource for &[u8] {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = buf.len().min(self.len());
+        buf[..n].copy_from_slice(&self[..n]);
+        *self = &self[n..];
+        n
+    }
+}
+
+impl<F: FnMut(&mut [u8]) -> usize> BodySource for F {
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        self(buf)
+    }
+}
+
+/// Receives encoded chunks, typically by writing them to the connection.
+/// Closures returning `Result<(), E>` implement it.
+pub trait ChunkSink {
+    type Error;
+
+    fn send(&mut self, chunk: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl<E, F: FnMut(&[u8]) -> Result<(), E>> ChunkSink for F {
+    type Error = E;
+
+    fn send(&mut self, chunk: &[u8]) -> Result<(), E> {
+        self(chunk)
+    }
+}
+
+/// Streams a body as `Content-Encoding: gzip`, one bounded step at a time.
+///
+/// Each [`step`](Self::step) does at most one `compress_trickle` call, so
+/// the CPU time per step is bounded by `config.trickle_size`, and sends a
+/// chunk whenever `chunk_size` bytes of output have accumulated. With
+/// [`chunked`](Self::chunked), chunks are also framed for
+/// `Transfer-Encoding: chunked`, for responses whose length is unknown up
+/// front.
+///
+/// ```
+/// use tricklezip::{CompressionConfig, GzipBody};
+///
+/// let page: &[u8] = b"<html><body>temp: 21.5 C</body></html>";
+/// let mut body = GzipBody::new(page, CompressionConfig::text(), 512).chunked();
+/// let mut response = Vec::new();
+/// response.extend_from_slice(b"HTTP/1.1 200 OK\r\n");
+/// response.extend_from_slice(body.headers().as_bytes());
+/// response.extend_from_slice(b"\r\n");
+/// let mut send = |chunk: &[u8]| -> Result<(), ()> {
+///     response.extend_from_slice(chunk);
+///     Ok(())
+/// };
+/// while !body.step(&mut send).unwrap() {
+///     // Serve other connections, feed the watchdog, ...
+/// }
+/// assert!(response.ends_with(b"0\r\n\r\n"));
+/// ```
+pub struct GzipBody<S: BodySource> {
+    source: S,
+    compressor: TrickleCompressor,
+    input: Vec<u8>,
+    in_pos: usize,
+    in_len: usize,
+    source_done: bool,
+    /// Encoded bytes waiting to be sent, after room for a chunk size line.
+    chunk: Vec<u8>,
+    chunk_len: usize,
+    chunked: bool,
+    finished: bool,
+}
+
+/// Room for the hex size line of a chunk, up to 16 digits plus CRLF.
+const SIZE_LINE: usize = 18;
+
+impl<S: BodySource> GzipBody<S> {
+    /// Compresses `source` with `config`, whose format is forced to gzip.
+    /// `chunk_size` is the amount of compressed data per sent chunk, and
+    /// also the size of the input buffer.
+    pub fn new(source: S, config: CompressionConfig, chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        Self {
+            source,
+            compressor: TrickleCompressor::new(config.with_format(ContainerFormat::Gzip)),
+            input: vec![0; chunk_size],
+            in_pos: 0,
+            in_len: 0,
+            source_done: false,
+            chunk: vec![0; SIZE_LINE + chunk_size + 2],
+            chunk_len: 0,
+            chunked: false,
+            finished: false,
+        }
+    }
+
+    /// Frames the output for `Transfer-Encoding: chunked`, including the
+    /// terminating zero-size chunk.
+    pub fn chunked(mut self) -> Self {
+        self.chunked = true;
+        self
+    }
+
+    /// Response header lines to send before the body, each ending in CRLF.
+    pub fn headers(&self) -> &'static str {
+        if self.chunked {
+            "Content-Encoding: gzip\r\nTransfer-Encoding: chunked\r\n"
+        } else {
+            "Content-Encoding: gzip\r\n"
+        }
+    }
+
+    /// The whole body has been sent.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Counters of the underlying compressor.
+    pub fn compressor(&self) -> &TrickleCompressor {
+        &self.compressor
+    }
+
+    /// Does one bounded unit of work, sending a chunk if one is complete.
+    /// Returns whether the body has been sent completely. Errors from the
+    /// sink are passed through; the body cannot be resumed after one.
+    pub fn step<K: ChunkSink>(&mut self, sink: &mut K) -> Result<bool, K::Error> {
+        if self.finished {
+            return Ok(true);
+        }
+        if self.in_pos == self.in_len && !self.source_done {
+            self.in_len = self.source.read(&mut self.input);
+            self.in_pos = 0;
+            self.source_done = self.in_len == 0;
+        }
+        let capacity = self.chunk.len() - 2;
+        let r = self
+            .compressor
+            .compress_trickle(
+                &self.input[self.in_pos..self.in_len],
+                &mut self.chunk[SIZE_LINE + self.chunk_len..capacity],
+                self.source_done.into(),
+            )
+            .expect("stream is not finished yet");
+        self.in_pos += r.consumed;
+        self.chunk_len += r.written;
+        let done = r.status == TrickleStatus::Finished;
+        if SIZE_LINE + self.chunk_len == capacity || done && self.chunk_len > 0 {
+            self.send_chunk(sink)?;
+        }
+        if done {
+            if self.chunked {
+                sink.send(b"0\r\n\r\n")?;
+            }
+            self.finished = true;
+        }
+        Ok(self.finished)
+    }
+
+    fn send_chunk<K: ChunkSink>(&mut self, sink: &mut K) -> Result<(), K::Error> {
+        let end = SIZE_LINE + self.chunk_len;
+        let mut start = SIZE_LINE;
+        if self.chunked {
+            self.chunk[end..end + 2].copy_from_slice(b"\r\n");
+            start -= 2;
+            self.chunk[start..SIZE_LINE].copy_from_slice(b"\r\n");
+            let mut len = self.chunk_len;
+            loop {
+                start -= 1;
+                self.chunk[start] = b"0123456789abcdef"[len % 16];
+                len /= 16;
+                if len == 0 {
+                    break;
+                }
+            }
+        }
+        let framed_end = if self.chunked { end + 2 } else { end };
+        self.chunk_len = 0;
+        sink.send(&self.chunk[start..framed_end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor::decompress_to_vec;
+
+    fn page() -> Vec<u8> {
+        let mut page = b"<html><body><table>".to_vec();
+        for i in 0..400u32 {
+            page.extend_from_slice(
+                alloc::format!("<tr><td>sensor {i}</td><td>ok</td></tr>").as_bytes(),
+            );
+        }
+        page.extend_from_slice(b"</table></body></html>");
+        page
+    }
+
+    /// Undoes `Transfer-Encoding: chunked`.
+    fn dechunk(mut data: &[u8]) -> Vec<u8> {
+        let mut body = Vec::new();
+        loop {
+            let line = data.iter().position(|&b| b == b'\r').unwrap();
+            let len =
+                usize::from_str_radix(core::str::from_utf8(&data[..line]).unwrap(), 16).unwrap();
+            assert_eq!(&data[line..line + 2], b"\r\n");
+            data = &data[line + 2..];
+            if len == 0 {
+                assert_eq!(data, b"\r\n");
+                return body;
+            }
+            body.extend_from_slice(&data[..len]);
+            assert_eq!(&data[len..len + 2], b"\r\n");
+            data = &data[len + 2..];
+        }
+    }
+
+    #[test]
+    fn plain_and_chunked_bodies_decode() {
+        let page = page();
+        for chunked in [false, true] {
+            let mut body = GzipBody::new(&page[..], CompressionConfig::text(), 300);
+            if chunked {
+                body = body.chunked();
+            }
+            let mut sent = Vec::new();
+            let mut chunks = 0;
+            let mut sink = |chunk: &[u8]| -> Result<(), ()> {
+                assert!(chunk.len() <= 300 + 2 * SIZE_LINE);
+                chunks += 1;
+                sent.extend_from_slice(chunk);
+                Ok(())
+            };
+            let mut steps = 0;
+            while !body.step(&mut sink).unwrap() {
+                steps += 1;
+            }
+            assert!(steps > 10);
+            assert!(chunks > 3);
+            let gz = if chunked { dechunk(&sent) } else { sent };
+            assert_eq!(decompress_to_vec(&gz, ContainerFormat::Gzip).unwrap(), page);
+        }
+    }
+
+    #[test]
+    fn sink_errors_stop_the_body() {
+        let page = page();
+        let mut body = GzipBody::new(&page[..], CompressionConfig::default(), 64);
+        let mut sink = |_: &[u8]| Err("connection reset");
+        let mut result = Ok(false);
+        while result == Ok(false) {
+            result = body.step(&mut sink);
+        }
+        assert_eq!(result, Err("connection reset"));
+        assert!(!body.is_finished());
+    }
+}
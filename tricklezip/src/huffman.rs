@@ -0,0 +1,869 @@
+//! Huffman coding of LZ77 tokens into DEFLATE blocks.
+
+#[cfg(feature = "tiny")]
+use core::marker::PhantomData;
+
+#[cfg(not(feature = "tiny"))]
+use crate::allocator::BoxIn;
+use crate::allocator::{Allocator, Global};
+use crate::bitwriter::BitWriter;
+use crate::lz77::Token;
+
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+/// Bases of the 30 DEFLATE distance codes, then of the two it reserves,
+/// which only the `extended-window` feature uses.
+pub(crate) const DIST_BASE: [u16; 32] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577, 32769, 49153,
+];
+pub(crate) const DIST_EXTRA: [u8; 32] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13, 14, 14,
+];
+/// Distance codes in use.
+#[cfg(not(feature = "extended-window"))]
+pub(crate) const DIST_CODES: usize = 30;
+#[cfg(feature = "extended-window")]
+pub(crate) const DIST_CODES: usize = 32;
+/// Longest distance the distance codes in use can express.
+pub(crate) const MAX_DISTANCE: usize =
+    DIST_BASE[DIST_CODES - 1] as usize + (1 << DIST_EXTRA[DIST_CODES - 1]) - 1;
+/// Transmission order of the code length code lengths.
+pub(crate) const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+pub(crate) const END_OF_BLOCK: usize = 256;
+pub(crate) const MAX_STORED: usize = 65535;
+/// Stored block header, padding and LEN/NLEN, at most.
+pub(crate) const STORED_OVERHEAD_BITS: usize = 3 + 7 + 32;
+
+/// Kind of DEFLATE block, as signalled by BTYPE.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+/// `LENGTH_BASE` index for each match length minus 3.
+#[cfg(not(feature = "tiny"))]
+static LENGTH_CODE: [u8; 256] = make_length_code();
+
+/// `DIST_BASE` index for distances up to 256 (indexed by distance - 1),
+/// followed by the index for longer distances by `(distance - 1) >> 7`.
+#[cfg(not(feature = "tiny"))]
+static DIST_CODE: [u8; 256 + MAX_DISTANCE / 128] = make_dist_code();
+
+#[cfg(not(feature = "tiny"))]
+const fn make_length_code() -> [u8; 256] {
+    let mut table = [0u8; 256];
+    let mut i = 0;
+    while i < LENGTH_BASE.len() - 1 {
+        let mut len = LENGTH_BASE[i] as usize;
+        while len < LENGTH_BASE[i + 1] as usize {
+            table[len - 3] = i as u8;
+            len += 1;
+        }
+        i += 1;
+    }
+    // The loop stops short of the last base: 258 has a code of its own.
+    table[255] = 28;
+    table
+}
+
+#[cfg(not(feature = "tiny"))]
+const fn make_dist_code() -> [u8; 256 + MAX_DISTANCE / 128] {
+    let mut table = [0u8; 256 + MAX_DISTANCE / 128];
+    let mut i = 0;
+    while i < DIST_CODES {
+        let start = DIST_BASE[i] as usize - 1;
+        let end = start + (1 << DIST_EXTRA[i]);
+        let mut d = start;
+        while d < end {
+            if d < 256 {
+                table[d] = i as u8;
+            } else {
+                table[256 + (d >> 7)] = i as u8;
+            }
+            d += 1;
+        }
+        i += 1;
+    }
+    table
+}
+
+/// Index into `LENGTH_BASE` for a match length of 3..=258.
+#[cfg(not(feature = "tiny"))]
+#[inline]
+pub(crate) fn length_index(length: usize) -> usize {
+    LENGTH_CODE[length - 3] as usize
+}
+
+/// Index into `DIST_BASE` for a distance of 1..=`MAX_DISTANCE`.
+#[cfg(not(feature = "tiny"))]
+#[inline]
+pub(crate) fn dist_index(distance: usize) -> usize {
+    let d = distance - 1;
+    if d < 256 {
+        DIST_CODE[d] as usize
+    } else {
+        DIST_CODE[256 + (d >> 7)] as usize
+    }
+}
+
+/// Index into `LENGTH_BASE` for a match length of 3..=258.
+///
+/// Searches the base table instead of keeping a 256-byte lookup table.
+#[cfg(feature = "tiny")]
+#[inline]
+pub(crate) fn length_index(length: usize) -> usize {
+    let mut i = LENGTH_BASE.len() - 1;
+    while LENGTH_BASE[i] as usize > length {
+        i -= 1;
+    }
+    i
+}
+
+/// Index into `DIST_BASE` for a distance of 1..=`MAX_DISTANCE`.
+///
+/// Searches the base table instead of keeping a 512-byte lookup table.
+#[cfg(feature = "tiny")]
+#[inline]
+pub(crate) fn dist_index(distance: usize) -> usize {
+    let mut i = DIST_CODES - 1;
+    while DIST_BASE[i] as usize > distance {
+        i -= 1;
+    }
+    i
+}
+
+#[inline]
+fn reverse_bits(code: u32, len: u32) -> u16 {
+    (code as u16).reverse_bits() >> (16 - len)
+}
+
+/// Source of (bit-reversed code, length) pairs for the two alphabets.
+trait Codes {
+    fn lit(&self, sym: usize) -> (u16, u32);
+    fn dist(&self, sym: usize) -> (u16, u32);
+}
+
+/// The fixed codes of RFC1951 section 3.2.6, computed rather than tabulated.
+struct FixedCodes;
+
+impl Codes for FixedCodes {
+    #[inline]
+    fn lit(&self, sym: usize) -> (u16, u32) {
+        let sym = sym as u32;
+        let (code, len) = match sym {
+            0..=143 => (0x30 + sym, 8),
+            144..=255 => (0x190 + sym - 144, 9),
+            256..=279 => (sym - 256, 7),
+            _ => (0xC0 + sym - 280, 8),
+        };
+        (reverse_bits(code, len), len)
+    }
+
+    #[inline]
+    fn dist(&self, sym: usize) -> (u16, u32) {
+        (reverse_bits(sym as u32, 5), 5)
+    }
+}
+
+fn write_tokens<C: Codes>(codes: &C, tokens: &[Token], w: &mut BitWriter<impl Allocator>) {
+    let mut rest = tokens;
+    while let Some(&token) = rest.first() {
+        match token {
+            Token::Literal(_) => {
+                // Text yields long literal runs; code them in one tight loop
+                // instead of dispatching on every token.
+                let run = rest
+                    .iter()
+                    .position(|t| matches!(t, Token::Match { .. }))
+                    .unwrap_or(rest.len());
+                w.write_codes(rest[..run].iter().map(|t| match *t {
+                    Token::Literal(b) => codes.lit(b as usize),
+                    Token::Match { .. } => unreachable!(),
+                }));
+                rest = &rest[run..];
+            }
+            Token::Match { length, distance } => {
+                let li = length_index(length as usize);
+                let (code, len) = codes.lit(257 + li);
+                w.write_bits(code as u32, len);
+                let extra = LENGTH_EXTRA[li] as u32;
+                if extra > 0 {
+                    w.write_bits((length - LENGTH_BASE[li]) as u32, extra);
+                }
+                let di = dist_index(distance as usize);
+                let (code, len) = codes.dist(di);
+                w.write_bits(code as u32, len);
+                let extra = DIST_EXTRA[di] as u32;
+                if extra > 0 {
+                    w.write_bits((distance - DIST_BASE[di]) as u32, extra);
+                }
+                rest = &rest[1..];
+            }
+        }
+    }
+    let (code, len) = codes.lit(END_OF_BLOCK);
+    w.write_bits(code as u32, len);
+}
+
+/// Size in bits of `tokens` plus end-of-block under the fixed codes.
+fn fixed_cost(tokens: &[Token]) -> usize {
+    let mut bits = 3;
+    for token in tokens {
+        bits += match *token {
+            Token::Literal(b) => FixedCodes.lit(b as usize).1 as usize,
+            Token::Match { length, distance } => {
+                let li = length_index(length as usize);
+                let di = dist_index(distance as usize);
+                FixedCodes.lit(257 + li).1 as usize
+                    + LENGTH_EXTRA[li] as usize
+                    + 5
+                    + DIST_EXTRA[di] as usize
+            }
+        };
+    }
+    bits + 7
+}
+
+fn stored_cost(raw: &[u8]) -> usize {
+    let chunks = raw.len().div_ceil(MAX_STORED).max(1);
+    raw.len() * 8 + chunks * STORED_OVERHEAD_BITS
+}
+
+pub(crate) fn write_stored(raw: &[u8], last: bool, w: &mut BitWriter<impl Allocator>) {
+    let mut chunks = raw.chunks(MAX_STORED).peekable();
+    if chunks.peek().is_none() {
+        w.write_bits(last as u32, 1);
+        w.write_bits(0, 2);
+        w.align();
+        w.write_bytes(&[0, 0, 0xFF, 0xFF]);
+        return;
+    }
+    while let Some(chunk) = chunks.next() {
+        let final_chunk = last && chunks.peek().is_none();
+        w.write_bits(final_chunk as u32, 1);
+        w.write_bits(0, 2);
+        w.align();
+        let len = chunk.len() as u16;
+        w.write_bytes(&len.to_le_bytes());
+        w.write_bytes(&(!len).to_le_bytes());
+        w.write_bytes(chunk);
+    }
+}
+
+/// Chooses the cheapest block type for a run of tokens and writes it.
+pub(crate) struct HuffmanCoder<A: Allocator = Global> {
+    /// Scratch space for dynamic blocks, only allocated when they are allowed.
+    #[cfg(not(feature = "tiny"))]
+    dynamic: Option<BoxIn<dynamic::DynamicTrees, A>>,
+    #[cfg(feature = "tiny")]
+    alloc: PhantomData<A>,
+    /// Write every block stored, skipping cost estimation.
+    pub(crate) stored_only: bool,
+    /// Cycles spent writing blocks since the caller last took them.
+    #[cfg(feature = "profiling")]
+    pub(crate) write_cycles: u64,
+}
+
+impl HuffmanCoder {
+    /// Bytes `new_in(allow_dynamic, ..)` allocates.
+    pub(crate) fn heap_usage_for(allow_dynamic: bool) -> usize {
+        #[cfg(not(feature = "tiny"))]
+        if allow_dynamic {
+            return core::mem::size_of::<dynamic::DynamicTrees>();
+        }
+        #[cfg(feature = "tiny")]
+        let _ = allow_dynamic;
+        0
+    }
+}
+
+impl<A: Allocator> HuffmanCoder<A> {
+    pub(crate) fn new_in(allow_dynamic: bool, alloc: A) -> Self {
+        #[cfg(feature = "tiny")]
+        let _ = (allow_dynamic, alloc);
+        Self {
+            #[cfg(not(feature = "tiny"))]
+            dynamic: allow_dynamic.then(|| BoxIn::new_in(dynamic::DynamicTrees::new(), alloc)),
+            #[cfg(feature = "tiny")]
+            alloc: PhantomData,
+            stored_only: false,
+            #[cfg(feature = "profiling")]
+            write_cycles: 0,
+        }
+    }
+
+    /// Clears the per-stream flags. The tree scratch space is rebuilt for
+    /// every block, so it is kept as is.
+    pub(crate) fn reset(&mut self) {
+        self.stored_only = false;
+        #[cfg(feature = "profiling")]
+        {
+            self.write_cycles = 0;
+        }
+    }
+
+    /// Bytes allocated for dynamic tree construction.
+    pub(crate) fn heap_usage(&self) -> usize {
+        #[cfg(not(feature = "tiny"))]
+        if self.dynamic.is_some() {
+            return core::mem::size_of::<dynamic::DynamicTrees>();
+        }
+        0
+    }
+
+    /// Picks the cheapest block type for `tokens`, leaving the dynamic trees
+    /// built if that is the choice. `raw` holds the uncompressed bytes the
+    /// tokens cover. Returns the type and the block size in bits.
+    pub(crate) fn choose(&mut self, tokens: &[Token], raw: &[u8]) -> (BlockType, usize) {
+        let stored = stored_cost(raw);
+        if self.stored_only {
+            return (BlockType::Stored, stored);
+        }
+        let fixed = fixed_cost(tokens);
+
+        #[cfg(not(feature = "tiny"))]
+        if let Some(trees) = self.dynamic.as_deref_mut() {
+            let dynamic = trees.build(tokens);
+            if dynamic < fixed && dynamic < stored {
+                return (BlockType::Dynamic, dynamic);
+            }
+        }
+
+        if stored < fixed {
+            (BlockType::Stored, stored)
+        } else {
+            (BlockType::Fixed, fixed)
+        }
+    }
+
+    /// Encodes `tokens` as one block of the cheapest type, returning the
+    /// type and its size in bits as estimated by [`choose`](Self::choose).
+    pub(crate) fn encode(
+        &mut self,
+        tokens: &[Token],
+        raw: &[u8],
+        last: bool,
+        w: &mut BitWriter<impl Allocator>,
+    ) -> (BlockType, usize) {
+        let (block, bits) = self.choose(tokens, raw);
+        self.write(block, tokens, raw, last, w);
+        (block, bits)
+    }
+
+    /// Encodes `tokens` as one block of type `block`, or fixed if dynamic
+    /// blocks are not available. Returns the type written.
+    pub(crate) fn encode_as(
+        &mut self,
+        block: BlockType,
+        tokens: &[Token],
+        raw: &[u8],
+        last: bool,
+        w: &mut BitWriter<impl Allocator>,
+    ) -> BlockType {
+        let block = match block {
+            #[cfg(not(feature = "tiny"))]
+            BlockType::Dynamic => match self.dynamic.as_deref_mut() {
+                Some(trees) => {
+                    trees.build(tokens);
+                    BlockType::Dynamic
+                }
+                None => BlockType::Fixed,
+            },
+            #[cfg(feature = "tiny")]
+            BlockType::Dynamic => BlockType::Fixed,
+            other => other,
+        };
+        self.write(block, tokens, raw, last, w);
+        block
+    }
+
+    /// Writes a block of type `block`; dynamic trees must have been built
+    /// for `tokens`.
+    fn write(
+        &mut self,
+        block: BlockType,
+        tokens: &[Token],
+        raw: &[u8],
+        last: bool,
+        w: &mut BitWriter<impl Allocator>,
+    ) {
+        #[cfg(feature = "profiling")]
+        let start = crate::cycles::now();
+        match block {
+            BlockType::Stored => write_stored(raw, last, w),
+            BlockType::Fixed => {
+                w.write_bits(last as u32, 1);
+                w.write_bits(1, 2);
+                write_tokens(&FixedCodes, tokens, w);
+            }
+            #[cfg(not(feature = "tiny"))]
+            BlockType::Dynamic => {
+                let trees = self.dynamic.as_deref().expect("dynamic block chosen");
+                w.write_bits(last as u32, 1);
+                w.write_bits(2, 2);
+                trees.write_header(w);
+                write_tokens(trees, tokens, w);
+            }
+            #[cfg(feature = "tiny")]
+            BlockType::Dynamic => unreachable!("dynamic blocks are compiled out"),
+        }
+        #[cfg(feature = "profiling")]
+        {
+            self.write_cycles += crate::cycles::since(start);
+        }
+    }
+}
+
+#[cfg(not(feature = "tiny"))]
+mod dynamic {
+    use super::*;
+
+    const MAX_BITS: u32 = 15;
+    const MAX_CLEN_BITS: u32 = 7;
+
+    /// Per-block frequency counts and the codes derived from them.
+    pub(super) struct DynamicTrees {
+        lit_freq: [u32; 286],
+        dist_freq: [u32; DIST_CODES],
+        lit_len: [u8; 286],
+        dist_len: [u8; DIST_CODES],
+        lit_code: [u16; 286],
+        dist_code: [u16; DIST_CODES],
+        clen_len: [u8; 19],
+        clen_code: [u16; 19],
+        /// Run-length coded lengths as (symbol, extra bits value).
+        rle: [(u8, u8); 286 + DIST_CODES],
+        rle_len: usize,
+        hlit: usize,
+        hdist: usize,
+        hclen: usize,
+    }
+
+    impl Codes for DynamicTrees {
+        #[inline]
+        fn lit(&self, sym: usize) -> (u16, u32) {
+            (self.lit_code[sym], self.lit_len[sym] as u32)
+        }
+
+        #[inline]
+        fn dist(&self, sym: usize) -> (u16, u32) {
+            (self.dist_code[sym], self.dist_len[sym] as u32)
+        }
+    }
+
+    impl DynamicTrees {
+        pub(super) fn new() -> Self {
+            Self {
+                lit_freq: [0; 286],
+                dist_freq: [0; DIST_CODES],
+                lit_len: [0; 286],
+                dist_len: [0; DIST_CODES],
+                lit_code: [0; 286],
+                dist_code: [0; DIST_CODES],
+                clen_len: [0; 19],
+                clen_code: [0; 19],
+                rle: [(0, 0); 286 + DIST_CODES],
+                rle_len: 0,
+                hlit: 0,
+                hdist: 0,
+                hclen: 0,
+            }
+        }
+
+        /// Builds the trees for `tokens` and returns the block size in bits.
+        pub(super) fn build(&mut self, tokens: &[Token]) -> usize {
+            self.lit_freq.fill(0);
+            self.dist_freq.fill(0);
+            for token in tokens {
+                match *token {
+                    Token::Literal(b) => self.lit_freq[b as usize] += 1,
+                    Token::Match { length, distance } => {
+                        self.lit_freq[257 + length_index(length as usize)] += 1;
+                        self.dist_freq[dist_index(distance as usize)] += 1;
+                    }
+                }
+            }
+            self.lit_freq[END_OF_BLOCK] = 1;
+            // Some inflaters reject blocks without any distance code.
+            if self.dist_freq.iter().all(|&f| f == 0) {
+                self.dist_freq[0] = 1;
+            }
+
+            build_lengths(&self.lit_freq, MAX_BITS, &mut self.lit_len);
+            build_lengths(&self.dist_freq, MAX_BITS, &mut self.dist_len);
+            assign_codes(&self.lit_len, &mut self.lit_code);
+            assign_codes(&self.dist_len, &mut self.dist_code);
+
+            self.hlit = 257 + trailing_used(&self.lit_len[257..]);
+            self.hdist = 1 + trailing_used(&self.dist_len[1..]);
+            self.run_length_encode();
+
+            let mut clen_freq = [0u32; 19];
+            for &(sym, _) in &self.rle[..self.rle_len] {
+                clen_freq[sym as usize] += 1;
+            }
+            build_lengths(&clen_freq, MAX_CLEN_BITS, &mut self.clen_len);
+            assign_codes(&self.clen_len, &mut self.clen_code);
+            self.hclen = 4 + CLEN_ORDER[4..]
+                .iter()
+                .rposition(|&i| self.clen_len[i] != 0)
+                .map_or(0, |p| p + 1);
+
+            let mut bits = 3 + 5 + 5 + 4 + 3 * self.hclen;
+            for &(sym, _) in &self.rle[..self.rle_len] {
+                bits += self.clen_len[sym as usize] as usize + clen_extra_bits(sym) as usize;
+            }
+            for (sym, &f) in self.lit_freq.iter().enumerate() {
+                let extra = if sym > 256 {
+                    LENGTH_EXTRA[sym - 257]
+                } else {
+                    0
+                };
+                bits += f as usize * (self.lit_len[sym] as usize + extra as usize);
+            }
+            for (sym, &f) in self.dist_freq.iter().enumerate() {
+                bits += f as usize * (self.dist_len[sym] as usize + DIST_EXTRA[sym] as usize);
+            }
+            bits
+        }
+
+        /// Run-length codes the concatenated literal/length and distance
+        /// code lengths with symbols 16 (repeat), 17 and 18 (zero runs).
+        fn run_length_encode(&mut self) {
+            let mut lengths = [0u8; 286 + DIST_CODES];
+            lengths[..self.hlit].copy_from_slice(&self.lit_len[..self.hlit]);
+            lengths[self.hlit..self.hlit + self.hdist]
+                .copy_from_slice(&self.dist_len[..self.hdist]);
+            let lengths = &lengths[..self.hlit + self.hdist];
+
+            self.rle_len = 0;
+            let mut i = 0;
+            while i < lengths.len() {
+                let value = lengths[i];
+                let mut run = lengths[i..].iter().take_while(|&&l| l == value).count();
+                i += run;
+                if value == 0 {
+                    while run >= 11 {
+                        let n = run.min(138);
+                        self.push_rle(18, (n - 11) as u8);
+                        run -= n;
+                    }
+                    if run >= 3 {
+                        self.push_rle(17, (run - 3) as u8);
+                        run = 0;
+                    }
+                } else {
+                    self.push_rle(value, 0);
+                    run -= 1;
+                    while run >= 3 {
+                        let n = run.min(6);
+                        self.push_rle(16, (n - 3) as u8);
+                        run -= n;
+                    }
+                }
+                for _ in 0..run {
+                    self.push_rle(value, 0);
+                }
+            }
+        }
+
+        fn push_rle(&mut self, sym: u8, extra: u8) {
+            self.rle[self.rle_len] = (sym, extra);
+            self.rle_len += 1;
+        }
+
+        /// Writes HLIT, HDIST, HCLEN and the code length sequences.
+        pub(super) fn write_header(&self, w: &mut BitWriter<impl Allocator>) {
+            w.write_bits((self.hlit - 257) as u32, 5);
+            w.write_bits((self.hdist - 1) as u32, 5);
+            w.write_bits((self.hclen - 4) as u32, 4);
+            for &i in &CLEN_ORDER[..self.hclen] {
+                w.write_bits(self.clen_len[i] as u32, 3);
+            }
+            for &(sym, extra) in &self.rle[..self.rle_len] {
+                let s = sym as usize;
+                w.write_bits(self.clen_code[s] as u32, self.clen_len[s] as u32);
+                let extra_bits = clen_extra_bits(sym);
+                if extra_bits > 0 {
+                    w.write_bits(extra as u32, extra_bits);
+                }
+            }
+        }
+    }
+
+    fn clen_extra_bits(sym: u8) -> u32 {
+        match sym {
+            16 => 2,
+            17 => 3,
+            18 => 7,
+            _ => 0,
+        }
+    }
+
+    /// Number of entries up to and including the last non-zero one.
+    fn trailing_used(lengths: &[u8]) -> usize {
+        lengths.iter().rposition(|&l| l != 0).map_or(0, |p| p + 1)
+    }
+
+    /// Computes length-limited Huffman code lengths for `freq`.
+    ///
+    /// Uses the in-place minimum redundancy algorithm of Moffat and
+    /// Katajainen, then moves overlong codes down until the Kraft sum fits
+    /// `max_bits`.
+    pub(crate) fn build_lengths(freq: &[u32], max_bits: u32, lengths: &mut [u8]) {
+        lengths.fill(0);
+        let mut syms = [(0u32, 0u16); 288];
+        let mut n = 0;
+        for (sym, &f) in freq.iter().enumerate() {
+            if f > 0 {
+                syms[n] = (f, sym as u16);
+                n += 1;
+            }
+        }
+        match n {
+            0 => return,
+            1 => {
+                // A lone code still needs a complete tree for some decoders.
+                let sym = syms[0].1 as usize;
+                lengths[sym] = 1;
+                lengths[if sym == 0 { 1 } else { 0 }] = 1;
+                return;
+            }
+            _ => {}
+        }
+        let syms = &mut syms[..n];
+        syms.sort_unstable();
+
+        let mut a = [0u32; 288];
+        for (k, &(f, _)) in a.iter_mut().zip(syms.iter()) {
+            *k = f;
+        }
+        let a = &mut a[..n];
+        a[0] += a[1];
+        let (mut root, mut leaf) = (0, 2);
+        for next in 1..n - 1 {
+            if leaf >= n || a[root] < a[leaf] {
+                a[next] = a[root];
+                a[root] = next as u32;
+                root += 1;
+            } else {
+                a[next] = a[leaf];
+                leaf += 1;
+            }
+            if leaf >= n || (root < next && a[root] < a[leaf]) {
+                a[next] += a[root];
+                a[root] = next as u32;
+                root += 1;
+            } else {
+                a[next] += a[leaf];
+                leaf += 1;
+            }
+        }
+        a[n - 2] = 0;
+        for next in (0..n - 2).rev() {
+            a[next] = a[a[next] as usize] + 1;
+        }
+        let (mut avail, mut used, mut depth) = (1usize, 0usize, 0u32);
+        let mut root = n as isize - 2;
+        let mut next = n as isize - 1;
+        while avail > 0 {
+            while root >= 0 && a[root as usize] == depth {
+                used += 1;
+                root -= 1;
+            }
+            while avail > used {
+                a[next as usize] = depth;
+                next -= 1;
+                avail -= 1;
+            }
+            avail = 2 * used;
+            depth += 1;
+            used = 0;
+        }
+
+        let mut count = [0u32; 16];
+        for &len in a.iter() {
+            count[len.min(max_bits) as usize] += 1;
+        }
+        let mut total: u32 = (1..=max_bits)
+            .map(|i| count[i as usize] << (max_bits - i))
+            .sum();
+        while total != 1 << max_bits {
+            count[max_bits as usize] -= 1;
+            for i in (1..max_bits as usize).rev() {
+                if count[i] != 0 {
+                    count[i] -= 1;
+                    count[i + 1] += 2;
+                    break;
+                }
+            }
+            total -= 1;
+        }
+
+        let mut j = n;
+        for len in 1..=max_bits {
+            for _ in 0..count[len as usize] {
+                j -= 1;
+                lengths[syms[j].1 as usize] = len as u8;
+            }
+        }
+    }
+
+    /// Assigns canonical, bit-reversed codes from code lengths.
+    pub(crate) fn assign_codes(lengths: &[u8], codes: &mut [u16]) {
+        let mut bl_count = [0u32; 16];
+        for &l in lengths {
+            bl_count[l as usize] += 1;
+        }
+        bl_count[0] = 0;
+        let mut next = [0u32; 16];
+        let mut code = 0;
+        for bits in 1..16 {
+            code = (code + bl_count[bits - 1]) << 1;
+            next[bits] = code;
+        }
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                codes[sym] = reverse_bits(next[len as usize], len as u32);
+                next[len as usize] += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(feature = "tiny"))]
+    use super::dynamic::build_lengths;
+    use super::*;
+    #[cfg(not(feature = "tiny"))]
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn code_indices_match_base_tables() {
+        for length in 3..=258 {
+            let i = length_index(length);
+            assert!(LENGTH_BASE[i] as usize <= length);
+            assert!(length - (LENGTH_BASE[i] as usize) < 1 << LENGTH_EXTRA[i]);
+        }
+        assert_eq!(length_index(258), 28);
+        for distance in 1..=32768 {
+            let i = dist_index(distance);
+            assert!(DIST_BASE[i] as usize <= distance);
+            assert!(distance - (DIST_BASE[i] as usize) < 1 << DIST_EXTRA[i]);
+        }
+    }
+
+    #[cfg(not(feature = "tiny"))]
+    fn kraft(lengths: &[u8], max_bits: u32) -> u32 {
+        lengths
+            .iter()
+            .filter(|&&l| l != 0)
+            .map(|&l| 1 << (max_bits - l as u32))
+            .sum()
+    }
+
+    #[cfg(not(feature = "tiny"))]
+    #[test]
+    fn lengths_are_limited_and_complete() {
+        // Fibonacci frequencies force a degenerate, very deep tree.
+        let mut freq = [0u32; 30];
+        let (mut a, mut b) = (1u32, 1u32);
+        for f in freq.iter_mut() {
+            *f = a;
+            (a, b) = (b, a + b);
+        }
+        let mut lengths = [0u8; 30];
+        build_lengths(&freq, 15, &mut lengths);
+        assert!(lengths.iter().all(|&l| (1..=15).contains(&l)));
+        assert_eq!(kraft(&lengths, 15), 1 << 15);
+    }
+
+    /// Literals of `before`, a copy of `length` from `distance` back, and
+    /// literals of `after`.
+    fn parse(before: &[u8], length: u16, distance: u16, after: &[u8]) -> Vec<Token> {
+        let literals = |bytes: &[u8]| bytes.iter().map(|&b| Token::Literal(b)).collect::<Vec<_>>();
+        let mut tokens = literals(before);
+        tokens.push(Token::Match { length, distance });
+        tokens.extend(literals(after));
+        tokens
+    }
+
+    #[test]
+    fn fixed_blocks_match_zlib_bit_for_bit() {
+        // zlib's raw level 9 output for "abcabcabcabc" and the fixed block
+        // of "fixed fixed fixed fixed\n", with its parse: lazy matching
+        // takes one more literal before each copy.
+        let cases: [(Vec<Token>, &[u8]); 2] = [
+            (
+                parse(b"abca", 8, 3, b""),
+                &[0x4B, 0x4C, 0x4A, 0x4E, 0x84, 0x21, 0x00],
+            ),
+            (
+                parse(b"fixed f", 16, 6, b"\n"),
+                &[
+                    0x4B, 0xCB, 0xAC, 0x48, 0x4D, 0x51, 0x48, 0x43, 0x27, 0xB9, 0x00,
+                ],
+            ),
+        ];
+        for (tokens, expected) in cases {
+            let mut coder = HuffmanCoder::new_in(false, Global);
+            let mut w = BitWriter::new_in(Global);
+            coder.encode_as(BlockType::Fixed, &tokens, &[], true, &mut w);
+            w.align();
+            let mut out = [0u8; 16];
+            let n = w.drain(&mut out);
+            assert_eq!(&out[..n], expected);
+        }
+    }
+
+    #[cfg(not(feature = "tiny"))]
+    #[test]
+    fn dynamic_headers_run_length_code_the_lengths() {
+        // Digits only: long runs of unused literals and equal lengths.
+        let digits: Vec<u8> = (0..2000u32).map(|i| b'0' + (i * 7 % 10) as u8).collect();
+        let tokens: Vec<Token> = digits.iter().map(|&b| Token::Literal(b)).collect();
+        let mut coder = HuffmanCoder::new_in(true, Global);
+        let mut w = BitWriter::new_in(Global);
+        let block = coder.encode_as(BlockType::Dynamic, &tokens, &digits, true, &mut w);
+        assert_eq!(block, BlockType::Dynamic);
+        w.align();
+        let mut out = vec![0u8; w.pending_len()];
+        w.drain(&mut out);
+
+        let description = crate::explain(&out, crate::ContainerFormat::Raw).unwrap();
+        let block = &description.blocks[0];
+        assert_eq!(block.output_len, digits.len());
+        let lengths = block.code_lengths.as_ref().unwrap();
+        let lit = &lengths.literal_length;
+        let data_bits: u64 =
+            digits.iter().map(|&b| lit[b as usize] as u64).sum::<u64>() + lit[END_OF_BLOCK] as u64;
+        // Sent one by one, the code lengths alone would take a bit each.
+        let header_bits = block.bits - data_bits;
+        assert!(header_bits < (lit.len() + lengths.distance.len()) as u64);
+    }
+
+    #[cfg(not(feature = "tiny"))]
+    #[test]
+    fn single_symbol_gets_a_complete_code() {
+        let mut freq = [0u32; 19];
+        freq[7] = 10;
+        let mut lengths = [0u8; 19];
+        build_lengths(&freq, 7, &mut lengths);
+        assert_eq!(lengths[7], 1);
+        assert_eq!(kraft(&lengths, 7), 1 << 7);
+    }
+}
@@ -0,0 +1,790 @@
+//! Streaming RFC1951 decoder.
+//!
+//! Decoding proceeds in small units (a block header, one code length, one
+//! literal or match). A unit only peeks at the bit buffer until it is
+//! complete, so running out of input in the middle of one simply leaves the
+//! bits buffered for the next call.
+
+use crate::allocator::{filled_in, Allocator, Global, VecIn};
+use crate::config::DecodeLimits;
+use crate::error::TrickleError;
+use crate::fuel::{limit, Fuel};
+use crate::huffman::{
+    CLEN_ORDER, DIST_BASE, DIST_CODES, DIST_EXTRA, END_OF_BLOCK, LENGTH_BASE, LENGTH_EXTRA,
+    MAX_DISTANCE,
+};
+
+/// Largest window DEFLATE allows, and the default.
+pub(crate) const MAX_WINDOW: usize = 32768;
+const MAX_BITS: usize = 15;
+/// Longest match, i.e. the most output a single symbol can produce.
+const MAX_MATCH: usize = 258;
+/// Bits of the longest literal/length/distance sequence: 15 + 5 + 15 + 13,
+/// or 14 extra distance bits with the extended distance codes.
+const MAX_SEQUENCE_BITS: u32 = 15 + 5 + 15 + DIST_EXTRA[DIST_CODES - 1] as u32;
+
+/// Canonical Huffman decoding table in the style of zlib's `puff`.
+pub(crate) struct Huffman<const N: usize> {
+    count: [u16; MAX_BITS + 1],
+    symbol: [u16; N],
+}
+
+impl<const N: usize> Huffman<N> {
+    pub(crate) fn new() -> Self {
+        Self {
+            count: [0; MAX_BITS + 1],
+            symbol: [0; N],
+        }
+    }
+
+    /// Builds the table and returns the number of unused code slots, which
+    /// is zero for a complete code.
+    pub(crate) fn build(&mut self, lengths: &[u8]) -> Result<u32, TrickleError> {
+        self.count = [0; MAX_BITS + 1];
+        for &len in lengths {
+            self.count[len as usize] += 1;
+        }
+        if self.count[0] as usize == lengths.len() {
+            return Ok(0);
+        }
+        let mut left: i32 = 1;
+        for len in 1..=MAX_BITS {
+            left <<= 1;
+            left -= self.count[len] as i32;
+            if left < 0 {
+                return Err(TrickleError::InvalidData);
+            }
+        }
+        let mut offs = [0u16; MAX_BITS + 1];
+        for len in 1..MAX_BITS {
+            offs[len + 1] = offs[len] + self.count[len];
+        }
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                self.symbol[offs[len as usize] as usize] = sym as u16;
+                offs[len as usize] += 1;
+            }
+        }
+        Ok(left as u32)
+    }
+
+    /// Decodes the symbol at the bottom of `bits`, returning it with its
+    /// code length, or `None` if no code matches.
+    #[inline]
+    pub(crate) fn decode_bits(&self, mut bits: u64) -> Option<(usize, u32)> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            code |= (bits & 1) as i32;
+            bits >>= 1;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Some((
+                    self.symbol[(index + code - first) as usize] as usize,
+                    len as u32,
+                ));
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        None
+    }
+
+    /// Whether an incomplete code is acceptable: only a single code of
+    /// length one may leave slots unused.
+    pub(crate) fn incomplete_ok(&self) -> bool {
+        self.count[1] == 1 && self.count[2..].iter().all(|&c| c == 0)
+    }
+}
+
+/// Builds the fixed literal/length and distance codes.
+pub(crate) fn build_fixed(
+    lit: &mut Huffman<288>,
+    dist: &mut Huffman<DIST_CODES>,
+) -> Result<(), TrickleError> {
+    let mut lengths = [0u8; 288 + DIST_CODES];
+    lengths[..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+    lengths[288..].fill(5);
+    lit.build(&lengths[..288])?;
+    // Incomplete without the extended distance codes: 30 and 31 exist but
+    // are never used.
+    dist.build(&lengths[288..])?;
+    Ok(())
+}
+
+/// Splits the 14 bits following a dynamic block header into the number of
+/// literal/length, distance and code length code lengths.
+pub(crate) fn dynamic_counts(bits: u32) -> Result<(usize, usize, usize), TrickleError> {
+    let hlit = (bits & 0x1F) as usize + 257;
+    let hdist = ((bits >> 5) & 0x1F) as usize + 1;
+    let hclen = ((bits >> 10) & 0xF) as usize + 4;
+    if hlit > 286 || hdist > DIST_CODES {
+        return Err(TrickleError::InvalidData);
+    }
+    Ok((hlit, hdist, hclen))
+}
+
+/// Builds the code length code, which must be complete.
+pub(crate) fn build_clen(clen: &mut Huffman<19>, lengths: &[u8]) -> Result<(), TrickleError> {
+    if clen.build(lengths)? != 0 {
+        return Err(TrickleError::InvalidData);
+    }
+    Ok(())
+}
+
+/// Builds the literal/length and distance codes of a dynamic block from
+/// its code lengths, the first `hlit` of which are literal/length ones.
+pub(crate) fn build_dynamic(
+    lit: &mut Huffman<288>,
+    dist: &mut Huffman<DIST_CODES>,
+    lengths: &[u8],
+    hlit: usize,
+) -> Result<(), TrickleError> {
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(TrickleError::InvalidData);
+    }
+    if lit.build(&lengths[..hlit])? != 0 && !lit.incomplete_ok() {
+        return Err(TrickleError::InvalidData);
+    }
+    if dist.build(&lengths[hlit..])? != 0 && !dist.incomplete_ok() {
+        return Err(TrickleError::InvalidData);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    StoredLen,
+    Stored { remaining: u16 },
+    DynCounts,
+    ClenLengths { index: usize },
+    CodeLengths { index: usize },
+    Codes,
+    Copy { length: usize, distance: usize },
+    Done,
+}
+
+/// Outcome of one `decompress_chunk` call.
+pub(crate) struct ChunkResult {
+    pub(crate) consumed: usize,
+    pub(crate) written: usize,
+    /// The final block has been fully decoded.
+    pub(crate) done: bool,
+    /// Decoding stopped at corrupt data; `consumed` and `written` cover
+    /// what came before it.
+    pub(crate) error: Option<TrickleError>,
+}
+
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+pub(crate) struct InflateState<A: Allocator = Global> {
+    state: State,
+    last: bool,
+    bit_buf: u64,
+    bit_count: u32,
+    window: VecIn<u8, A>,
+    window_pos: usize,
+    /// Bytes produced so far, capped at the window size.
+    history: usize,
+    lit: Huffman<288>,
+    dist: Huffman<DIST_CODES>,
+    clen: Huffman<19>,
+    lengths: [u8; 286 + DIST_CODES],
+    hlit: usize,
+    hdist: usize,
+    hclen: usize,
+    /// Blocks decoded through their end-of-block code or stored length.
+    pub(crate) blocks: u32,
+    max_blocks: u32,
+    /// Code length steps of the current dynamic header so far.
+    table_iterations: usize,
+    max_table_iterations: usize,
+}
+
+impl<A: Allocator> InflateState<A> {
+    /// Creates a decoder whose window, a power of two up to `MAX_DISTANCE`
+    /// bytes, is allocated in `alloc`.
+    pub(crate) fn new_in(window_size: usize, alloc: A) -> Self {
+        debug_assert!(window_size.is_power_of_two() && window_size <= MAX_DISTANCE);
+        Self {
+            state: State::Header,
+            last: false,
+            bit_buf: 0,
+            bit_count: 0,
+            window: filled_in(0, window_size, alloc),
+            window_pos: 0,
+            history: 0,
+            lit: Huffman::new(),
+            dist: Huffman::new(),
+            clen: Huffman::new(),
+            lengths: [0; 286 + DIST_CODES],
+            hlit: 0,
+            hdist: 0,
+            hclen: 0,
+            blocks: 0,
+            max_blocks: u32::MAX,
+            table_iterations: 0,
+            max_table_iterations: usize::MAX,
+        }
+    }
+
+    /// Applies the block and table limits of `limits`; the window is sized
+    /// on creation.
+    pub(crate) fn set_limits(&mut self, limits: &DecodeLimits) {
+        self.max_blocks = limits.max_blocks;
+        self.max_table_iterations = limits.max_table_iterations;
+    }
+
+    /// Starts over on a new stream, keeping the window allocation.
+    pub(crate) fn reset(&mut self) {
+        self.state = State::Header;
+        self.last = false;
+        self.bit_buf = 0;
+        self.bit_count = 0;
+        self.window_pos = 0;
+        self.history = 0;
+        self.blocks = 0;
+    }
+
+    pub(crate) fn window_size(&self) -> usize {
+        self.window.len()
+    }
+
+    /// Bytes left of a match that the output filled up in the middle of.
+    pub(crate) fn pending_copy(&self) -> usize {
+        match self.state {
+            State::Copy { length, .. } => length,
+            _ => 0,
+        }
+    }
+
+    /// Uses the tail of `dict` as history that matches may refer to. Only
+    /// valid before any input.
+    pub(crate) fn prime(&mut self, dict: &[u8]) {
+        let size = self.window.len();
+        let dict = &dict[dict.len().saturating_sub(size)..];
+        self.window[..dict.len()].copy_from_slice(dict);
+        self.window_pos = dict.len() & (size - 1);
+        self.history = dict.len();
+    }
+
+    /// Hands back a whole byte still held in the bit buffer, for reading the
+    /// container trailer after the final block.
+    pub(crate) fn take_byte(&mut self) -> Option<u8> {
+        if self.bit_count < 8 {
+            return None;
+        }
+        let b = self.bit_buf as u8;
+        self.consume(8);
+        Some(b)
+    }
+
+    /// Queues `bytes` as the first input, e.g. ones read while detecting
+    /// the container format. Only valid before any input.
+    pub(crate) fn unread(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.bit_buf |= (b as u64) << self.bit_count;
+            self.bit_count += 8;
+        }
+    }
+
+    /// Makes sure at least `n` bits are buffered, pulling bytes from `input`.
+    #[inline]
+    fn need(&mut self, input: &mut Input, n: u32) -> bool {
+        while self.bit_count < n {
+            let Some(&b) = input.data.get(input.pos) else {
+                return false;
+            };
+            input.pos += 1;
+            self.bit_buf |= (b as u64) << self.bit_count;
+            self.bit_count += 8;
+        }
+        true
+    }
+
+    /// Reads `n` bits at bit offset `*off` without consuming them.
+    #[inline]
+    fn peek(&mut self, input: &mut Input, off: &mut u32, n: u32) -> Option<u32> {
+        if !self.need(input, *off + n) {
+            return None;
+        }
+        let v = (self.bit_buf >> *off) as u32 & ((1u32 << n) - 1);
+        *off += n;
+        Some(v)
+    }
+
+    #[inline]
+    fn consume(&mut self, n: u32) {
+        self.bit_buf >>= n;
+        self.bit_count -= n;
+    }
+
+    /// Decodes one symbol at bit offset `*off`. `Ok(None)` means more input
+    /// is needed.
+    fn decode<const N: usize>(
+        &mut self,
+        input: &mut Input,
+        off: &mut u32,
+        table: fn(&Self) -> &Huffman<N>,
+    ) -> Result<Option<usize>, TrickleError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_BITS {
+            if !self.need(input, *off + len as u32) {
+                return Ok(None);
+            }
+            code |= ((self.bit_buf >> (*off + len as u32 - 1)) & 1) as i32;
+            let h = table(self);
+            let count = h.count[len] as i32;
+            if code - count < first {
+                *off += len as u32;
+                return Ok(Some(h.symbol[(index + code - first) as usize] as usize));
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(TrickleError::InvalidData)
+    }
+
+    #[inline]
+    fn put(&mut self, out: &mut [u8], written: &mut usize, byte: u8) {
+        out[*written] = byte;
+        *written += 1;
+        let size = self.window.len();
+        self.window[self.window_pos] = byte;
+        self.window_pos = (self.window_pos + 1) & (size - 1);
+        if self.history < size {
+            self.history += 1;
+        }
+    }
+
+    /// Decodes from `input` into `output` until one of them runs out, the
+    /// final block ends or the data turns out to be corrupt.
+    pub(crate) fn decompress_chunk(&mut self, input: &[u8], output: &mut [u8]) -> ChunkResult {
+        let mut input = Input {
+            data: input,
+            pos: 0,
+        };
+        let mut written = 0;
+        let error = self.run(&mut input, output, &mut written).err();
+        if self.state == State::Done {
+            self.align_to_byte();
+            // The fast path reads ahead; whole bytes past the final block
+            // belong to the trailer or whatever follows the stream.
+            let ahead = (self.bit_count / 8).min(input.pos as u32);
+            input.pos -= ahead as usize;
+            self.bit_count -= ahead * 8;
+            self.bit_buf &= !(u64::MAX << self.bit_count);
+        }
+        ChunkResult {
+            consumed: input.pos,
+            written,
+            done: self.state == State::Done,
+            error,
+        }
+    }
+
+    fn run(
+        &mut self,
+        input: &mut Input,
+        output: &mut [u8],
+        written: &mut usize,
+    ) -> Result<(), TrickleError> {
+        // A step that makes progress takes at least one bit or writes at
+        // least one byte, so the bits on hand and the output space bound
+        // the steps, plus the one that finds nothing left to do.
+        let bits = self.bit_count as usize + 1;
+        let mut fuel = Fuel::new(limit(
+            8,
+            input.data.len(),
+            output.len().saturating_add(bits),
+        ));
+        loop {
+            let burnt = fuel.burn();
+            debug_assert!(burnt, "inflate step made no progress");
+            if !burnt {
+                return Err(TrickleError::InvalidData);
+            }
+            let before = self.state;
+            let progressed = match self.state {
+                State::Done => return Ok(()),
+                State::Header => self.read_header(input)?,
+                State::StoredLen => self.read_stored_len(input)?,
+                State::Stored { remaining } => self.copy_stored(input, output, written, remaining),
+                State::DynCounts => self.read_dyn_counts(input)?,
+                State::ClenLengths { index } => self.read_clen_length(input, index)?,
+                State::CodeLengths { index } => self.read_code_length(input, index)?,
+                State::Codes => self.read_codes(input, output, written)?,
+                State::Copy { length, distance } => {
+                    let before = *written;
+                    self.copy_match(output, written, length, distance);
+                    *written > before
+                }
+            };
+            if before != State::Header && matches!(self.state, State::Header | State::Done) {
+                self.blocks += 1;
+            }
+            if !progressed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Drops the bits left of a partly consumed byte, e.g. the padding of
+    /// the final one.
+    pub(crate) fn align_to_byte(&mut self) {
+        let pad = self.bit_count % 8;
+        self.consume(pad);
+    }
+
+    /// Drops all buffered bits, e.g. ones that preceded lost input.
+    pub(crate) fn discard_bits(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    /// Continues with a block header and no history, as at a full-flush
+    /// point. Whole bytes still buffered are kept as its first input.
+    pub(crate) fn restart(&mut self) {
+        self.state = State::Header;
+        self.last = false;
+        self.history = 0;
+    }
+
+    fn read_header(&mut self, input: &mut Input) -> Result<bool, TrickleError> {
+        let mut off = 0;
+        let Some(bits) = self.peek(input, &mut off, 3) else {
+            return Ok(false);
+        };
+        if self.blocks >= self.max_blocks {
+            return Err(TrickleError::LimitExceeded {
+                limit: "max_blocks",
+            });
+        }
+        self.consume(off);
+        self.last = bits & 1 != 0;
+        self.state = match bits >> 1 {
+            0 => {
+                let pad = self.bit_count % 8;
+                self.consume(pad);
+                State::StoredLen
+            }
+            1 => {
+                build_fixed(&mut self.lit, &mut self.dist)?;
+                State::Codes
+            }
+            2 => State::DynCounts,
+            _ => return Err(TrickleError::InvalidData),
+        };
+        Ok(true)
+    }
+
+    fn read_stored_len(&mut self, input: &mut Input) -> Result<bool, TrickleError> {
+        let mut off = 0;
+        let Some(len) = self.peek(input, &mut off, 16) else {
+            return Ok(false);
+        };
+        let Some(nlen) = self.peek(input, &mut off, 16) else {
+            return Ok(false);
+        };
+        if len != !nlen & 0xFFFF {
+            return Err(TrickleError::InvalidData);
+        }
+        self.consume(off);
+        self.state = self.after_stored(len as u16);
+        Ok(true)
+    }
+
+    fn after_stored(&self, remaining: u16) -> State {
+        match (remaining, self.last) {
+            (0, true) => State::Done,
+            (0, false) => State::Header,
+            _ => State::Stored { remaining },
+        }
+    }
+
+    fn copy_stored(
+        &mut self,
+        input: &mut Input,
+        out: &mut [u8],
+        written: &mut usize,
+        mut remaining: u16,
+    ) -> bool {
+        let start = *written;
+        // Whole bytes may still sit in the bit buffer after the header.
+        while remaining > 0 && *written < out.len() && self.bit_count >= 8 {
+            let b = self.bit_buf as u8;
+            self.consume(8);
+            self.put(out, written, b);
+            remaining -= 1;
+        }
+        while remaining > 0 && *written < out.len() && input.pos < input.data.len() {
+            let b = input.data[input.pos];
+            input.pos += 1;
+            self.put(out, written, b);
+            remaining -= 1;
+        }
+        self.state = self.after_stored(remaining);
+        *written > start || remaining == 0
+    }
+
+    fn read_dyn_counts(&mut self, input: &mut Input) -> Result<bool, TrickleError> {
+        let mut off = 0;
+        let Some(bits) = self.peek(input, &mut off, 14) else {
+            return Ok(false);
+        };
+        self.consume(off);
+        (self.hlit, self.hdist, self.hclen) = dynamic_counts(bits)?;
+        self.lengths[..19].fill(0);
+        self.table_iterations = 0;
+        self.state = State::ClenLengths { index: 0 };
+        Ok(true)
+    }
+
+    fn read_clen_length(&mut self, input: &mut Input, index: usize) -> Result<bool, TrickleError> {
+        let mut off = 0;
+        let Some(len) = self.peek(input, &mut off, 3) else {
+            return Ok(false);
+        };
+        self.table_step()?;
+        self.consume(off);
+        self.lengths[CLEN_ORDER[index]] = len as u8;
+        if index + 1 < self.hclen {
+            self.state = State::ClenLengths { index: index + 1 };
+        } else {
+            let lengths = self.lengths;
+            build_clen(&mut self.clen, &lengths[..19])?;
+            self.state = State::CodeLengths { index: 0 };
+        }
+        Ok(true)
+    }
+
+    fn read_code_length(&mut self, input: &mut Input, index: usize) -> Result<bool, TrickleError> {
+        let mut off = 0;
+        let Some(sym) = self.decode(input, &mut off, |s| &s.clen)? else {
+            return Ok(false);
+        };
+        let total = self.hlit + self.hdist;
+        let (value, repeat) = match sym {
+            0..=15 => (sym as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(TrickleError::InvalidData);
+                }
+                let Some(extra) = self.peek(input, &mut off, 2) else {
+                    return Ok(false);
+                };
+                (self.lengths[index - 1], 3 + extra as usize)
+            }
+            17 => {
+                let Some(extra) = self.peek(input, &mut off, 3) else {
+                    return Ok(false);
+                };
+                (0, 3 + extra as usize)
+            }
+            _ => {
+                let Some(extra) = self.peek(input, &mut off, 7) else {
+                    return Ok(false);
+                };
+                (0, 11 + extra as usize)
+            }
+        };
+        if index + repeat > total {
+            return Err(TrickleError::InvalidData);
+        }
+        self.table_step()?;
+        self.consume(off);
+        self.lengths[index..index + repeat].fill(value);
+        let index = index + repeat;
+        if index < total {
+            self.state = State::CodeLengths { index };
+            return Ok(true);
+        }
+
+        let lengths = self.lengths;
+        build_dynamic(&mut self.lit, &mut self.dist, &lengths[..total], self.hlit)?;
+        self.state = State::Codes;
+        Ok(true)
+    }
+
+    /// Counts a step of parsing a dynamic header against its limit.
+    fn table_step(&mut self) -> Result<(), TrickleError> {
+        self.table_iterations += 1;
+        if self.table_iterations > self.max_table_iterations {
+            return Err(TrickleError::LimitExceeded {
+                limit: "max_table_iterations",
+            });
+        }
+        Ok(())
+    }
+
+    fn read_codes(
+        &mut self,
+        input: &mut Input,
+        out: &mut [u8],
+        written: &mut usize,
+    ) -> Result<bool, TrickleError> {
+        loop {
+            if self.read_codes_fast(input, out, written)? {
+                return Ok(true);
+            }
+            let mut off = 0;
+            let Some(sym) = self.decode(input, &mut off, |s| &s.lit)? else {
+                return Ok(false);
+            };
+            // With the output full, only an end-of-block code can still be
+            // taken, so an exactly sized buffer sees the stream finish.
+            if *written == out.len() && sym != END_OF_BLOCK {
+                return Ok(false);
+            }
+            if sym < 256 {
+                self.consume(off);
+                self.put(out, written, sym as u8);
+                continue;
+            }
+            if sym == END_OF_BLOCK {
+                self.consume(off);
+                self.state = if self.last {
+                    State::Done
+                } else {
+                    State::Header
+                };
+                return Ok(true);
+            }
+            let li = sym - 257;
+            if li >= LENGTH_BASE.len() {
+                return Err(TrickleError::InvalidData);
+            }
+            let Some(extra) = self.peek(input, &mut off, LENGTH_EXTRA[li] as u32) else {
+                return Ok(false);
+            };
+            let length = LENGTH_BASE[li] as usize + extra as usize;
+            let Some(di) = self.decode(input, &mut off, |s| &s.dist)? else {
+                return Ok(false);
+            };
+            if di >= DIST_CODES {
+                return Err(TrickleError::InvalidData);
+            }
+            let Some(extra) = self.peek(input, &mut off, DIST_EXTRA[di] as u32) else {
+                return Ok(false);
+            };
+            let distance = DIST_BASE[di] as usize + extra as usize;
+            self.check_distance(distance)?;
+            self.consume(off);
+            self.copy_match(out, written, length, distance);
+            if self.state != State::Codes {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Decodes symbols straight from the bit buffer while at least 8 input
+    /// bytes and a full match worth of output space remain. Each refill
+    /// loads a whole word and tops the buffer up to at least 56 bits, enough
+    /// for one complete match or several literals, so the per-bit input
+    /// checks of the unit-wise path are skipped. Returns `true` when the
+    /// block ended.
+    fn read_codes_fast(
+        &mut self,
+        input: &mut Input,
+        out: &mut [u8],
+        written: &mut usize,
+    ) -> Result<bool, TrickleError> {
+        while input.data.len() - input.pos >= 8 && out.len() - *written >= MAX_MATCH {
+            let mut word = [0; 8];
+            word.copy_from_slice(&input.data[input.pos..input.pos + 8]);
+            let bytes = (63 - self.bit_count) / 8;
+            self.bit_buf |= u64::from_le_bytes(word) << self.bit_count;
+            // Bits above the last whole byte taken are not ours yet.
+            self.bit_count += bytes * 8;
+            self.bit_buf &= u64::MAX >> (64 - self.bit_count);
+            input.pos += bytes as usize;
+
+            while self.bit_count >= MAX_SEQUENCE_BITS && out.len() - *written >= MAX_MATCH {
+                let (sym, len) = self
+                    .lit
+                    .decode_bits(self.bit_buf)
+                    .ok_or(TrickleError::InvalidData)?;
+                self.consume(len);
+                if sym < 256 {
+                    self.put(out, written, sym as u8);
+                    continue;
+                }
+                if sym == END_OF_BLOCK {
+                    self.state = if self.last {
+                        State::Done
+                    } else {
+                        State::Header
+                    };
+                    return Ok(true);
+                }
+                let li = sym - 257;
+                if li >= LENGTH_BASE.len() {
+                    return Err(TrickleError::InvalidData);
+                }
+                let length = LENGTH_BASE[li] as usize + self.take_bits(LENGTH_EXTRA[li] as u32);
+                let (di, len) = self
+                    .dist
+                    .decode_bits(self.bit_buf)
+                    .ok_or(TrickleError::InvalidData)?;
+                self.consume(len);
+                if di >= DIST_CODES {
+                    return Err(TrickleError::InvalidData);
+                }
+                let distance = DIST_BASE[di] as usize + self.take_bits(DIST_EXTRA[di] as u32);
+                self.check_distance(distance)?;
+                self.copy_match(out, written, length, distance);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Consumes `n` buffered bits and returns them.
+    #[inline]
+    fn take_bits(&mut self, n: u32) -> usize {
+        let v = (self.bit_buf & ((1u64 << n) - 1)) as usize;
+        self.consume(n);
+        v
+    }
+
+    /// Rejects a match reaching before the output, or beyond a window
+    /// smaller than the stream was compressed for.
+    #[inline]
+    fn check_distance(&self, distance: usize) -> Result<(), TrickleError> {
+        if distance <= self.history {
+            Ok(())
+        } else if self.history == self.window.len() {
+            Err(TrickleError::WindowTooLarge)
+        } else {
+            Err(TrickleError::InvalidData)
+        }
+    }
+
+    /// Copies up to `length` bytes from `distance` back, suspending in
+    /// `State::Copy` if the output fills first.
+    fn copy_match(&mut self, out: &mut [u8], written: &mut usize, length: usize, distance: usize) {
+        let n = length.min(out.len() - *written);
+        for _ in 0..n {
+            let size = self.window.len();
+            let b = self.window[(self.window_pos + size - distance) & (size - 1)];
+            self.put(out, written, b);
+        }
+        self.state = if n < length {
+            State::Copy {
+                length: length - n,
+                distance,
+            }
+        } else {
+            State::Codes
+        };
+    }
+}
@@ -1,14 +1,228 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
-    }
-}
+//! A DEFLATE compression library for embedded devices, which does not use up
+//! all your CPU time at once.
+//!
+//! Work is split into small steps: every call to
+//! [`TrickleCompressor::compress_trickle`] processes a bounded amount of input
+//! and returns, so compression can be interleaved with the rest of the
+//! firmware's main loop. Output is standard RFC1951 DEFLATE, optionally
+//! wrapped in a zlib (RFC1950) or gzip (RFC1952) container.
+//!
+//! The crate is `no_std` (with `alloc`) when the default `std` feature is
+//! disabled.
+//!
+//! No call loops unboundedly, whatever the input: every loop in the codec
+//! either walks a slice or is capped by an iteration count fixed on entry
+//! from the sizes of the buffers passed in and the configuration. A
+//! decoder step that would exceed its cap reports
+//! [`TrickleError::InvalidData`] instead.
+//!
+//! # Features
+//!
+//! - `std` (default): `std::error::Error` impls,
+//!   [`TrickleCompressor::compress_timed`], which times itself with
+//!   [`StdClock`] (without `std`,
+//!   [`compress_timed_with`](TrickleCompressor::compress_timed_with) takes
+//!   any [`TimeSource`]), the multi-threaded [`compress_parallel`], and
+//!   [`AdaptiveCompressor`], which trades ratio for speed to meet a
+//!   throughput target, [`CompressorPool`] for servers handling many
+//!   connections, the `flate2`-style gzip `Read` and `Write` adapters in
+//!   [`compat::flate2`], [`DictionaryBuilder`], which trains preset
+//!   dictionaries on sample payloads, [`TarGzWriter`] for `.tar.gz`
+//!   archives of log files, the [`gzip_file`] and [`gunzip_file`]
+//!   helpers and [`spawn_compressor`], which runs a compressor on a
+//!   background thread behind a pair of channels.
+//! - `tiny`: builds a minimal compressor for small MCUs, for the smallest
+//!   code size. Dynamic Huffman blocks are compiled out (only fixed and
+//!   stored blocks are emitted), CRC-32 is computed bitwise instead of
+//!   through a 1KB table and the fixed codes are computed rather than
+//!   tabulated. Compressible data comes out larger, the more so the more
+//!   its symbol frequencies stray from the fixed codes; incompressible data
+//!   is stored either way, so [`compress_bound`] holds unchanged.
+//! - `crc-bitwise`: only the bitwise CRC-32 of `tiny`, which implies it,
+//!   for targets short on flash that still want dynamic blocks. Hashing
+//!   with [`Crc32`] and the gzip container gets about 8x slower.
+//! - `simd`: compares match candidates 16 bytes at a time with SSE2 on
+//!   x86_64 and NEON on aarch64, for faster host-side compression of large
+//!   inputs. Other targets ignore it. Output is identical either way.
+//! - `profiling`: counts CPU cycles spent in match finding, Huffman coding
+//!   and bit writing into [`CompressionStats`], using the DWT cycle counter
+//!   of Cortex-M3 and later cores. The application has to enable the
+//!   counter; on other targets the counts stay zero.
+//! - `allocator_api` (nightly only): [`TrickleCompressor::new_in`] places
+//!   all of the compressor's heap buffers in a caller-supplied
+//!   [`Allocator`], such as an arena in CCM or external SRAM, and
+//!   `TrickleDecompressor::new_in` does the same for the decompressor's
+//!   window. A [`Workspace`] lends one static buffer to streams that run
+//!   one after another.
+//! - `ffi-zlib`: exports a zlib-compatible C ABI (`deflateInit_`,
+//!   `deflate`, `inflate`, ...) with a `z_stream` of the same layout, so C
+//!   middleware can link against the crate as a drop-in zlib; see [`ffi`].
+//! - `log`: debug records for stream start and finish, early abort and
+//!   decoding errors, and a trace record per block with its type and
+//!   size, through the [`log`](https://docs.rs/log) facade under the
+//!   `tricklezip` target.
+//! - `defmt`: implements [`defmt::Format`](https://docs.rs/defmt) for
+//!   [`CompressionStats`], with the same one-line summary as its
+//!   `Display`.
+//! - `rayon`: implies `std`. [`par_compress_chunks`] compresses a batch of
+//!   inputs into one stream each on the [`rayon`](https://docs.rs/rayon)
+//!   thread pool, returning them in input order.
+//! - `log-dict`: embeds [`LOG_DICTIONARY`], about 1.1KB of fragments common
+//!   in syslog lines and JSON telemetry, with the
+//!   [`CompressionConfig::structured_logs`] preset,
+//!   [`TrickleCompressor::with_log_dictionary`] and
+//!   [`TrickleDecompressor::with_log_dictionary`], so batches of a few lines
+//!   compress well without a trained dictionary.
+//! - `async`: [`AsyncTrickleCompressor`], whose
+//!   [`compress`](AsyncTrickleCompressor::compress) future yields to the
+//!   executor between `trickle_size` steps. It depends on no runtime.
+//! - `embassy`: implies `async`.
+//!   [`AsyncTrickleCompressor::compress_embassy`] yields through
+//!   `embassy_futures::yield_now`, and
+//!   [`AsyncTrickleCompressor::compress_until`] also stops at an
+//!   `embassy_time::Instant` deadline. [`EmbassyClock`] is a [`TimeSource`]
+//!   on the Embassy time driver.
+//! - `extended-window`: allows a `window_size` of 65536 for raw streams,
+//!   for links where both ends run this crate. Matches then reach up to
+//!   64KB back through the two distance codes DEFLATE reserves, so the
+//!   output is **not** DEFLATE: zlib and other inflaters reject it, and
+//!   so does a [`TrickleDecompressor`] without a 64KB window from
+//!   [`TrickleDecompressor::with_window_size`]. Lengths are those of
+//!   DEFLATE, so it is not Deflate64 either. Hash chains for a 64KB
+//!   window take 4 bytes per entry instead of 2; smaller windows keep
+//!   2-byte entries in this build too.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod adaptive;
+mod adler32;
+mod allocator;
+#[cfg(feature = "async")]
+mod async_compressor;
+mod bitwriter;
+mod block;
+mod checksum;
+mod clock;
+mod coap;
+pub mod compat;
+mod compressor;
+mod config;
+mod crc32;
+#[cfg(feature = "profiling")]
+mod cycles;
+mod decompressor;
+mod deflate;
+mod delta;
+#[cfg(feature = "std")]
+mod dictionary;
+#[cfg(feature = "embassy")]
+mod embassy;
+mod entropy;
+mod error;
+mod explain;
+#[cfg(feature = "ffi-zlib")]
+pub mod ffi;
+#[cfg(feature = "std")]
+mod file;
+mod firmware;
+mod frame;
+mod fuel;
+mod http;
+mod huffman;
+mod inflate;
+#[cfg(feature = "log-dict")]
+mod log_dictionary;
+mod logging;
+mod long_range;
+mod lz77;
+mod mqtt;
+mod oneshot;
+mod packet;
+#[cfg(feature = "std")]
+mod parallel;
+mod png;
+#[cfg(feature = "std")]
+mod pool;
+mod prefix;
+mod spsc;
+mod stats;
+#[cfg(feature = "std")]
+mod tar;
+mod tee;
+mod tokens;
+mod transform;
+mod typestate;
+mod uart;
+#[cfg(feature = "std")]
+mod worker;
+#[cfg(feature = "allocator_api")]
+mod workspace;
+
+#[cfg(feature = "std")]
+pub use adaptive::AdaptiveCompressor;
+#[cfg(feature = "allocator_api")]
+pub use allocator::{Allocator, Global};
+#[cfg(feature = "async")]
+pub use async_compressor::AsyncTrickleCompressor;
+pub use block::BlockEncoder;
+pub use checksum::{Adler32, Checksum, NoChecksum, Xxh32};
+#[cfg(feature = "std")]
+pub use clock::StdClock;
+pub use clock::TimeSource;
+pub use coap::{CoapBlock, CoapBlockwise};
+pub use compressor::{
+    compress_bound, compress_chunks_to_vec, compress_to_fit, compress_to_vec,
+    estimate_compressed_size, ExceedsLimit, FlushMode, TrickleCompressor, TrickleResult,
+    TrickleStatus, WorkBudget,
+};
+pub use config::{
+    BufferSizes, CompressionConfig, CompressionLevel, ContainerFormat, DecodeLimits,
+    DecompressorConfig, EarlyAbort, Strategy,
+};
+pub use crc32::{Crc32, CrcBackend, SoftwareCrc};
+pub use decompressor::{decompress_to_vec, find_restart_point, TrickleDecompressor};
+pub use delta::{build_delta, DeltaDecoder};
+#[cfg(feature = "std")]
+pub use dictionary::{Dictionary, DictionaryBuilder, MAX_DICTIONARY_SIZE};
+#[cfg(feature = "embassy")]
+pub use embassy::EmbassyClock;
+pub use entropy::{byte_entropy, ByteHistogram};
+pub use error::TrickleError;
+pub use explain::{explain, BlockDescription, CodeLengths, StreamDescription, Symbol};
+#[cfg(feature = "std")]
+pub use file::{gunzip_file, gzip_file};
+pub use firmware::FirmwareImage;
+pub use frame::{FrameDecoder, FrameEncoder, FrameProgress, FrameStatus, MAX_FRAME_CHUNK};
+pub use http::{BodySource, ChunkSink, GzipBody};
+pub use huffman::BlockType;
+#[cfg(feature = "log-dict")]
+pub use log_dictionary::{LOG_DICTIONARY, LOG_DICTIONARY_ID};
+pub use long_range::{compress_long_range, decompress_long_range, LongRangeIndex, LongRangeMatch};
+pub use lz77::Token;
+pub use mqtt::{compress_payload, Payload};
+pub use oneshot::{decompress_in_place, decompress_into, in_place_offset};
+pub use packet::{compress_packet, decompress_packet};
+#[cfg(feature = "rayon")]
+pub use parallel::par_compress_chunks;
+#[cfg(feature = "std")]
+pub use parallel::{compress_parallel, ParallelOptions};
+pub use png::{encode_png, PngColor, PngEncoder, PngFilter};
+#[cfg(feature = "std")]
+pub use pool::{CompressorPool, Pooled};
+pub use spsc::{ByteQueue, QueueConsumer, QueueProducer};
+pub use stats::{BlockInfo, CompressionStats, DecompressProgress, StatsSink};
+#[cfg(feature = "std")]
+pub use tar::TarGzWriter;
+pub use tee::{OutputSink, SinkProgress, TeeCompressor, TeeResult};
+pub use tokens::{MatchFinder, TokenBatch};
+pub use transform::BlockTransform;
+pub use typestate::{FinishStep, Finished, Finishing, StreamPhase, Streaming, Trickle};
+pub use uart::{UartDeframer, UartFramer, MAX_UART_PAYLOAD, UART_FRAME_OVERHEAD};
+#[cfg(feature = "std")]
+pub use worker::{spawn_compressor, CompressedChunk};
+#[cfg(feature = "allocator_api")]
+pub use workspace::{Workspace, WorkspaceAlloc};
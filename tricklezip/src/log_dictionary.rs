@@ -0,0 +1,160 @@
+//! Built-in preset dictionary for structured logs.
+
+use crate::compressor::TrickleCompressor;
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::decompressor::TrickleDecompressor;
+
+/// Fragments common in syslog lines and JSON telemetry, about 1.1KB.
+///
+/// Matches prefer short distances, so the most frequent fragments come
+/// last. The contents are part of the format: changing them breaks
+/// streams compressed with the old dictionary, which is why it has a
+/// [`LOG_DICTIONARY_ID`].
+pub static LOG_DICTIONARY: &[u8] = concat!(
+    // Syslog: RFC 5424 and BSD-style headers, common daemons.
+    "Jan Feb Mar Apr May Jun Jul Aug Sep Oct Nov Dec ",
+    "Mon Tue Wed Thu Fri Sat Sun ",
+    "<13>1 <14>1 <134>1 <165>1 - - - ",
+    "kernel: systemd[1]: sshd[ cron[ dhcpcd[ wpa_supplicant[ NetworkManager[ ",
+    "Started Stopped Starting Stopping Reached target ",
+    "Accepted publickey for Failed password for invalid user from port ssh2",
+    "connection refused connection reset by peer timed out retrying in ",
+    "emerg alert crit notice TRACE DEBUG INFO WARN WARNING ERROR FATAL ",
+    "[DEBUG] [INFO] [WARN] [ERROR] level=debug level=info level=warn level=error ",
+    " msg=\" err=\" caller= component= ",
+    // JSON telemetry: units, states and common keys.
+    "\"unit\":\"C\"\"unit\":\"%\"\"unit\":\"V\"\"unit\":\"mA\"\"unit\":\"hPa\"",
+    "\"status\":\"ok\",\"status\":\"error\",\"state\":\"on\",\"state\":\"off\",",
+    "\"online\":true,\"online\":false,\"alarm\":false,\"error\":null,",
+    "\"fw\":\"\"firmware\":\"\"version\":\"\"model\":\"\"serial\":\"",
+    "\"lat\":\"lon\":\"alt\":\"speed\":\"heading\":",
+    "\"voltage\":\"current\":\"power\":\"energy\":\"pressure\":",
+    "\"battery\":\"rssi\":-\"snr\":\"uptime\":\"count\":",
+    "\"humidity\":\"temperature\":\"temp_c\":\"value\":",
+    "\"sensor\":\"\"device_id\":\"\"device\":\"\"host\":\"\"service\":\"",
+    "\"level\":\"debug\",\"level\":\"info\",\"level\":\"warn\",\"level\":\"error\",",
+    "\"message\":\"\"msg\":\"\"time\":\"\"ts\":\"timestamp\":\"",
+    "\"},{\"}]}\n{\"",
+    "T00:00:00.000Z\n2026-01-01T00:00:00Z ",
+)
+.as_bytes();
+
+/// Adler-32 of [`LOG_DICTIONARY`], the `DICTID` a zlib header announces
+/// when it is used.
+pub const LOG_DICTIONARY_ID: u32 = 0xAD7D_6F42;
+
+impl CompressionConfig {
+    /// Preset for small batches of log lines or JSON telemetry, to be used
+    /// with [`TrickleCompressor::with_log_dictionary`]. An 8KB window keeps
+    /// both the dictionary and recent lines in reach; the
+    /// [`Filtered`](crate::Strategy::Filtered) strategy leaves digits to the
+    /// literal code.
+    pub fn structured_logs() -> Self {
+        Self {
+            strategy: crate::config::Strategy::Filtered,
+            window_size: 8192,
+            hash_bits: 13,
+            max_chain_length: 32,
+            nice_length: 128,
+            block_size: 1024,
+            ..Self::default()
+        }
+    }
+}
+
+impl TrickleCompressor {
+    /// Creates a compressor whose window starts out holding
+    /// [`LOG_DICTIONARY`], so even a batch of a few lines finds matches.
+    ///
+    /// The output is raw DEFLATE whatever the format in `config`: neither
+    /// zlib nor gzip headers are written, and the data decodes only with
+    /// [`TrickleDecompressor::with_log_dictionary`]. The dictionary is lost
+    /// on [`reset`](Self::reset); create a new compressor per batch.
+    ///
+    /// ```
+    /// use tricklezip::{compress_to_vec, CompressionConfig, FlushMode, TrickleCompressor, TrickleDecompressor};
+    ///
+    /// let batch = br#"{"ts":1760000000,"device_id":"pump-3","level":"info","temp_c":21.5}"#;
+    /// let mut compressor = TrickleCompressor::with_log_dictionary(CompressionConfig::structured_logs());
+    /// let mut packed = [0u8; 256];
+    /// let r = compressor.compress_trickle(batch, &mut packed, FlushMode::Finish).unwrap();
+    /// assert!(r.written < compress_to_vec(batch, &CompressionConfig::structured_logs()).len());
+    ///
+    /// let mut decompressor = TrickleDecompressor::with_log_dictionary();
+    /// let mut out = [0u8; 256];
+    /// let d = decompressor.decompress_trickle(&packed[..r.written], &mut out).unwrap();
+    /// assert_eq!(&out[..d.written], &batch[..]);
+    /// ```
+    pub fn with_log_dictionary(config: CompressionConfig) -> Self {
+        let mut compressor = Self::new(config.with_format(ContainerFormat::Raw));
+        compressor.prime(LOG_DICTIONARY);
+        compressor
+    }
+}
+
+impl TrickleDecompressor {
+    /// Creates a raw DEFLATE decompressor for data from
+    /// [`TrickleCompressor::with_log_dictionary`]. Like the compressor, it
+    /// loses the dictionary on [`reset`](Self::reset).
+    pub fn with_log_dictionary() -> Self {
+        let mut decompressor = Self::new(ContainerFormat::Raw);
+        decompressor.prime(LOG_DICTIONARY);
+        decompressor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adler32;
+    use crate::compressor::{compress_to_vec, FlushMode, TrickleStatus};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn dictionary_id_matches() {
+        assert_eq!(adler32::update(1, LOG_DICTIONARY), LOG_DICTIONARY_ID);
+    }
+
+    #[test]
+    fn small_batches_shrink_and_round_trip() {
+        let mut batch = Vec::new();
+        for i in 0..4 {
+            batch.extend_from_slice(
+                alloc::format!(
+                    "{{\"ts\":\"2026-10-15T08:0{i}:00Z\",\"device_id\":\"meter-17\",\"level\":\"info\",\"voltage\":230.{i},\"status\":\"ok\"}}\n"
+                )
+                .as_bytes(),
+            );
+        }
+        batch.extend_from_slice(b"<14>1 2026-10-15T08:05:00Z gw-2 sshd 311 - - Accepted publickey for ops from 10.0.0.7 port 50122 ssh2\n");
+        let config = CompressionConfig::structured_logs();
+
+        let mut compressor = TrickleCompressor::with_log_dictionary(config.clone());
+        let mut packed = Vec::new();
+        let mut buf = [0u8; 64];
+        let mut consumed = 0;
+        loop {
+            let r = compressor
+                .compress_trickle(&batch[consumed..], &mut buf, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            packed.extend_from_slice(&buf[..r.written]);
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+        }
+        let plain = compress_to_vec(&batch, &config.with_format(ContainerFormat::Raw));
+        assert!(
+            packed.len() * 10 < plain.len() * 8,
+            "{} vs {}",
+            packed.len(),
+            plain.len()
+        );
+
+        let mut decompressor = TrickleDecompressor::with_log_dictionary();
+        let mut out = alloc::vec![0u8; batch.len()];
+        let r = decompressor.decompress_trickle(&packed, &mut out).unwrap();
+        assert_eq!(r.status, TrickleStatus::Finished);
+        assert_eq!(out, batch);
+    }
+}
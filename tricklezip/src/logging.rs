@@ -0,0 +1,79 @@
+//! Debug and trace records through the `log` facade.
+//!
+//! With the `log` feature these forward to `log::debug!` and `log::trace!`
+//! under the `tricklezip` target. Without it they compile to nothing, but
+//! the arguments are still type-checked so call sites cannot rot.
+
+#[cfg(feature = "log")]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        ::log::debug!(target: "tricklezip", $($arg)+)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! debug {
+    ($($arg:tt)+) => {
+        if false {
+            let _ = ::core::format_args!($($arg)+);
+        }
+    };
+}
+
+#[cfg(feature = "log")]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        ::log::trace!(target: "tricklezip", $($arg)+)
+    };
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! trace {
+    ($($arg:tt)+) => {
+        if false {
+            let _ = ::core::format_args!($($arg)+);
+        }
+    };
+}
+
+pub(crate) use {debug, trace};
+
+#[cfg(all(test, feature = "log", feature = "std"))]
+mod tests {
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    use crate::{compress_to_vec, CompressionConfig};
+
+    /// Levels of the records logged under the `tricklezip` target.
+    struct Capture(Mutex<Vec<log::Level>>);
+
+    impl log::Log for Capture {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            if record.target() == "tricklezip" {
+                self.0.lock().unwrap().push(record.level());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static CAPTURE: Capture = Capture(Mutex::new(Vec::new()));
+
+    #[test]
+    fn records_reach_the_log_facade() {
+        log::set_logger(&CAPTURE).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        compress_to_vec(
+            &b"pump=on flow=3.2\n".repeat(50),
+            &CompressionConfig::default(),
+        );
+        let records = CAPTURE.0.lock().unwrap();
+        assert!(records.contains(&log::Level::Debug));
+        assert!(records.contains(&log::Level::Trace));
+    }
+}
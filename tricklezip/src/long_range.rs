@@ -0,0 +1,363 @@
+//! Detection of repeats that lie too far back for the DEFLATE window.
+//!
+//! DEFLATE distances stop at 32KB, so a multi-KB structure that reappears
+//! further into a large log is coded from scratch. [`LongRangeIndex`] cuts
+//! the input into content-defined chunks and remembers a hash of each, so
+//! such repeats can be found in constant memory per chunk. The emitted
+//! stream can only exploit them by resetting: ending the current stream and
+//! starting a new one primed with the earlier region as its dictionary.
+//! [`compress_long_range`] does just that, and [`decompress_long_range`]
+//! finds each dictionary again in the output decoded so far.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::adler32;
+use crate::compressor::{FlushMode, TrickleCompressor, TrickleStatus};
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::decompressor::TrickleDecompressor;
+use crate::error::TrickleError;
+
+/// DEFLATE cannot refer further back than this.
+const MAX_DISTANCE: u64 = 32768;
+const MIN_CHUNK: usize = 256;
+const MAX_CHUNK: usize = 8192;
+/// Boundary when the low bits of the rolling hash are zero: about one
+/// chunk per 2KB.
+const BOUNDARY_MASK: u64 = (1 << 11) - 1;
+
+/// Per-byte values of the gear rolling hash.
+static GEAR: [u64; 256] = make_gear();
+
+const fn make_gear() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0x9E37_79B9_7F4A_7C15u64;
+    let mut i = 0;
+    while i < 256 {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// A chunk of input whose content already appeared beyond the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LongRangeMatch {
+    /// Stream offset of the repeated chunk.
+    pub offset: u64,
+    /// Stream offset of its earlier occurrence.
+    pub source: u64,
+    pub length: usize,
+}
+
+/// Content-defined chunk index over a whole stream.
+///
+/// Chunks are identified by a 64-bit hash, so a reported match is a strong
+/// candidate rather than a guarantee; compare the bytes before relying on
+/// it. Memory grows by about 32 bytes per distinct chunk (one per 2KB of
+/// input on average).
+#[derive(Debug, Clone, Default)]
+pub struct LongRangeIndex {
+    seen: BTreeMap<u64, u64>,
+    /// Stream offset of the next byte to be fed.
+    pos: u64,
+    /// Stream offset where the current chunk started.
+    chunk_start: u64,
+    rolling: u64,
+    /// FNV-1a hash of the current chunk's content.
+    content: u64,
+}
+
+impl LongRangeIndex {
+    pub fn new() -> Self {
+        Self {
+            content: FNV_OFFSET,
+            ..Self::default()
+        }
+    }
+
+    /// Bytes fed so far.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Number of distinct chunks remembered.
+    pub fn chunks(&self) -> usize {
+        self.seen.len()
+    }
+
+    /// Stream offsets of the first occurrence of every chunk remembered.
+    fn sources(&self) -> impl Iterator<Item = u64> + '_ {
+        self.seen.values().copied()
+    }
+
+    /// Feeds the next part of the stream and calls `found` for every chunk
+    /// completed within it that repeats one seen more than 32KB earlier.
+    pub fn update(&mut self, data: &[u8], mut found: impl FnMut(LongRangeMatch)) {
+        for &b in data {
+            self.rolling = (self.rolling << 1).wrapping_add(GEAR[b as usize]);
+            self.content = (self.content ^ b as u64).wrapping_mul(FNV_PRIME);
+            self.pos += 1;
+            let len = (self.pos - self.chunk_start) as usize;
+            let boundary = len >= MIN_CHUNK && self.rolling & BOUNDARY_MASK == 0;
+            if boundary || len >= MAX_CHUNK {
+                self.end_chunk(len, &mut found);
+            }
+        }
+    }
+
+    fn end_chunk(&mut self, len: usize, found: &mut impl FnMut(LongRangeMatch)) {
+        let key = self.content ^ (len as u64).rotate_left(48);
+        match self.seen.get(&key) {
+            Some(&source) if self.chunk_start - source > MAX_DISTANCE => {
+                found(LongRangeMatch {
+                    offset: self.chunk_start,
+                    source,
+                    length: len,
+                });
+            }
+            Some(_) => {}
+            None => {
+                self.seen.insert(key, self.chunk_start);
+            }
+        }
+        // The rolling hash is kept: it only depends on the last 64 bytes, so
+        // boundaries resynchronize inside repeated content.
+        self.chunk_start = self.pos;
+        self.content = FNV_OFFSET;
+    }
+}
+
+/// Compresses `input` as a series of zlib streams with `config`, whose
+/// format is forced to zlib, starting a new stream wherever
+/// [`LongRangeIndex`] finds a repeat beyond the window. That stream has the
+/// input from the earlier occurrence on as its preset dictionary, a byte
+/// short of the window so that the repeat lies just within reach.
+///
+/// Each stream is standard zlib, and any inflater given the dictionaries
+/// decodes them; [`decompress_long_range`] looks them up by their DICTID.
+///
+/// ```
+/// use tricklezip::{compress_long_range, compress_to_vec, decompress_long_range, CompressionConfig};
+///
+/// let mut x = 1u32;
+/// let mut noise = |len: usize| -> Vec<u8> {
+///     (0..len)
+///         .map(|_| {
+///             x = x.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+///             (x >> 16) as u8
+///         })
+///         .collect()
+/// };
+/// let report = noise(10_000);
+/// let mut log = report.clone();
+/// log.extend(noise(40_000));
+/// log.extend(&report);
+///
+/// let config = CompressionConfig::default();
+/// let packed = compress_long_range(&log, &config);
+/// assert!(packed.len() + 5_000 < compress_to_vec(&log, &config).len());
+/// assert_eq!(decompress_long_range(&packed).unwrap(), log);
+/// ```
+pub fn compress_long_range(input: &[u8], config: &CompressionConfig) -> Vec<u8> {
+    let config = config.clone().with_format(ContainerFormat::Zlib);
+    let dict_len = config.window() - 1;
+    let mut resets: Vec<(usize, usize)> = Vec::new();
+    LongRangeIndex::new().update(input, |m| {
+        let (offset, source) = (m.offset as usize, m.source as usize);
+        // A repeat running on from the last reset is covered by its
+        // dictionary as far as that reaches.
+        if let Some(&(last, last_source)) = resets.last() {
+            if offset - source == last - last_source && offset + m.length <= last + dict_len {
+                return;
+            }
+        }
+        resets.push((offset, source));
+    });
+
+    let mut compressor = TrickleCompressor::new(config);
+    let mut out = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let mut start = 0;
+    let mut dict = None;
+    let ends = resets
+        .iter()
+        .map(|&(offset, source)| (offset, Some(source)));
+    for (end, next_dict) in ends.chain([(input.len(), None)]) {
+        compressor.reset();
+        if let Some(source) = dict {
+            compressor
+                .set_dictionary(&input[source..source + dict_len])
+                .expect("zlib stream not started");
+        }
+        let segment = &input[start..end];
+        let mut consumed = 0;
+        loop {
+            let r = compressor
+                .compress_trickle(&segment[consumed..], &mut buf, FlushMode::Finish)
+                .expect("stream is not finished");
+            consumed += r.consumed;
+            out.extend_from_slice(&buf[..r.written]);
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+        }
+        (start, dict) = (end, next_dict);
+    }
+    out
+}
+
+/// Decompresses the output of [`compress_long_range`]. Fails with
+/// [`NeedDictionary`](TrickleError::NeedDictionary) if a stream asks for a
+/// dictionary that starts at none of the chunks decoded before it.
+pub fn decompress_long_range(input: &[u8]) -> Result<Vec<u8>, TrickleError> {
+    let mut decompressor = TrickleDecompressor::new(ContainerFormat::Zlib);
+    let mut index = LongRangeIndex::new();
+    // Adler-32 of the dictionary taken from a chunk start on, per start and
+    // dictionary length.
+    let mut ids: BTreeMap<(u64, usize), u32> = BTreeMap::new();
+    let mut out = Vec::new();
+    let mut buf = vec![0u8; 4096];
+    let mut consumed = 0;
+    loop {
+        decompressor.reset();
+        let cmf = input.get(consumed).copied().unwrap_or(0);
+        let dict_len = (256 << (cmf >> 4).min(7)) - 1;
+        while !decompressor.is_finished() {
+            match decompressor.decompress_trickle(&input[consumed..], &mut buf) {
+                Ok(r) => {
+                    consumed += r.consumed;
+                    out.extend_from_slice(&buf[..r.written]);
+                    index.update(&buf[..r.written], |_| {});
+                    if r.status == TrickleStatus::NeedInput && consumed == input.len() {
+                        return Err(TrickleError::UnexpectedEof);
+                    }
+                }
+                Err(TrickleError::NeedDictionary { id }) => {
+                    let source = index
+                        .sources()
+                        .map(|source| source as usize)
+                        .filter(|&source| source + dict_len <= out.len())
+                        .find(|&source| {
+                            let dict = &out[source..source + dict_len];
+                            *ids.entry((source as u64, dict_len))
+                                .or_insert_with(|| adler32::update(1, dict))
+                                == id
+                        })
+                        .ok_or(TrickleError::NeedDictionary { id })?;
+                    decompressor.set_dictionary(&out[source..source + dict_len])?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        if consumed == input.len() {
+            return Ok(out);
+        }
+    }
+}
+
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn noise(seed: u32, len: usize) -> Vec<u8> {
+        let mut x = seed;
+        (0..len)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_repeat_beyond_window() {
+        let record = noise(1, 20_000);
+        let mut data = record.clone();
+        data.extend_from_slice(&noise(2, 100_000));
+        let repeat_at = data.len() as u64;
+        data.extend_from_slice(&record);
+
+        let mut index = LongRangeIndex::new();
+        let mut matches = Vec::new();
+        // Arbitrary feed sizes must not change the chunking.
+        for part in data.chunks(777) {
+            index.update(part, |m| matches.push(m));
+        }
+        assert!(!matches.is_empty());
+        for m in &matches {
+            assert!(m.offset >= repeat_at);
+            let (a, b) = (m.source as usize, m.offset as usize);
+            assert_eq!(data[a..a + m.length], data[b..b + m.length]);
+        }
+        let covered: usize = matches.iter().map(|m| m.length).sum();
+        assert!(covered > 10_000, "{covered}");
+    }
+
+    #[test]
+    fn near_repeats_are_left_to_deflate() {
+        let record = noise(3, 6000);
+        let mut data = record.clone();
+        data.extend_from_slice(&noise(4, 1000));
+        data.extend_from_slice(&record);
+        let mut index = LongRangeIndex::new();
+        index.update(&data, |m| panic!("unexpected {m:?}"));
+        assert_eq!(index.position(), data.len() as u64);
+    }
+
+    #[test]
+    fn resets_pay_off_beyond_the_window() {
+        let record = noise(5, 20_000);
+        let mut data = record.clone();
+        data.extend_from_slice(&noise(6, 100_000));
+        data.extend_from_slice(&record);
+        data.extend_from_slice(&noise(7, 50_000));
+        data.extend_from_slice(&record);
+        let config = CompressionConfig::default();
+        let packed = compress_long_range(&data, &config);
+        let plain = crate::compress_to_vec(&data, &config).len();
+        assert!(packed.len() + 30_000 < plain, "{} vs {plain}", packed.len());
+        assert_eq!(decompress_long_range(&packed).unwrap(), data);
+
+        // A 1KB dictionary covers about half of every 2KB chunk.
+        let config = CompressionConfig::low_memory();
+        let packed = compress_long_range(&data, &config);
+        assert!(packed.len() < crate::compress_to_vec(&data, &config).len());
+        assert_eq!(decompress_long_range(&packed).unwrap(), data);
+
+        let packed = compress_long_range(&[], &CompressionConfig::default());
+        assert_eq!(decompress_long_range(&packed).unwrap(), []);
+    }
+
+    #[test]
+    fn unknown_dictionaries_are_reported() {
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let mut compressor = TrickleCompressor::new(config);
+        compressor.set_dictionary(b"not in the output").unwrap();
+        let mut packed = [0u8; 64];
+        let r = compressor
+            .compress_trickle(b"output", &mut packed, FlushMode::Finish)
+            .unwrap();
+        assert!(matches!(
+            decompress_long_range(&packed[..r.written]),
+            Err(TrickleError::NeedDictionary { .. })
+        ));
+        let packed = compress_long_range(b"output", &CompressionConfig::default());
+        assert_eq!(
+            decompress_long_range(&packed[..packed.len() - 1]),
+            Err(TrickleError::UnexpectedEof)
+        );
+    }
+}
@@ -0,0 +1,663 @@
+//! LZ77 match finding over a sliding window with hash chains.
+
+use crate::allocator::{filled_in, Allocator, Global, VecIn};
+use crate::config::{CompressionConfig, Strategy};
+use crate::fuel::Fuel;
+use crate::prefix::common_prefix;
+
+pub(crate) const MIN_MATCH: usize = 3;
+pub(crate) const MAX_MATCH: usize = 258;
+/// Length-3 matches further away than this cost more than three literals.
+const TOO_FAR: usize = 4096;
+/// Empty chain link. Position 0 is never offered as a match candidate.
+const NIL: usize = 0;
+
+/// Bytes per hash chain link for a window of `window_size`. History plus
+/// lookahead of a 64KB window needs more than 16 bits; every smaller
+/// window keeps 2-byte links, even in `extended-window` builds.
+fn link_size(window_size: usize) -> usize {
+    if window_size > 32768 {
+        4
+    } else {
+        2
+    }
+}
+
+/// Hash chain links: window buffer indices, one `u16` each, or two (low
+/// half first) for a 64KB window, as [`link_size`] says.
+struct Links<A: Allocator>(VecIn<u16, A>);
+
+impl<A: Allocator> Links<A> {
+    fn new_in(len: usize, window_size: usize, alloc: A) -> Self {
+        Self(filled_in(
+            NIL as u16,
+            len * link_size(window_size) / 2,
+            alloc,
+        ))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    fn get(&self, index: usize, wide: bool) -> usize {
+        if wide {
+            self.0[2 * index] as usize | (self.0[2 * index + 1] as usize) << 16
+        } else {
+            self.0[index] as usize
+        }
+    }
+
+    #[inline]
+    fn set(&mut self, index: usize, pos: usize, wide: bool) {
+        if wide {
+            self.0[2 * index] = pos as u16;
+            self.0[2 * index + 1] = (pos >> 16) as u16;
+        } else {
+            self.0[index] = pos as u16;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.fill(NIL as u16);
+    }
+
+    /// Moves every link `shift` positions back; older ones fall to 0, i.e.
+    /// NIL.
+    fn rebase(&mut self, shift: usize, wide: bool) {
+        if wide {
+            for link in self.0.chunks_exact_mut(2) {
+                let pos = (link[0] as usize | (link[1] as usize) << 16).saturating_sub(shift);
+                link[0] = pos as u16;
+                link[1] = (pos >> 16) as u16;
+            }
+        } else {
+            let shift = shift as u16;
+            for pos in self.0.iter_mut() {
+                *pos = pos.saturating_sub(shift);
+            }
+        }
+    }
+
+    fn rotate_left(&mut self, links: usize, wide: bool) {
+        self.0.rotate_left(if wide { 2 * links } else { links });
+    }
+
+    fn heap_usage(&self) -> usize {
+        self.0.capacity() * 2
+    }
+}
+
+/// One step of LZ77 output, as produced by [`MatchFinder`](crate::MatchFinder).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    /// A byte with no worthwhile match.
+    Literal(u8),
+    /// A copy of `length` bytes, 3 to 258, from `distance` bytes back, 1 to
+    /// the window size. The copy may overlap the bytes it produces.
+    Match { length: u16, distance: u16 },
+}
+
+pub(crate) struct Lz77Encoder<A: Allocator = Global> {
+    /// History followed by not yet processed lookahead bytes.
+    window: VecIn<u8, A>,
+    window_size: usize,
+    /// Position of the next byte to tokenize.
+    strstart: usize,
+    /// Bytes available at and after `strstart`.
+    lookahead: usize,
+    /// Start of the data covered by the current block, kept for stored blocks.
+    block_start: usize,
+    /// Most recent position per hash, as a window buffer index.
+    head: Links<A>,
+    /// Previous position with the same hash, indexed by position modulo the
+    /// window size.
+    prev: Links<A>,
+    hash_shift: u8,
+    max_chain: usize,
+    /// Longest match emitted; below `MAX_MATCH` for tiny windows so the
+    /// lookahead stays small.
+    max_match: usize,
+    /// Lookahead needed to search for a full-length match at `strstart`.
+    min_lookahead: usize,
+    nice_length: usize,
+    /// Matches shorter than this are only taken if the next position does
+    /// not start a longer one; 0 for greedy parsing.
+    max_lazy: u16,
+    /// `(length, distance)` of the match found at `strstart` while deciding
+    /// on the one before it, with a length of 0 for none. Kept narrow like
+    /// tokens so the smallest presets stay under 1KB of state.
+    next_match: (u16, u16),
+    strategy: Strategy,
+}
+
+/// Lengths of the window, hash head and hash chain buffers for `config`.
+fn buffer_lens(config: &CompressionConfig) -> (usize, usize, usize) {
+    let window_size = config.window();
+    let min_lookahead = MAX_MATCH.min(window_size / 8) + MIN_MATCH + 1;
+    // Small windows slide more often rather than doubling their footprint.
+    let lookahead_size = if window_size >= 4096 {
+        window_size
+    } else {
+        2 * min_lookahead
+    };
+    let hash_len = 1 << config.hash_bits.clamp(4, 16);
+    // Pure Huffman coding never walks the chains, and a chain length of
+    // one only ever looks at the head.
+    let (head_len, prev_len) = match config.strategy {
+        Strategy::HuffmanOnly | Strategy::Rle => (0, 0),
+        _ if config.max_chain_length <= 1 => (hash_len, 0),
+        _ => (hash_len, window_size),
+    };
+    (window_size + lookahead_size, head_len, prev_len)
+}
+
+impl Lz77Encoder {
+    /// Bytes `new_in(config, ..)` allocates.
+    pub(crate) fn heap_usage_for(config: &CompressionConfig) -> usize {
+        let (window_len, head_len, prev_len) = buffer_lens(config);
+        window_len + (head_len + prev_len) * link_size(config.window())
+    }
+
+    /// Fewest input bytes a block closed to free the window covers: the
+    /// lookahead area beyond the lookahead the match finder waits for.
+    pub(crate) fn min_block_len(config: &CompressionConfig) -> usize {
+        let window_size = config.window();
+        let min_lookahead = MAX_MATCH.min(window_size / 8) + MIN_MATCH + 1;
+        let (window_len, _, _) = buffer_lens(config);
+        (window_len - window_size)
+            .saturating_sub(min_lookahead)
+            .max(1)
+    }
+}
+
+impl<A: Allocator + Clone> Lz77Encoder<A> {
+    pub(crate) fn new_in(config: &CompressionConfig, alloc: A) -> Self {
+        let window_size = config.window();
+        let max_match = MAX_MATCH.min(window_size / 8);
+        let min_lookahead = max_match + MIN_MATCH + 1;
+        let hash_bits = config.hash_bits.clamp(4, 16);
+        let max_chain = config.max_chain_length.max(1) as usize;
+        let (window_len, head_len, prev_len) = buffer_lens(config);
+        Self {
+            window: filled_in(0, window_len, alloc.clone()),
+            window_size,
+            strstart: 0,
+            lookahead: 0,
+            block_start: 0,
+            head: Links::new_in(head_len, window_size, alloc.clone()),
+            prev: Links::new_in(prev_len, window_size, alloc),
+            hash_shift: 32 - hash_bits,
+            max_chain,
+            max_match,
+            min_lookahead,
+            nice_length: (config.nice_length as usize).clamp(MIN_MATCH, max_match),
+            // Runs are found greedily, as in zlib.
+            max_lazy: match config.strategy {
+                Strategy::Default | Strategy::Filtered => config.max_lazy_match,
+                _ => 0,
+            },
+            next_match: (0, 0),
+            strategy: config.strategy,
+        }
+    }
+
+    /// Forgets all history, keeping the allocations. Stale window bytes are
+    /// unreachable once the hash heads are cleared.
+    pub(crate) fn reset(&mut self, config: &CompressionConfig) {
+        self.forget_history();
+        self.set_effort(
+            config.max_chain_length.max(1) as usize,
+            config.nice_length as usize,
+        );
+    }
+
+    /// Drops the window contents but keeps the current effort settings.
+    pub(crate) fn forget_history(&mut self) {
+        self.strstart = 0;
+        self.lookahead = 0;
+        self.block_start = 0;
+        self.next_match = (0, 0);
+        self.head.clear();
+        self.prev.clear();
+    }
+
+    /// Copies as much of `input` into the window as fits, sliding old history
+    /// out when needed. Returns the number of bytes taken.
+    pub(crate) fn fill(&mut self, input: &[u8]) -> usize {
+        if self.strstart + self.lookahead == self.window.len() {
+            self.slide();
+        }
+        let end = self.strstart + self.lookahead;
+        let n = input.len().min(self.window.len() - end);
+        self.window[end..end + n].copy_from_slice(&input[..n]);
+        self.lookahead += n;
+        n
+    }
+
+    /// Drops history older than one window, but never bytes of the current block.
+    fn slide(&mut self) {
+        let shift = self
+            .strstart
+            .saturating_sub(self.window_size)
+            .min(self.block_start);
+        if shift == 0 {
+            return;
+        }
+        let end = self.strstart + self.lookahead;
+        self.window.copy_within(shift..end, 0);
+        self.strstart -= shift;
+        self.block_start -= shift;
+        // `prev` is indexed by position modulo the window, so move each link
+        // to the slot of its rebased position.
+        let wide = self.wide();
+        if !self.prev.is_empty() {
+            self.prev.rotate_left(shift & (self.window_size - 1), wide);
+        }
+        self.head.rebase(shift, wide);
+        self.prev.rebase(shift, wide);
+    }
+
+    /// Loads the tail of `dict` as history that matches may refer to. Only
+    /// valid before any input has been filled.
+    pub(crate) fn prime(&mut self, dict: &[u8]) {
+        debug_assert!(self.strstart == 0 && self.lookahead == 0);
+        let dict = &dict[dict.len().saturating_sub(self.window_size)..];
+        self.window[..dict.len()].copy_from_slice(dict);
+        self.strstart = dict.len();
+        self.block_start = dict.len();
+        for pos in 0..dict.len().saturating_sub(MIN_MATCH - 1) {
+            self.insert(pos);
+        }
+    }
+
+    /// Changes how hard the match finder searches from the next position on.
+    /// A `max_chain` of 0 emits literals only, whatever the strategy; chains longer than one only
+    /// help if the encoder was created with a chain length above one.
+    ///
+    /// A match already found at the current position is still emitted:
+    /// searching there again would link the position into its hash chain
+    /// a second time, pointing it at itself and cutting the chain off.
+    pub(crate) fn set_effort(&mut self, max_chain: usize, nice_length: usize) {
+        self.max_chain = max_chain;
+        self.nice_length = nice_length.clamp(MIN_MATCH, self.max_match);
+    }
+
+    /// Whether unprocessed input is waiting in the window.
+    pub(crate) fn has_lookahead(&self) -> bool {
+        self.lookahead > 0
+    }
+
+    /// Uncompressed bytes covered by the current block.
+    pub(crate) fn block_data(&self) -> &[u8] {
+        &self.window[self.block_start..self.strstart]
+    }
+
+    pub(crate) fn start_block(&mut self) {
+        self.block_start = self.strstart;
+    }
+
+    /// Tokenizes up to `max_positions` input bytes into `tokens`, stopping
+    /// early when `tokens` reaches `max_tokens`. Without `flush` it keeps
+    /// enough lookahead to find full-length matches. Returns the number of
+    /// positions advanced, and whether it stopped at a match that would
+    /// take it past `max_positions`, which is kept for the next call. With
+    /// `may_exceed`, a first match that long is taken anyway, so budgets
+    /// shorter than a match still make progress.
+    pub(crate) fn encode(
+        &mut self,
+        tokens: &mut VecIn<Token, A>,
+        max_tokens: usize,
+        max_positions: usize,
+        may_exceed: bool,
+        flush: bool,
+    ) -> (usize, bool) {
+        let mut advanced = 0;
+        // Each pass advances by at least one position.
+        while tokens.len() < max_tokens && advanced < max_positions && self.lookahead > 0 {
+            if self.lookahead < self.min_lookahead && !flush {
+                break;
+            }
+            let (length, distance) = match core::mem::take(&mut self.next_match) {
+                (0, _) => self.find_match(),
+                (length, distance) => (length as usize, distance as usize),
+            };
+            let exceeds = length >= MIN_MATCH && length > max_positions - advanced;
+            if exceeds && (advanced > 0 || !may_exceed) {
+                self.next_match = (length as u16, distance as u16);
+                return (advanced, true);
+            }
+            // Lazy evaluation: a longer match one byte on is worth a literal.
+            let lazy = length >= MIN_MATCH && length < self.max_lazy as usize && self.lookahead > 1;
+            if lazy {
+                self.strstart += 1;
+                self.lookahead -= 1;
+                let next = self.find_match();
+                self.strstart -= 1;
+                self.lookahead += 1;
+                if next.0 > length {
+                    tokens.push(Token::Literal(self.window[self.strstart]));
+                    self.strstart += 1;
+                    self.lookahead -= 1;
+                    advanced += 1;
+                    self.next_match = (next.0 as u16, next.1 as u16);
+                    continue;
+                }
+            }
+            if length >= MIN_MATCH {
+                tokens.push(Token::Match {
+                    length: length as u16,
+                    distance: distance as u16,
+                });
+                // The first position, and the second after a lazy search,
+                // were already hashed.
+                let hashed = if lazy { 2 } else { 1 };
+                for pos in self.strstart + hashed..self.strstart + length {
+                    self.insert(pos);
+                }
+                self.strstart += length;
+                self.lookahead -= length;
+                advanced += length;
+            } else {
+                tokens.push(Token::Literal(self.window[self.strstart]));
+                self.strstart += 1;
+                self.lookahead -= 1;
+                advanced += 1;
+            }
+        }
+        (advanced, false)
+    }
+
+    /// Bytes allocated for the window and hash chains.
+    pub(crate) fn heap_usage(&self) -> usize {
+        self.window.capacity() + self.head.heap_usage() + self.prev.heap_usage()
+    }
+
+    /// Whether the hash chains take 4-byte links.
+    #[inline]
+    fn wide(&self) -> bool {
+        cfg!(feature = "extended-window") && link_size(self.window_size) == 4
+    }
+
+    #[inline]
+    fn hash(&self, pos: usize) -> usize {
+        let w = &self.window;
+        let v = w[pos] as u32 | (w[pos + 1] as u32) << 8 | (w[pos + 2] as u32) << 16;
+        (v.wrapping_mul(0x9E37_79B1) >> self.hash_shift) as usize
+    }
+
+    /// Links `pos` into its hash chain and returns the previous chain head.
+    #[inline]
+    fn insert(&mut self, pos: usize) -> usize {
+        if self.head.is_empty() || pos + MIN_MATCH > self.strstart + self.lookahead {
+            return NIL;
+        }
+        let h = self.hash(pos);
+        let wide = self.wide();
+        let old = self.head.get(h, wide);
+        if !self.prev.is_empty() {
+            self.prev.set(pos & (self.window_size - 1), old, wide);
+        }
+        self.head.set(h, pos, wide);
+        old
+    }
+
+    /// Returns `(length, distance)` of the best match at `strstart`, with a
+    /// length below `MIN_MATCH` meaning "emit a literal".
+    fn find_match(&mut self) -> (usize, usize) {
+        let max_len = self.lookahead.min(self.max_match);
+        match self.strategy {
+            _ if self.max_chain == 0 => (0, 0),
+            Strategy::HuffmanOnly => (0, 0),
+            Strategy::Rle => {
+                if self.strstart == 0 || max_len < MIN_MATCH {
+                    return (0, 0);
+                }
+                let len = self.match_len(self.strstart - 1, self.strstart, max_len);
+                (len, 1)
+            }
+            _ => {
+                let cur = self.strstart;
+                let candidate = self.insert(cur);
+                if max_len < MIN_MATCH {
+                    return (0, 0);
+                }
+                let (len, dist) = self.longest_match(cur, candidate, max_len);
+                let filtered = self.strategy == Strategy::Filtered && len <= 5;
+                if filtered || (len == MIN_MATCH && dist > TOO_FAR) {
+                    (0, 0)
+                } else {
+                    (len, dist)
+                }
+            }
+        }
+    }
+
+    fn longest_match(&self, cur: usize, mut candidate: usize, max_len: usize) -> (usize, usize) {
+        // Candidates only ever move back, but the walk also stops after
+        // `max_chain` of them.
+        let mut chain = Fuel::new(self.max_chain);
+        let mut best_len = MIN_MATCH - 1;
+        let mut best_dist = 0;
+        let w = &self.window;
+        while candidate != NIL && candidate < cur && cur - candidate < self.window_size {
+            if w[candidate + best_len] == w[cur + best_len] && w[candidate] == w[cur] {
+                let len = self.match_len(candidate, cur, max_len);
+                if len > best_len {
+                    best_len = len;
+                    best_dist = cur - candidate;
+                    if len >= self.nice_length || len == max_len {
+                        break;
+                    }
+                }
+            }
+            if !chain.burn() || chain.left() == 0 || self.prev.is_empty() {
+                break;
+            }
+            let next = self
+                .prev
+                .get(candidate & (self.window_size - 1), self.wide());
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        (best_len, best_dist)
+    }
+
+    #[inline]
+    fn match_len(&self, a: usize, b: usize, max_len: usize) -> usize {
+        let w = &self.window;
+        common_prefix(&w[a..a + max_len], &w[b..b + max_len])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn chains_take_two_bytes_per_entry() {
+        let encoder = Lz77Encoder::new_in(&CompressionConfig::default(), Global);
+        // 32K history + 32K lookahead, then 2^15 heads and 32K links.
+        assert_eq!(encoder.heap_usage(), 65536 + (32768 + 32768) * 2);
+    }
+
+    #[cfg(feature = "extended-window")]
+    #[test]
+    fn only_64k_windows_take_four_byte_links() {
+        let config = CompressionConfig::default()
+            .with_format(crate::ContainerFormat::Raw)
+            .with_window_size(65536);
+        let encoder = Lz77Encoder::new_in(&config, Global);
+        assert_eq!(encoder.heap_usage(), 131072 + (32768 + 65536) * 4);
+        assert_eq!(Lz77Encoder::heap_usage_for(&config), encoder.heap_usage());
+    }
+
+    /// Tokens for `data` from a fresh encoder.
+    fn tokenize(config: &CompressionConfig, data: &[u8]) -> Vec<Token> {
+        let mut encoder = Lz77Encoder::new_in(config, Global);
+        let mut tokens = VecIn::new_in(Global);
+        let mut pos = 0;
+        while pos < data.len() {
+            pos += encoder.fill(&data[pos..]);
+            encoder.encode(&mut tokens, usize::MAX, usize::MAX, true, pos == data.len());
+            encoder.start_block();
+        }
+        tokens.to_vec()
+    }
+
+    #[test]
+    fn matches_stay_within_the_window_and_258_bytes() {
+        let config = CompressionConfig::default().with_window_size(4096);
+        let mut x = 7u32;
+        let mut noise = |n| -> Vec<u8> {
+            (0..n)
+                .map(|_| {
+                    x ^= x << 13;
+                    x ^= x >> 17;
+                    x ^= x << 5;
+                    x as u8
+                })
+                .collect()
+        };
+        let (near, far) = (noise(4095), noise(4096));
+        let mut data = [&near[..], &near[..], &far[..], &far[..]].concat();
+        data.extend_from_slice(&[b'a'; 1000]);
+
+        let tokens = tokenize(&config, &data);
+        let mut pos = 0;
+        let mut copied_near = 0;
+        for token in &tokens {
+            match *token {
+                Token::Literal(_) => pos += 1,
+                Token::Match { length, distance } => {
+                    assert!((3..=258).contains(&length));
+                    // The copy a full window back is out of reach.
+                    assert!((1..4096).contains(&distance));
+                    let (length, distance) = (length as usize, distance as usize);
+                    assert_eq!(data[pos..pos + length], data[pos - distance..][..length]);
+                    if distance == 4095 {
+                        copied_near += length;
+                    }
+                    pos += length;
+                }
+            }
+        }
+        assert_eq!(pos, data.len());
+        assert!(copied_near >= 4090);
+        assert!(tokens.contains(&Token::Match {
+            length: 258,
+            distance: 1
+        }));
+    }
+
+    #[test]
+    fn lazy_matching_defers_to_a_longer_match() {
+        // At "abcdefghij" the history offers "abc", and one byte on the
+        // longer "bcdefghij".
+        let data = b"-abcX-bcdefghij-abcdefghij";
+        let tail = |max_lazy_match| {
+            let config = CompressionConfig {
+                max_lazy_match,
+                ..CompressionConfig::default()
+            };
+            let tokens = tokenize(&config, data);
+            tokens[tokens.len() - 2..].to_vec()
+        };
+        assert_eq!(
+            tail(16),
+            [
+                Token::Literal(b'a'),
+                Token::Match {
+                    length: 9,
+                    distance: 11
+                }
+            ]
+        );
+        assert_eq!(
+            tail(0),
+            [
+                Token::Match {
+                    length: 3,
+                    distance: 15
+                },
+                Token::Match {
+                    length: 7,
+                    distance: 11
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn effort_changes_keep_the_pending_match() {
+        let config = CompressionConfig::default();
+        let words = [
+            "temp", "=", "21", ".5", " hum", "=4", "0", " id", "=7", "\n",
+        ];
+        let mut x = 1u32;
+        let mut data = Vec::new();
+        for _ in 0..4000 {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            data.extend_from_slice(words[(x >> 16) as usize % words.len()].as_bytes());
+        }
+        let mut encoder = Lz77Encoder::new_in(&config, Global);
+        let mut tokens = VecIn::new_in(Global);
+        assert_eq!(encoder.fill(&data), data.len());
+        // Short steps leave a match pending across most changes: one found
+        // by a lazy search one byte on, or one past the position budget.
+        while encoder.has_lookahead() {
+            let max_tokens = tokens.len() + 2;
+            encoder.encode(&mut tokens, max_tokens, 8, true, true);
+            encoder.set_effort(
+                config.max_chain_length as usize,
+                config.nice_length as usize,
+            );
+        }
+        // The same parse, and so the same ratio, as without the changes.
+        assert_eq!(tokens.to_vec(), tokenize(&config, &data));
+
+        let mut out = Vec::new();
+        for token in tokens.iter() {
+            match *token {
+                Token::Literal(b) => out.push(b),
+                Token::Match { length, distance } => {
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance as usize]);
+                    }
+                }
+            }
+        }
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn links_survive_slides() {
+        let config = CompressionConfig::default().with_window_size(4096);
+        let mut encoder = Lz77Encoder::new_in(&config, Global);
+        let data = b"abcdefghijklmnopqrstuvwxyz0123456789".repeat(2000);
+        let mut tokens = VecIn::new_in(Global);
+        let mut pos = 0;
+        while pos < data.len() {
+            pos += encoder.fill(&data[pos..]);
+            encoder.encode(&mut tokens, usize::MAX, usize::MAX, true, pos == data.len());
+            encoder.start_block();
+        }
+        encoder.encode(&mut tokens, usize::MAX, usize::MAX, true, true);
+        // After the first period (plus one byte, as position 0 is never a
+        // candidate) everything is a match at distance 36, even once
+        // positions have been rebased by many slides.
+        let literals = tokens
+            .iter()
+            .filter(|t| matches!(t, Token::Literal(_)))
+            .count();
+        assert_eq!(literals, 37);
+        assert!(tokens.iter().all(|t| match t {
+            Token::Match { distance, .. } => *distance == 36,
+            Token::Literal(_) => true,
+        }));
+    }
+}
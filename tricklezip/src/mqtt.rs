@@ -0,0 +1,104 @@
+//! Size-limited payload compression for MQTT and similar message buses.
+
+use alloc::vec::Vec;
+
+use crate::compressor::compress_to_fit;
+use crate::config::CompressionConfig;
+
+/// What to publish; see [`compress_payload`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Payload<'a> {
+    /// The compressed payload, smaller than the original and within the
+    /// limit. Flag it, e.g. with a user property or topic suffix, so
+    /// subscribers know to decompress.
+    Compressed(Vec<u8>),
+    /// Compression did not pay off or did not fit; publish the original.
+    /// It may itself exceed the limit.
+    Original(&'a [u8]),
+}
+
+impl Payload<'_> {
+    pub fn is_compressed(&self) -> bool {
+        matches!(self, Payload::Compressed(_))
+    }
+
+    /// The bytes to publish.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Payload::Compressed(data) => data,
+            Payload::Original(data) => data,
+        }
+    }
+}
+
+/// Compresses `payload` with `config` if the result is smaller than the
+/// original and at most `limit` bytes, e.g. the broker's maximum packet
+/// size minus topic and header overhead.
+///
+/// Built on [`compress_to_fit`](crate::compress_to_fit) with the smaller of
+/// the two sizes, so compression stops as soon as it cannot pay off
+/// instead of finishing the whole payload first.
+///
+/// ```
+/// use tricklezip::{compress_payload, CompressionConfig, Payload};
+///
+/// let reading = br#"{"t":21.5,"h":40,"t2":21.5,"h2":40,"t3":21.5,"h3":40}"#;
+/// let payload = compress_payload(reading, 256, &CompressionConfig::telemetry());
+/// assert!(payload.as_bytes().len() <= reading.len());
+///
+/// let tiny = compress_payload(b"21.5", 256, &CompressionConfig::telemetry());
+/// assert_eq!(tiny, Payload::Original(b"21.5"));
+/// ```
+pub fn compress_payload<'a>(
+    payload: &'a [u8],
+    limit: usize,
+    config: &CompressionConfig,
+) -> Payload<'a> {
+    let cap = limit.min(payload.len().saturating_sub(1));
+    match compress_to_fit(payload, cap, config) {
+        Ok(packed) => Payload::Compressed(packed),
+        Err(_) => Payload::Original(payload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContainerFormat;
+    use crate::decompressor::decompress_to_vec;
+
+    #[test]
+    fn compresses_only_when_it_pays_off_and_fits() {
+        let config = CompressionConfig::default().with_format(ContainerFormat::Zlib);
+        let json =
+            br#"{"id":"node-7","temp":[21.5,21.5,21.6,21.5,21.5,21.6,21.5,21.5]}"#.repeat(20);
+
+        let payload = compress_payload(&json, 1024, &config);
+        assert!(payload.is_compressed());
+        assert!(payload.as_bytes().len() < json.len());
+        assert_eq!(
+            decompress_to_vec(payload.as_bytes(), ContainerFormat::Zlib).unwrap(),
+            json
+        );
+
+        // Too small a limit for even the compressed form.
+        assert_eq!(
+            compress_payload(&json, 20, &config),
+            Payload::Original(&json)
+        );
+
+        // Incompressible data is sent as is.
+        let mut x = 0x1234_5678u32;
+        let noise: Vec<u8> = (0..500)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (x >> 16) as u8
+            })
+            .collect();
+        assert_eq!(
+            compress_payload(&noise, 1024, &config),
+            Payload::Original(&noise)
+        );
+        assert_eq!(compress_payload(b"", 1024, &config), Payload::Original(b""));
+    }
+}
@@ -0,0 +1,419 @@
+//! Whole-buffer DEFLATE decoding without heap allocation.
+//!
+//! The streaming decoder keeps a 32KB window on the heap. When the whole
+//! output buffer is available, earlier output serves as the window, so a
+//! bootloader can unpack an image with nothing but a few hundred bytes of
+//! Huffman tables on the stack.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::config::ContainerFormat;
+use crate::decompressor::decompress_to_vec;
+use crate::error::TrickleError;
+use crate::fuel::{limit, Fuel};
+use crate::huffman::{
+    CLEN_ORDER, DIST_BASE, DIST_CODES, DIST_EXTRA, END_OF_BLOCK, LENGTH_BASE, LENGTH_EXTRA,
+};
+use crate::inflate::{build_clen, build_dynamic, build_fixed, dynamic_counts, Huffman};
+
+/// Where compressed data is read from and decompressed data written to.
+pub(crate) trait Buffers {
+    fn input(&self) -> &[u8];
+    fn output(&mut self) -> &mut [u8];
+    /// Whether output up to `end` may be written once `read` bytes of
+    /// input have been consumed.
+    fn reserve(&mut self, read: usize, end: usize) -> bool;
+}
+
+/// Input and output in separate buffers.
+pub(crate) struct Separate<'a> {
+    pub(crate) input: &'a [u8],
+    pub(crate) output: &'a mut [u8],
+}
+
+impl Buffers for Separate<'_> {
+    fn input(&self) -> &[u8] {
+        self.input
+    }
+
+    fn output(&mut self) -> &mut [u8] {
+        self.output
+    }
+
+    fn reserve(&mut self, _read: usize, end: usize) -> bool {
+        end <= self.output.len()
+    }
+}
+
+/// Input in `buf[start..end]`, decoded to the front of the same buffer.
+struct InPlace<'a> {
+    buf: &'a mut [u8],
+    start: usize,
+    end: usize,
+}
+
+impl Buffers for InPlace<'_> {
+    fn input(&self) -> &[u8] {
+        &self.buf[self.start..self.end]
+    }
+
+    fn output(&mut self) -> &mut [u8] {
+        self.buf
+    }
+
+    // Output never reaches input that is still unread.
+    fn reserve(&mut self, read: usize, end: usize) -> bool {
+        end <= self.start + read
+    }
+}
+
+/// Separate buffers, recording how far output runs ahead of input.
+struct Measure<'a> {
+    input: &'a [u8],
+    output: Vec<u8>,
+    offset: usize,
+}
+
+impl Buffers for Measure<'_> {
+    fn input(&self) -> &[u8] {
+        self.input
+    }
+
+    fn output(&mut self) -> &mut [u8] {
+        &mut self.output
+    }
+
+    fn reserve(&mut self, read: usize, end: usize) -> bool {
+        self.offset = self.offset.max(end.saturating_sub(read));
+        end <= self.output.len()
+    }
+}
+
+/// Decompresses raw DEFLATE data from `input` into `output` in one call,
+/// returning the number of bytes written. Unlike [`decompress_to_vec`],
+/// this allocates nothing, so it suits bootloaders without a heap.
+///
+/// [`decompress_to_vec`]: crate::decompress_to_vec
+///
+/// ```
+/// use tricklezip::{compress_to_vec, decompress_into, CompressionConfig};
+///
+/// let packed = compress_to_vec(b"boot boot boot boot", &CompressionConfig::default());
+/// let mut out = [0u8; 64];
+/// let n = decompress_into(&packed, &mut out).unwrap();
+/// assert_eq!(&out[..n], b"boot boot boot boot");
+/// ```
+pub fn decompress_into(input: &[u8], output: &mut [u8]) -> Result<usize, TrickleError> {
+    let (_, written) = inflate(&mut Separate { input, output })?;
+    Ok(written)
+}
+
+/// Decompresses raw DEFLATE data stored at `buf[start..end]` into the front
+/// of the same buffer, returning the number of bytes written. A bootloader
+/// can load an image into the end of its RAM or flash staging area and
+/// unpack it without a second full-size buffer.
+///
+/// Output races ahead of input over compressible stretches and falls back
+/// over incompressible ones, so `start` must be at least the value
+/// [`in_place_offset`] computes on the host; typically a little below the
+/// decompressed length. The data needs no other preparation. If output
+/// would overwrite unread input, this fails with `OutputTooSmall`; the
+/// input already consumed has been overwritten by then, so it must be
+/// reloaded before a retry. A `start..end` range that is reversed or runs
+/// past the end of `buf` fails with `InvalidConfig { field: "range" }`.
+///
+/// ```
+/// use tricklezip::{compress_to_vec, decompress_in_place, in_place_offset, CompressionConfig};
+///
+/// let data = b"0123456789".repeat(50);
+/// let packed = compress_to_vec(&data, &CompressionConfig::default());
+/// let start = in_place_offset(&packed).unwrap();
+///
+/// let mut ram = vec![0u8; start + packed.len()];
+/// ram[start..].copy_from_slice(&packed);
+/// let n = decompress_in_place(&mut ram, start, start + packed.len()).unwrap();
+/// assert_eq!(&ram[..n], &data[..]);
+/// ```
+pub fn decompress_in_place(
+    buf: &mut [u8],
+    start: usize,
+    end: usize,
+) -> Result<usize, TrickleError> {
+    if start > end || end > buf.len() {
+        return Err(TrickleError::InvalidConfig { field: "range" });
+    }
+    let (_, written) = inflate(&mut InPlace { buf, start, end })?;
+    Ok(written)
+}
+
+/// The smallest offset at which raw DEFLATE data can be placed for
+/// [`decompress_in_place`]. The buffer then needs `offset + compressed.len()`
+/// bytes, at least the decompressed length. Meant for the build host; it
+/// decodes the whole stream.
+pub fn in_place_offset(compressed: &[u8]) -> Result<usize, TrickleError> {
+    let len = decompress_to_vec(compressed, ContainerFormat::Raw)?.len();
+    let mut measure = Measure {
+        input: compressed,
+        output: vec![0; len],
+        offset: 0,
+    };
+    inflate(&mut measure)?;
+    Ok(measure.offset)
+}
+
+/// Decodes a complete raw DEFLATE stream, returning the input bytes
+/// consumed (the final one possibly only partly) and output bytes written.
+pub(crate) fn inflate<B: Buffers>(buffers: &mut B) -> Result<(usize, usize), TrickleError> {
+    let mut d = Decoder {
+        buffers,
+        pos: 0,
+        out: 0,
+    };
+    loop {
+        let last = d.take(1)? == 1;
+        match d.take(2)? {
+            0 => d.stored()?,
+            1 => d.fixed()?,
+            2 => d.dynamic()?,
+            _ => return Err(TrickleError::InvalidData),
+        }
+        if last {
+            return Ok((d.pos.div_ceil(8) as usize, d.out));
+        }
+    }
+}
+
+struct Decoder<'a, B: Buffers> {
+    buffers: &'a mut B,
+    /// Input position in bits.
+    pos: u64,
+    /// Output bytes written.
+    out: usize,
+}
+
+impl<B: Buffers> Decoder<'_, B> {
+    /// The next 56 or more input bits from `pos`. Past the end of the
+    /// input they read as zeros, which `skip` then refuses to consume.
+    fn peek(&self) -> u64 {
+        let data = self.buffers.input();
+        let start = (self.pos / 8) as usize;
+        let mut word = [0u8; 8];
+        if start < data.len() {
+            let n = (data.len() - start).min(8);
+            word[..n].copy_from_slice(&data[start..start + n]);
+        }
+        u64::from_le_bytes(word) >> (self.pos % 8)
+    }
+
+    fn skip(&mut self, n: u32) -> Result<(), TrickleError> {
+        if self.pos + n as u64 > self.buffers.input().len() as u64 * 8 {
+            return Err(TrickleError::UnexpectedEof);
+        }
+        self.pos += n as u64;
+        Ok(())
+    }
+
+    fn take(&mut self, n: u32) -> Result<u32, TrickleError> {
+        let value = (self.peek() & ((1u64 << n) - 1)) as u32;
+        self.skip(n)?;
+        Ok(value)
+    }
+
+    fn decode<const N: usize>(&mut self, code: &Huffman<N>) -> Result<usize, TrickleError> {
+        let (sym, len) = code
+            .decode_bits(self.peek())
+            .ok_or(TrickleError::InvalidData)?;
+        self.skip(len)?;
+        Ok(sym)
+    }
+
+    /// Makes room for `n` more output bytes. Only whole bytes count as
+    /// read; a partly consumed one is still needed.
+    fn reserve(&mut self, n: usize) -> Result<(), TrickleError> {
+        let read = (self.pos / 8) as usize;
+        if !self.buffers.reserve(read, self.out + n) {
+            return Err(TrickleError::OutputTooSmall);
+        }
+        Ok(())
+    }
+
+    fn stored(&mut self) -> Result<(), TrickleError> {
+        self.pos = self.pos.div_ceil(8) * 8;
+        let len = self.take(16)? as usize;
+        if self.take(16)? as usize != !len & 0xFFFF {
+            return Err(TrickleError::InvalidData);
+        }
+        let start = (self.pos / 8) as usize;
+        if start + len > self.buffers.input().len() {
+            return Err(TrickleError::UnexpectedEof);
+        }
+        self.pos += len as u64 * 8;
+        self.reserve(len)?;
+        // One byte at a time: when decoding in place the ranges may be
+        // adjacent, never overlapping in the wrong direction.
+        for i in 0..len {
+            let b = self.buffers.input()[start + i];
+            self.buffers.output()[self.out + i] = b;
+        }
+        self.out += len;
+        Ok(())
+    }
+
+    fn fixed(&mut self) -> Result<(), TrickleError> {
+        let mut lit = Huffman::<288>::new();
+        let mut dist = Huffman::<DIST_CODES>::new();
+        build_fixed(&mut lit, &mut dist)?;
+        self.codes(&lit, &dist)
+    }
+
+    fn dynamic(&mut self) -> Result<(), TrickleError> {
+        let (hlit, hdist, hclen) = dynamic_counts(self.take(14)?)?;
+        let mut clen_lengths = [0u8; 19];
+        for &sym in &CLEN_ORDER[..hclen] {
+            clen_lengths[sym] = self.take(3)? as u8;
+        }
+        let mut clen = Huffman::<19>::new();
+        build_clen(&mut clen, &clen_lengths)?;
+        let mut lengths = [0u8; 286 + DIST_CODES];
+        let mut n = 0;
+        while n < hlit + hdist {
+            let (value, repeat) = match self.decode(&clen)? {
+                sym @ 0..=15 => (sym as u8, 1),
+                16 if n == 0 => return Err(TrickleError::InvalidData),
+                16 => (lengths[n - 1], 3 + self.take(2)? as usize),
+                17 => (0, 3 + self.take(3)? as usize),
+                _ => (0, 11 + self.take(7)? as usize),
+            };
+            if n + repeat > hlit + hdist {
+                return Err(TrickleError::InvalidData);
+            }
+            lengths[n..n + repeat].fill(value);
+            n += repeat;
+        }
+        let mut lit = Huffman::<288>::new();
+        let mut dist = Huffman::<DIST_CODES>::new();
+        build_dynamic(&mut lit, &mut dist, &lengths[..hlit + hdist], hlit)?;
+        self.codes(&lit, &dist)
+    }
+
+    fn codes(
+        &mut self,
+        lit: &Huffman<288>,
+        dist: &Huffman<DIST_CODES>,
+    ) -> Result<(), TrickleError> {
+        // Every symbol takes at least one bit of input, and the one ending
+        // the block is the last.
+        let unread = self.buffers.input().len() as u64 * 8 - self.pos;
+        let mut fuel = Fuel::new(limit(1, unread as usize, 1));
+        loop {
+            let burnt = fuel.burn();
+            debug_assert!(burnt, "more symbols than input bits");
+            if !burnt {
+                return Err(TrickleError::InvalidData);
+            }
+            let sym = self.decode(lit)?;
+            if sym < END_OF_BLOCK {
+                self.reserve(1)?;
+                let out = self.out;
+                self.buffers.output()[out] = sym as u8;
+                self.out += 1;
+                continue;
+            }
+            if sym == END_OF_BLOCK {
+                return Ok(());
+            }
+            let li = sym - 257;
+            if li >= LENGTH_BASE.len() {
+                return Err(TrickleError::InvalidData);
+            }
+            let length = LENGTH_BASE[li] as usize + self.take(LENGTH_EXTRA[li] as u32)? as usize;
+            let di = self.decode(dist)?;
+            if di >= DIST_CODES {
+                return Err(TrickleError::InvalidData);
+            }
+            let distance = DIST_BASE[di] as usize + self.take(DIST_EXTRA[di] as u32)? as usize;
+            if distance > self.out {
+                return Err(TrickleError::InvalidData);
+            }
+            self.reserve(length)?;
+            let out = self.buffers.output();
+            for i in self.out..self.out + length {
+                out[i] = out[i - distance];
+            }
+            self.out += length;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+    use crate::config::{CompressionConfig, Strategy};
+
+    #[test]
+    fn matches_the_streaming_decoder() {
+        let data = b"fw: 0x0800_4000 vector table, reset handler, hard fault\n".repeat(300);
+        for strategy in [Strategy::Default, Strategy::Fixed, Strategy::HuffmanOnly] {
+            let packed =
+                compress_to_vec(&data, &CompressionConfig::default().with_strategy(strategy));
+            let mut out = vec![0u8; data.len()];
+            assert_eq!(decompress_into(&packed, &mut out), Ok(data.len()));
+            assert_eq!(out, data);
+            assert_eq!(
+                decompress_into(&packed, &mut out[..data.len() - 1]),
+                Err(TrickleError::OutputTooSmall)
+            );
+            assert_eq!(
+                decompress_into(&packed[..packed.len() / 2], &mut out),
+                Err(TrickleError::UnexpectedEof)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_what_the_streaming_decoder_rejects() {
+        // A reserved block type, and a dynamic block with 287
+        // literal/length codes.
+        for packed in [&[0x07][..], &[0xF5, 0, 0]] {
+            let mut out = [0u8; 64];
+            let streaming = decompress_to_vec(packed, ContainerFormat::Raw);
+            assert!(streaming.is_err());
+            assert_eq!(decompress_into(packed, &mut out).err(), streaming.err());
+        }
+    }
+
+    #[test]
+    fn in_place_needs_the_measured_offset() {
+        let mut data = b"header ".repeat(500);
+        // Incompressible tail: output falls back towards the input here.
+        let mut x = 1u32;
+        data.extend((0..3000).map(|_| {
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (x >> 24) as u8
+        }));
+        let packed = compress_to_vec(&data, &CompressionConfig::default());
+        let offset = in_place_offset(&packed).unwrap();
+        assert!(offset > data.len() - packed.len());
+        assert!(offset < data.len());
+
+        let mut buf = vec![0u8; offset + packed.len() + 4];
+        buf[offset..offset + packed.len()].copy_from_slice(&packed);
+        let n = decompress_in_place(&mut buf, offset, offset + packed.len()).unwrap();
+        assert_eq!(&buf[..n], &data[..]);
+        assert_eq!(&buf[offset + packed.len()..], &[0; 4]);
+
+        let at = offset - 1;
+        buf[at..at + packed.len()].copy_from_slice(&packed);
+        assert_eq!(
+            decompress_in_place(&mut buf, at, at + packed.len()),
+            Err(TrickleError::OutputTooSmall)
+        );
+
+        let range = Err(TrickleError::InvalidConfig { field: "range" });
+        assert_eq!(decompress_in_place(&mut buf, at + 1, at), range);
+        let past = buf.len() + 1;
+        assert_eq!(decompress_in_place(&mut buf, at, past), range);
+    }
+}
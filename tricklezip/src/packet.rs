@@ -0,0 +1,115 @@
+//! Framing-free compression for tiny telemetry frames.
+
+use alloc::vec::Vec;
+
+use crate::compressor::compress_to_vec;
+use crate::config::CompressionConfig;
+use crate::error::TrickleError;
+use crate::oneshot::decompress_into;
+
+/// Compresses a payload of up to a few hundred bytes with
+/// [`CompressionConfig::small_packet`], optionally behind one
+/// application-defined `header` byte, e.g. a message type or sequence
+/// number.
+///
+/// Zlib adds 6 bytes of framing and gzip 18, which is more than DEFLATE
+/// saves on most 50-byte frames. Here the packet is the header byte, if
+/// any, followed by one raw fixed Huffman block. The link layer already
+/// provides the length and integrity check a container would add.
+/// Decode with [`decompress_packet`].
+///
+/// ```
+/// use tricklezip::{compress_packet, decompress_packet};
+///
+/// let frame = br#"{"id":"n7","t":21.5,"h":40.5,"t1":21.5,"h1":40.5}"#;
+/// let packet = compress_packet(frame, Some(0x42));
+/// assert!(packet.len() < frame.len());
+///
+/// let mut out = [0u8; 128];
+/// let (header, n) = decompress_packet(&packet, true, &mut out).unwrap();
+/// assert_eq!(header, Some(0x42));
+/// assert_eq!(&out[..n], &frame[..]);
+/// ```
+pub fn compress_packet(payload: &[u8], header: Option<u8>) -> Vec<u8> {
+    let body = compress_to_vec(payload, &CompressionConfig::small_packet());
+    match header {
+        Some(byte) => [&[byte][..], &body].concat(),
+        None => body,
+    }
+}
+
+/// Decodes a packet from [`compress_packet`] into `out` without allocating,
+/// returning the header byte, if `has_header`, and the payload length.
+/// Fails with `OutputTooSmall` if the payload does not fit into `out`.
+pub fn decompress_packet(
+    packet: &[u8],
+    has_header: bool,
+    out: &mut [u8],
+) -> Result<(Option<u8>, usize), TrickleError> {
+    let (header, body) = match (has_header, packet) {
+        (false, body) => (None, body),
+        (true, [byte, body @ ..]) => (Some(*byte), body),
+        (true, []) => return Err(TrickleError::UnexpectedEof),
+    };
+    Ok((header, decompress_into(body, out)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContainerFormat;
+
+    #[test]
+    fn packets_beat_zlib_on_small_frames() {
+        let frame =
+            b"{\"dev\":12,\"seq\":4711,\"temp\":21.5,\"hum\":40,\"bat\":3.61,\"st\":\"ok\"}";
+        let packet = compress_packet(frame, None);
+        let zlib = compress_to_vec(
+            frame,
+            &CompressionConfig::default().with_format(ContainerFormat::Zlib),
+        );
+        assert!(
+            packet.len() + 6 <= zlib.len(),
+            "{} vs {}",
+            packet.len(),
+            zlib.len()
+        );
+
+        let mut out = [0u8; 128];
+        assert_eq!(
+            decompress_packet(&packet, false, &mut out),
+            Ok((None, frame.len()))
+        );
+        assert_eq!(&out[..frame.len()], &frame[..]);
+
+        let packet = compress_packet(frame, Some(7));
+        assert_eq!(packet[0], 7);
+        assert_eq!(
+            decompress_packet(&packet, true, &mut out[..10]),
+            Err(TrickleError::OutputTooSmall)
+        );
+        assert_eq!(
+            decompress_packet(&[], true, &mut out),
+            Err(TrickleError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn empty_and_incompressible_payloads() {
+        let mut out = [0u8; 64];
+        let packet = compress_packet(&[], Some(1));
+        assert!(packet.len() <= 3);
+        assert_eq!(decompress_packet(&packet, true, &mut out), Ok((Some(1), 0)));
+
+        let noise: Vec<u8> = (0..40u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        let packet = compress_packet(&noise, None);
+        assert!(packet.len() <= noise.len() + 5);
+        assert_eq!(
+            decompress_packet(&packet, false, &mut out),
+            Ok((None, noise.len()))
+        );
+        assert_eq!(&out[..noise.len()], &noise[..]);
+    }
+}
@@ -0,0 +1,245 @@
+//! Multi-threaded compression of large inputs for host-side tools.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::compressor::{gzip_header, zlib_header};
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::deflate::DeflateState;
+use crate::{adler32, crc32};
+
+/// How [`compress_parallel`] splits its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParallelOptions {
+    /// Uncompressed bytes per independently compressed chunk.
+    pub chunk_size: usize,
+    /// Worker threads, or 0 for one per available core.
+    pub threads: usize,
+    /// Prime each chunk with the window preceding it, so matches can reach
+    /// across chunk boundaries. Costs a little extra hashing per chunk.
+    pub carry_window: bool,
+}
+
+impl Default for ParallelOptions {
+    fn default() -> Self {
+        Self {
+            chunk_size: 128 * 1024,
+            threads: 0,
+            carry_window: true,
+        }
+    }
+}
+
+/// Compresses `input` on several threads and joins the pieces into a single
+/// stream in `config.format`.
+///
+/// Each chunk becomes a run of DEFLATE blocks ending on a byte boundary
+/// (with an empty stored block, like zlib's sync flush), so the pieces
+/// concatenate into one valid stream that any inflater reads as usual. The
+/// output is slightly larger than a sequential run: blocks cannot span
+/// chunks, and without `carry_window` neither can matches.
+pub fn compress_parallel(
+    input: &[u8],
+    config: &CompressionConfig,
+    options: &ParallelOptions,
+) -> Vec<u8> {
+    let chunk_size = options.chunk_size.max(1);
+    let chunks = input.len().div_ceil(chunk_size).max(1);
+    let parts = for_each_index(chunks, options.threads, |index| {
+        let start = index * chunk_size;
+        let end = (start + chunk_size).min(input.len());
+        let dict = if options.carry_window {
+            &input[..start]
+        } else {
+            &[]
+        };
+        compress_piece(&input[start..end], dict, config, index + 1 == chunks)
+    });
+
+    let mut out = Vec::with_capacity(parts.iter().map(Vec::len).sum::<usize>() + 18);
+    match config.format {
+        ContainerFormat::Raw => {}
+        ContainerFormat::Zlib | ContainerFormat::Auto => {
+            out.extend_from_slice(&zlib_header(config))
+        }
+        ContainerFormat::Gzip => out.extend_from_slice(&gzip_header(config)),
+    }
+    for part in &parts {
+        out.extend_from_slice(part);
+    }
+    match config.format {
+        ContainerFormat::Raw => {}
+        ContainerFormat::Zlib | ContainerFormat::Auto => {
+            out.extend_from_slice(&adler32::update(1, input).to_be_bytes())
+        }
+        ContainerFormat::Gzip => {
+            out.extend_from_slice(&crc32::update(0, input).to_le_bytes());
+            out.extend_from_slice(&(input.len() as u32).to_le_bytes());
+        }
+    }
+    out
+}
+
+/// Compresses each of `inputs` into its own complete stream on the rayon
+/// thread pool, returning them in input order. Run it inside
+/// `ThreadPool::install` to use a pool other than the global one.
+///
+/// Meant for asset pipelines packing many small files: with
+/// [`ContainerFormat::Gzip`] every result is a standalone gzip member, and
+/// members may also be concatenated into one multi-member `.gz` file.
+#[cfg(feature = "rayon")]
+pub fn par_compress_chunks(inputs: &[&[u8]], config: &CompressionConfig) -> Vec<Vec<u8>> {
+    use crate::compressor::compress_to_vec;
+    use rayon::prelude::*;
+
+    inputs
+        .par_iter()
+        .map(|input| compress_to_vec(input, config))
+        .collect()
+}
+
+/// Runs `f` for every index below `count` on up to `threads` scoped threads
+/// (0 for one per core) and collects the results in index order.
+fn for_each_index<T, F>(count: usize, threads: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    let threads = match threads {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    }
+    .min(count);
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<T>>> = Mutex::new((0..count).map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= count {
+                    break;
+                }
+                let result = f(index);
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every index is processed"))
+        .collect()
+}
+
+/// Raw DEFLATE data for one chunk, byte aligned and final only for the last.
+fn compress_piece(piece: &[u8], dict: &[u8], config: &CompressionConfig, last: bool) -> Vec<u8> {
+    let mut state = DeflateState::new(config);
+    state.prime(dict);
+    state.ends_stream = last;
+    let mut consumed = 0;
+    while !state.is_finished() {
+        consumed += state
+            .compress_chunk(&piece[consumed..], usize::MAX, true)
+            .consumed;
+    }
+    state.writer.align();
+    let mut out = vec![0; state.writer.pending_len()];
+    state.writer.drain(&mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compress_to_vec, decompress_to_vec};
+
+    fn sample() -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..8_000u32 {
+            let line = alloc::format!(
+                "frame {} block {} crc {:08x}\n",
+                i,
+                i % 17,
+                i.wrapping_mul(2_654_435_761)
+            );
+            data.extend_from_slice(line.as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn parallel_streams_decode_in_every_format() {
+        let data = sample();
+        for format in [
+            ContainerFormat::Raw,
+            ContainerFormat::Zlib,
+            ContainerFormat::Gzip,
+        ] {
+            let config = CompressionConfig::default().with_format(format);
+            for carry_window in [false, true] {
+                let options = ParallelOptions {
+                    chunk_size: 64 * 1024,
+                    threads: 3,
+                    carry_window,
+                };
+                let packed = compress_parallel(&data, &config, &options);
+                assert_eq!(decompress_to_vec(&packed, format).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn carried_window_stays_close_to_sequential() {
+        let data = sample();
+        // In a 32KB window, short matches far into the random CRCs cost more
+        // than they save, as with zlib, and blur the comparison.
+        let config = CompressionConfig::default().with_window_size(8192);
+        let sequential = compress_to_vec(&data, &config).len();
+        let options = ParallelOptions {
+            chunk_size: 16 * 1024,
+            ..ParallelOptions::default()
+        };
+        let carried = compress_parallel(&data, &config, &options).len();
+        let independent = compress_parallel(
+            &data,
+            &config,
+            &ParallelOptions {
+                carry_window: false,
+                ..options
+            },
+        )
+        .len();
+        assert!(carried < independent);
+        assert!(carried < sequential + sequential / 20);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn chunk_results_keep_input_order() {
+        let files: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; 100 + i as usize * 37]).collect();
+        let inputs: Vec<&[u8]> = files.iter().map(Vec::as_slice).collect();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let members = par_compress_chunks(&inputs, &config);
+        assert_eq!(members.len(), files.len());
+        for (member, file) in members.iter().zip(&files) {
+            assert_eq!(
+                &decompress_to_vec(member, ContainerFormat::Gzip).unwrap(),
+                file
+            );
+        }
+        assert!(par_compress_chunks(&[], &config).is_empty());
+    }
+
+    #[test]
+    fn empty_input() {
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let packed = compress_parallel(&[], &config, &ParallelOptions::default());
+        assert!(decompress_to_vec(&packed, ContainerFormat::Gzip)
+            .unwrap()
+            .is_empty());
+    }
+}
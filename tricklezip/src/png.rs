@@ -0,0 +1,454 @@
+//! PNG output: IHDR, zlib-compressed IDAT chunks and IEND around a
+//! [`TrickleCompressor`].
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::compressor::{TrickleCompressor, TrickleStatus};
+use crate::config::{CompressionConfig, ContainerFormat};
+use crate::crc32;
+use crate::http::ChunkSink;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// Compressed bytes per IDAT chunk.
+const IDAT_LEN: usize = 8192;
+
+/// Pixel layout of 8-bit PNG images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngColor {
+    Gray,
+    GrayAlpha,
+    Rgb,
+    Rgba,
+}
+
+impl PngColor {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PngColor::Gray => 1,
+            PngColor::GrayAlpha => 2,
+            PngColor::Rgb => 3,
+            PngColor::Rgba => 4,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            PngColor::Gray => 0,
+            PngColor::GrayAlpha => 4,
+            PngColor::Rgb => 2,
+            PngColor::Rgba => 6,
+        }
+    }
+}
+
+/// Per-row prediction applied before compression.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    /// Mean of the left and upper bytes.
+    Average,
+    Paeth,
+    /// Picks the filter with the smallest sum of absolute residuals for
+    /// each row, the heuristic libpng uses. Costs one extra row buffer.
+    #[default]
+    Adaptive,
+}
+
+/// Writes a PNG a row at a time, so a device can dump a framebuffer
+/// without a second copy of it in RAM.
+///
+/// The signature and IHDR go out with the first row, IDAT chunks whenever
+/// 8KB of compressed data have accumulated and the last IDAT with IEND on
+/// [`finish`](Self::finish). Pair it with [`CompressionConfig::png`].
+///
+/// ```
+/// use tricklezip::{CompressionConfig, PngColor, PngEncoder};
+///
+/// let mut file = Vec::new();
+/// let mut sink = |chunk: &[u8]| -> Result<(), ()> {
+///     file.extend_from_slice(chunk);
+///     Ok(())
+/// };
+/// let mut png = PngEncoder::new(64, 16, PngColor::Rgb, CompressionConfig::png());
+/// for y in 0..16u8 {
+///     let row: Vec<u8> = (0..64u8).flat_map(|x| [x * 4, y * 16, 128]).collect();
+///     png.write_row(&row, &mut sink).unwrap();
+/// }
+/// png.finish(&mut sink).unwrap();
+/// assert!(file.starts_with(b"\x89PNG\r\n\x1a\n"));
+/// assert!(file.ends_with(b"IEND\xae\x42\x60\x82"));
+/// ```
+pub struct PngEncoder {
+    compressor: TrickleCompressor,
+    width: u32,
+    height: u32,
+    color: PngColor,
+    filter: PngFilter,
+    rows: u32,
+    /// Previous unfiltered row, zero before the first.
+    prev: Vec<u8>,
+    /// Filter type byte and filtered row.
+    line: Vec<u8>,
+    /// Candidate row while choosing an adaptive filter.
+    candidate: Vec<u8>,
+    /// Chunk length and type, compressed data, room for the CRC.
+    idat: Vec<u8>,
+    idat_len: usize,
+}
+
+impl PngEncoder {
+    /// Encodes a `width` by `height` image. The container format of
+    /// `config` is forced to zlib, which PNG requires.
+    ///
+    /// # Panics
+    ///
+    /// If either dimension is zero or above 2^31 - 1.
+    pub fn new(width: u32, height: u32, color: PngColor, config: CompressionConfig) -> Self {
+        assert!(
+            (1..=i32::MAX as u32).contains(&width) && (1..=i32::MAX as u32).contains(&height),
+            "PNG dimensions must be between 1 and 2^31 - 1"
+        );
+        let row_len = width as usize * color.bytes_per_pixel();
+        Self {
+            compressor: TrickleCompressor::new(config.with_format(ContainerFormat::Zlib)),
+            width,
+            height,
+            color,
+            filter: PngFilter::default(),
+            rows: 0,
+            prev: vec![0; row_len],
+            line: vec![0; 1 + row_len],
+            candidate: Vec::new(),
+            idat: vec![0; 8 + IDAT_LEN + 4],
+            idat_len: 0,
+        }
+    }
+
+    /// Selects the row filter, [`Adaptive`](PngFilter::Adaptive) by
+    /// default. [`PngFilter::None`] suits palette-like UI screenshots.
+    pub fn filter(mut self, filter: PngFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Bytes expected per row: `width` pixels of the color type.
+    pub fn row_len(&self) -> usize {
+        self.prev.len()
+    }
+
+    /// Filters and compresses the next row, sending chunks as they fill.
+    ///
+    /// # Panics
+    ///
+    /// If `row` is not [`row_len`](Self::row_len) bytes long, or all
+    /// `height` rows have been written already.
+    pub fn write_row<K: ChunkSink>(&mut self, row: &[u8], sink: &mut K) -> Result<(), K::Error> {
+        assert_eq!(
+            row.len(),
+            self.row_len(),
+            "row length does not match the image width"
+        );
+        assert!(self.rows < self.height, "all rows have been written");
+        if self.rows == 0 {
+            self.send_header(sink)?;
+        }
+        let bpp = self.color.bytes_per_pixel();
+        if self.filter == PngFilter::Adaptive {
+            self.candidate.resize(self.line.len(), 0);
+            let mut best = u64::MAX;
+            for filter in [
+                PngFilter::None,
+                PngFilter::Sub,
+                PngFilter::Up,
+                PngFilter::Average,
+                PngFilter::Paeth,
+            ] {
+                apply_filter(filter, row, &self.prev, bpp, &mut self.candidate);
+                let cost = self.candidate[1..]
+                    .iter()
+                    .map(|&b| (b as i8).unsigned_abs() as u64)
+                    .sum();
+                if cost < best {
+                    best = cost;
+                    core::mem::swap(&mut self.line, &mut self.candidate);
+                }
+            }
+        } else {
+            apply_filter(self.filter, row, &self.prev, bpp, &mut self.line);
+        }
+        self.prev.copy_from_slice(row);
+        self.rows += 1;
+        self.compress(false, sink)
+    }
+
+    /// Ends the zlib stream and sends the last IDAT chunk and IEND.
+    ///
+    /// # Panics
+    ///
+    /// If fewer than `height` rows have been written.
+    pub fn finish<K: ChunkSink>(&mut self, sink: &mut K) -> Result<(), K::Error> {
+        assert_eq!(self.rows, self.height, "not all rows have been written");
+        self.compress(true, sink)?;
+        if self.idat_len > 0 {
+            self.send_idat(sink)?;
+        }
+        sink.send(&chunk_bytes(b"IEND", &[]))
+    }
+
+    /// Compresses the current line, or finishes the stream.
+    fn compress<K: ChunkSink>(&mut self, finish: bool, sink: &mut K) -> Result<(), K::Error> {
+        // Taken out for the loop, which sends chunks through `self`.
+        let line = core::mem::take(&mut self.line);
+        let input: &[u8] = if finish { &[] } else { &line };
+        let mut consumed = 0;
+        let result = loop {
+            let r = self
+                .compressor
+                .compress_trickle(
+                    &input[consumed..],
+                    &mut self.idat[8 + self.idat_len..8 + IDAT_LEN],
+                    finish.into(),
+                )
+                .expect("stream is not finished yet");
+            consumed += r.consumed;
+            self.idat_len += r.written;
+            if self.idat_len == IDAT_LEN {
+                if let Err(e) = self.send_idat(sink) {
+                    break Err(e);
+                }
+            }
+            if matches!(r.status, TrickleStatus::NeedInput | TrickleStatus::Finished) {
+                break Ok(());
+            }
+        };
+        self.line = line;
+        result
+    }
+
+    fn send_header<K: ChunkSink>(&mut self, sink: &mut K) -> Result<(), K::Error> {
+        let mut ihdr = [0u8; 13];
+        ihdr[..4].copy_from_slice(&self.width.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&self.height.to_be_bytes());
+        // Bit depth 8, deflate, adaptive filtering, no interlace.
+        ihdr[8..].copy_from_slice(&[8, self.color.code(), 0, 0, 0]);
+        sink.send(&SIGNATURE)?;
+        sink.send(&chunk_bytes(b"IHDR", &ihdr))
+    }
+
+    fn send_idat<K: ChunkSink>(&mut self, sink: &mut K) -> Result<(), K::Error> {
+        let end = 8 + self.idat_len;
+        self.idat[..4].copy_from_slice(&(self.idat_len as u32).to_be_bytes());
+        self.idat[4..8].copy_from_slice(b"IDAT");
+        let crc = crc32::update(0, &self.idat[4..end]);
+        self.idat[end..end + 4].copy_from_slice(&crc.to_be_bytes());
+        self.idat_len = 0;
+        sink.send(&self.idat[..end + 4])
+    }
+}
+
+/// A small chunk with its length, type and CRC.
+fn chunk_bytes(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = Vec::with_capacity(12 + data.len());
+    chunk.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    chunk.extend_from_slice(kind);
+    chunk.extend_from_slice(data);
+    let crc = crc32::update(0, &chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    chunk
+}
+
+/// Writes the filter type byte and the filtered `row` into `out`.
+fn apply_filter(filter: PngFilter, row: &[u8], prev: &[u8], bpp: usize, out: &mut [u8]) {
+    out[0] = match filter {
+        PngFilter::None | PngFilter::Adaptive => 0,
+        PngFilter::Sub => 1,
+        PngFilter::Up => 2,
+        PngFilter::Average => 3,
+        PngFilter::Paeth => 4,
+    };
+    for i in 0..row.len() {
+        let a = if i >= bpp { row[i - bpp] } else { 0 };
+        let b = prev[i];
+        let c = if i >= bpp { prev[i - bpp] } else { 0 };
+        let predicted = match filter {
+            PngFilter::None | PngFilter::Adaptive => 0,
+            PngFilter::Sub => a,
+            PngFilter::Up => b,
+            PngFilter::Average => ((a as u16 + b as u16) / 2) as u8,
+            PngFilter::Paeth => paeth(a, b, c),
+        };
+        out[1 + i] = row[i].wrapping_sub(predicted);
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Encodes a whole image held in memory, rows top to bottom without
+/// padding.
+///
+/// # Panics
+///
+/// If `pixels` does not hold exactly `height` rows.
+pub fn encode_png(
+    width: u32,
+    height: u32,
+    color: PngColor,
+    pixels: &[u8],
+    config: CompressionConfig,
+) -> Vec<u8> {
+    let mut encoder = PngEncoder::new(width, height, color, config);
+    let row_len = encoder.row_len();
+    assert_eq!(
+        pixels.len(),
+        row_len * height as usize,
+        "pixel data does not match the dimensions"
+    );
+    let mut png = Vec::new();
+    let mut sink = |chunk: &[u8]| -> Result<(), core::convert::Infallible> {
+        png.extend_from_slice(chunk);
+        Ok(())
+    };
+    for row in pixels.chunks(row_len) {
+        let Ok(()) = encoder.write_row(row, &mut sink);
+    }
+    let Ok(()) = encoder.finish(&mut sink);
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::decompressor::decompress_to_vec;
+
+    /// Checks chunk CRCs and returns IHDR and the concatenated IDAT data.
+    fn read_chunks(mut png: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        assert_eq!(png[..8], SIGNATURE);
+        png = &png[8..];
+        let (mut ihdr, mut idat) = (Vec::new(), Vec::new());
+        loop {
+            let len = u32::from_be_bytes(png[..4].try_into().unwrap()) as usize;
+            let (kind, data) = (&png[4..8], &png[8..8 + len]);
+            let crc = u32::from_be_bytes(png[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32::update(0, &png[4..8 + len]));
+            match kind {
+                b"IHDR" => ihdr = data.to_vec(),
+                b"IDAT" => idat.extend_from_slice(data),
+                b"IEND" => {
+                    assert_eq!(png.len(), 12);
+                    return (ihdr, idat);
+                }
+                _ => panic!("unexpected chunk"),
+            }
+            png = &png[12 + len..];
+        }
+    }
+
+    fn unfilter(data: &[u8], row_len: usize, bpp: usize) -> Vec<u8> {
+        let mut pixels: Vec<u8> = Vec::new();
+        let mut prev = vec![0u8; row_len];
+        for line in data.chunks(row_len + 1) {
+            let mut row = vec![0u8; row_len];
+            for i in 0..row_len {
+                let a = if i >= bpp { row[i - bpp] } else { 0 };
+                let c = if i >= bpp { prev[i - bpp] } else { 0 };
+                let predicted = match line[0] {
+                    0 => 0,
+                    1 => a,
+                    2 => prev[i],
+                    3 => ((a as u16 + prev[i] as u16) / 2) as u8,
+                    4 => paeth(a, prev[i], c),
+                    _ => panic!("bad filter type"),
+                };
+                row[i] = line[1 + i].wrapping_add(predicted);
+            }
+            pixels.extend_from_slice(&row);
+            prev = row;
+        }
+        pixels
+    }
+
+    #[test]
+    fn images_decode_with_every_filter() {
+        let (width, height) = (300u32, 40u32);
+        let pixels: Vec<u8> = (0..height)
+            .flat_map(|y| {
+                (0..width).flat_map(move |x| [(x + y) as u8, (x * y / 50) as u8, 200, 255])
+            })
+            .collect();
+        for filter in [
+            PngFilter::None,
+            PngFilter::Sub,
+            PngFilter::Up,
+            PngFilter::Average,
+            PngFilter::Paeth,
+            PngFilter::Adaptive,
+        ] {
+            let mut png = Vec::new();
+            let mut sink = |chunk: &[u8]| -> Result<(), ()> {
+                png.extend_from_slice(chunk);
+                Ok(())
+            };
+            let mut encoder =
+                PngEncoder::new(width, height, PngColor::Rgba, CompressionConfig::png())
+                    .filter(filter);
+            for row in pixels.chunks(encoder.row_len()) {
+                encoder.write_row(row, &mut sink).unwrap();
+            }
+            encoder.finish(&mut sink).unwrap();
+
+            let (ihdr, idat) = read_chunks(&png);
+            assert_eq!(ihdr, [0, 0, 1, 44, 0, 0, 0, 40, 8, 6, 0, 0, 0]);
+            let data = decompress_to_vec(&idat, ContainerFormat::Zlib).unwrap();
+            assert_eq!(unfilter(&data, width as usize * 4, 4), pixels);
+        }
+    }
+
+    #[test]
+    fn large_images_span_several_idat_chunks() {
+        let mut x = 7u32;
+        let pixels: Vec<u8> = (0..256 * 128)
+            .map(|_| {
+                x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (x >> 24) as u8
+            })
+            .collect();
+        let png = encode_png(256, 128, PngColor::Gray, &pixels, CompressionConfig::png());
+        let (_, idat) = read_chunks(&png);
+        assert!(idat.len() > 2 * IDAT_LEN);
+        let data = decompress_to_vec(&idat, ContainerFormat::Zlib).unwrap();
+        assert_eq!(unfilter(&data, 256, 1), pixels);
+    }
+
+    #[test]
+    fn adaptive_filter_considers_average() {
+        // Each byte is the mean of its left neighbour and the (zero) row
+        // above, plus a step every 8 pixels: Average leaves only the steps.
+        let mut row = [0u8; 64];
+        for x in 0..row.len() {
+            let left = if x > 0 { row[x - 1] } else { 0 };
+            row[x] = left / 2 + if x % 8 == 0 { 90 } else { 0 };
+        }
+        let png = encode_png(64, 1, PngColor::Gray, &row, CompressionConfig::png());
+        let (_, idat) = read_chunks(&png);
+        let data = decompress_to_vec(&idat, ContainerFormat::Zlib).unwrap();
+        assert_eq!(data[0], 3);
+        assert_eq!(unfilter(&data, 64, 1), row);
+    }
+}
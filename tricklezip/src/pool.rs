@@ -0,0 +1,192 @@
+//! Reusable compressor state for servers handling many short streams.
+
+use core::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use alloc::vec::Vec;
+
+use crate::compressor::TrickleCompressor;
+use crate::config::CompressionConfig;
+use crate::decompressor::TrickleDecompressor;
+
+/// Thread-safe pool of compressors and decompressors sharing one
+/// configuration.
+///
+/// Checked-out instances are ready to start a new stream and go back to the
+/// pool, reset, when the guard is dropped. At most `capacity` idle instances
+/// of each kind are kept; beyond that, returned ones are freed. Checking out
+/// never blocks: an empty pool builds a fresh instance.
+///
+/// ```
+/// use tricklezip::{CompressionConfig, CompressorPool, FlushMode, TrickleStatus};
+///
+/// let pool = CompressorPool::new(CompressionConfig::default(), 16);
+/// let mut out = [0u8; 256];
+/// let mut compressor = pool.compressor();
+/// let result = compressor.compress_trickle(b"temp=21.5", &mut out, FlushMode::Finish).unwrap();
+/// assert_eq!(result.status, TrickleStatus::Finished);
+/// ```
+pub struct CompressorPool {
+    config: CompressionConfig,
+    capacity: usize,
+    compressors: Mutex<Vec<TrickleCompressor>>,
+    decompressors: Mutex<Vec<TrickleDecompressor>>,
+}
+
+impl CompressorPool {
+    pub fn new(config: CompressionConfig, capacity: usize) -> Self {
+        Self {
+            config,
+            capacity,
+            compressors: Mutex::new(Vec::new()),
+            decompressors: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn config(&self) -> &CompressionConfig {
+        &self.config
+    }
+
+    /// Takes an idle compressor or builds a new one.
+    pub fn compressor(&self) -> Pooled<'_, TrickleCompressor> {
+        let idle = self.compressors.lock().unwrap().pop();
+        Pooled {
+            item: Some(idle.unwrap_or_else(|| TrickleCompressor::new(self.config.clone()))),
+            home: &self.compressors,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Takes an idle decompressor for `config.format` or builds a new one.
+    pub fn decompressor(&self) -> Pooled<'_, TrickleDecompressor> {
+        let idle = self.decompressors.lock().unwrap().pop();
+        Pooled {
+            item: Some(idle.unwrap_or_else(|| TrickleDecompressor::new(self.config.format))),
+            home: &self.decompressors,
+            capacity: self.capacity,
+        }
+    }
+
+    /// Idle compressors and decompressors currently held.
+    pub fn idle(&self) -> (usize, usize) {
+        (
+            self.compressors.lock().unwrap().len(),
+            self.decompressors.lock().unwrap().len(),
+        )
+    }
+}
+
+/// Stream state that can be handed back to a [`CompressorPool`].
+pub trait Poolable {
+    fn reset(&mut self);
+}
+
+impl Poolable for TrickleCompressor {
+    fn reset(&mut self) {
+        TrickleCompressor::reset(self);
+    }
+}
+
+impl Poolable for TrickleDecompressor {
+    fn reset(&mut self) {
+        TrickleDecompressor::reset(self);
+    }
+}
+
+/// An instance checked out of a [`CompressorPool`]; returns to it on drop.
+pub struct Pooled<'a, T: Poolable> {
+    item: Option<T>,
+    home: &'a Mutex<Vec<T>>,
+    capacity: usize,
+}
+
+impl<T: Poolable> Deref for Pooled<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.item.as_ref().expect("present until dropped")
+    }
+}
+
+impl<T: Poolable> DerefMut for Pooled<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.item.as_mut().expect("present until dropped")
+    }
+}
+
+impl<T: Poolable> Drop for Pooled<'_, T> {
+    fn drop(&mut self) {
+        let Some(mut item) = self.item.take() else {
+            return;
+        };
+        // A poisoned pool only loses the chance to reuse this instance.
+        let Ok(mut idle) = self.home.lock() else {
+            return;
+        };
+        if idle.len() < self.capacity {
+            item.reset();
+            idle.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+    use crate::config::ContainerFormat;
+    use crate::{FlushMode, TrickleStatus};
+
+    fn round_trip(pool: &CompressorPool, data: &[u8]) {
+        let mut packed = alloc::vec![0u8; data.len() + 64];
+        let mut compressor = pool.compressor();
+        let result = compressor
+            .compress_trickle(data, &mut packed, FlushMode::Finish)
+            .unwrap();
+        assert_eq!(result.status, TrickleStatus::Finished);
+        packed.truncate(result.written);
+        assert_eq!(packed, compress_to_vec(data, pool.config()));
+
+        let mut unpacked = alloc::vec![0u8; data.len()];
+        let mut decompressor = pool.decompressor();
+        let result = decompressor
+            .decompress_trickle(&packed, &mut unpacked)
+            .unwrap();
+        assert_eq!(&unpacked[..result.written], data);
+    }
+
+    #[test]
+    fn returned_instances_are_reset_and_reused() {
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let pool = CompressorPool::new(config, 2);
+        for i in 0..5u8 {
+            round_trip(&pool, &[b'a' + i; 300]);
+        }
+        assert_eq!(pool.idle(), (1, 1));
+    }
+
+    #[test]
+    fn idle_instances_are_bounded() {
+        let pool = CompressorPool::new(CompressionConfig::default(), 2);
+        let held: Vec<_> = (0..5).map(|_| pool.compressor()).collect();
+        assert_eq!(pool.idle(), (0, 0));
+        drop(held);
+        assert_eq!(pool.idle(), (2, 0));
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let pool = CompressorPool::new(CompressionConfig::default(), 4);
+        std::thread::scope(|scope| {
+            for t in 0..8u8 {
+                let pool = &pool;
+                scope.spawn(move || {
+                    for i in 0..20u8 {
+                        round_trip(pool, &[t, i, t ^ i].repeat(100));
+                    }
+                });
+            }
+        });
+        assert!(pool.idle().0 <= 4);
+    }
+}
@@ -0,0 +1,124 @@
+//! Common prefix length of two byte strings, the innermost loop of the match
+//! finder.
+
+/// Number of leading bytes `a` and `b` have in common, up to the shorter
+/// length.
+#[inline]
+pub(crate) fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    let max_len = a.len().min(b.len());
+    let (a, b) = (&a[..max_len], &b[..max_len]);
+    #[cfg(feature = "simd")]
+    let start = simd::common_prefix(a, b);
+    #[cfg(not(feature = "simd"))]
+    let start = 0;
+    start + wordwise(&a[start..], &b[start..])
+}
+
+const WORD: usize = core::mem::size_of::<usize>();
+
+/// Compares a native word at a time; the first differing byte is found from
+/// the trailing zeros of the XOR. Loads go through `from_le_bytes`, so they
+/// need no alignment and give the same answer on big-endian targets.
+#[inline]
+fn wordwise(a: &[u8], b: &[u8]) -> usize {
+    let mut i = 0;
+    for (wa, wb) in a.chunks_exact(WORD).zip(b.chunks_exact(WORD)) {
+        let diff = load(wa) ^ load(wb);
+        if diff != 0 {
+            return i + diff.trailing_zeros() as usize / 8;
+        }
+        i += WORD;
+    }
+    i + a[i..]
+        .iter()
+        .zip(&b[i..])
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+#[inline]
+fn load(bytes: &[u8]) -> usize {
+    let mut word = [0; WORD];
+    word.copy_from_slice(bytes);
+    usize::from_le_bytes(word)
+}
+
+/// 16-byte vector comparison. SSE2 and NEON are part of the x86_64 and
+/// aarch64 baselines, so no runtime detection is needed; other targets skip
+/// straight to the word loop.
+#[cfg(feature = "simd")]
+mod simd {
+    const LANES: usize = 16;
+
+    /// Compares whole 16-byte chunks and returns either the exact mismatch
+    /// position or the start of the tail the word loop has to finish.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    pub(super) fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+        use core::arch::x86_64::{__m128i, _mm_cmpeq_epi8, _mm_loadu_si128, _mm_movemask_epi8};
+
+        let mut i = 0;
+        while i + LANES <= a.len() {
+            // SAFETY: both slices hold at least `i + LANES` bytes, unaligned
+            // loads are allowed and SSE2 is always available on x86_64.
+            let mask = unsafe {
+                let va = _mm_loadu_si128(a.as_ptr().add(i) as *const __m128i);
+                let vb = _mm_loadu_si128(b.as_ptr().add(i) as *const __m128i);
+                _mm_movemask_epi8(_mm_cmpeq_epi8(va, vb)) as u32
+            };
+            if mask != 0xFFFF {
+                return i + (!mask).trailing_zeros() as usize;
+            }
+            i += LANES;
+        }
+        i
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    #[inline]
+    pub(super) fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+        use core::arch::aarch64::{vceqq_u8, vld1q_u8, vminvq_u8};
+
+        let mut i = 0;
+        while i + LANES <= a.len() {
+            // SAFETY: both slices hold at least `i + LANES` bytes and NEON is
+            // always available on aarch64.
+            let all_equal = unsafe {
+                let eq = vceqq_u8(vld1q_u8(a.as_ptr().add(i)), vld1q_u8(b.as_ptr().add(i)));
+                vminvq_u8(eq) == 0xFF
+            };
+            if !all_equal {
+                // The word loop locates the mismatch within this chunk.
+                break;
+            }
+            i += LANES;
+        }
+        i
+    }
+
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[inline]
+    pub(super) fn common_prefix(_a: &[u8], _b: &[u8]) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_naive_comparison() {
+        let a: alloc::vec::Vec<u8> = (0..300u32).map(|i| (i * 7 % 13) as u8).collect();
+        for mismatch in [0, 1, 15, 16, 17, 31, 32, 100, 257, 299] {
+            let mut b = a.clone();
+            b[mismatch] ^= 0x80;
+            for len in [0, 3, 16, 40, 258, 300] {
+                let expected = mismatch.min(len);
+                assert_eq!(common_prefix(&a[..len], &b[..len]), expected);
+            }
+        }
+        assert_eq!(common_prefix(&a, &a), a.len());
+        assert_eq!(common_prefix(&a[..10], &a), 10);
+    }
+}
@@ -0,0 +1,261 @@
+//! Lock-free single-producer, single-consumer byte queue, for input that an
+//! interrupt handler or a DMA completion hands to the compressing task.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Ring buffer of `N` bytes, `N` a power of two, with one writing and one
+/// reading end that need no lock between them.
+///
+/// [`split`](Self::split) it into a [`QueueProducer`] for the interrupt
+/// handler and a [`QueueConsumer`] for the task, which passes the latter to
+/// [`TrickleCompressor::compress_from_queue`](crate::TrickleCompressor::compress_from_queue)
+/// or [`TrickleDecompressor::decompress_from_queue`](crate::TrickleDecompressor::decompress_from_queue).
+/// Those read straight out of the ring, so bytes are not copied into an
+/// intermediate buffer first.
+///
+/// ```
+/// use tricklezip::{compress_to_vec, ByteQueue, CompressionConfig, TrickleCompressor, TrickleStatus};
+///
+/// let mut queue = ByteQueue::<64>::new();
+/// let (mut producer, mut consumer) = queue.split();
+/// let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+/// let samples = b"adc=0511 adc=0512 ".repeat(40);
+/// let mut out = vec![0u8; 256];
+/// let (mut sent, mut written) = (0, 0);
+/// loop {
+///     // An interrupt handler would push this as samples arrive.
+///     sent += producer.push(&samples[sent..]);
+///     let done = sent == samples.len();
+///     let r = compressor
+///         .compress_from_queue(&mut consumer, &mut out[written..], done.into())
+///         .unwrap();
+///     written += r.written;
+///     if r.status == TrickleStatus::Finished {
+///         break;
+///     }
+/// }
+/// assert_eq!(out[..written], compress_to_vec(&samples, &CompressionConfig::default()));
+/// ```
+pub struct ByteQueue<const N: usize> {
+    buf: UnsafeCell<[u8; N]>,
+    /// Bytes ever read; only the consumer stores it.
+    read: AtomicUsize,
+    /// Bytes ever written; only the producer stores it.
+    write: AtomicUsize,
+    /// `split_static` has handed out the two ends.
+    taken: AtomicBool,
+}
+
+// SAFETY: the producer only writes bytes the consumer has released and the
+// consumer only reads bytes the producer has committed; the release and
+// acquire on the two counters order those accesses.
+unsafe impl<const N: usize> Sync for ByteQueue<N> {}
+
+impl<const N: usize> ByteQueue<N> {
+    /// An empty queue, usable in a `static` with
+    /// [`split_static`](Self::split_static).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `N` is not a power of two.
+    pub const fn new() -> Self {
+        assert!(N.is_power_of_two(), "queue size must be a power of two");
+        Self {
+            buf: UnsafeCell::new([0; N]),
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+            taken: AtomicBool::new(false),
+        }
+    }
+
+    /// The writing and the reading end. Borrowing the queue mutably makes
+    /// them the only two.
+    pub fn split(&mut self) -> (QueueProducer<'_, N>, QueueConsumer<'_, N>) {
+        (QueueProducer { queue: self }, QueueConsumer { queue: self })
+    }
+
+    /// The two ends of a queue in a `static`, for the first caller only;
+    /// later calls get `None`, so they stay the only two. Needs atomic
+    /// swaps, which a Cortex-M0 lacks.
+    ///
+    /// ```
+    /// use tricklezip::ByteQueue;
+    ///
+    /// static RX: ByteQueue<64> = ByteQueue::new();
+    ///
+    /// let (mut producer, mut consumer) = RX.split_static().unwrap();
+    /// assert!(RX.split_static().is_none());
+    /// producer.push(b"ok");
+    /// assert_eq!(consumer.read_slice(), b"ok");
+    /// ```
+    #[cfg(target_has_atomic = "8")]
+    pub fn split_static(
+        &'static self,
+    ) -> Option<(QueueProducer<'static, N>, QueueConsumer<'static, N>)> {
+        if self.taken.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        Some((QueueProducer { queue: self }, QueueConsumer { queue: self }))
+    }
+
+    /// `len` bytes from the free-running position `at`, which must not
+    /// cross the end of the ring.
+    fn region(&self, at: usize, len: usize) -> *mut u8 {
+        debug_assert!(at % N + len <= N);
+        // SAFETY: the offset stays within the array, as asserted.
+        unsafe { self.buf.get().cast::<u8>().add(at % N) }
+    }
+}
+
+impl<const N: usize> Default for ByteQueue<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Writing end of a [`ByteQueue`].
+pub struct QueueProducer<'a, const N: usize> {
+    queue: &'a ByteQueue<N>,
+}
+
+impl<const N: usize> QueueProducer<'_, N> {
+    /// Free space, up to the end of the ring, for a DMA transfer or an
+    /// in-place read to fill before [`commit`](Self::commit)ting it. Empty
+    /// while the queue is full.
+    pub fn write_slice(&mut self) -> &mut [u8] {
+        let write = self.queue.write.load(Ordering::Relaxed);
+        let read = self.queue.read.load(Ordering::Acquire);
+        let free = N - write.wrapping_sub(read);
+        let len = free.min(N - write % N);
+        // SAFETY: the consumer has released these bytes and does not touch
+        // them until they are committed.
+        unsafe { core::slice::from_raw_parts_mut(self.queue.region(write, len), len) }
+    }
+
+    /// Hands the first `n` bytes of the [`write_slice`](Self::write_slice)
+    /// to the consumer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` exceeds the length of the write slice.
+    pub fn commit(&mut self, n: usize) {
+        assert!(
+            n <= self.write_slice().len(),
+            "committed past the write slice"
+        );
+        let write = self.queue.write.load(Ordering::Relaxed);
+        self.queue
+            .write
+            .store(write.wrapping_add(n), Ordering::Release);
+    }
+
+    /// Copies as much of `data` as fits into the queue, returning how much.
+    pub fn push(&mut self, data: &[u8]) -> usize {
+        let mut pushed = 0;
+        // At most two passes: up to the end of the ring, then from its start.
+        for _ in 0..2 {
+            let slice = self.write_slice();
+            let n = slice.len().min(data.len() - pushed);
+            slice[..n].copy_from_slice(&data[pushed..pushed + n]);
+            self.commit(n);
+            pushed += n;
+        }
+        pushed
+    }
+}
+
+/// Reading end of a [`ByteQueue`].
+pub struct QueueConsumer<'a, const N: usize> {
+    queue: &'a ByteQueue<N>,
+}
+
+impl<const N: usize> QueueConsumer<'_, N> {
+    /// Committed bytes not yet released.
+    pub fn len(&self) -> usize {
+        let read = self.queue.read.load(Ordering::Relaxed);
+        self.queue.write.load(Ordering::Acquire).wrapping_sub(read)
+    }
+
+    /// Whether there is nothing to read right now.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Committed bytes up to the end of the ring. The rest of
+    /// [`len`](Self::len), if any, follows from the start of the ring once
+    /// these are released.
+    pub fn read_slice(&self) -> &[u8] {
+        let read = self.queue.read.load(Ordering::Relaxed);
+        let len = self.len().min(N - read % N);
+        // SAFETY: the producer has committed these bytes and does not touch
+        // them until they are released.
+        unsafe { core::slice::from_raw_parts(self.queue.region(read, len), len) }
+    }
+
+    /// Gives the first `n` bytes of the [`read_slice`](Self::read_slice)
+    /// back to the producer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` exceeds the length of the read slice.
+    pub fn release(&mut self, n: usize) {
+        assert!(n <= self.read_slice().len(), "released past the read slice");
+        let read = self.queue.read.load(Ordering::Relaxed);
+        self.queue
+            .read
+            .store(read.wrapping_add(n), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around_the_end_of_the_ring() {
+        let mut queue = ByteQueue::<8>::new();
+        let (mut producer, mut consumer) = queue.split();
+        assert_eq!(producer.push(b"abcdef"), 6);
+        assert_eq!(consumer.read_slice(), b"abcdef");
+        consumer.release(5);
+        assert_eq!(producer.push(b"ghijklmnop"), 7);
+        assert!(producer.write_slice().is_empty());
+        assert_eq!(consumer.len(), 8);
+        assert_eq!(consumer.read_slice(), b"fgh");
+        consumer.release(3);
+        assert_eq!(consumer.read_slice(), b"ijklm");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn bytes_cross_threads_in_order() {
+        use alloc::vec::Vec;
+
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        let mut queue = ByteQueue::<32>::new();
+        let (mut producer, mut consumer) = queue.split();
+        let mut received = Vec::new();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                let mut sent = 0;
+                while sent < data.len() {
+                    match producer.push(&data[sent..(sent + 7).min(data.len())]) {
+                        0 => std::thread::yield_now(),
+                        n => sent += n,
+                    }
+                }
+            });
+            while received.len() < data.len() {
+                let slice = consumer.read_slice();
+                received.extend_from_slice(slice);
+                let n = slice.len();
+                consumer.release(n);
+                if n == 0 {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        assert_eq!(received, data);
+    }
+}
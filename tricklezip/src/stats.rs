@@ -0,0 +1,244 @@
+use alloc::string::{String, ToString};
+use core::fmt;
+
+use crate::huffman::BlockType;
+
+/// A DEFLATE block that has just been completed; see
+/// [`TrickleCompressor::on_block_complete`](crate::TrickleCompressor::on_block_complete).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockInfo {
+    pub block_type: BlockType,
+    /// Uncompressed bytes the block covers.
+    pub input_bytes: usize,
+    /// Encoded size of the block, headers included. Blocks are not byte
+    /// aligned, so this is in bits.
+    pub output_bits: usize,
+    /// DEFLATE data emitted so far including this block, in bits, not
+    /// counting the container header. The block ends at this bit offset.
+    pub stream_bits: u64,
+    /// Whether the block has BFINAL set.
+    pub last: bool,
+}
+
+/// Decompression progress; see
+/// [`TrickleDecompressor::on_progress`](crate::TrickleDecompressor::on_progress).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecompressProgress {
+    /// Decompressed bytes produced so far.
+    pub bytes_out: u64,
+    /// DEFLATE blocks decoded completely.
+    pub blocks: u32,
+    /// The stream, trailer included, has been verified.
+    pub finished: bool,
+}
+
+/// Destination for periodically published [`CompressionStats`], such as an
+/// RTT channel, an MQTT metrics topic or a counters registry; see
+/// [`TrickleCompressor::set_stats_sink`](crate::TrickleCompressor::set_stats_sink).
+///
+/// Publishing happens inside `compress_trickle`, so implementations should
+/// queue or copy the values rather than block on I/O. Closures taking
+/// `&CompressionStats` implement it.
+pub trait StatsSink {
+    fn publish(&mut self, stats: &CompressionStats);
+}
+
+impl<F: FnMut(&CompressionStats)> StatsSink for F {
+    fn publish(&mut self, stats: &CompressionStats) {
+        self(stats)
+    }
+}
+
+/// Running counters for a compression stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Uncompressed bytes consumed.
+    pub bytes_in: u64,
+    /// Compressed bytes written, including container framing.
+    pub bytes_out: u64,
+    pub stored_blocks: u32,
+    pub fixed_blocks: u32,
+    pub dynamic_blocks: u32,
+    pub literals: u64,
+    pub matches: u64,
+    /// Calls to `compress_trickle`, including ones that only drained output.
+    pub trickle_calls: u32,
+    /// Input positions run through the match finder, the unit that
+    /// `trickle_size` budgets. Unlike `bytes_in` this does not run ahead of
+    /// the encoder while input waits in the window.
+    pub work_units: u64,
+    /// Calls that returned because the `trickle_size` budget was used up
+    /// rather than for lack of input or output space.
+    pub budget_suspensions: u32,
+    /// Restart points written, see
+    /// [`CompressionConfig::restart_interval`](crate::CompressionConfig::restart_interval)
+    /// and [`FlushMode::Full`](crate::FlushMode::Full).
+    pub restarts: u32,
+    /// Cycles spent filling the window and finding matches.
+    #[cfg(feature = "profiling")]
+    pub lz77_cycles: u64,
+    /// Cycles spent counting frequencies, building trees and choosing the
+    /// block type.
+    #[cfg(feature = "profiling")]
+    pub huffman_cycles: u64,
+    /// Cycles spent writing block headers and codes to the output buffer.
+    #[cfg(feature = "profiling")]
+    pub bitwriter_cycles: u64,
+}
+
+impl CompressionStats {
+    /// Total number of DEFLATE blocks emitted.
+    pub fn blocks(&self) -> u32 {
+        self.stored_blocks + self.fixed_blocks + self.dynamic_blocks
+    }
+
+    /// Output size divided by input size, or 0.0 before any input.
+    pub fn ratio(&self) -> f32 {
+        if self.bytes_in == 0 {
+            0.0
+        } else {
+            self.bytes_out as f32 / self.bytes_in as f32
+        }
+    }
+
+    /// The one-line [`Display`](fmt::Display) form, e.g.
+    /// `12000 B in, 3456 B out (28.8%), 3 blocks`, for logging the outcome
+    /// of a stream once it has finished.
+    pub fn summary(&self) -> String {
+        self.to_string()
+    }
+
+    /// `bytes_out / bytes_in` in tenths of a percent, rounded, so printing
+    /// it does not pull float formatting into the firmware.
+    fn ratio_permille(&self) -> u64 {
+        (self.bytes_out.saturating_mul(1000) + self.bytes_in / 2)
+            .checked_div(self.bytes_in)
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for CompressionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let permille = self.ratio_permille();
+        write!(
+            f,
+            "{} B in, {} B out ({}.{}%), {} blocks",
+            self.bytes_in,
+            self.bytes_out,
+            permille / 10,
+            permille % 10,
+            self.blocks()
+        )
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for CompressionStats {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        let permille = self.ratio_permille();
+        defmt::write!(
+            f,
+            "{=u64} B in, {=u64} B out ({=u64}.{=u64}%), {=u32} blocks",
+            self.bytes_in,
+            self.bytes_out,
+            permille / 10,
+            permille % 10,
+            self.blocks()
+        )
+    }
+}
+
+/// Compression ratio weighted towards the most recent `span` input bytes.
+///
+/// An exponential moving average over input bytes: each block moves the
+/// estimate towards its own ratio by the fraction of `span` it covers, so
+/// after `span` bytes of new content the old content has lost about two
+/// thirds of its weight, and after three spans it is down to 5%. Costs
+/// eight bytes instead of a history buffer.
+pub(crate) struct RatioWindow {
+    span: u32,
+    /// Negative until the first block, which sets the ratio directly.
+    ratio: f32,
+}
+
+impl RatioWindow {
+    pub(crate) fn new(span: u32) -> Self {
+        Self {
+            span: span.max(1),
+            ratio: -1.0,
+        }
+    }
+
+    /// Accounts a block encoding `bytes_in` input bytes into `bits_out` bits.
+    pub(crate) fn record(&mut self, bytes_in: u64, bits_out: u64) {
+        if bytes_in == 0 {
+            return;
+        }
+        let block = bits_out as f32 / 8.0 / bytes_in as f32;
+        let weight = if self.ratio < 0.0 {
+            1.0
+        } else {
+            // 1 - e^(-bytes/span), the decay a byte-by-byte average would
+            // apply over the whole block. `f32::exp` needs std, so e^-x is
+            // taken as (e^(-x/4))^4 with a second-order series inside.
+            let q = (bytes_in as f32 / self.span as f32).min(8.0) / 4.0;
+            let decay = 1.0 / (1.0 + q + q * q / 2.0);
+            1.0 - decay * decay * decay * decay
+        };
+        self.ratio += (block - self.ratio) * weight;
+    }
+
+    /// Output size divided by input size, or 0.0 before any block.
+    pub(crate) fn ratio(&self) -> f32 {
+        self.ratio.max(0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_is_one_line() {
+        let stats = CompressionStats {
+            bytes_in: 12000,
+            bytes_out: 3456,
+            stored_blocks: 1,
+            dynamic_blocks: 2,
+            ..Default::default()
+        };
+        assert_eq!(stats.summary(), "12000 B in, 3456 B out (28.8%), 3 blocks");
+        assert_eq!(
+            CompressionStats::default().summary(),
+            "0 B in, 0 B out (0.0%), 0 blocks"
+        );
+        let expanded = CompressionStats {
+            bytes_in: 3,
+            bytes_out: 13,
+            fixed_blocks: 1,
+            ..Default::default()
+        };
+        assert_eq!(expanded.summary(), "3 B in, 13 B out (433.3%), 1 blocks");
+    }
+
+    #[test]
+    fn old_content_fades_out() {
+        let mut window = RatioWindow::new(8000);
+        for _ in 0..20 {
+            window.record(1000, 8 * 900);
+        }
+        assert!((window.ratio() - 0.9).abs() < 1e-3);
+        for _ in 0..8 {
+            window.record(1000, 8 * 100);
+        }
+        let after_one_span = window.ratio();
+        assert!((0.3..0.45).contains(&after_one_span), "{after_one_span}");
+        for _ in 0..16 {
+            window.record(1000, 8 * 100);
+        }
+        assert!(window.ratio() < 0.15);
+        // A block much longer than the span replaces the history.
+        window.record(100_000, 8 * 50_000);
+        assert!((window.ratio() - 0.5).abs() < 0.01);
+    }
+}
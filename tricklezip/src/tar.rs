@@ -0,0 +1,221 @@
+//! `.tar.gz` archives written in one pass.
+
+use std::io::{self, Write};
+
+use crate::compat::flate2::write::GzEncoder;
+use crate::config::CompressionConfig;
+
+const BLOCK: usize = 512;
+
+/// Streams files into a gzip-compressed ustar archive.
+///
+/// Each file is announced with its size by [`start_file`](Self::start_file)
+/// and its contents are then written through the [`Write`] impl, so log
+/// files can be copied from an SD card to the network without holding any
+/// of them in memory. Nothing is buffered beyond the compressor's window
+/// and an 8KB staging buffer.
+///
+/// ```
+/// use std::io::Write;
+/// use tricklezip::{CompressionConfig, TarGzWriter};
+///
+/// let mut tar = TarGzWriter::new(Vec::new(), CompressionConfig::text());
+/// tar.append_file("logs/boot.log", 0o644, 1_760_000_000, b"boot ok\n").unwrap();
+/// tar.start_file("logs/app.log", 10, 0o644, 1_760_000_060).unwrap();
+/// tar.write_all(b"app ")?;
+/// tar.write_all(b"ready\n")?;
+/// let targz = tar.finish().unwrap();
+/// assert_eq!(&targz[..2], &[0x1F, 0x8B]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct TarGzWriter<W: Write> {
+    gz: GzEncoder<W>,
+    /// Bytes of the current file still to be written.
+    remaining: u64,
+    /// Zero bytes that complete the current file's last block.
+    padding: usize,
+}
+
+impl<W: Write> TarGzWriter<W> {
+    /// Writes the archive to `w`, compressed with `config`; its format is
+    /// forced to gzip.
+    pub fn new(w: W, config: CompressionConfig) -> Self {
+        Self {
+            gz: GzEncoder::with_config(w, config),
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /// Starts a regular file of `size` bytes, to be written next. `mode`
+    /// holds the permission bits and `mtime` is in seconds since the Unix
+    /// epoch.
+    ///
+    /// Fails with `InvalidInput` if the previous file is incomplete, or if
+    /// `path` does not fit ustar's 100-byte name plus 155-byte prefix.
+    pub fn start_file(&mut self, path: &str, size: u64, mode: u32, mtime: u64) -> io::Result<()> {
+        self.end_file()?;
+        let header = ustar_header(path, size, mode, mtime)?;
+        self.gz.write_all(&header)?;
+        self.remaining = size;
+        self.padding = (BLOCK - (size % BLOCK as u64) as usize) % BLOCK;
+        Ok(())
+    }
+
+    /// Adds a whole file held in memory.
+    pub fn append_file(
+        &mut self,
+        path: &str,
+        mode: u32,
+        mtime: u64,
+        data: &[u8],
+    ) -> io::Result<()> {
+        self.start_file(path, data.len() as u64, mode, mtime)?;
+        self.write_all(data)
+    }
+
+    /// Completes the last file, writes the end-of-archive marker and the
+    /// gzip trailer, and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.end_file()?;
+        self.gz.write_all(&[0; 2 * BLOCK])?;
+        self.gz.finish()
+    }
+
+    fn end_file(&mut self) -> io::Result<()> {
+        if self.remaining > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "previous file is shorter than its declared size",
+            ));
+        }
+        self.gz.write_all(&[0; BLOCK][..self.padding])?;
+        self.padding = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for TarGzWriter<W> {
+    /// Writes contents of the current file. Data beyond its declared size
+    /// is rejected with `InvalidInput`.
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.len() as u64 > self.remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "data exceeds the declared file size",
+            ));
+        }
+        let n = self.gz.write(data)?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.gz.flush()
+    }
+}
+
+fn ustar_header(path: &str, size: u64, mode: u32, mtime: u64) -> io::Result<[u8; BLOCK]> {
+    let invalid = |msg| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let path = path.as_bytes();
+    // Split long paths at a slash into prefix and name.
+    let (prefix, name) = if path.len() <= 100 {
+        (&path[..0], path)
+    } else {
+        let split = path
+            .iter()
+            .enumerate()
+            .filter(|&(i, &b)| b == b'/' && i <= 155 && path.len() - i - 1 <= 100)
+            .map(|(i, _)| i)
+            .next()
+            .ok_or_else(|| invalid("path too long for a ustar header"))?;
+        (&path[..split], &path[split + 1..])
+    };
+    if name.is_empty() {
+        return Err(invalid("empty file name"));
+    }
+    if size >= 1 << 33 || mtime >= 1 << 33 {
+        return Err(invalid("size or mtime too large for a ustar header"));
+    }
+
+    let mut header = [0u8; BLOCK];
+    header[..name.len()].copy_from_slice(name);
+    octal(&mut header[100..108], (mode & 0o7777) as u64);
+    octal(&mut header[108..116], 0);
+    octal(&mut header[116..124], 0);
+    octal(&mut header[124..136], size);
+    octal(&mut header[136..148], mtime);
+    header[156] = b'0';
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[345..345 + prefix.len()].copy_from_slice(prefix);
+    // The checksum is computed with its own field set to spaces.
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    octal(&mut header[148..155], sum as u64);
+    Ok(header)
+}
+
+/// Zero-padded octal digits followed by a NUL, filling `field`.
+fn octal(field: &mut [u8], mut value: u64) {
+    let (digits, nul) = field.split_at_mut(field.len() - 1);
+    nul[0] = 0;
+    for d in digits.iter_mut().rev() {
+        *d = b'0' + (value & 7) as u8;
+        value >>= 3;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ContainerFormat;
+    use crate::decompressor::decompress_to_vec;
+
+    fn field(header: &[u8], range: core::ops::Range<usize>) -> u64 {
+        let text = core::str::from_utf8(&header[range]).unwrap();
+        u64::from_str_radix(text.trim_end_matches(['\0', ' ']), 8).unwrap()
+    }
+
+    #[test]
+    fn archive_layout() {
+        let long_dir = "d".repeat(120);
+        let long_path = std::format!("{long_dir}/sensor.log");
+        let mut tar = TarGzWriter::new(Vec::new(), CompressionConfig::default());
+        tar.append_file("a.txt", 0o640, 1_700_000_000, b"hello\n")
+            .unwrap();
+        tar.start_file(&long_path, 1024, 0o644, 5).unwrap();
+        for _ in 0..4 {
+            tar.write_all(&[b'x'; 256]).unwrap();
+        }
+        let raw = decompress_to_vec(&tar.finish().unwrap(), ContainerFormat::Gzip).unwrap();
+
+        // Header, one data block, header, two data blocks, end marker.
+        assert_eq!(raw.len(), 7 * BLOCK);
+        let h = &raw[..BLOCK];
+        assert_eq!(&h[..6], b"a.txt\0");
+        assert_eq!(field(h, 100..108), 0o640);
+        assert_eq!(field(h, 124..136), 6);
+        assert_eq!(field(h, 136..148), 1_700_000_000);
+        let mut sum: u64 = h.iter().map(|&b| b as u64).sum();
+        sum -= h[148..156].iter().map(|&b| b as u64).sum::<u64>() - 8 * b' ' as u64;
+        assert_eq!(field(h, 148..155), sum);
+        assert_eq!(&raw[BLOCK..BLOCK + 7], b"hello\n\0");
+
+        let h = &raw[2 * BLOCK..3 * BLOCK];
+        assert_eq!(&h[..11], b"sensor.log\0");
+        assert_eq!(&h[345..345 + 120], long_dir.as_bytes());
+        assert!(raw[5 * BLOCK..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn size_mismatches_are_rejected() {
+        let mut tar = TarGzWriter::new(Vec::new(), CompressionConfig::default());
+        tar.start_file("short.log", 10, 0o644, 0).unwrap();
+        tar.write_all(b"12345").unwrap();
+        let err = tar.start_file("next.log", 1, 0o644, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = tar.write_all(b"too long, too long").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(ustar_header(&"x".repeat(101), 0, 0, 0).is_err());
+    }
+}
@@ -0,0 +1,238 @@
+//! One compressed stream delivered to two destinations.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::compressor::{TrickleCompressor, TrickleStatus};
+use crate::config::CompressionConfig;
+use crate::error::TrickleError;
+
+/// Destination that may take only part of the data it is offered, such as
+/// a UART transmit queue or a flash page buffer.
+///
+/// Closures returning the number of bytes taken implement it, and so does
+/// `Vec<u8>`, which always takes everything.
+pub trait OutputSink {
+    /// Takes a prefix of `data` and returns its length, 0 when full for now.
+    fn accept(&mut self, data: &[u8]) -> usize;
+}
+
+impl<F: FnMut(&[u8]) -> usize> OutputSink for F {
+    fn accept(&mut self, data: &[u8]) -> usize {
+        self(data)
+    }
+}
+
+impl OutputSink for Vec<u8> {
+    fn accept(&mut self, data: &[u8]) -> usize {
+        self.extend_from_slice(data);
+        data.len()
+    }
+}
+
+/// What one sink did during a [`TeeCompressor::compress`] call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SinkProgress {
+    /// Bytes the sink took.
+    pub written: usize,
+    /// Compressed bytes still waiting for this sink.
+    pub pending: usize,
+    /// The sink took less than it was offered.
+    pub stalled: bool,
+}
+
+/// Progress made by a [`TeeCompressor::compress`] call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TeeResult {
+    /// Input bytes consumed.
+    pub consumed: usize,
+    /// The first and the second sink, in the order they were passed.
+    pub sinks: [SinkProgress; 2],
+    /// [`NeedOutput`](TrickleStatus::NeedOutput) means the staging buffer
+    /// is full, or the stream complete, and a sink has to catch up;
+    /// [`Finished`](TrickleStatus::Finished) that both have all of it.
+    pub status: TrickleStatus,
+}
+
+/// Compresses once and hands the output to two sinks, e.g. a flash backup
+/// and a UART uplink, each at its own pace.
+///
+/// Output is staged in a buffer until both sinks have taken it, so the
+/// faster one can run up to the buffer size ahead of the slower one before
+/// compression pauses. Each call reports per sink how much it took, how
+/// much is still waiting for it and whether it pushed back.
+///
+/// ```
+/// use tricklezip::{compress_to_vec, CompressionConfig, TeeCompressor, TrickleStatus};
+///
+/// let log = b"t=21.5 ok\n".repeat(300);
+/// let mut tee = TeeCompressor::new(CompressionConfig::default(), 256);
+/// let mut flash = Vec::new();
+/// let mut uart = Vec::new();
+/// // The UART takes at most 16 bytes per call.
+/// let mut uart_tx = |data: &[u8]| {
+///     let n = data.len().min(16);
+///     uart.extend_from_slice(&data[..n]);
+///     n
+/// };
+/// let mut consumed = 0;
+/// loop {
+///     let r = tee.compress(&log[consumed..], true, &mut flash, &mut uart_tx).unwrap();
+///     consumed += r.consumed;
+///     if r.status == TrickleStatus::Finished {
+///         break;
+///     }
+/// }
+/// let packed = compress_to_vec(&log, &CompressionConfig::default());
+/// assert_eq!(flash, packed);
+/// assert_eq!(uart, packed);
+/// ```
+pub struct TeeCompressor {
+    compressor: TrickleCompressor,
+    /// Output not yet taken by both sinks.
+    buf: Vec<u8>,
+    len: usize,
+    /// Bytes of `buf` each sink has taken.
+    taken: [usize; 2],
+}
+
+impl TeeCompressor {
+    /// Compresses with `config`, staging up to `buffer_size` bytes of
+    /// output for the slower sink.
+    pub fn new(config: CompressionConfig, buffer_size: usize) -> Self {
+        Self {
+            compressor: TrickleCompressor::new(config),
+            buf: vec![0; buffer_size.max(1)],
+            len: 0,
+            taken: [0; 2],
+        }
+    }
+
+    /// Counters of the underlying compressor.
+    pub fn compressor(&self) -> &TrickleCompressor {
+        &self.compressor
+    }
+
+    /// Compressed bytes waiting for the first and the second sink.
+    pub fn pending(&self) -> [usize; 2] {
+        self.taken.map(|taken| self.len - taken)
+    }
+
+    /// The stream is complete and both sinks have all of it.
+    pub fn is_finished(&self) -> bool {
+        self.compressor.is_finished() && self.pending() == [0, 0]
+    }
+
+    /// Offers waiting output to both sinks, then compresses up to
+    /// `config.trickle_size` bytes of `input` and offers the result too.
+    ///
+    /// As with [`TrickleCompressor::compress_trickle`], unconsumed input
+    /// must be passed again, `finish` marks the end of the data, and calls
+    /// continue until the status is [`Finished`](TrickleStatus::Finished).
+    /// The sinks must be passed in the same order every time.
+    pub fn compress<A: OutputSink, B: OutputSink>(
+        &mut self,
+        input: &[u8],
+        finish: bool,
+        first: &mut A,
+        second: &mut B,
+    ) -> Result<TeeResult, TrickleError> {
+        let mut sinks = [SinkProgress::default(); 2];
+        self.offer(first, second, &mut sinks);
+        let mut consumed = 0;
+        let mut status = TrickleStatus::Finished;
+        if !self.compressor.is_finished() {
+            let r = self.compressor.compress_trickle(
+                input,
+                &mut self.buf[self.len..],
+                finish.into(),
+            )?;
+            consumed = r.consumed;
+            self.len += r.written;
+            status = r.status;
+            self.offer(first, second, &mut sinks);
+        }
+        let pending = self.pending();
+        for (sink, pending) in sinks.iter_mut().zip(pending) {
+            sink.pending = pending;
+        }
+        let status = match status {
+            _ if self.is_finished() => TrickleStatus::Finished,
+            TrickleStatus::Finished => TrickleStatus::NeedOutput,
+            status => status,
+        };
+        Ok(TeeResult {
+            consumed,
+            sinks,
+            status,
+        })
+    }
+
+    /// Hands each sink what it has not taken yet, then drops what both
+    /// have from the buffer.
+    fn offer<A: OutputSink, B: OutputSink>(
+        &mut self,
+        first: &mut A,
+        second: &mut B,
+        progress: &mut [SinkProgress; 2],
+    ) {
+        let sinks: [&mut dyn OutputSink; 2] = [first, second];
+        for ((sink, taken), progress) in sinks.into_iter().zip(&mut self.taken).zip(progress) {
+            let data = &self.buf[*taken..self.len];
+            if data.is_empty() {
+                continue;
+            }
+            let n = sink.accept(data).min(data.len());
+            *taken += n;
+            progress.written += n;
+            progress.stalled |= n < data.len();
+        }
+        let done = self.taken[0].min(self.taken[1]);
+        self.buf.copy_within(done..self.len, 0);
+        self.len -= done;
+        self.taken = self.taken.map(|taken| taken - done);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+    use crate::config::BufferSizes;
+
+    #[test]
+    fn slow_sink_holds_back_only_itself() {
+        let data: Vec<u8> = (0..4000u32)
+            .flat_map(|i| alloc::format!("{} ", i * 7 % 1000).into_bytes())
+            .collect();
+        let config = CompressionConfig::default().with_buffer_sizes(BufferSizes::LOW_LATENCY);
+        let mut tee = TeeCompressor::new(config.clone(), 128);
+        let mut fast = Vec::new();
+        let mut slow = Vec::new();
+        let mut slow_tx = |chunk: &[u8]| {
+            let n = chunk.len().min(3);
+            slow.extend_from_slice(&chunk[..n]);
+            n
+        };
+        let (mut consumed, mut stalls, mut full) = (0, 0, 0);
+        loop {
+            let r = tee
+                .compress(&data[consumed..], true, &mut fast, &mut slow_tx)
+                .unwrap();
+            consumed += r.consumed;
+            assert!(!r.sinks[0].stalled);
+            assert_eq!(r.sinks[0].pending, 0);
+            assert!(r.sinks[1].pending <= 128);
+            stalls += r.sinks[1].stalled as u32;
+            full += (r.status == TrickleStatus::NeedOutput) as u32;
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+        }
+        assert!(tee.is_finished());
+        assert!(stalls > 0 && full > 0);
+        let packed = compress_to_vec(&data, &config);
+        assert_eq!(fast, packed);
+        assert_eq!(slow, packed);
+    }
+}
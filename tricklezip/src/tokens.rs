@@ -0,0 +1,192 @@
+//! The match finder on its own, for entropy coders of your own.
+
+use crate::allocator::{Global, VecIn};
+use crate::config::CompressionConfig;
+use crate::lz77::{Lz77Encoder, Token};
+
+/// Runs the LZ77 match finder of [`TrickleCompressor`] without the Huffman
+/// stage, handing out the token stream for a different entropy coder, e.g.
+/// an arithmetic coder or a hardware unit.
+///
+/// Work is bounded the same way: each [`tokenize`](Self::tokenize) call
+/// advances at most `config.trickle_size` input positions and produces at
+/// most `config.block_size` tokens. Of `config`, only the match finder
+/// settings matter: window size, hash bits, chain length, nice length and
+/// strategy. Replaying the tokens in order reproduces the input.
+///
+/// [`TrickleCompressor`]: crate::TrickleCompressor
+///
+/// ```
+/// use tricklezip::{CompressionConfig, MatchFinder, Token};
+///
+/// let input = b"abcabcabcabc abc";
+/// let mut finder = MatchFinder::new(&CompressionConfig::default());
+/// let mut replay = Vec::new();
+/// let mut consumed = 0;
+/// while !finder.is_finished() {
+///     let batch = finder.tokenize(&input[consumed..], true);
+///     consumed += batch.consumed;
+///     for token in batch {
+///         match token {
+///             Token::Literal(b) => replay.push(b),
+///             Token::Match { length, distance } => {
+///                 for _ in 0..length {
+///                     replay.push(replay[replay.len() - distance as usize]);
+///                 }
+///             }
+///         }
+///     }
+/// }
+/// assert_eq!(replay, input);
+/// ```
+pub struct MatchFinder {
+    lz77: Lz77Encoder,
+    tokens: VecIn<Token, Global>,
+    max_tokens: usize,
+    budget: usize,
+    finished: bool,
+}
+
+/// Tokens found by one [`MatchFinder::tokenize`] call.
+pub struct TokenBatch<'a> {
+    /// Input bytes taken; the rest must be passed again.
+    pub consumed: usize,
+    tokens: core::slice::Iter<'a, Token>,
+}
+
+impl Iterator for TokenBatch<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.tokens.next().copied()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.tokens.size_hint()
+    }
+}
+
+impl ExactSizeIterator for TokenBatch<'_> {}
+
+impl MatchFinder {
+    pub fn new(config: &CompressionConfig) -> Self {
+        let max_tokens = config.block_size.max(1);
+        Self {
+            lz77: Lz77Encoder::new_in(config, Global),
+            tokens: VecIn::with_capacity_in(max_tokens, Global),
+            max_tokens,
+            budget: config.trickle_size.max(1),
+            finished: false,
+        }
+    }
+
+    /// All input has been tokenized after a call with `finish`.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Starts over with an empty window, keeping the buffers.
+    pub fn reset(&mut self, config: &CompressionConfig) {
+        self.lz77.reset(config);
+        self.tokens.clear();
+        self.finished = false;
+    }
+
+    /// Takes what fits of `input` into the window and returns the tokens
+    /// for as much of it as the budget allows. Without `finish`, the last
+    /// bytes are held back until more input shows whether they start a
+    /// match. Pass `finish` once `input` holds the end of the data, and
+    /// call until [`is_finished`](Self::is_finished).
+    pub fn tokenize(&mut self, input: &[u8], finish: bool) -> TokenBatch<'_> {
+        self.tokens.clear();
+        let mut consumed = 0;
+        let mut work = 0;
+        while !self.finished && work < self.budget && self.tokens.len() < self.max_tokens {
+            let filled = self.lz77.fill(&input[consumed..]);
+            consumed += filled;
+            let flush = finish && consumed == input.len();
+            let (advanced, deferred) = self.lz77.encode(
+                &mut self.tokens,
+                self.max_tokens,
+                self.budget - work,
+                work == 0,
+                flush,
+            );
+            work += advanced;
+            // Nothing is kept for stored blocks, so let the window slide.
+            self.lz77.start_block();
+            if deferred {
+                break;
+            }
+            if advanced == 0 && filled == 0 {
+                self.finished = flush && !self.lz77.has_lookahead();
+                break;
+            }
+        }
+        TokenBatch {
+            consumed,
+            tokens: self.tokens.iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::config::Strategy;
+
+    fn replay(tokens: &[Token]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for &token in tokens {
+            match token {
+                Token::Literal(b) => out.push(b),
+                Token::Match { length, distance } => {
+                    assert!((3..=258).contains(&length));
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance as usize]);
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn tokens_replay_to_the_input_within_budget() {
+        let data: Vec<u8> = (0..5000u32)
+            .flat_map(|i| alloc::format!("id={} v={};", i % 40, i % 7).into_bytes())
+            .collect();
+        for config in [
+            CompressionConfig::default(),
+            CompressionConfig::tiny_window(256),
+            CompressionConfig::default().with_strategy(Strategy::Rle),
+        ] {
+            let mut finder = MatchFinder::new(&config);
+            let mut tokens = Vec::new();
+            let mut consumed = 0;
+            while !finder.is_finished() {
+                // Feed in pieces, finishing only with the last one.
+                let end = (consumed + 700).min(data.len());
+                let batch = finder.tokenize(&data[consumed..end], end == data.len());
+                consumed += batch.consumed;
+                assert!(batch.len() <= config.block_size);
+                let covered: usize = batch
+                    .map(|t| {
+                        tokens.push(t);
+                        match t {
+                            Token::Literal(_) => 1,
+                            Token::Match { length, .. } => length as usize,
+                        }
+                    })
+                    .sum();
+                assert!(covered < config.trickle_size + 258);
+            }
+            assert_eq!(replay(&tokens), data);
+            if config.strategy != Strategy::Rle {
+                assert!(tokens.len() < data.len() / 3);
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! In-place transforms of the compressed stream, such as encryption.
+
+/// Transforms compressed bytes in place, e.g. AES-CTR or a whitening
+/// sequence for a radio link.
+///
+/// The stream reaches the transform in pieces of arbitrary size, split
+/// wherever the caller's buffers happen to end, so only transforms that
+/// work byte by byte along the stream are suitable: stream ciphers and
+/// block ciphers in CTR mode, not CBC. Closures taking `&mut [u8]`
+/// implement it.
+///
+/// Register one with [`TrickleCompressor::set_transform`] and reverse it
+/// with [`TrickleDecompressor::set_transform`] and
+/// [`decompress_trickle_mut`]. It covers the whole output, including the
+/// zlib or gzip framing.
+///
+/// [`TrickleCompressor::set_transform`]: crate::TrickleCompressor::set_transform
+/// [`TrickleDecompressor::set_transform`]: crate::TrickleDecompressor::set_transform
+/// [`decompress_trickle_mut`]: crate::TrickleDecompressor::decompress_trickle_mut
+pub trait BlockTransform {
+    /// Transforms `data`, which directly follows the bytes passed to the
+    /// previous call.
+    fn apply(&mut self, data: &mut [u8]);
+}
+
+impl<F: FnMut(&mut [u8])> BlockTransform for F {
+    fn apply(&mut self, data: &mut [u8]) {
+        self(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use crate::compressor::{compress_to_vec, FlushMode, TrickleCompressor, TrickleStatus};
+    use crate::config::{CompressionConfig, ContainerFormat};
+    use crate::decompressor::TrickleDecompressor;
+
+    /// XORs the stream with a keystream from a xorshift generator.
+    fn keystream(seed: u32) -> impl FnMut(&mut [u8]) + Send {
+        let mut state = seed;
+        move |data: &mut [u8]| {
+            for b in data {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                *b ^= state as u8;
+            }
+        }
+    }
+
+    #[test]
+    fn transform_round_trips_through_small_buffers() {
+        let data: Vec<u8> = (0..4000u32)
+            .flat_map(|i| alloc::format!("{} ", i % 97).into_bytes())
+            .collect();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let mut compressor = TrickleCompressor::new(config.clone());
+        compressor.set_transform(keystream(0x2545_F491));
+        let mut sealed = Vec::new();
+        let mut out = [0u8; 37];
+        let mut consumed = 0;
+        while !compressor.is_finished() {
+            let r = compressor
+                .compress_trickle(&data[consumed..], &mut out, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            sealed.extend_from_slice(&out[..r.written]);
+        }
+        let mut plain = compress_to_vec(&data, &config);
+        assert_eq!(sealed.len(), plain.len());
+        assert_ne!(sealed, plain);
+        keystream(0x2545_F491)(&mut plain);
+        assert_eq!(sealed, plain);
+
+        let mut decompressor = TrickleDecompressor::new(ContainerFormat::Gzip);
+        decompressor.set_transform(keystream(0x2545_F491));
+        let mut unpacked = Vec::new();
+        let mut out = vec![0u8; 100];
+        let mut pos = 0;
+        loop {
+            // Hand over a few fresh bytes each time next to the unconsumed ones.
+            let end = (pos + 23).min(sealed.len());
+            let r = decompressor
+                .decompress_trickle_mut(&mut sealed[pos..end], &mut out)
+                .unwrap();
+            pos += r.consumed;
+            unpacked.extend_from_slice(&out[..r.written]);
+            if r.status == TrickleStatus::Finished {
+                break;
+            }
+        }
+        assert_eq!(unpacked, data);
+    }
+}
@@ -0,0 +1,222 @@
+//! Compile-time checked stream phases on top of [`TrickleCompressor`].
+
+use core::marker::PhantomData;
+
+use crate::compressor::{FlushMode, TrickleCompressor, TrickleResult, TrickleStatus};
+use crate::config::CompressionConfig;
+use crate::stats::CompressionStats;
+
+/// Phase of a [`Trickle`] stream: [`Streaming`], [`Finishing`] or
+/// [`Finished`]. Sealed.
+pub trait StreamPhase: sealed::Sealed {}
+
+/// Accepting input; no end of data announced yet.
+#[derive(Debug)]
+pub struct Streaming;
+/// The end of the data has been announced; draining the last blocks and
+/// the trailer.
+#[derive(Debug)]
+pub struct Finishing;
+/// Complete; only stats and reuse are left.
+#[derive(Debug)]
+pub struct Finished;
+
+impl StreamPhase for Streaming {}
+impl StreamPhase for Finishing {}
+impl StreamPhase for Finished {}
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for super::Streaming {}
+    impl Sealed for super::Finishing {}
+    impl Sealed for super::Finished {}
+}
+
+/// A [`TrickleCompressor`] whose phase is part of its type, so the
+/// mistakes `compress_trickle` can only report at run time do not compile:
+/// feeding a stream after announcing its end, or reusing one before its
+/// trailer is out.
+///
+/// [`finish`](Trickle::finish) consumes the streaming compressor, and a
+/// finishing one only turns into a [`Finished`] one once
+/// [`compress`](Trickle::<Finishing>::compress) reports the end. Rust
+/// cannot force a value to be used, so dropping a stream without finishing
+/// it is caught as an unused `#[must_use]` value where possible, not as an
+/// error.
+///
+/// ```
+/// use tricklezip::{CompressionConfig, FinishStep, Trickle};
+///
+/// let mut stream = Trickle::new(CompressionConfig::default());
+/// let mut out = [0u8; 256];
+/// let mut input: &[u8] = b"temp=21.5 temp=21.5 ";
+/// let r = stream.compress(input, &mut out);
+/// input = &input[r.consumed..];
+/// let mut written = r.written;
+///
+/// let mut finishing = stream.finish();
+/// let done = loop {
+///     match finishing.compress(input, &mut out[written..]) {
+///         (r, FinishStep::More(next)) => {
+///             input = &input[r.consumed..];
+///             written += r.written;
+///             finishing = next;
+///         }
+///         (r, FinishStep::Done(done)) => {
+///             written += r.written;
+///             break done;
+///         }
+///     }
+/// };
+/// assert_eq!(done.stats().bytes_out, written as u64);
+/// // done.compress(...) does not exist: the stream is over.
+/// let _reused: Trickle = done.reset();
+/// ```
+///
+/// ```compile_fail
+/// use tricklezip::{CompressionConfig, Trickle};
+///
+/// let mut stream = Trickle::new(CompressionConfig::default());
+/// let _finishing = stream.finish();
+/// stream.compress(b"late", &mut [0u8; 16]); // `stream` was moved
+/// ```
+#[must_use = "a stream has to be finished to produce valid output"]
+pub struct Trickle<S: StreamPhase = Streaming> {
+    compressor: TrickleCompressor,
+    phase: PhantomData<S>,
+}
+
+/// Outcome of [`Trickle::<Finishing>::compress`].
+#[must_use = "a stream has to be finished to produce valid output"]
+pub enum FinishStep {
+    /// More calls are needed.
+    More(Trickle<Finishing>),
+    /// The trailer has been written completely.
+    Done(Trickle<Finished>),
+}
+
+impl<S: StreamPhase> Trickle<S> {
+    fn into_phase<T: StreamPhase>(self) -> Trickle<T> {
+        Trickle {
+            compressor: self.compressor,
+            phase: PhantomData,
+        }
+    }
+
+    pub fn config(&self) -> &CompressionConfig {
+        self.compressor.config()
+    }
+
+    /// Counters so far; final once the stream is [`Finished`].
+    pub fn stats(&self) -> &CompressionStats {
+        self.compressor.stats()
+    }
+}
+
+impl Trickle<Streaming> {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            compressor: TrickleCompressor::new(config),
+            phase: PhantomData,
+        }
+    }
+
+    /// [`TrickleCompressor::compress_trickle`] without `finish`, which
+    /// cannot fail in this phase.
+    pub fn compress(&mut self, input: &[u8], output: &mut [u8]) -> TrickleResult {
+        self.compressor
+            .compress_trickle(input, output, FlushMode::None)
+            .expect("stream is not finished yet")
+    }
+
+    /// Announces the end of the data.
+    pub fn finish(self) -> Trickle<Finishing> {
+        self.into_phase()
+    }
+}
+
+impl Trickle<Finishing> {
+    /// [`TrickleCompressor::compress_trickle`] with `finish`: `input` is
+    /// the rest of the data not consumed yet, if any.
+    pub fn compress(mut self, input: &[u8], output: &mut [u8]) -> (TrickleResult, FinishStep) {
+        let r = self
+            .compressor
+            .compress_trickle(input, output, FlushMode::Finish)
+            .expect("stream is not finished yet");
+        let step = if r.status == TrickleStatus::Finished {
+            FinishStep::Done(self.into_phase())
+        } else {
+            FinishStep::More(self)
+        };
+        (r, step)
+    }
+}
+
+impl Trickle<Finished> {
+    /// Starts a new stream with the same configuration and buffers.
+    pub fn reset(mut self) -> Trickle<Streaming> {
+        self.compressor.reset();
+        self.into_phase()
+    }
+
+    /// The underlying compressor, finished.
+    pub fn into_inner(self) -> TrickleCompressor {
+        self.compressor
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::config::ContainerFormat;
+    use crate::decompressor::decompress_to_vec;
+
+    fn run(stream: Trickle, data: &[u8]) -> (Vec<u8>, Trickle<Finished>) {
+        let mut stream = stream;
+        let mut out = vec![0u8; 64];
+        let mut packed = Vec::new();
+        let mut consumed = 0;
+        for piece in data.chunks(100) {
+            let mut pos = 0;
+            while pos < piece.len() {
+                let r = stream.compress(&piece[pos..], &mut out);
+                pos += r.consumed;
+                packed.extend_from_slice(&out[..r.written]);
+            }
+            consumed += pos;
+        }
+        assert_eq!(consumed, data.len());
+        let mut finishing = stream.finish();
+        loop {
+            match finishing.compress(&[], &mut out) {
+                (r, FinishStep::More(next)) => {
+                    packed.extend_from_slice(&out[..r.written]);
+                    finishing = next;
+                }
+                (r, FinishStep::Done(done)) => {
+                    packed.extend_from_slice(&out[..r.written]);
+                    return (packed, done);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn phases_round_trip_and_reuse() {
+        let data = b"level=info msg=\"sample\" v=3\n".repeat(50);
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let (packed, done) = run(Trickle::new(config), &data);
+        assert_eq!(
+            decompress_to_vec(&packed, ContainerFormat::Gzip).unwrap(),
+            data
+        );
+        assert_eq!(done.stats().bytes_in, data.len() as u64);
+
+        let (again, done) = run(done.reset(), &data);
+        assert_eq!(again, packed);
+        assert!(done.into_inner().is_finished());
+    }
+}
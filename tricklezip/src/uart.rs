@@ -0,0 +1,399 @@
+//! Framing for compressed streams over a plain serial line.
+//!
+//! Each piece of compressed output travels in a frame with a sequence
+//! number and a CRC-16, so the receiver drops line noise and damaged
+//! frames and notices lost ones. All fields are little-endian:
+//!
+//! | offset | size | field                                              |
+//! |-------:|-----:|----------------------------------------------------|
+//! |      0 |    2 | sync, `A5 5A`                                      |
+//! |      2 |    1 | sequence number, wrapping at 256                   |
+//! |      3 |    2 | payload length, at most [`MAX_UART_PAYLOAD`]       |
+//! |      5 |    n | payload: the next piece of the compressed stream   |
+//! |  5 + n |    2 | CRC-16/CCITT-FALSE of bytes 2 to `5 + n`           |
+//!
+//! Unlike [`FrameEncoder`](crate::FrameEncoder) chunks, frames are pieces
+//! of one stream, so the overhead stays at 7 bytes per frame. The price is
+//! that history crosses frames: a lost frame costs the data up to the next
+//! restart point, see [`UartDeframer`].
+
+use alloc::vec::Vec;
+
+use crate::compressor::{TrickleResult, TrickleStatus};
+use crate::decompressor::TrickleDecompressor;
+use crate::error::TrickleError;
+
+const SYNC: [u8; 2] = [0xA5, 0x5A];
+const HEADER_LEN: usize = 5;
+const CRC_LEN: usize = 2;
+/// Bytes a frame adds to its payload.
+pub const UART_FRAME_OVERHEAD: usize = HEADER_LEN + CRC_LEN;
+/// Largest payload of a frame.
+pub const MAX_UART_PAYLOAD: usize = 4096;
+
+/// CRC-16/CCITT-FALSE, computed bitwise: serial links are slow enough that
+/// a table would not pay for its 512 bytes.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFFu16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Wraps pieces of a compressed stream in frames for a serial line.
+///
+/// ```
+/// use tricklezip::{
+///     CompressionConfig, ContainerFormat, FlushMode, TrickleCompressor, TrickleDecompressor, TrickleStatus,
+///     UartDeframer, UartFramer, UART_FRAME_OVERHEAD,
+/// };
+///
+/// let log = b"12:00:01 pump on\n12:00:09 pump off\n".repeat(100);
+/// let mut compressor = TrickleCompressor::new(CompressionConfig::default());
+/// let mut framer = UartFramer::new();
+/// let mut line = Vec::new();
+/// let mut consumed = 0;
+/// loop {
+///     let mut piece = [0u8; 64];
+///     let r = compressor.compress_trickle(&log[consumed..], &mut piece, FlushMode::Finish).unwrap();
+///     consumed += r.consumed;
+///     let mut frame = [0u8; 64 + UART_FRAME_OVERHEAD];
+///     let n = framer.encode(&piece[..r.written], &mut frame).unwrap();
+///     line.extend_from_slice(&frame[..n]);
+///     if r.status == TrickleStatus::Finished {
+///         break;
+///     }
+/// }
+///
+/// let mut deframer = UartDeframer::new(TrickleDecompressor::new(ContainerFormat::Raw), 64);
+/// let mut out = vec![0u8; log.len()];
+/// let r = deframer.receive(&line, &mut out).unwrap();
+/// assert_eq!(r.status, TrickleStatus::Finished);
+/// assert_eq!(out, log);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct UartFramer {
+    sequence: u8,
+}
+
+impl UartFramer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes `payload` as the next frame to the start of `frame` and
+    /// returns the frame length, `payload.len() + UART_FRAME_OVERHEAD`.
+    /// Fails with `OutputTooSmall`, numbering no frame, if `frame` is
+    /// shorter than that. Empty payloads are framed too, though there is
+    /// no need to send them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `payload` is longer than [`MAX_UART_PAYLOAD`].
+    pub fn encode(&mut self, payload: &[u8], frame: &mut [u8]) -> Result<usize, TrickleError> {
+        assert!(
+            payload.len() <= MAX_UART_PAYLOAD,
+            "payload longer than MAX_UART_PAYLOAD"
+        );
+        let len = payload.len() + UART_FRAME_OVERHEAD;
+        let Some(frame) = frame.get_mut(..len) else {
+            return Err(TrickleError::OutputTooSmall);
+        };
+        frame[..2].copy_from_slice(&SYNC);
+        frame[2] = self.sequence;
+        frame[3..5].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        frame[HEADER_LEN..len - CRC_LEN].copy_from_slice(payload);
+        let crc = crc16(&frame[SYNC.len()..len - CRC_LEN]);
+        frame[len - CRC_LEN..].copy_from_slice(&crc.to_le_bytes());
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(len)
+    }
+}
+
+/// Picks [`UartFramer`] frames out of received serial bytes and feeds
+/// their payloads to a decompressor.
+///
+/// Noise between frames and frames failing their CRC are skipped, and so
+/// are repeats of frames already received. A gap in the sequence numbers
+/// means frames were lost. With
+/// [`set_recovery`](TrickleDecompressor::set_recovery) enabled on the
+/// decompressor, decoding then resumes at the next restart point, which
+/// the sender places with
+/// [`CompressionConfig::restart_interval`](crate::CompressionConfig::restart_interval);
+/// without it, the stream fails with [`InvalidData`](TrickleError::InvalidData).
+pub struct UartDeframer {
+    decompressor: TrickleDecompressor,
+    max_payload: usize,
+    /// Received bytes from the start of a possible frame on.
+    buf: Vec<u8>,
+    /// Decoding position and end of the payload of the complete frame at
+    /// the start of `buf`.
+    payload: Option<(usize, usize)>,
+    /// Sequence number of the next frame, once one was received.
+    expected: Option<u8>,
+    lost: u32,
+}
+
+impl UartDeframer {
+    /// Feeds `decompressor` from frames with payloads of up to
+    /// `max_payload` bytes, clamped to [`MAX_UART_PAYLOAD`]. Frames
+    /// claiming to be longer are taken for noise.
+    pub fn new(decompressor: TrickleDecompressor, max_payload: usize) -> Self {
+        let max_payload = max_payload.min(MAX_UART_PAYLOAD);
+        Self {
+            decompressor,
+            max_payload,
+            buf: Vec::with_capacity(max_payload + UART_FRAME_OVERHEAD),
+            payload: None,
+            expected: None,
+            lost: 0,
+        }
+    }
+
+    /// The decompressor fed, e.g. for its [`resyncs`](TrickleDecompressor::resyncs).
+    pub fn decompressor(&self) -> &TrickleDecompressor {
+        &self.decompressor
+    }
+
+    /// Frames found missing from the sequence so far.
+    pub fn lost_frames(&self) -> u32 {
+        self.lost
+    }
+
+    /// Takes received bytes and decompresses the payloads of the frames
+    /// they complete into `output`.
+    ///
+    /// As with [`TrickleDecompressor::decompress_trickle`], unconsumed
+    /// bytes must be passed again. A [`NeedOutput`](TrickleStatus::NeedOutput)
+    /// status means the current payload did not fit; the next call goes on
+    /// with it before taking more bytes.
+    pub fn receive(
+        &mut self,
+        bytes: &[u8],
+        output: &mut [u8],
+    ) -> Result<TrickleResult, TrickleError> {
+        let mut consumed = 0;
+        let mut written = 0;
+        loop {
+            if let Some((pos, end)) = self.payload {
+                let r = self
+                    .decompressor
+                    .decompress_trickle(&self.buf[pos..end], &mut output[written..])?;
+                written += r.written;
+                let pos = pos + r.consumed;
+                if r.status == TrickleStatus::NeedOutput || pos < end {
+                    self.payload = Some((pos, end));
+                    return Ok(TrickleResult {
+                        consumed,
+                        written,
+                        status: r.status,
+                    });
+                }
+                self.payload = None;
+                self.buf.drain(..end + CRC_LEN);
+                if r.status == TrickleStatus::Finished {
+                    return Ok(TrickleResult {
+                        consumed,
+                        written,
+                        status: r.status,
+                    });
+                }
+                self.scan()?;
+                continue;
+            }
+            if consumed == bytes.len() {
+                return Ok(TrickleResult {
+                    consumed,
+                    written,
+                    status: TrickleStatus::NeedInput,
+                });
+            }
+            // Take what completes the header, or the frame it announces.
+            let want = match self.buf.len() {
+                len if len < HEADER_LEN => HEADER_LEN - len,
+                len => self.frame_len() - len,
+            };
+            let n = want.min(bytes.len() - consumed);
+            self.buf.extend_from_slice(&bytes[consumed..consumed + n]);
+            consumed += n;
+            self.scan()?;
+        }
+    }
+
+    /// Length of the frame whose header starts `buf`.
+    fn frame_len(&self) -> usize {
+        u16::from_le_bytes([self.buf[3], self.buf[4]]) as usize + UART_FRAME_OVERHEAD
+    }
+
+    /// Drops bytes from the front of `buf` until it holds the start of a
+    /// plausible frame, and takes that frame's payload once it is complete
+    /// and intact.
+    fn scan(&mut self) -> Result<(), TrickleError> {
+        loop {
+            let n = self.buf.len().min(SYNC.len());
+            let plausible = self.buf[..n] == SYNC[..n]
+                && (self.buf.len() < HEADER_LEN
+                    || self.frame_len() - UART_FRAME_OVERHEAD <= self.max_payload);
+            if !plausible {
+                self.skip();
+                continue;
+            }
+            if self.buf.len() < HEADER_LEN || self.buf.len() < self.frame_len() {
+                return Ok(());
+            }
+            let end = self.frame_len() - CRC_LEN;
+            let crc = u16::from_le_bytes([self.buf[end], self.buf[end + 1]]);
+            if crc16(&self.buf[SYNC.len()..end]) != crc {
+                self.skip();
+                continue;
+            }
+            let sequence = self.buf[2];
+            let behind = self
+                .expected
+                .map_or(0, |expected| sequence.wrapping_sub(expected));
+            if behind >= 128 {
+                // A repeat of a frame already received.
+                self.buf.drain(..end + CRC_LEN);
+                continue;
+            }
+            if behind > 0 {
+                self.lost += behind as u32;
+                self.decompressor.skip_lost_input()?;
+            }
+            self.expected = Some(sequence.wrapping_add(1));
+            self.payload = Some((HEADER_LEN, end));
+            return Ok(());
+        }
+    }
+
+    /// Drops the first byte of `buf` and any that cannot start a frame.
+    fn skip(&mut self) {
+        let next = self.buf[1..]
+            .iter()
+            .position(|&b| b == SYNC[0])
+            .map_or(self.buf.len(), |i| i + 1);
+        self.buf.drain(..next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::{compress_to_vec, FlushMode, TrickleCompressor};
+    use crate::config::{CompressionConfig, ContainerFormat};
+    use alloc::vec;
+
+    fn log() -> Vec<u8> {
+        (0..3000u32)
+            .flat_map(|i| alloc::format!("t={i} v={}\n", i * 13 % 97).into_bytes())
+            .collect()
+    }
+
+    /// Frames of up to `piece` compressed bytes each.
+    fn frames(data: &[u8], config: &CompressionConfig, piece: usize) -> Vec<Vec<u8>> {
+        let mut compressor = TrickleCompressor::new(config.clone());
+        let mut framer = UartFramer::new();
+        let mut frames = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let mut out = vec![0u8; piece];
+            let r = compressor
+                .compress_trickle(&data[consumed..], &mut out, FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            if r.written > 0 {
+                let mut frame = vec![0u8; piece + UART_FRAME_OVERHEAD];
+                let n = framer.encode(&out[..r.written], &mut frame).unwrap();
+                frame.truncate(n);
+                frames.push(frame);
+            }
+            if r.status == TrickleStatus::Finished {
+                return frames;
+            }
+        }
+    }
+
+    /// Feeds `line` in pieces of `step` bytes through an output of `room`.
+    fn receive(deframer: &mut UartDeframer, line: &[u8], step: usize, room: usize) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut consumed = 0;
+        loop {
+            let mut out = vec![0u8; room];
+            let end = (consumed + step).min(line.len());
+            let r = deframer.receive(&line[consumed..end], &mut out).unwrap();
+            consumed += r.consumed;
+            received.extend_from_slice(&out[..r.written]);
+            if r.status == TrickleStatus::Finished || consumed == line.len() && r.written == 0 {
+                return received;
+            }
+        }
+    }
+
+    #[test]
+    fn noise_damage_and_repeats_are_skipped() {
+        let data = log();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let sent = frames(&data, &config, 100);
+        let payloads: Vec<&[u8]> = sent
+            .iter()
+            .map(|f| &f[HEADER_LEN..f.len() - CRC_LEN])
+            .collect();
+        assert_eq!(payloads.concat(), compress_to_vec(&data, &config));
+        let mut line = b"\xA5\xA5\x5A\x00boot noise".to_vec();
+        for (i, frame) in sent.iter().enumerate() {
+            if i % 5 == 1 {
+                // Damaged first, then sent again intact.
+                let mut bad = frame.clone();
+                bad[frame.len() / 2] ^= 0x40;
+                line.extend_from_slice(&bad);
+            }
+            line.extend_from_slice(frame);
+            if i % 7 == 3 {
+                line.extend_from_slice(frame);
+            }
+            line.push(0xA5);
+        }
+        for (step, room) in [(1, 7), (33, 500), (line.len(), data.len())] {
+            let mut deframer =
+                UartDeframer::new(TrickleDecompressor::new(ContainerFormat::Gzip), 100);
+            assert_eq!(receive(&mut deframer, &line, step, room), data);
+            assert!(deframer.decompressor().is_finished());
+            assert_eq!(deframer.lost_frames(), 0);
+        }
+    }
+
+    #[test]
+    fn lost_frames_resume_at_the_next_restart_point() {
+        let data = log();
+        let config = CompressionConfig::default().with_restart_interval(2048);
+        let mut sent = frames(&data, &config, 64);
+        sent.remove(sent.len() / 2);
+        let line = sent.concat();
+
+        let mut strict = UartDeframer::new(TrickleDecompressor::new(ContainerFormat::Raw), 64);
+        let mut out = vec![0u8; data.len()];
+        assert_eq!(
+            strict.receive(&line, &mut out),
+            Err(TrickleError::InvalidData)
+        );
+
+        let mut decompressor = TrickleDecompressor::new(ContainerFormat::Raw);
+        decompressor.set_recovery(true);
+        let mut deframer = UartDeframer::new(decompressor, 64);
+        let received = receive(&mut deframer, &line, 50, 300);
+        assert!(deframer.decompressor().is_finished());
+        assert_eq!(deframer.lost_frames(), 1);
+        assert_eq!(deframer.decompressor().resyncs(), 1);
+        assert!(received.len() < data.len());
+        assert!(data.starts_with(&received[..2048]));
+        assert!(data.ends_with(&received[received.len() - 2048..]));
+    }
+}
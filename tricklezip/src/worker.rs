@@ -0,0 +1,112 @@
+//! Compression on a background thread, fed and drained through channels.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::compressor::{TrickleCompressor, TrickleStatus};
+use crate::config::CompressionConfig;
+
+/// Compressed output of one input chunk sent to a [`spawn_compressor`]
+/// worker, or the end of the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressedChunk {
+    /// Compressed bytes, possibly none while the compressor is still
+    /// collecting a block.
+    pub data: Vec<u8>,
+    /// Uncompressed bytes taken in so far, this chunk included.
+    pub bytes_in: u64,
+    /// The stream is complete; nothing follows this chunk.
+    pub last: bool,
+}
+
+/// Starts a thread that owns a compressor for `config`, so a desktop
+/// companion tool gets pipelined compression without thread plumbing.
+///
+/// Send uncompressed chunks into the returned `Sender`. Each is compressed
+/// as soon as it arrives, and whatever output that produces comes back as a
+/// [`CompressedChunk`]; chunks that only fill the compressor's buffers
+/// produce none. Dropping the `Sender` finishes the stream, which ends with
+/// a chunk marked `last`. The thread exits after that, or as soon as the
+/// `Receiver` is dropped.
+///
+/// ```
+/// use tricklezip::{compress_to_vec, spawn_compressor, CompressionConfig};
+///
+/// let (input, output) = spawn_compressor(CompressionConfig::default());
+/// let log = b"12:00:01 link up\n".repeat(1000);
+/// for line in log.chunks(170) {
+///     input.send(line.to_vec()).unwrap();
+/// }
+/// drop(input);
+///
+/// let packed: Vec<u8> = output.iter().flat_map(|chunk| chunk.data).collect();
+/// assert_eq!(packed, compress_to_vec(&log, &CompressionConfig::default()));
+/// ```
+pub fn spawn_compressor(config: CompressionConfig) -> (Sender<Vec<u8>>, Receiver<CompressedChunk>) {
+    let (input_tx, input_rx) = mpsc::channel::<Vec<u8>>();
+    let (output_tx, output_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut compressor = TrickleCompressor::new(config);
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let (input, finish) = match input_rx.recv() {
+                Ok(input) => (input, false),
+                Err(_) => (Vec::new(), true),
+            };
+            let mut data = Vec::new();
+            let mut consumed = 0;
+            loop {
+                let r = compressor
+                    .compress_trickle(&input[consumed..], &mut buf, finish.into())
+                    .expect("stream is not finished yet");
+                consumed += r.consumed;
+                data.extend_from_slice(&buf[..r.written]);
+                if matches!(r.status, TrickleStatus::NeedInput | TrickleStatus::Finished) {
+                    break;
+                }
+            }
+            if data.is_empty() && !finish {
+                continue;
+            }
+            let chunk = CompressedChunk {
+                data,
+                bytes_in: compressor.stats().bytes_in,
+                last: finish,
+            };
+            if output_tx.send(chunk).is_err() || finish {
+                return;
+            }
+        }
+    });
+    (input_tx, output_rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressor::compress_to_vec;
+    use crate::config::ContainerFormat;
+
+    #[test]
+    fn chunks_come_back_in_order_and_end_with_last() {
+        let data: Vec<u8> = (0..20_000u32)
+            .flat_map(|i| alloc::format!("{} ", i % 613).into_bytes())
+            .collect();
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let (input, output) = spawn_compressor(config.clone());
+        for piece in data.chunks(5000) {
+            input.send(piece.to_vec()).unwrap();
+        }
+        drop(input);
+
+        let chunks: Vec<CompressedChunk> = output.iter().collect();
+        let last = chunks.last().unwrap();
+        assert!(last.last);
+        assert_eq!(last.bytes_in, data.len() as u64);
+        assert!(chunks[..chunks.len() - 1].iter().all(|c| !c.last));
+        let packed: Vec<u8> = chunks.into_iter().flat_map(|c| c.data).collect();
+        assert_eq!(packed, compress_to_vec(&data, &config));
+    }
+}
@@ -0,0 +1,248 @@
+//! One RAM arena lent in turn to streams that never run at the same time.
+
+use core::alloc::{AllocError, Layout};
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use crate::allocator::Allocator;
+
+/// A fixed buffer that sequentially used streams allocate their buffers
+/// from, so a device that compresses its uplink and later decompresses its
+/// downlink only needs RAM for the larger of the two.
+///
+/// The workspace belongs to the one task that runs those streams: it keeps
+/// its fill level in [`Cell`]s, so it is not `Sync` and cannot itself be a
+/// `static`, though the buffer it borrows can be.
+///
+/// [`lend`](Workspace::lend) hands out a bump allocator over the whole
+/// buffer. It borrows the workspace mutably, so the compiler makes sure
+/// every stream using one loan is gone before the next loan starts over
+/// from the beginning. Freeing is a no-op except for the most recent
+/// allocation, which is given back.
+///
+/// ```
+/// #![feature(allocator_api)]
+/// use tricklezip::{
+///     compress_to_vec, CompressionConfig, ContainerFormat, FlushMode, TrickleCompressor,
+///     TrickleDecompressor, Workspace,
+/// };
+///
+/// let mut ram = vec![0u8; 256 * 1024];
+/// let mut workspace = Workspace::new(&mut ram);
+///
+/// let mut uplink = TrickleCompressor::new_in(CompressionConfig::default(), workspace.lend());
+/// let mut packed = [0u8; 64];
+/// uplink.compress_trickle(b"rssi=-70 rssi=-70", &mut packed, FlushMode::Finish).unwrap();
+/// drop(uplink);
+/// let uplink_ram = workspace.peak();
+///
+/// let downlink_packed = compress_to_vec(b"set interval=60", &CompressionConfig::default());
+/// let mut downlink = TrickleDecompressor::new_in(ContainerFormat::Raw, workspace.lend());
+/// let mut out = [0u8; 32];
+/// let d = downlink.decompress_trickle(&downlink_packed, &mut out).unwrap();
+/// assert_eq!(&out[..d.written], b"set interval=60");
+/// drop(downlink);
+/// // The decompressor reused the compressor's bytes.
+/// assert_eq!(workspace.peak(), uplink_ram);
+/// ```
+pub struct Workspace<'buf> {
+    base: NonNull<u8>,
+    len: usize,
+    used: Cell<usize>,
+    peak: Cell<usize>,
+    buf: PhantomData<&'buf mut [u8]>,
+}
+
+impl<'buf> Workspace<'buf> {
+    pub fn new(buf: &'buf mut [u8]) -> Self {
+        Self {
+            len: buf.len(),
+            base: NonNull::from(buf).cast(),
+            used: Cell::new(0),
+            peak: Cell::new(0),
+            buf: PhantomData,
+        }
+    }
+
+    /// Size of the buffer.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Most bytes in use at once over all loans so far, including alignment
+    /// padding: how large the buffer has to be.
+    pub fn peak(&self) -> usize {
+        self.peak.get()
+    }
+
+    /// An allocator over the whole buffer, for the next stream.
+    pub fn lend(&mut self) -> WorkspaceAlloc<'_> {
+        self.used.set(0);
+        WorkspaceAlloc { workspace: self }
+    }
+
+    fn offset_of(&self, ptr: NonNull<u8>) -> usize {
+        ptr.as_ptr() as usize - self.base.as_ptr() as usize
+    }
+}
+
+/// Allocator handed out by [`Workspace::lend`].
+#[derive(Clone, Copy)]
+pub struct WorkspaceAlloc<'a> {
+    workspace: &'a Workspace<'a>,
+}
+
+impl WorkspaceAlloc<'_> {
+    /// Bytes of the workspace in use by this loan.
+    pub fn used(&self) -> usize {
+        self.workspace.used.get()
+    }
+
+    fn set_used(&self, used: usize) {
+        let ws = self.workspace;
+        ws.used.set(used);
+        ws.peak.set(ws.peak.get().max(used));
+    }
+}
+
+// SAFETY: blocks are carved from disjoint ranges of a buffer borrowed for
+// the workspace's lifetime, which `lend` only starts reusing once every
+// handle of the previous loan, and so everything allocated with it, is gone.
+unsafe impl Allocator for WorkspaceAlloc<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ws = self.workspace;
+        let addr = ws.base.as_ptr() as usize + ws.used.get();
+        let start = addr
+            .checked_next_multiple_of(layout.align())
+            .ok_or(AllocError)?;
+        let offset = start - ws.base.as_ptr() as usize;
+        let end = offset.checked_add(layout.size()).ok_or(AllocError)?;
+        if end > ws.len {
+            return Err(AllocError);
+        }
+        self.set_used(end);
+        // SAFETY: `offset <= end <= len`, inside the buffer.
+        let ptr = unsafe { ws.base.add(offset) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let offset = self.workspace.offset_of(ptr);
+        if offset + layout.size() == self.used() {
+            self.set_used(offset);
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let offset = self.workspace.offset_of(ptr);
+        let last = offset + old_layout.size() == self.used();
+        let aligned = (ptr.as_ptr() as usize).is_multiple_of(new_layout.align());
+        if last && aligned {
+            let end = offset.checked_add(new_layout.size()).ok_or(AllocError)?;
+            if end > self.workspace.len {
+                return Err(AllocError);
+            }
+            self.set_used(end);
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
+        let new = self.allocate(new_layout)?;
+        // SAFETY: the blocks do not overlap, `new` being past `used`, and
+        // the caller guarantees `ptr` is valid for `old_layout`.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                new.cast::<u8>().as_ptr(),
+                old_layout.size(),
+            );
+            self.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::compressor::{compress_to_vec, FlushMode, TrickleCompressor};
+    use crate::config::{CompressionConfig, ContainerFormat};
+    use crate::decompressor::TrickleDecompressor;
+
+    #[test]
+    fn uplink_and_downlink_share_one_arena() {
+        let data = b"t=20.5 h=41 p=1013\n".repeat(300);
+        let config = CompressionConfig::default().with_format(ContainerFormat::Gzip);
+        let mut ram = vec![0u8; 256 * 1024];
+        let mut workspace = Workspace::new(&mut ram);
+
+        let mut compressor = TrickleCompressor::new_in(config.clone(), workspace.lend());
+        let mut packed = vec![0u8; data.len()];
+        let (mut consumed, mut written) = (0, 0);
+        while !compressor.is_finished() {
+            let r = compressor
+                .compress_trickle(&data[consumed..], &mut packed[written..], FlushMode::Finish)
+                .unwrap();
+            consumed += r.consumed;
+            written += r.written;
+        }
+        packed.truncate(written);
+        assert_eq!(packed, compress_to_vec(&data, &config));
+        let compressor_heap = compressor.memory_usage() - core::mem::size_of_val(&compressor);
+        drop(compressor);
+
+        let mut decompressor = TrickleDecompressor::new_in(ContainerFormat::Gzip, workspace.lend());
+        let mut out = Vec::new();
+        let mut buf = [0u8; 256];
+        let mut pos = 0;
+        while !decompressor.is_finished() {
+            let r = decompressor
+                .decompress_trickle(&packed[pos..], &mut buf)
+                .unwrap();
+            pos += r.consumed;
+            out.extend_from_slice(&buf[..r.written]);
+        }
+        assert_eq!(out, data);
+        drop(decompressor);
+
+        // The decompressor's window fit into bytes the compressor used.
+        assert!(workspace.peak() >= compressor_heap);
+        assert!(workspace.peak() < compressor_heap + 32768);
+    }
+
+    #[test]
+    fn out_of_space_and_last_block_reuse() {
+        #[repr(align(8))]
+        struct Ram([u8; 64]);
+        let mut ram = Ram([0; 64]);
+        let mut workspace = Workspace::new(&mut ram.0);
+        let alloc = workspace.lend();
+        let a = alloc
+            .allocate(Layout::from_size_align(24, 8).unwrap())
+            .unwrap();
+        assert_eq!(alloc.used(), 24);
+        assert!(alloc.allocate(Layout::new::<[u8; 48]>()).is_err());
+        // SAFETY: `a` came from `alloc` with this layout.
+        let b = unsafe {
+            alloc.grow(
+                a.cast(),
+                Layout::from_size_align(24, 8).unwrap(),
+                Layout::from_size_align(40, 8).unwrap(),
+            )
+        }
+        .unwrap();
+        assert_eq!(b.cast::<u8>(), a.cast::<u8>());
+        assert_eq!(alloc.used(), 40);
+        // SAFETY: as above, after growing.
+        unsafe { alloc.deallocate(b.cast(), Layout::from_size_align(40, 8).unwrap()) };
+        assert_eq!(alloc.used(), 0);
+        assert_eq!(workspace.peak(), 40);
+    }
+}